@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use npc_sim_bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+
+const SIZES: [usize; 3] = [1_000, 10_000, 20_000];
+
+/// agents spread evenly over a 20000 by 20000 world, the same area the `NavGrid` covers
+fn positions(count: usize) -> Vec<Vec2> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    (0..count)
+        .map(|_| {
+            Vec2::new(
+                rng.gen_range(-10000.0..10000.0),
+                rng.gen_range(-10000.0..10000.0),
+            )
+        })
+        .collect()
+}
+
+/// every agent looking for the agents within 300 units, like steering does every frame
+fn neighbour_queries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("neighbour_queries");
+    group.sample_size(10);
+    for count in SIZES {
+        let positions = positions(count);
+        let entities: Vec<Entity> = (0..count as u32).map(Entity::from_raw).collect();
+        let mut index = SpatialIndex::default();
+        for (entity, position) in entities.iter().zip(&positions) {
+            index.insert(*entity, *position);
+        }
+
+        group.bench_with_input(BenchmarkId::new("indexed", count), &count, |b, _| {
+            b.iter(|| {
+                positions
+                    .iter()
+                    .map(|position| index.query_radius(*position, 300.0).count())
+                    .sum::<usize>()
+            })
+        });
+        if count <= 10_000 {
+            group.bench_with_input(BenchmarkId::new("brute_force", count), &count, |b, _| {
+                b.iter(|| {
+                    positions
+                        .iter()
+                        .map(|position| {
+                            positions
+                                .iter()
+                                .filter(|other| other.distance_squared(*position) <= 300.0 * 300.0)
+                                .count()
+                        })
+                        .sum::<usize>()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// a whole frame of the headless simulation, including zone membership and steering
+fn simulation_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulation_frame");
+    group.sample_size(10);
+    for count in SIZES {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins).add_plugin(SimulationPlugin);
+        for (index, position) in positions(count).into_iter().enumerate() {
+            spawn_agent(&mut app.world, format!("agent {}", index), position);
+        }
+        app.update();
+
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| app.update())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, neighbour_queries, simulation_frame);
+criterion_main!(benches);
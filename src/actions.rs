@@ -8,11 +8,18 @@ pub struct ActionsPlugin;
 impl Plugin for ActionsPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Actions>().add_system_set(
-            SystemSet::on_update(GameState::Playing).with_system(set_movement_actions),
+            SystemSet::on_update(GameState::Playing)
+                .label(ActionsLabel)
+                .with_system(set_movement_actions)
+                .with_system(set_clock_actions),
         );
     }
 }
 
+#[derive(SystemLabel, Clone, Eq, PartialEq, Debug, Hash)]
+/// `ActionsLabel` labels the systems that fill in `Actions`, systems reading `Actions` run after it
+pub struct ActionsLabel;
+
 #[derive(Default)]
 /// `Actions` is a struct that contains a single field, `camera_movement`, which is an `Option<Vec2>`.
 ///
@@ -24,8 +31,14 @@ impl Plugin for ActionsPlugin {
 /// Properties:
 ///
 /// * `camera_movement`: This is a Vec2 that represents the movement of the camera.
+/// * `toggle_pause`: Pause or unpause the simulation clock this frame.
+/// * `step`: Run one tick of the simulation clock this frame.
+/// * `change_speed`: Make the simulation clock faster (`Some(true)`) or slower (`Some(false)`) this frame.
 pub struct Actions {
     pub camera_movement: Option<Vec2>,
+    pub toggle_pause: bool,
+    pub step: bool,
+    pub change_speed: Option<bool>,
 }
 
 /// If the player is pressing or releasing a movement key, set the camera movement to the direction the
@@ -92,13 +105,36 @@ fn set_movement_actions(mut actions: ResMut<Actions>, keyboard_input: Res<Input<
     }
 }
 
-/// Creating a new type called `GameControl` that can be one of four values: `Up`, `Down`, `Left`, or
-/// `Right`.
+/// `set_clock_actions` sets the clock actions for the keys the player just pressed
+///
+/// Arguments:
+///
+/// * `actions`: ResMut<Actions> - This is the Actions resource that we created earlier.
+/// * `keyboard_input`: Res<Input<KeyCode>>
+fn set_clock_actions(mut actions: ResMut<Actions>, keyboard_input: Res<Input<KeyCode>>) {
+    actions.toggle_pause = GameControl::Pause.just_pressed(&keyboard_input);
+    actions.step = GameControl::Step.just_pressed(&keyboard_input);
+    actions.change_speed = if GameControl::Faster.just_pressed(&keyboard_input) {
+        Some(true)
+    } else if GameControl::Slower.just_pressed(&keyboard_input) {
+        Some(false)
+    } else {
+        None
+    };
+}
+
+/// Creating a new type called `GameControl` that can be one of four camera movements: `Up`, `Down`,
+/// `Left`, or `Right`, or one of the simulation clock controls: `Pause`, `Step`, `Faster` or
+/// `Slower`.
 enum GameControl {
     Up,
     Down,
     Left,
     Right,
+    Pause,
+    Step,
+    Faster,
+    Slower,
 }
 
 impl GameControl {
@@ -129,6 +165,16 @@ impl GameControl {
                 keyboard_input.just_released(KeyCode::D)
                     || keyboard_input.just_released(KeyCode::Right)
             }
+            GameControl::Pause => keyboard_input.just_released(KeyCode::Space),
+            GameControl::Step => keyboard_input.just_released(KeyCode::Period),
+            GameControl::Faster => {
+                keyboard_input.just_released(KeyCode::Equals)
+                    || keyboard_input.just_released(KeyCode::NumpadAdd)
+            }
+            GameControl::Slower => {
+                keyboard_input.just_released(KeyCode::Minus)
+                    || keyboard_input.just_released(KeyCode::NumpadSubtract)
+            }
         }
     }
 
@@ -156,6 +202,16 @@ impl GameControl {
             GameControl::Right => {
                 keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right)
             }
+            GameControl::Pause => keyboard_input.pressed(KeyCode::Space),
+            GameControl::Step => keyboard_input.pressed(KeyCode::Period),
+            GameControl::Faster => {
+                keyboard_input.pressed(KeyCode::Equals)
+                    || keyboard_input.pressed(KeyCode::NumpadAdd)
+            }
+            GameControl::Slower => {
+                keyboard_input.pressed(KeyCode::Minus)
+                    || keyboard_input.pressed(KeyCode::NumpadSubtract)
+            }
         }
     }
 
@@ -176,6 +232,16 @@ impl GameControl {
                 keyboard_input.just_pressed(KeyCode::D)
                     || keyboard_input.just_pressed(KeyCode::Right)
             }
+            GameControl::Pause => keyboard_input.just_pressed(KeyCode::Space),
+            GameControl::Step => keyboard_input.just_pressed(KeyCode::Period),
+            GameControl::Faster => {
+                keyboard_input.just_pressed(KeyCode::Equals)
+                    || keyboard_input.just_pressed(KeyCode::NumpadAdd)
+            }
+            GameControl::Slower => {
+                keyboard_input.just_pressed(KeyCode::Minus)
+                    || keyboard_input.just_pressed(KeyCode::NumpadSubtract)
+            }
         }
     }
 }
@@ -1,11 +1,20 @@
 use std::ops::DerefMut;
 
+use crate::behaviour::BehaviourTree;
+use crate::camera::cursor_world_position;
+use crate::clock::SimClock;
+use crate::editor::ZoneEditor;
+use crate::goap::GoapAgent;
 use crate::loading::TextureAssets;
+use crate::needs::Needs;
+use crate::spatial::SpatialIndex;
+use crate::steering::{MaxAcceleration, MaxSpeed, SteeringSettings, Velocity};
+use crate::utility::{Activity, UtilityBrain};
 use crate::windows::UiStates;
-use crate::GameState;
+use crate::{GameState, SimLabel, SimStage};
 
 use bevy::prelude::*;
-use bevy::render::camera::{Camera2d, RenderTarget};
+use bevy::render::camera::Camera2d;
 use bevy_egui::EguiContext;
 use bevy_prototype_lyon::draw::{DrawMode, FillMode, StrokeMode};
 use bevy_prototype_lyon::geometry::GeometryBuilder;
@@ -15,19 +24,37 @@ pub struct AgentPlugin;
 
 impl Plugin for AgentPlugin {
     /// `build` is a function that takes a mutable reference to an `App` and adds a systems to it.
-    /// these systems control agents in the data driven model
+    /// these systems control agents in the data driven model and do not need a window or renderer
     ///
     /// Arguments:
     ///
     /// * `app`: The application instance.
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_agent))
-            .add_system_set(
-                SystemSet::on_update(GameState::Playing)
-                    .with_system(click_agent)
-                    .with_system(update_agent),
-            )
-            .insert_resource(Msaa { samples: 4 });
+        app.add_system_set_to_stage(
+            SimStage,
+            SystemSet::on_update(GameState::Playing)
+                .with_system(update_agent.label(SimLabel::UpdateAgent)),
+        );
+    }
+}
+
+pub struct AgentVisualsPlugin;
+
+impl Plugin for AgentVisualsPlugin {
+    /// `build` adds the systems that draw agents and their destinations and let the user
+    /// select agents with the mouse. these sit on top of the systems added by `AgentPlugin`
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(attach_agent_sprites)
+                .with_system(click_agent)
+                .with_system(update_destination_markers),
+        )
+        .insert_resource(Msaa { samples: 4 });
     }
 }
 
@@ -49,83 +76,171 @@ pub struct Agent {
     pub destination: Option<Vec2>,
 }
 
-/// `spawn_agent` spawns a new agent with a sprite and a name
+impl Agent {
+    /// `new` creates an agent with the given name and no destination
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the agent.
+    pub fn new(name: impl Into<String>) -> Self {
+        Agent {
+            name: name.into(),
+            destination: None,
+        }
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Eq)]
+/// `Faction` is the group an agent belongs to, zones can let in whole factions at once.
+pub struct Faction(pub String);
+
+#[derive(Bundle)]
+/// `AgentBundle` is everything the simulation needs to treat an entity as an agent.
+/// spawn it with `commands.spawn_bundle(AgentBundle::new("john", Vec2::ZERO))`.
+///
+/// Properties:
+///
+/// * `agent`: The agent component.
+/// * `needs`: The needs of the agent, all fully satisfied.
+/// * `brain`: Lets the agent choose its own actions.
+/// * `activity`: What the agent is currently doing.
+/// * `velocity`: How the agent is moving, starts standing still.
+/// * `max_speed`: The fastest the agent can move.
+/// * `max_acceleration`: How quickly the agent can change its velocity.
+/// * `transform`: The position of the agent.
+pub struct AgentBundle {
+    pub agent: Agent,
+    pub needs: Needs,
+    pub brain: UtilityBrain,
+    pub activity: Activity,
+    pub velocity: Velocity,
+    pub max_speed: MaxSpeed,
+    pub max_acceleration: MaxAcceleration,
+    #[bundle]
+    pub transform: TransformBundle,
+}
+
+impl AgentBundle {
+    /// `new` creates an agent bundle at `position`
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the agent.
+    /// * `position`: Where in the world the agent starts.
+    pub fn new(name: impl Into<String>, position: Vec2) -> Self {
+        AgentBundle {
+            agent: Agent::new(name),
+            needs: Needs::default(),
+            brain: UtilityBrain::default(),
+            activity: Activity::default(),
+            velocity: Velocity::default(),
+            max_speed: MaxSpeed::default(),
+            max_acceleration: MaxAcceleration::default(),
+            transform: TransformBundle::from_transform(Transform::from_translation(
+                position.extend(1.0),
+            )),
+        }
+    }
+}
+
+/// `spawn_agent` spawns a new agent straight into the world, useful outside of systems such as in
+/// tests or when setting up an `App`. inside systems use `AgentBundle` with `Commands`.
 ///
 /// Arguments:
 ///
-/// * `commands`: Commands - This is the list of commands that bevy completes and is used to to spawn an entity in this example.
+/// * `world`: The world to spawn the agent in.
+/// * `name`: The name of the agent.
+/// * `position`: Where in the world the agent starts.
+///
+/// Returns:
+///
+/// The entity of the new agent.
+pub fn spawn_agent(world: &mut World, name: impl Into<String>, position: Vec2) -> Entity {
+    world
+        .spawn()
+        .insert_bundle(AgentBundle::new(name, position))
+        .id()
+}
+
+/// `attach_agent_sprites` gives every agent that does not have a sprite yet the bevy texture
+///
+/// Arguments:
+///
+/// * `agents`: Query<Entity, (With<Agent>, Without<Sprite>)> - query containing agents that are not drawn yet.
 /// * `textures`: Res<TextureAssets> - resource containing texture assets used to give the entity a texture.
-fn spawn_agent(mut commands: Commands, textures: Res<TextureAssets>) {
-    commands
-        .spawn_bundle(SpriteBundle {
-            texture: textures.texture_bevy.clone(),
-            transform: Transform::from_translation(Vec3::new(1., 1., 1.)),
-            ..Default::default()
-        })
-        .insert(Agent {
-            name: "john".to_string(),
-            destination: None,
-        });
+/// * `commands`: Commands - This is the list of commands that bevy completes and is used to add the sprite components.
+fn attach_agent_sprites(
+    agents: Query<Entity, (With<Agent>, Without<Sprite>)>,
+    textures: Res<TextureAssets>,
+    mut commands: Commands,
+) {
+    for entity in agents.iter() {
+        commands
+            .entity(entity)
+            .insert(Sprite::default())
+            .insert(textures.texture_bevy.clone())
+            .insert(Visibility::default());
+    }
 }
 
 #[derive(Debug, Component)]
 pub struct DestinationMarker;
 
-/// `update_agent` updates the agent's position and rotation in accordance with destination
-/// This is checking if the agent has reached its destination and if it has then it deletes the
-/// destination.
+/// `update_agent` moves every agent by its `Velocity` and turns it to face where it is going.
+/// agents within the arrival radius of their destination have arrived and forget it
 ///
 /// Arguments:
 ///
-/// * `agent_query`: Query<(&mut Agent, Entity)> - query containing agents and their entities.
-/// * `destination_visual_query`: Query<Entity, With<DestinationMarker>> - query containing entities with the destination marker.
-/// * `transform_q`: Query<&mut Transform> - query containing transforms.
-/// * `time`: Res<Time> - resource containing the time, used to get delta time between frames.
-/// * `commands`: Commands - This is the list of commands that bevy completes and is used to to de-spawn and create entities in this example.
+/// * `agent_query`: Query<(&mut Agent, &mut Transform, &Velocity)> - query containing agents, their transforms and velocities.
+/// * `settings`: Res<SteeringSettings> - resource containing the arrival radius.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
 fn update_agent(
-    mut agent_query: Query<(&mut Agent, Entity)>,
-    destination_visual_query: Query<Entity, With<DestinationMarker>>,
-    mut transform_q: Query<&mut Transform>,
-    time: Res<Time>,
+    mut agent_query: Query<(&mut Agent, &mut Transform, &Velocity)>,
+    settings: Res<SteeringSettings>,
+    clock: Res<SimClock>,
+) {
+    for (mut agent, mut transform, velocity) in agent_query.iter_mut() {
+        transform.translation += velocity.0.extend(0.0) * clock.delta_seconds();
+        if velocity.0.length_squared() > f32::EPSILON {
+            let angle = velocity.0.y.atan2(velocity.0.x);
+            transform.rotation = Quat::from_axis_angle(Vec3::new(0., 0., 1.), angle);
+        }
+
+        let position = transform.translation.truncate();
+        if agent
+            .destination
+            .is_some_and(|destination| destination.distance(position) <= settings.arrival_radius)
+        {
+            agent.destination = None;
+        }
+    }
+}
+
+/// `update_destination_markers` draws a hexagon at the destination of every agent and removes the
+/// hexagons of destinations that are no longer used.
+///
+/// Arguments:
+///
+/// * `agent_query`: Query<&Agent> - query containing agents.
+/// * `destination_visual_query`: Query<(Entity, &Transform), With<DestinationMarker>> - query containing entities with the destination marker.
+/// * `commands`: Commands - This is the list of commands that bevy completes and is used to to de-spawn and create entities in this example.
+fn update_destination_markers(
+    agent_query: Query<&Agent>,
+    destination_visual_query: Query<(Entity, &Transform), With<DestinationMarker>>,
     mut commands: Commands,
 ) {
     let mut valid_dests: Vec<Entity> = Vec::new();
 
-    for (agent, entity) in agent_query.iter_mut() {
-        let mut transform: Mut<Transform> = transform_q.get_mut(entity).unwrap();
-        let mut agent: Mut<Agent> = agent;
-
+    for agent in agent_query.iter() {
         if let Some(destination) = agent.destination {
-            // mu life is broken
-            let diff = destination - transform.translation.truncate();
-            let angle = diff.y.atan2(diff.x);
-            transform.rotation = Quat::from_axis_angle(Vec3::new(0., 0., 1.), angle);
-
-            let move_dir = transform.local_x() * 1000.0 * time.delta_seconds();
-            transform.translation += move_dir;
-
-            let scale_x: f32 = 20.0;
-            let scale_y: f32 = 20.0;
-            let pos_x: f32 = transform.translation.x;
-            let pos_y: f32 = transform.translation.y;
-
-            // if the destination is within the bounds of the agent then print the agent name
-            if destination.x >= pos_x - scale_x / 2.0
-                && destination.x <= pos_x + scale_x / 2.0
-                && destination.y >= pos_y - scale_y / 2.0
-                && destination.y <= pos_y + scale_y / 2.0
-            {
-                agent.destination = None;
-            }
-
-            let destination_visual: Option<(&Transform, Entity)> = destination_visual_query
+            let destination_visual: Option<Entity> = destination_visual_query
                 .iter()
-                .map(|entity| (transform_q.get(entity).unwrap(), entity))
-                .find(|(t, _)| {
-                    t.translation.y == destination.y && t.translation.x == destination.x
-                });
+                .find(|(_, t)| t.translation.y == destination.y && t.translation.x == destination.x)
+                .map(|(entity, _)| entity);
 
-            if destination_visual.is_none() {
+            if let Some(entity) = destination_visual {
+                valid_dests.push(entity);
+            } else {
                 let shape = shapes::RegularPolygon {
                     sides: 6,
                     feature: shapes::RegularPolygonFeature::Radius(200.0),
@@ -142,12 +257,10 @@ fn update_agent(
                         Transform::from_translation(destination.extend(0.0)),
                     ))
                     .insert(DestinationMarker);
-            } else if let Some((_, entity)) = destination_visual {
-                valid_dests.push(entity);
             }
         }
     }
-    for dest in destination_visual_query.iter() {
+    for (dest, _) in destination_visual_query.iter() {
         if !valid_dests.contains(&dest) {
             commands.entity(dest).despawn();
         }
@@ -155,85 +268,62 @@ fn update_agent(
 }
 
 /// 'click_agent' converts the mouse click position to
-/// a world position, and then checks if the click was inside
-/// the bounding box of any agents. If it was, it adds the agent to the list of selected agents
+/// a world position, and then looks up the agents around the click in the `SpatialIndex`. every
+/// agent within the bounding box of the click is added to the list of selected agents. clicking
+/// where there are no agents sends the selected agents there, unless an AI decides where they go.
+/// clicks are left to the zone editor while it is open
 ///
 /// Arguments:
 ///
-/// * `agent_query`: Query<(&mut Agent, &Transform, &Sprite, Entity)> - query containing agents, their transforms, their sprites, and their entities.
+/// * `agent_query`: Query<&mut Agent, (Without<UtilityBrain>, Without<BehaviourTree>, Without<GoapAgent>)> - query containing agents without an AI.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index used to find clicked agents.
 /// * `mouse_input`: Res<Input<MouseButton>> - resource containing mouse button inputs.
 /// * `windows`: Res<Windows> - resource containing all of the windows.
 /// * `camera_query`: Query<(&Camera, &GlobalTransform), With<Camera2d>> - query containing the camera and its global transform.
 /// * `ui_states`: ResMut<UiStates> - resource containing a list of entities that are being rendered in the user interface.
 /// * `egui_context`: Res<EguiContext> - resource containing the context for the Egui user interface.
+/// * `editor`: Option<Res<ZoneEditor>> - resource containing the state of the zone editor, if it is added.
 fn click_agent(
-    mut agent_query: Query<(&mut Agent, &Transform, &Sprite, Entity)>,
+    mut agent_query: Query<
+        &mut Agent,
+        (
+            Without<UtilityBrain>,
+            Without<BehaviourTree>,
+            Without<GoapAgent>,
+        ),
+    >,
+    index: Res<SpatialIndex>,
     mouse_input: Res<Input<MouseButton>>,
     windows: Res<Windows>,
     camera_query: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
     mut ui_states: ResMut<UiStates>,
     egui_context: Res<EguiContext>,
+    editor: Option<Res<ZoneEditor>>,
 ) {
-    let win = windows.get_primary().expect("no primary window");
+    if editor.is_some_and(|editor| editor.enabled) {
+        return;
+    }
     if mouse_input.just_pressed(MouseButton::Left) {
-        if let Some(_cursor_pos) = win.cursor_position() {
-            // println!("click at {:?}", cursor_pos);
-
-            // convert the cursor position to a world position
-            // get the camera info and transform
-            // assuming there is exactly one main camera entity, so query::single() is OK
-            let (camera, camera_transform) = camera_query.single();
-
-            // get the window that the camera is displaying to (or the primary window)
-            let wnd = if let RenderTarget::Window(id) = camera.target {
-                windows.get(id).unwrap()
-            } else {
-                windows.get_primary().unwrap()
-            };
-
-            // check if the cursor is inside the window and get its position
-            if let Some(screen_pos) = wnd.cursor_position() {
-                // get the size of the window
-                let window_size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
+        // assuming there is exactly one main camera entity, so query::single() is OK
+        let (camera, camera_transform) = camera_query.single();
 
-                // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
-                let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
-
-                // matrix for undoing the projection and camera transform
-                let ndc_to_world =
-                    camera_transform.compute_matrix() * camera.projection_matrix.inverse();
-
-                // use it to convert ndc to world-space coordinates
-                let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
-
-                // reduce it to a 2D value
-                let world_pos: Vec2 = world_pos.truncate();
-
-                // println!("World coords: {}/{}", world_pos.x, world_pos.y);
-
-                for (agent, transform, sprite, entity) in agent_query.iter_mut() {
-                    // println!("{:?}", agent)
-                    let mut agent: Mut<Agent> = agent;
-                    let transform: &Transform = transform;
-                    let _sprite: &Sprite = sprite;
-
-                    // println!("{:?}", transform);
-                    if !egui_context.ctx().wants_pointer_input() {
+        if let Some(world_pos) = cursor_world_position(&windows, camera, camera_transform) {
+            let half_size = Vec2::splat(300.0 / 2.0);
+            let clicked: Vec<Entity> = index
+                .query_rect(world_pos - half_size, world_pos + half_size)
+                .map(|(entity, _)| entity)
+                .collect();
+            if clicked.is_empty() && !egui_context.ctx().wants_pointer_input() {
+                for entity in ui_states.agents.iter() {
+                    if let Ok(mut agent) = agent_query.get_mut(*entity) {
                         agent.destination = Some(world_pos);
                     }
+                }
+            }
 
-                    let scale_x: f32 = 300.0;
-                    let scale_y: f32 = 300.0;
-                    let pos_x: f32 = transform.translation.x;
-                    let pos_y: f32 = transform.translation.y;
-                    if world_pos.x >= pos_x - scale_x / 2.0
-                        && world_pos.x <= pos_x + scale_x / 2.0
-                        && world_pos.y >= pos_y - scale_y / 2.0
-                        && world_pos.y <= pos_y + scale_y / 2.0
-                        && !ui_states.agents.contains(&entity)
-                    {
-                        ui_states.deref_mut().agents.push(entity);
-                    }
+            for entity in clicked {
+                if !ui_states.agents.contains(&entity) {
+                    ui_states.deref_mut().agents.push(entity);
                 }
             }
         }
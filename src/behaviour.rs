@@ -0,0 +1,606 @@
+use crate::agent::Agent;
+use crate::clock::SimClock;
+use crate::memory::Beliefs;
+use crate::needs::{NeedKind, Needs};
+use crate::zone::{Queued, Zone, ZoneRefused};
+use crate::{GameState, SimLabel, SimStage};
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub struct BehaviourTreePlugin;
+
+impl Plugin for BehaviourTreePlugin {
+    /// `build` adds the system that ticks the behaviour tree of every agent that has one. this
+    /// works headless, trees can be built in code or parsed with `BehaviourNode::from_ron`
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            SimStage,
+            SystemSet::on_update(GameState::Playing).with_system(
+                tick_behaviour_trees
+                    .label(SimLabel::ChooseAction)
+                    .after(SimLabel::UpdateNeeds)
+                    .before(SimLabel::UpdateAgent),
+            ),
+        );
+    }
+}
+
+pub struct BehaviourAssetPlugin;
+
+impl Plugin for BehaviourAssetPlugin {
+    /// `build` registers `.bt.ron` files as `BehaviourTreeAsset`s and gives agents with a
+    /// `BehaviourTreeHandle` a `BehaviourTree` once the file is loaded. needs the `AssetServer`
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_asset::<BehaviourTreeAsset>()
+            .init_asset_loader::<BehaviourTreeLoader>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing).with_system(instantiate_behaviour_trees),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// `Status` is the result of ticking a node.
+pub enum Status {
+    Running,
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// `ParallelPolicy` decides when a `Parallel` node is done.
+pub enum ParallelPolicy {
+    /// succeeds when every child succeeded, fails as soon as one fails
+    RequireAll,
+    /// succeeds as soon as one child succeeded, fails when every child failed
+    RequireOne,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `BlackboardValue` is a value stored in the `Blackboard` of a tree.
+pub enum BlackboardValue {
+    Bool(bool),
+    Number(f32),
+    Text(String),
+    /// an entity of the running simulation, written as its id in save files
+    #[serde(with = "crate::save::entity_bits")]
+    Entity(Entity),
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+/// `Blackboard` is the memory a behaviour tree shares between its nodes.
+pub struct Blackboard {
+    values: BTreeMap<String, BlackboardValue>,
+}
+
+impl Blackboard {
+    /// `get` gets a value from the blackboard
+    pub fn get(&self, key: &str) -> Option<&BlackboardValue> {
+        self.values.get(key)
+    }
+
+    /// `set` stores a value in the blackboard, replacing the old value
+    pub fn set(&mut self, key: impl Into<String>, value: BlackboardValue) {
+        self.values.insert(key.into(), value);
+    }
+
+    /// `remove` removes a value from the blackboard
+    pub fn remove(&mut self, key: &str) -> Option<BlackboardValue> {
+        self.values.remove(key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `BehaviourNode` is a node of a behaviour tree, either a composite, a decorator or a leaf task.
+pub enum BehaviourNode {
+    /// runs its children in order until one fails
+    Sequence(Vec<BehaviourNode>),
+    /// runs its children in order until one succeeds
+    Selector(Vec<BehaviourNode>),
+    /// runs all of its children every tick
+    Parallel {
+        policy: ParallelPolicy,
+        children: Vec<BehaviourNode>,
+    },
+    /// turns success into failure and failure into success
+    Inverter(Box<BehaviourNode>),
+    /// always succeeds once its child is done
+    Succeeder(Box<BehaviourNode>),
+    /// runs its child `times` times, or forever if `times` is `None`. stops when the child fails
+    Repeat {
+        times: Option<u32>,
+        child: Box<BehaviourNode>,
+    },
+    /// walks to the center of the zone with this name, succeeds once inside the zone. keeps running
+    /// while the agent queues outside it
+    MoveToZone(String),
+    /// waits for a number of seconds
+    Wait(f32),
+    /// finds the nearest agent within `range` and spends `seconds` with it, raising the social need
+    InteractWithAgent { range: f32, seconds: f32 },
+    /// succeeds if the need is below the threshold
+    NeedBelow { need: NeedKind, threshold: f32 },
+    /// stores a value in the blackboard
+    SetBlackboard { key: String, value: BlackboardValue },
+    /// succeeds if the blackboard holds this value
+    CheckBlackboard { key: String, value: BlackboardValue },
+}
+
+impl BehaviourNode {
+    /// `from_ron` parses a node from the RON format used by `.bt.ron` files
+    pub fn from_ron(ron: &str) -> Result<Self, ron::Error> {
+        ron::from_str(ron)
+    }
+
+    /// `node_count` is the number of nodes in this subtree, including itself
+    pub fn node_count(&self) -> usize {
+        1 + self
+            .children()
+            .iter()
+            .map(|child| child.node_count())
+            .sum::<usize>()
+    }
+
+    /// `children` are the direct children of the node
+    pub fn children(&self) -> Vec<&BehaviourNode> {
+        match self {
+            BehaviourNode::Sequence(children)
+            | BehaviourNode::Selector(children)
+            | BehaviourNode::Parallel { children, .. } => children.iter().collect(),
+            BehaviourNode::Inverter(child)
+            | BehaviourNode::Succeeder(child)
+            | BehaviourNode::Repeat { child, .. } => vec![child.as_ref()],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `NodeMemory` is the state a node keeps between ticks.
+enum NodeMemory {
+    Fresh,
+    Child(usize),
+    Timer(f32),
+    Count(u32),
+    Statuses(Vec<Status>),
+    Moving,
+    Partner(#[serde(with = "crate::save::entity_bits")] Entity, f32),
+}
+
+#[derive(Debug, Clone, PartialEq, Component, Serialize, Deserialize)]
+/// `BehaviourTree` drives an agent with a tree of nodes. agents with a tree are ignored by the
+/// utility AI. the tree starts again from the root every time it finishes.
+///
+/// Properties:
+///
+/// * `root`: the root node of the tree.
+/// * `blackboard`: the memory shared between nodes.
+/// * `status`: the result of the last tick.
+pub struct BehaviourTree {
+    root: BehaviourNode,
+    memory: Vec<NodeMemory>,
+    pub blackboard: Blackboard,
+    pub status: Status,
+}
+
+impl BehaviourTree {
+    pub fn new(root: BehaviourNode) -> Self {
+        BehaviourTree {
+            memory: vec![NodeMemory::Fresh; root.node_count()],
+            root,
+            blackboard: Blackboard::default(),
+            status: Status::Running,
+        }
+    }
+
+    /// `root` is the root node of the tree
+    pub fn root(&self) -> &BehaviourNode {
+        &self.root
+    }
+
+    /// `map_entities` swaps every entity the tree remembers for another one, such as when a saved
+    /// tree is loaded into a new world. nodes that remember an entity that is gone start over
+    ///
+    /// Arguments:
+    ///
+    /// * `map`: Gets the new entity for an old one, `None` if the entity is gone.
+    pub fn map_entities(&mut self, map: impl Fn(Entity) -> Option<Entity>) {
+        for memory in self.memory.iter_mut() {
+            if let NodeMemory::Partner(partner, elapsed) = memory {
+                *memory = match map(*partner) {
+                    Some(partner) => NodeMemory::Partner(partner, *elapsed),
+                    None => NodeMemory::Fresh,
+                };
+            }
+        }
+        self.blackboard.values.retain(|_, value| match value {
+            BlackboardValue::Entity(entity) => match map(*entity) {
+                Some(mapped) => {
+                    *entity = mapped;
+                    true
+                }
+                None => false,
+            },
+            _ => true,
+        });
+    }
+
+    /// `tick` runs the tree once
+    fn tick(&mut self, world: &mut TickWorld) -> Status {
+        let mut context = TickContext {
+            world,
+            memory: &mut self.memory,
+            blackboard: &mut self.blackboard,
+        };
+        self.status = context.tick(&self.root, 0);
+        self.status
+    }
+}
+
+/// `TickWorld` is the part of the world a tree can see and change while ticking.
+struct TickWorld<'a> {
+    entity: Entity,
+    agent: &'a mut Agent,
+    position: Vec2,
+    needs: Option<&'a mut Needs>,
+    queued: bool,
+    refused: bool,
+    zones: &'a [(&'a Zone, Vec2)],
+    agents: &'a [(Entity, Vec2)],
+    delta: f32,
+}
+
+struct TickContext<'a, 'w> {
+    world: &'a mut TickWorld<'w>,
+    memory: &'a mut [NodeMemory],
+    blackboard: &'a mut Blackboard,
+}
+
+impl<'a, 'w> TickContext<'a, 'w> {
+    /// `tick` ticks a node and resets the memory of its subtree once it is done
+    fn tick(&mut self, node: &BehaviourNode, index: usize) -> Status {
+        let status = self.tick_node(node, index);
+        if status != Status::Running {
+            for memory in &mut self.memory[index..index + node.node_count()] {
+                *memory = NodeMemory::Fresh;
+            }
+        }
+        status
+    }
+
+    fn tick_node(&mut self, node: &BehaviourNode, index: usize) -> Status {
+        match node {
+            BehaviourNode::Sequence(children) => {
+                self.tick_in_order(children, index, Status::Success)
+            }
+            BehaviourNode::Selector(children) => {
+                self.tick_in_order(children, index, Status::Failure)
+            }
+            BehaviourNode::Parallel { policy, children } => {
+                let mut statuses = match &self.memory[index] {
+                    NodeMemory::Statuses(statuses) => statuses.clone(),
+                    _ => vec![Status::Running; children.len()],
+                };
+                let mut child_index = index + 1;
+                for (child, status) in children.iter().zip(statuses.iter_mut()) {
+                    if *status == Status::Running {
+                        *status = self.tick(child, child_index);
+                    }
+                    child_index += child.node_count();
+                }
+                let count = |wanted| statuses.iter().filter(|s| **s == wanted).count();
+                let result = match policy {
+                    ParallelPolicy::RequireAll if count(Status::Failure) > 0 => Status::Failure,
+                    ParallelPolicy::RequireAll if count(Status::Success) == children.len() => {
+                        Status::Success
+                    }
+                    ParallelPolicy::RequireOne if count(Status::Success) > 0 => Status::Success,
+                    ParallelPolicy::RequireOne if count(Status::Failure) == children.len() => {
+                        Status::Failure
+                    }
+                    _ => Status::Running,
+                };
+                self.memory[index] = NodeMemory::Statuses(statuses);
+                result
+            }
+            BehaviourNode::Inverter(child) => match self.tick(child, index + 1) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            BehaviourNode::Succeeder(child) => match self.tick(child, index + 1) {
+                Status::Running => Status::Running,
+                _ => Status::Success,
+            },
+            BehaviourNode::Repeat { times, child } => {
+                let done = match self.memory[index] {
+                    NodeMemory::Count(done) => done,
+                    _ => 0,
+                };
+                match self.tick(child, index + 1) {
+                    Status::Failure => Status::Failure,
+                    Status::Running => {
+                        self.memory[index] = NodeMemory::Count(done);
+                        Status::Running
+                    }
+                    Status::Success if times.is_some_and(|times| done + 1 >= times) => {
+                        Status::Success
+                    }
+                    Status::Success => {
+                        self.memory[index] = NodeMemory::Count(done + 1);
+                        Status::Running
+                    }
+                }
+            }
+            BehaviourNode::MoveToZone(name) => {
+                let center = match self.world.zones.iter().find(|(zone, _)| &zone.name == name) {
+                    Some((zone, center)) if zone.contains(*center, self.world.position) => {
+                        if self.memory[index] == NodeMemory::Moving {
+                            self.world.agent.destination = None;
+                        }
+                        return Status::Success;
+                    }
+                    Some((_, center)) => *center,
+                    None => return Status::Failure,
+                };
+                // agents waiting outside a full zone are given their destination when let in, agents
+                // turned away give up
+                if self.memory[index] == NodeMemory::Moving && self.world.queued {
+                    return Status::Running;
+                }
+                if self.memory[index] == NodeMemory::Moving && self.world.refused {
+                    return Status::Failure;
+                }
+                if self.memory[index] != NodeMemory::Moving
+                    || self.world.agent.destination.is_none()
+                {
+                    self.world.agent.destination = Some(center);
+                    self.memory[index] = NodeMemory::Moving;
+                }
+                Status::Running
+            }
+            BehaviourNode::Wait(seconds) => {
+                let remaining = match self.memory[index] {
+                    NodeMemory::Timer(remaining) => remaining,
+                    _ => *seconds,
+                } - self.world.delta;
+                self.memory[index] = NodeMemory::Timer(remaining);
+                if remaining <= 0.0 {
+                    Status::Success
+                } else {
+                    Status::Running
+                }
+            }
+            BehaviourNode::InteractWithAgent { range, seconds } => {
+                let (partner, remaining) = match self.memory[index] {
+                    NodeMemory::Partner(partner, remaining) => (partner, remaining),
+                    _ => {
+                        let position = self.world.position;
+                        let nearest = self
+                            .world
+                            .agents
+                            .iter()
+                            .filter(|(entity, _)| *entity != self.world.entity)
+                            .map(|(entity, other)| (*entity, other.distance(position)))
+                            .filter(|(_, distance)| distance <= range)
+                            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+                        match nearest {
+                            Some((partner, _)) => (partner, *seconds),
+                            None => return Status::Failure,
+                        }
+                    }
+                };
+                self.blackboard
+                    .set("partner", BlackboardValue::Entity(partner));
+                if let Some(needs) = self.world.needs.as_deref_mut() {
+                    let social = needs.get(NeedKind::Social) + 0.1 * self.world.delta;
+                    needs.set(NeedKind::Social, social);
+                }
+                let remaining = remaining - self.world.delta;
+                self.memory[index] = NodeMemory::Partner(partner, remaining);
+                if remaining <= 0.0 {
+                    Status::Success
+                } else {
+                    Status::Running
+                }
+            }
+            BehaviourNode::NeedBelow { need, threshold } => match self.world.needs.as_deref() {
+                Some(needs) if needs.get(*need) < *threshold => Status::Success,
+                _ => Status::Failure,
+            },
+            BehaviourNode::SetBlackboard { key, value } => {
+                self.blackboard.set(key.clone(), value.clone());
+                Status::Success
+            }
+            BehaviourNode::CheckBlackboard { key, value } => {
+                if self.blackboard.get(key) == Some(value) {
+                    Status::Success
+                } else {
+                    Status::Failure
+                }
+            }
+        }
+    }
+
+    /// `tick_in_order` runs children one after the other, stopping at the first child that does
+    /// not return `keep_going`. used by sequences and selectors
+    fn tick_in_order(
+        &mut self,
+        children: &[BehaviourNode],
+        index: usize,
+        keep_going: Status,
+    ) -> Status {
+        let mut current = match self.memory[index] {
+            NodeMemory::Child(current) => current,
+            _ => 0,
+        };
+        let mut child_index = index
+            + 1
+            + children[..current]
+                .iter()
+                .map(|child| child.node_count())
+                .sum::<usize>();
+
+        while let Some(child) = children.get(current) {
+            let status = self.tick(child, child_index);
+            if status == Status::Running {
+                self.memory[index] = NodeMemory::Child(current);
+                return Status::Running;
+            }
+            if status != keep_going {
+                return status;
+            }
+            child_index += child.node_count();
+            current += 1;
+        }
+        keep_going
+    }
+}
+
+/// `tick_behaviour_trees` ticks the behaviour tree of every agent that has one, trees of agents
+/// with `Beliefs` only find the zones the agent knows about. moving to a zone fails once the zone
+/// turns the agent away
+///
+/// Arguments:
+///
+/// * `trees`: Query<(Entity, &mut BehaviourTree, &mut Agent, &Transform, Option<&mut Needs>, Option<&Beliefs>, Option<&Queued>)> - query containing agents with a tree.
+/// * `zones`: Query<(&Zone, &Transform)> - query containing every zone, for agents without `Beliefs`.
+/// * `agents`: Query<(Entity, &Transform), With<Agent>> - query containing every agent.
+/// * `refusals`: EventReader<ZoneRefused> - reads the agents zones turned away.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+fn tick_behaviour_trees(
+    mut trees: Query<(
+        Entity,
+        &mut BehaviourTree,
+        &mut Agent,
+        &Transform,
+        Option<&mut Needs>,
+        Option<&Beliefs>,
+        Option<&Queued>,
+    )>,
+    zones: Query<(&Zone, &Transform)>,
+    agents: Query<(Entity, &Transform), With<Agent>>,
+    mut refusals: EventReader<ZoneRefused>,
+    clock: Res<SimClock>,
+) {
+    let refused: BTreeSet<Entity> = refusals.iter().map(|refusal| refusal.agent).collect();
+    let zones: Vec<(&Zone, Vec2)> = zones
+        .iter()
+        .map(|(zone, transform)| (zone, transform.translation.truncate()))
+        .collect();
+    let agents: Vec<(Entity, Vec2)> = agents
+        .iter()
+        .map(|(entity, transform)| (entity, transform.translation.truncate()))
+        .collect();
+
+    for (entity, mut tree, mut agent, transform, mut needs, beliefs, queued) in trees.iter_mut() {
+        let believed: Vec<(&Zone, Vec2)>;
+        let zones: &[(&Zone, Vec2)] = match beliefs {
+            Some(beliefs) => {
+                believed = beliefs
+                    .zones
+                    .values()
+                    .map(|belief| (&belief.zone, belief.center))
+                    .collect();
+                &believed
+            }
+            None => &zones,
+        };
+        let mut world = TickWorld {
+            entity,
+            agent: &mut agent,
+            position: transform.translation.truncate(),
+            needs: needs.as_deref_mut(),
+            queued: queued.is_some(),
+            refused: refused.contains(&entity),
+            zones,
+            agents: &agents,
+            delta: clock.delta_seconds(),
+        };
+        tree.tick(&mut world);
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "6f2f4a4e-9a53-4c1b-8d0c-3e7e0a3b5c21"]
+/// `BehaviourTreeAsset` is a behaviour tree loaded from a `.bt.ron` file.
+///
+/// Properties:
+///
+/// * `root`: The root node of the tree.
+pub struct BehaviourTreeAsset {
+    pub root: BehaviourNode,
+}
+
+#[derive(Default)]
+/// `BehaviourTreeLoader` loads `.bt.ron` files into `BehaviourTreeAsset`s.
+pub struct BehaviourTreeLoader;
+
+impl AssetLoader for BehaviourTreeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let asset: BehaviourTreeAsset = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(asset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["bt.ron"]
+    }
+}
+
+#[derive(Debug, Clone, Component)]
+/// `BehaviourTreeHandle` gives an agent the tree from a `.bt.ron` file once it is loaded. the tree
+/// is rebuilt when the file changes.
+pub struct BehaviourTreeHandle(pub Handle<BehaviourTreeAsset>);
+
+/// `instantiate_behaviour_trees` builds a `BehaviourTree` for every agent whose tree asset was
+/// loaded or changed
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &BehaviourTreeHandle, Option<&BehaviourTree>)> - query containing agents with a tree asset.
+/// * `assets`: Res<Assets<BehaviourTreeAsset>> - resource containing the loaded trees.
+/// * `asset_events`: EventReader<AssetEvent<BehaviourTreeAsset>> - events for changed trees.
+/// * `commands`: Commands - This is the command buffer that we use to insert the trees.
+fn instantiate_behaviour_trees(
+    agents: Query<(Entity, &BehaviourTreeHandle, Option<&BehaviourTree>)>,
+    assets: Res<Assets<BehaviourTreeAsset>>,
+    mut asset_events: EventReader<AssetEvent<BehaviourTreeAsset>>,
+    mut commands: Commands,
+) {
+    let modified: Vec<Handle<BehaviourTreeAsset>> = asset_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.clone()),
+            _ => None,
+        })
+        .collect();
+
+    for (entity, handle, tree) in agents.iter() {
+        if tree.is_some() && !modified.contains(&handle.0) {
+            continue;
+        }
+        if let Some(asset) = assets.get(&handle.0) {
+            commands
+                .entity(entity)
+                .insert(BehaviourTree::new(asset.root.clone()));
+        }
+    }
+}
@@ -3,7 +3,7 @@ use crate::actions::Actions;
 use crate::GameState;
 use bevy::input::mouse::{MouseMotion, MouseWheel};
 use bevy::prelude::*;
-use bevy::render::camera::Camera2d;
+use bevy::render::camera::{Camera2d, RenderTarget};
 
 pub struct CameraPlugin;
 
@@ -42,6 +42,46 @@ fn spawn_camera(mut commands: Commands) {
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
 }
 
+/// `cursor_world_position` converts the position of the cursor in the window a camera draws to
+/// into a world position
+///
+/// Arguments:
+///
+/// * `windows`: All of the windows.
+/// * `camera`: The camera.
+/// * `camera_transform`: The global transform of the camera.
+///
+/// Returns:
+///
+/// The world position under the cursor, or `None` if the cursor is outside the window.
+pub fn cursor_world_position(
+    windows: &Windows,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<Vec2> {
+    // get the window that the camera is displaying to (or the primary window)
+    let wnd = if let RenderTarget::Window(id) = camera.target {
+        windows.get(id)?
+    } else {
+        windows.get_primary()?
+    };
+
+    // check if the cursor is inside the window and get its position
+    let screen_pos = wnd.cursor_position()?;
+
+    // get the size of the window
+    let window_size = Vec2::new(wnd.width(), wnd.height());
+
+    // convert screen position [0..resolution] to ndc [-1..1] (gpu coordinates)
+    let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+
+    // matrix for undoing the projection and camera transform
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix.inverse();
+
+    // use it to convert ndc to world-space coordinates and reduce it to a 2D value
+    Some(ndc_to_world.project_point3(ndc.extend(-1.0)).truncate())
+}
+
 /// If the camera movement action is set, move the camera by the amount of the action multiplied by the
 /// speed and the time delta
 ///
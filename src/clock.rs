@@ -0,0 +1,407 @@
+use crate::actions::{Actions, ActionsLabel};
+use crate::{GameState, SimLabel, SimStage};
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+
+/// how many real seconds one in-world day lasts
+pub const DAY_LENGTH_SECONDS: f32 = 24.0 * 60.0;
+
+/// how many seconds of simulation a tick covers by default
+pub const DEFAULT_TICK_SECONDS: f32 = 1.0 / 60.0;
+
+/// the slowest the simulation clock can run, as a multiple of real time
+pub const MIN_SPEED: f32 = 0.25;
+
+/// the fastest the simulation clock can run, as a multiple of real time
+pub const MAX_SPEED: f32 = 64.0;
+
+/// the hour the sky starts to get light
+pub const DAWN_START: f32 = 5.0;
+/// the hour it is fully light
+pub const DAY_START: f32 = 7.0;
+/// the hour the sky starts to get dark
+pub const DUSK_START: f32 = 19.0;
+/// the hour it is fully dark
+pub const NIGHT_START: f32 = 21.0;
+
+/// the background colour at noon
+const DAY_COLOUR: Color = Color::rgb(0.4, 0.4, 0.4);
+/// the background colour at midnight
+const NIGHT_COLOUR: Color = Color::rgb(0.05, 0.06, 0.15);
+/// the colour laid over the world at midnight, it fades out as it gets light
+const NIGHT_OVERLAY_COLOUR: Color = Color::rgba(0.02, 0.03, 0.12, 0.6);
+/// how far the night overlay reaches from the middle of the world
+const NIGHT_OVERLAY_SIZE: f32 = 1_000_000.0;
+
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    /// `build` adds the simulation clock that runs `SimStage` once for every tick and the in-world
+    /// date and time of day
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeOfDay>()
+            .init_resource::<SimClock>()
+            .add_system_to_stage(CoreStage::First, queue_ticks)
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(advance_time_of_day.label(SimLabel::AdvanceClock)),
+            );
+    }
+}
+
+pub struct ClockVisualsPlugin;
+
+impl Plugin for ClockVisualsPlugin {
+    /// `build` adds the keys and the toolbar that pause, step and speed up the simulation clock,
+    /// and tints the world darker at night
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_enter(GameState::Playing).with_system(spawn_night_overlay),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(control_clock.after(ActionsLabel))
+                .with_system(render_clock_toolbar)
+                .with_system(tint_day_and_night),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `TimeOfDay` is a resource with the in-world time of day.
+///
+/// Properties:
+///
+/// * `hours`: the hour of the day, from `0.0` up to but not including `24.0`.
+pub struct TimeOfDay {
+    pub hours: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        TimeOfDay { hours: 8.0 }
+    }
+}
+
+impl TimeOfDay {
+    /// `fraction` is how far through the day it is, from `0.0` at midnight to `1.0`
+    pub fn fraction(&self) -> f32 {
+        self.hours / 24.0
+    }
+
+    /// `daylight` is how light it is, `1.0` from `DAY_START` to `DUSK_START` and `0.0` from
+    /// `NIGHT_START` to `DAWN_START`, fading in between
+    pub fn daylight(&self) -> f32 {
+        let hours = self.hours;
+        if !(DAWN_START..NIGHT_START).contains(&hours) {
+            0.0
+        } else if hours < DAY_START {
+            (hours - DAWN_START) / (DAY_START - DAWN_START)
+        } else if hours < DUSK_START {
+            1.0
+        } else {
+            1.0 - (hours - DUSK_START) / (NIGHT_START - DUSK_START)
+        }
+    }
+}
+
+/// `format_hours` writes an hour of the day as hours and minutes, like `14:05`
+pub fn format_hours(hours: f32) -> String {
+    let minutes = (hours * 60.0) as u32;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `SimClock` is a resource with the simulation clock. the simulation moves forward in ticks of
+/// `step` seconds no matter how long frames take, so the same ticks always give the same result.
+/// simulation systems read `delta_seconds` from here instead of from `Time`. it can be paused, run
+/// faster or slower, and stepped one tick at a time while paused.
+///
+/// Properties:
+///
+/// * `step`: How many seconds of simulation a tick covers.
+/// * `tick`: How many ticks have run.
+/// * `day`: How many in-world days have passed, the time of day is in `TimeOfDay`.
+/// * `paused`: Run no ticks until unpaused or stepped.
+/// * `speed`: How many seconds are simulated for every second of real time, from `MIN_SPEED` to `MAX_SPEED`.
+/// * `real_time`: Run as many ticks as fit in the time the last frame took. when this is off every update runs `speed` ticks, which is how headless runs go as fast as possible.
+/// * `max_ticks_per_update`: The most ticks to run in one update at normal speed, slow frames make the simulation fall behind instead of taking longer and longer.
+/// * `accumulator`: Seconds that have passed but have not been simulated yet.
+/// * `steps`: Ticks asked for with `step_once` that have not run yet.
+/// * `pending`: Ticks still to run this update.
+pub struct SimClock {
+    pub step: f32,
+    pub tick: u64,
+    pub day: u32,
+    pub paused: bool,
+    speed: f32,
+    pub real_time: bool,
+    pub max_ticks_per_update: u32,
+    accumulator: f32,
+    steps: u32,
+    pending: u32,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        SimClock {
+            step: DEFAULT_TICK_SECONDS,
+            tick: 0,
+            day: 0,
+            paused: false,
+            speed: 1.0,
+            real_time: false,
+            max_ticks_per_update: 5,
+            accumulator: 0.0,
+            steps: 0,
+            pending: 0,
+        }
+    }
+}
+
+impl SimClock {
+    /// `delta_seconds` is how many seconds of simulation the current tick covers
+    pub fn delta_seconds(&self) -> f32 {
+        self.step
+    }
+
+    /// `elapsed_seconds` is how many seconds have been simulated
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.tick as f64 * self.step as f64
+    }
+
+    /// `seconds_since` is how many seconds have been simulated since a tick
+    pub fn seconds_since(&self, tick: u64) -> f32 {
+        self.tick.saturating_sub(tick) as f32 * self.step
+    }
+
+    /// `speed` is how many seconds are simulated for every second of real time
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// `set_speed` changes the speed, keeping it between `MIN_SPEED` and `MAX_SPEED`
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// `faster` doubles the speed, up to `MAX_SPEED`
+    pub fn faster(&mut self) {
+        self.set_speed(self.speed * 2.0);
+    }
+
+    /// `slower` halves the speed, down to `MIN_SPEED`
+    pub fn slower(&mut self) {
+        self.set_speed(self.speed / 2.0);
+    }
+
+    /// `step_once` runs exactly one tick on the next update, even while paused
+    pub fn step_once(&mut self) {
+        self.steps += 1;
+    }
+
+    /// `date_time` is the in-world day and time, like `day 3 14:05`
+    ///
+    /// Arguments:
+    ///
+    /// * `time_of_day`: &TimeOfDay - the time of day.
+    ///
+    /// Returns:
+    ///
+    /// A string with the day and the time in hours and minutes.
+    pub fn date_time(&self, time_of_day: &TimeOfDay) -> String {
+        format!("day {} {}", self.day + 1, format_hours(time_of_day.hours))
+    }
+}
+
+/// `queue_ticks` works out how many ticks to run this update. nothing is simulated outside of
+/// `GameState::Playing`, and only stepped ticks run while paused
+///
+/// Arguments:
+///
+/// * `clock`: ResMut<SimClock> - resource containing the simulation clock.
+/// * `time`: Res<Time> - resource containing the time, used to get delta time between frames.
+/// * `state`: Option<Res<State<GameState>>> - resource containing the game state.
+fn queue_ticks(mut clock: ResMut<SimClock>, time: Res<Time>, state: Option<Res<State<GameState>>>) {
+    if !state.is_some_and(|state| *state.current() == GameState::Playing) {
+        clock.pending = 0;
+        return;
+    }
+    if clock.paused {
+        clock.pending = std::mem::take(&mut clock.steps).min(1);
+        return;
+    }
+    clock.steps = 0;
+
+    // lockstep runs pretend every update took exactly one tick of real time
+    let passed = if clock.real_time {
+        time.delta_seconds()
+    } else {
+        clock.step
+    };
+    clock.accumulator += passed * clock.speed;
+    let due = (clock.accumulator / clock.step).floor();
+    clock.accumulator -= due * clock.step;
+    let most = (clock.max_ticks_per_update as f32 * clock.speed.max(1.0)).ceil();
+    clock.pending = due.min(most) as u32;
+}
+
+/// `run_ticks` is the run criteria of `SimStage`, it runs the stage once for every tick queued by
+/// `queue_ticks`
+///
+/// Arguments:
+///
+/// * `clock`: ResMut<SimClock> - resource containing the simulation clock.
+pub(crate) fn run_ticks(mut clock: ResMut<SimClock>) -> ShouldRun {
+    if clock.pending == 0 {
+        return ShouldRun::No;
+    }
+    clock.pending -= 1;
+    clock.tick += 1;
+    ShouldRun::YesAndCheckAgain
+}
+
+/// `advance_time_of_day` moves the time of day forward so a day lasts `DAY_LENGTH_SECONDS`, and
+/// starts a new day at midnight
+///
+/// Arguments:
+///
+/// * `time_of_day`: ResMut<TimeOfDay> - resource containing the time of day.
+/// * `clock`: ResMut<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+fn advance_time_of_day(mut time_of_day: ResMut<TimeOfDay>, mut clock: ResMut<SimClock>) {
+    let hours = time_of_day.hours + clock.delta_seconds() * 24.0 / DAY_LENGTH_SECONDS;
+    if hours >= 24.0 {
+        clock.day += 1;
+    }
+    time_of_day.hours = hours.rem_euclid(24.0);
+}
+
+/// `control_clock` pauses, steps and changes the speed of the simulation clock from the player's
+/// actions
+///
+/// Arguments:
+///
+/// * `actions`: Res<Actions> - resource containing the player's actions.
+/// * `clock`: ResMut<SimClock> - resource containing the simulation clock.
+fn control_clock(actions: Res<Actions>, mut clock: ResMut<SimClock>) {
+    if actions.toggle_pause {
+        clock.paused = !clock.paused;
+    }
+    if actions.step {
+        clock.step_once();
+    }
+    match actions.change_speed {
+        Some(true) => clock.faster(),
+        Some(false) => clock.slower(),
+        None => {}
+    }
+}
+
+/// `render_clock_toolbar` renders a toolbar along the top of the window with the in-world date and
+/// time and the buttons that control the simulation clock
+///
+/// Arguments:
+///
+/// * `clock`: ResMut<SimClock> - resource containing the simulation clock.
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day.
+/// * `egui_context`: ResMut<EguiContext> - resource containing the context for the Egui user interface.
+fn render_clock_toolbar(
+    mut clock: ResMut<SimClock>,
+    time_of_day: Res<TimeOfDay>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    egui::TopBottomPanel::top("clock").show(egui_context.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.strong(clock.date_time(&time_of_day));
+            ui.separator();
+            let label = if clock.paused { "Play" } else { "Pause" };
+            if ui.button(label).clicked() {
+                clock.paused = !clock.paused;
+            }
+            if ui
+                .add_enabled(clock.paused, egui::Button::new("Step"))
+                .clicked()
+            {
+                clock.step_once();
+            }
+            ui.separator();
+            if ui
+                .add_enabled(clock.speed() > MIN_SPEED, egui::Button::new("Slower"))
+                .clicked()
+            {
+                clock.slower();
+            }
+            ui.label(format!("{}x", clock.speed()));
+            if ui
+                .add_enabled(clock.speed() < MAX_SPEED, egui::Button::new("Faster"))
+                .clicked()
+            {
+                clock.faster();
+            }
+            ui.separator();
+            ui.label(format!("tick {}", clock.tick));
+        });
+    });
+}
+
+#[derive(Debug, Component)]
+/// `NightOverlay` marks the sprite laid over the world to darken it at night
+pub struct NightOverlay;
+
+/// `spawn_night_overlay` spawns a see-through sprite over the agents and zones that
+/// `tint_day_and_night` darkens at night
+///
+/// Arguments:
+///
+/// * `commands`: Commands - This is the command buffer that we use to spawn the overlay.
+fn spawn_night_overlay(mut commands: Commands) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::NONE,
+                custom_size: Some(Vec2::splat(NIGHT_OVERLAY_SIZE)),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 5.0),
+            ..default()
+        })
+        .insert(NightOverlay);
+}
+
+/// `tint_day_and_night` fades the background and the night overlay with the daylight
+///
+/// Arguments:
+///
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day.
+/// * `clear_colour`: ResMut<ClearColor> - resource containing the background colour.
+/// * `overlays`: Query<&mut Sprite, With<NightOverlay>> - query containing the night overlay.
+fn tint_day_and_night(
+    time_of_day: Res<TimeOfDay>,
+    mut clear_colour: ResMut<ClearColor>,
+    mut overlays: Query<&mut Sprite, With<NightOverlay>>,
+) {
+    let daylight = time_of_day.daylight();
+    let [day_r, day_g, day_b, _] = DAY_COLOUR.as_rgba_f32();
+    let [night_r, night_g, night_b, _] = NIGHT_COLOUR.as_rgba_f32();
+    clear_colour.0 = Color::rgb(
+        night_r + (day_r - night_r) * daylight,
+        night_g + (day_g - night_g) * daylight,
+        night_b + (day_b - night_b) * daylight,
+    );
+    for mut sprite in overlays.iter_mut() {
+        sprite.color = NIGHT_OVERLAY_COLOUR;
+        sprite
+            .color
+            .set_a(NIGHT_OVERLAY_COLOUR.a() * (1.0 - daylight));
+    }
+}
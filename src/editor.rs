@@ -0,0 +1,623 @@
+use crate::agent::Agent;
+use crate::camera::cursor_world_position;
+use crate::needs::Amenity;
+use crate::zone::{
+    spawn_zone_definitions, OpeningHours, Zone, ZoneBundle, ZoneColour, ZoneDefinition, ZoneKind,
+    ZoneShape,
+};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy::render::camera::Camera2d;
+use bevy_egui::{egui, EguiContext};
+use bevy_prototype_lyon::prelude::*;
+
+pub struct ZoneEditorPlugin;
+
+impl Plugin for ZoneEditorPlugin {
+    /// `build` adds the zone editor, opened with `EDITOR_KEY` or from its window
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ZoneEditor>().add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(toggle_zone_editor)
+                .with_system(edit_zones_with_mouse.after(toggle_zone_editor))
+                .with_system(render_zone_editor.after(edit_zones_with_mouse))
+                .with_system(draw_zone_editor.after(render_zone_editor)),
+        );
+    }
+}
+
+/// the key that opens and closes the zone editor
+const EDITOR_KEY: KeyCode = KeyCode::F2;
+/// how close the cursor has to be to the corner of a zone to resize it, in pixels on screen
+const HANDLE_SIZE: f32 = 12.0;
+/// the smallest width and height a zone can be drawn or resized to
+const MIN_ZONE_SIZE: f32 = 50.0;
+/// where zones are saved to and loaded from unless another file is picked
+const DEFAULT_ZONES_PATH: &str = "assets/zones.ron";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// `EditorTool` is what dragging the mouse does in the zone editor.
+pub enum EditorTool {
+    /// clicking selects a zone, dragging moves it and dragging its corner resizes it
+    Select,
+    /// dragging draws a new rectangle zone
+    Draw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `Drag` is what the mouse is doing while the left button is held down.
+pub enum Drag {
+    /// a new zone is being drawn from `start` to the cursor
+    Draw { start: Vec2 },
+    /// `zone` is being moved, `offset` is from the cursor to the center of the zone
+    Move { zone: Entity, offset: Vec2 },
+    /// the top right corner of `zone` is being dragged
+    Resize { zone: Entity },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `ZoneEditor` is the state of the zone editor.
+///
+/// Properties:
+///
+/// * `enabled`: If the editor is open, the mouse edits zones instead of selecting agents while it is.
+/// * `tool`: What dragging the mouse does.
+/// * `selected`: The zone shown in the editor window.
+/// * `drag`: What the mouse is doing while the left button is held down.
+/// * `cursor`: Where the cursor is in the world.
+/// * `path`: The file zones are saved to and loaded from.
+/// * `status`: The result of the last save or load.
+pub struct ZoneEditor {
+    pub enabled: bool,
+    pub tool: EditorTool,
+    pub selected: Option<Entity>,
+    pub drag: Option<Drag>,
+    pub cursor: Option<Vec2>,
+    pub path: String,
+    pub status: String,
+}
+
+impl Default for ZoneEditor {
+    fn default() -> Self {
+        ZoneEditor {
+            enabled: false,
+            tool: EditorTool::Select,
+            selected: None,
+            drag: None,
+            cursor: None,
+            path: DEFAULT_ZONES_PATH.to_string(),
+            status: String::new(),
+        }
+    }
+}
+
+#[derive(Component)]
+/// `ZoneEditorOverlay` marks the shapes the editor draws over the zones.
+struct ZoneEditorOverlay;
+
+/// `resized` drags the top right corner of a shape to `corner`, keeping its center in place. only
+/// rectangles and circles can be resized with the mouse
+///
+/// Arguments:
+///
+/// * `shape`: The shape to resize.
+/// * `corner`: Where the corner is dragged to, relative to the center of the shape.
+///
+/// Returns:
+///
+/// The resized shape, or `None` if the shape can not be resized.
+fn resized(shape: &ZoneShape, corner: Vec2) -> Option<ZoneShape> {
+    let half = corner.abs().max(Vec2::splat(MIN_ZONE_SIZE / 2.0));
+    match shape {
+        ZoneShape::Rectangle { .. } => Some(ZoneShape::Rectangle {
+            width: half.x * 2.0,
+            height: half.y * 2.0,
+        }),
+        ZoneShape::Circle { .. } => Some(ZoneShape::Circle {
+            radius: half.max_element(),
+        }),
+        _ => None,
+    }
+}
+
+/// `write_zone_file` writes the zones saved by the editor to `path`
+#[cfg(not(target_arch = "wasm32"))]
+fn write_zone_file(path: &str, ron: &str) -> anyhow::Result<()> {
+    Ok(std::fs::write(path, ron)?)
+}
+
+/// `write_zone_file` always fails in the browser, there is no file system to write to
+#[cfg(target_arch = "wasm32")]
+fn write_zone_file(_path: &str, _ron: &str) -> anyhow::Result<()> {
+    anyhow::bail!("saving zones is not supported in the browser")
+}
+
+/// `read_zone_file` reads the zones the editor saved to `path`
+#[cfg(not(target_arch = "wasm32"))]
+fn read_zone_file(path: &str) -> anyhow::Result<String> {
+    Ok(std::fs::read_to_string(path)?)
+}
+
+/// `read_zone_file` always fails in the browser, there is no file system to read from
+#[cfg(target_arch = "wasm32")]
+fn read_zone_file(_path: &str) -> anyhow::Result<String> {
+    anyhow::bail!("loading zones is not supported in the browser")
+}
+
+/// `toggle_zone_editor` opens and closes the editor with `EDITOR_KEY` and deletes the selected zone
+/// with the delete key
+///
+/// Arguments:
+///
+/// * `editor`: ResMut<ZoneEditor> - resource containing the state of the editor.
+/// * `keyboard_input`: Res<Input<KeyCode>> - resource containing keyboard inputs.
+/// * `egui_context`: Res<EguiContext> - resource containing the context for the Egui user interface.
+/// * `commands`: Commands - This is the command buffer that we use to despawn deleted zones.
+fn toggle_zone_editor(
+    mut editor: ResMut<ZoneEditor>,
+    keyboard_input: Res<Input<KeyCode>>,
+    egui_context: Res<EguiContext>,
+    mut commands: Commands,
+) {
+    if keyboard_input.just_pressed(EDITOR_KEY) {
+        editor.enabled = !editor.enabled;
+        editor.drag = None;
+    }
+    if editor.enabled
+        && keyboard_input.just_pressed(KeyCode::Delete)
+        && !egui_context.ctx().wants_keyboard_input()
+    {
+        if let Some(zone) = editor.selected.take() {
+            commands.entity(zone).despawn();
+        }
+    }
+}
+
+/// `edit_zones_with_mouse` draws, selects, moves and resizes zones with the left mouse button
+/// while the editor is open
+///
+/// Arguments:
+///
+/// * `editor`: ResMut<ZoneEditor> - resource containing the state of the editor.
+/// * `zones`: Query<(Entity, &mut Zone, &mut Transform)> - query containing every zone.
+/// * `mouse_input`: Res<Input<MouseButton>> - resource containing mouse button inputs.
+/// * `windows`: Res<Windows> - resource containing all of the windows.
+/// * `camera_query`: Query<(&Camera, &GlobalTransform, &OrthographicProjection), With<Camera2d>> - query containing the camera, its global transform and its zoom.
+/// * `egui_context`: Res<EguiContext> - resource containing the context for the Egui user interface.
+/// * `commands`: Commands - This is the command buffer that we use to spawn drawn zones.
+fn edit_zones_with_mouse(
+    mut editor: ResMut<ZoneEditor>,
+    mut zones: Query<(Entity, &mut Zone, &mut Transform)>,
+    mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform, &OrthographicProjection), With<Camera2d>>,
+    egui_context: Res<EguiContext>,
+    mut commands: Commands,
+) {
+    if !editor.enabled {
+        return;
+    }
+    let (camera, camera_transform, projection) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let cursor = cursor_world_position(&windows, camera, camera_transform);
+    if editor.cursor != cursor {
+        editor.cursor = cursor;
+    }
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => return,
+    };
+
+    if mouse_input.just_pressed(MouseButton::Left) && !egui_context.ctx().wants_pointer_input() {
+        editor.drag = match editor.tool {
+            EditorTool::Draw => Some(Drag::Draw { start: cursor }),
+            EditorTool::Select => {
+                let handle = HANDLE_SIZE * projection.scale;
+                let on_handle = editor.selected.and_then(|selected| {
+                    let (_, zone, transform) = zones.get(selected).ok()?;
+                    let (_, max) = zone.bounds(transform.translation.truncate());
+                    resized(&zone.shape, Vec2::ONE)?;
+                    (max.distance(cursor) <= handle).then_some(Drag::Resize { zone: selected })
+                });
+                on_handle.or_else(|| {
+                    // the smallest zone under the cursor wins so rooms can be picked out of buildings
+                    let (zone, center) = zones
+                        .iter()
+                        .filter(|(_, zone, transform)| {
+                            zone.contains(transform.translation.truncate(), cursor)
+                        })
+                        .min_by(|(_, a, a_transform), (_, b, b_transform)| {
+                            let area = |zone: &Zone, transform: &Transform| {
+                                let (min, max) = zone.bounds(transform.translation.truncate());
+                                (max - min).x * (max - min).y
+                            };
+                            area(a, a_transform).total_cmp(&area(b, b_transform))
+                        })
+                        .map(|(entity, _, transform)| (entity, transform.translation.truncate()))?;
+                    editor.selected = Some(zone);
+                    Some(Drag::Move {
+                        zone,
+                        offset: center - cursor,
+                    })
+                })
+            }
+        };
+        if editor.tool == EditorTool::Select && editor.drag.is_none() {
+            editor.selected = None;
+        }
+    }
+
+    if mouse_input.pressed(MouseButton::Left) {
+        match editor.drag {
+            Some(Drag::Move { zone, offset }) => {
+                if let Ok((_, _, mut transform)) = zones.get_mut(zone) {
+                    let position = cursor + offset;
+                    if transform.translation.truncate() != position {
+                        transform.translation = position.extend(transform.translation.z);
+                    }
+                }
+            }
+            Some(Drag::Resize { zone }) => {
+                if let Ok((_, mut zone, transform)) = zones.get_mut(zone) {
+                    let corner = cursor - transform.translation.truncate();
+                    if let Some(shape) = resized(&zone.shape, corner) {
+                        if shape != zone.shape {
+                            zone.shape = shape;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if mouse_input.just_released(MouseButton::Left) {
+        if let Some(Drag::Draw { start }) = editor.drag {
+            let size = (cursor - start).abs();
+            if size.x >= MIN_ZONE_SIZE && size.y >= MIN_ZONE_SIZE {
+                let name = (1..)
+                    .map(|number| format!("zone {}", number))
+                    .find(|name| zones.iter().all(|(_, zone, _)| &zone.name != name))
+                    .unwrap();
+                let zone = commands
+                    .spawn_bundle(ZoneBundle::new(
+                        name,
+                        (start + cursor) / 2.0,
+                        size.x,
+                        size.y,
+                    ))
+                    .id();
+                editor.selected = Some(zone);
+            }
+        }
+        editor.drag = None;
+    }
+}
+
+/// `render_zone_editor` renders the zone editor window. it lists every zone and lets the user edit
+/// the selected one, save every zone to a file and load them back
+///
+/// Arguments:
+///
+/// * `editor`: ResMut<ZoneEditor> - resource containing the state of the editor.
+/// * `zones`: Query<(Entity, &mut Zone, &mut Transform, Option<&mut ZoneColour>, Option<&Amenity>)> - query containing every zone and the amenities saved with them.
+/// * `agents`: Query<(Entity, &Agent)> - query containing agents, used to name them in saved allow-lists.
+/// * `egui_context`: ResMut<EguiContext> - resource containing the context for the Egui user interface.
+/// * `commands`: Commands - This is the command buffer that we use to delete and load zones.
+fn render_zone_editor(
+    mut editor: ResMut<ZoneEditor>,
+    mut zones: Query<(
+        Entity,
+        &mut Zone,
+        &mut Transform,
+        Option<&mut ZoneColour>,
+        Option<&Amenity>,
+    )>,
+    agents: Query<(Entity, &Agent)>,
+    mut egui_context: ResMut<EguiContext>,
+    mut commands: Commands,
+) {
+    egui::Window::new("Zone Editor").show(egui_context.ctx_mut(), |ui| {
+        let mut enabled = editor.enabled;
+        ui.checkbox(&mut enabled, "edit zones (F2)");
+        if enabled != editor.enabled {
+            editor.enabled = enabled;
+            editor.drag = None;
+        }
+        if !editor.enabled {
+            return;
+        }
+
+        let mut tool = editor.tool;
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut tool, EditorTool::Select, "select");
+            ui.radio_value(&mut tool, EditorTool::Draw, "draw");
+        });
+        if tool != editor.tool {
+            editor.tool = tool;
+        }
+
+        let mut names: Vec<(Entity, String)> = zones
+            .iter()
+            .map(|(entity, zone, _, _, _)| (entity, zone.name.clone()))
+            .collect();
+        names.sort_by(|(_, a), (_, b)| a.cmp(b));
+        ui.collapsing("zones", |ui| {
+            for (entity, name) in names.iter() {
+                if ui
+                    .selectable_label(editor.selected == Some(*entity), name)
+                    .clicked()
+                {
+                    editor.selected = Some(*entity);
+                }
+            }
+        });
+
+        if let Some((entity, mut zone, mut transform, colour, _)) = editor
+            .selected
+            .and_then(|selected| zones.get_mut(selected).ok())
+        {
+            ui.separator();
+            // edit copies so zones are only marked as changed when something was edited
+            let mut edited = zone.clone();
+            ui.horizontal(|ui| {
+                ui.label("name");
+                ui.text_edit_singleline(&mut edited.name);
+            });
+            egui::ComboBox::from_label("kind")
+                .selected_text(edited.kind.name())
+                .show_ui(ui, |ui| {
+                    for kind in ZoneKind::ALL {
+                        ui.selectable_value(&mut edited.kind, kind, kind.name());
+                    }
+                });
+
+            let mut position = transform.translation.truncate();
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut position.x).prefix("X:"));
+                ui.add(egui::DragValue::new(&mut position.y).prefix("Y:"));
+            });
+            if position != transform.translation.truncate() {
+                transform.translation = position.extend(transform.translation.z);
+            }
+
+            ui.horizontal(|ui| match &mut edited.shape {
+                ZoneShape::Rectangle { width, height } => {
+                    ui.add(
+                        egui::DragValue::new(width)
+                            .prefix("width:")
+                            .clamp_range(MIN_ZONE_SIZE..=f32::MAX),
+                    );
+                    ui.add(
+                        egui::DragValue::new(height)
+                            .prefix("height:")
+                            .clamp_range(MIN_ZONE_SIZE..=f32::MAX),
+                    );
+                }
+                ZoneShape::Circle { radius } => {
+                    ui.add(
+                        egui::DragValue::new(radius)
+                            .prefix("radius:")
+                            .clamp_range(MIN_ZONE_SIZE / 2.0..=f32::MAX),
+                    );
+                }
+                _ => {
+                    ui.label("this shape can only be edited in a zone file");
+                }
+            });
+
+            let mut limited = edited.capacity.is_some();
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut limited, "capacity");
+                let mut capacity = edited.capacity.unwrap_or(10);
+                if limited {
+                    ui.add(egui::DragValue::new(&mut capacity));
+                }
+                edited.capacity = limited.then_some(capacity);
+            });
+
+            let mut has_hours = edited.hours.is_some();
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut has_hours, "opening hours");
+                let mut hours = edited.hours.unwrap_or(OpeningHours {
+                    open: 8.0,
+                    close: 18.0,
+                });
+                if has_hours {
+                    ui.add(
+                        egui::DragValue::new(&mut hours.open)
+                            .prefix("open:")
+                            .clamp_range(0.0..=24.0),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut hours.close)
+                            .prefix("close:")
+                            .clamp_range(0.0..=24.0),
+                    );
+                }
+                edited.hours = has_hours.then_some(hours);
+            });
+
+            let parent_name = |parent: Option<Entity>| {
+                names
+                    .iter()
+                    .find(|(other, _)| Some(*other) == parent)
+                    .map_or("none", |(_, name)| name.as_str())
+            };
+            egui::ComboBox::from_label("part of")
+                .selected_text(parent_name(edited.parent))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut edited.parent, None, "none");
+                    for (other, name) in names.iter().filter(|(other, _)| *other != entity) {
+                        ui.selectable_value(&mut edited.parent, Some(*other), name);
+                    }
+                });
+
+            if edited != *zone {
+                *zone = edited;
+            }
+
+            if let Some(mut colour) = colour {
+                let mut rgba = colour.0.as_rgba_f32();
+                ui.horizontal(|ui| {
+                    ui.label("colour");
+                    ui.color_edit_button_rgba_unmultiplied(&mut rgba);
+                });
+                if rgba != colour.0.as_rgba_f32() {
+                    let [r, g, b, a] = rgba;
+                    colour.0 = Color::rgba(r, g, b, a);
+                }
+            }
+
+            if ui.button("Delete zone").clicked() {
+                commands.entity(entity).despawn();
+                editor.selected = None;
+            }
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("file");
+            ui.text_edit_singleline(&mut editor.path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                let name_of = |entity: Entity| {
+                    zones
+                        .get(entity)
+                        .map(|(_, zone, _, _, _)| zone.name.clone())
+                        .or_else(|_| agents.get(entity).map(|(_, agent)| agent.name.clone()))
+                        .ok()
+                };
+                let definitions: Vec<ZoneDefinition> = zones
+                    .iter()
+                    .map(|(_, zone, transform, colour, amenity)| ZoneDefinition {
+                        amenity: amenity.cloned(),
+                        ..ZoneDefinition::from_zone(
+                            zone,
+                            transform.translation.truncate(),
+                            colour,
+                            name_of,
+                        )
+                    })
+                    .collect();
+                editor.status =
+                    match ron::ser::to_string_pretty(&definitions, ron::ser::PrettyConfig::new())
+                        .map_err(anyhow::Error::from)
+                        .and_then(|ron| write_zone_file(&editor.path, &ron))
+                    {
+                        Ok(()) => format!("saved {} zones", definitions.len()),
+                        Err(error) => format!("could not save: {}", error),
+                    };
+            }
+            if ui.button("Load").clicked() {
+                let agent_named = |name: &str| {
+                    agents
+                        .iter()
+                        .find(|(_, agent)| agent.name == name)
+                        .map(|(entity, _)| entity)
+                };
+                editor.status = match read_zone_file(&editor.path)
+                    .and_then(|ron| Ok(ron::from_str::<Vec<ZoneDefinition>>(&ron)?))
+                    .and_then(|definitions| {
+                        spawn_zone_definitions(&mut commands, &definitions, agent_named)
+                    }) {
+                    Ok(loaded) => {
+                        // the old zones are only removed once the file turned out to be valid
+                        for (entity, _) in names.iter() {
+                            commands.entity(*entity).despawn();
+                        }
+                        editor.selected = None;
+                        format!("loaded {} zones", loaded.len())
+                    }
+                    Err(error) => format!("could not load: {}", error),
+                };
+            }
+        });
+        if !editor.status.is_empty() {
+            ui.label(&editor.status);
+        }
+    });
+}
+
+/// `draw_zone_editor` outlines the selected zone, its resize handle and the zone being drawn
+///
+/// Arguments:
+///
+/// * `editor`: Res<ZoneEditor> - resource containing the state of the editor.
+/// * `zones`: Query<(&Zone, &Transform)> - query containing every zone.
+/// * `overlays`: Query<Entity, With<ZoneEditorOverlay>> - query containing the shapes drawn last frame.
+/// * `projection_query`: Query<&OrthographicProjection, With<Camera2d>> - query containing the zoom of the camera.
+/// * `commands`: Commands - This is the command buffer that we use to spawn and despawn the shapes.
+fn draw_zone_editor(
+    editor: Res<ZoneEditor>,
+    zones: Query<(&Zone, &Transform)>,
+    overlays: Query<Entity, With<ZoneEditorOverlay>>,
+    projection_query: Query<&OrthographicProjection, With<Camera2d>>,
+    mut commands: Commands,
+) {
+    for overlay in overlays.iter() {
+        commands.entity(overlay).despawn();
+    }
+    if !editor.enabled {
+        return;
+    }
+    let outline = DrawMode::Stroke(StrokeMode::new(Color::YELLOW, 15.0));
+
+    if let Some((zone, transform)) = editor
+        .selected
+        .and_then(|selected| zones.get(selected).ok())
+    {
+        let builder =
+            zone.shape
+                .outlines()
+                .into_iter()
+                .fold(GeometryBuilder::new(), |builder, points| {
+                    builder.add(&shapes::Polygon {
+                        points,
+                        closed: true,
+                    })
+                });
+        let mut overlay_transform = *transform;
+        overlay_transform.translation.z += 1.0;
+        commands
+            .spawn_bundle(builder.build(outline, overlay_transform))
+            .insert(ZoneEditorOverlay);
+
+        if resized(&zone.shape, Vec2::ONE).is_some() {
+            let scale = projection_query
+                .get_single()
+                .map_or(1.0, |projection| projection.scale);
+            let (_, max) = zone.bounds(transform.translation.truncate());
+            commands
+                .spawn_bundle(GeometryBuilder::build_as(
+                    &shapes::Rectangle {
+                        extents: Vec2::splat(HANDLE_SIZE * 2.0 * scale),
+                        origin: RectangleOrigin::Center,
+                    },
+                    DrawMode::Fill(FillMode::color(Color::YELLOW)),
+                    Transform::from_translation(max.extend(overlay_transform.translation.z)),
+                ))
+                .insert(ZoneEditorOverlay);
+        }
+    }
+
+    if let (Some(Drag::Draw { start }), Some(cursor)) = (editor.drag, editor.cursor) {
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &shapes::Rectangle {
+                    extents: (cursor - start).abs(),
+                    origin: RectangleOrigin::Center,
+                },
+                outline,
+                Transform::from_translation(((start + cursor) / 2.0).extend(10.0)),
+            ))
+            .insert(ZoneEditorOverlay);
+    }
+}
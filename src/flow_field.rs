@@ -0,0 +1,279 @@
+use crate::agent::Agent;
+use crate::navigation::{NavGrid, OpenCell};
+use crate::spatial::SpatialIndex;
+use crate::zone::{destination_zone, Zone, ZoneShape};
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use std::collections::{BTreeMap, BinaryHeap};
+
+pub struct FlowFieldPlugin;
+
+impl Plugin for FlowFieldPlugin {
+    /// `build` adds the flow fields that crowds heading to the same zone share instead of searching
+    /// a path each
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FlowFieldSettings>()
+            .init_resource::<FlowFields>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing).with_system(
+                    update_flow_fields
+                        .label(SimLabel::UpdateFlowFields)
+                        .after(SimLabel::ChooseAction)
+                        .after(SimLabel::UpdateNavGrid)
+                        .before(SimLabel::PlanPaths),
+                ),
+            );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `FlowFieldSettings` controls when agents switch from their own path to a shared flow field.
+///
+/// Properties:
+///
+/// * `crowd_size`: How many agents need to head to the same zone before a flow field is built for it.
+pub struct FlowFieldSettings {
+    pub crowd_size: usize,
+}
+
+impl Default for FlowFieldSettings {
+    fn default() -> Self {
+        FlowFieldSettings { crowd_size: 4 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `FlowField` stores, for every cell of the `NavGrid`, the neighbouring cell that is one step
+/// closer to a zone. any number of agents can sample it without searching.
+///
+/// Properties:
+///
+/// * `zone`: The zone the field leads to.
+/// * `center`: The center of the zone when the field was built.
+/// * `shape`: The shape of the zone when the field was built.
+/// * `generation`: The `NavGrid` generation the field was built on.
+/// * `costs`: The walking distance in cells from every cell to the zone, row by row.
+/// * `next`: The cell to step to from every cell, `None` inside the zone and in unreachable cells.
+pub struct FlowField {
+    pub zone: Entity,
+    center: Vec2,
+    shape: ZoneShape,
+    generation: u64,
+    costs: Vec<f32>,
+    next: Vec<Option<IVec2>>,
+}
+
+impl FlowField {
+    /// `build` floods the grid outwards from every walkable cell inside the zone
+    ///
+    /// Arguments:
+    ///
+    /// * `grid`: The grid to build the field on.
+    /// * `zone_entity`: The entity of the zone.
+    /// * `zone`: The zone to lead to.
+    /// * `center`: The center of the zone.
+    pub fn build(grid: &NavGrid, zone_entity: Entity, zone: &Zone, center: Vec2) -> Self {
+        let cells = (grid.size.x * grid.size.y) as usize;
+        let mut costs = vec![f32::INFINITY; cells];
+        let mut open = BinaryHeap::new();
+
+        let mut goals: Vec<IVec2> = (0..grid.size.y)
+            .flat_map(|y| (0..grid.size.x).map(move |x| IVec2::new(x, y)))
+            .filter(|cell| !grid.is_blocked(*cell) && zone.contains(center, grid.center_of(*cell)))
+            .collect();
+        if goals.is_empty() {
+            // zones smaller than a cell do not contain any cell center
+            goals.extend(grid.cell_of(center).filter(|cell| !grid.is_blocked(*cell)));
+        }
+        for cell in goals {
+            costs[grid.index(cell)] = 0.0;
+            open.push(OpenCell {
+                estimate: 0.0,
+                cell,
+            });
+        }
+
+        // the grid is walked backwards from the zone, which works because moving is symmetric
+        while let Some(OpenCell { cell, estimate }) = open.pop() {
+            if estimate > costs[grid.index(cell)] {
+                continue;
+            }
+            for (neighbour, step_cost) in grid.neighbours(cell) {
+                let index = grid.index(neighbour);
+                if estimate + step_cost < costs[index] {
+                    costs[index] = estimate + step_cost;
+                    open.push(OpenCell {
+                        estimate: costs[index],
+                        cell: neighbour,
+                    });
+                }
+            }
+        }
+
+        let next = (0..cells as i32)
+            .map(|index| {
+                let cell = IVec2::new(index % grid.size.x, index / grid.size.x);
+                let cost = costs[index as usize];
+                if cost == 0.0 || !cost.is_finite() {
+                    return None;
+                }
+                grid.neighbours(cell)
+                    .map(|(neighbour, _)| (neighbour, costs[grid.index(neighbour)]))
+                    .filter(|(_, neighbour_cost)| *neighbour_cost < cost)
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(neighbour, _)| neighbour)
+            })
+            .collect();
+
+        FlowField {
+            zone: zone_entity,
+            center,
+            shape: zone.shape.clone(),
+            generation: grid.generation(),
+            costs,
+            next,
+        }
+    }
+
+    /// `is_current` checks if the field still matches the grid and the zone
+    pub fn is_current(&self, grid: &NavGrid, zone: &Zone, center: Vec2) -> bool {
+        self.generation == grid.generation() && self.center == center && self.shape == zone.shape
+    }
+
+    /// `cost_at` is the walking distance in cells from a position to the zone
+    ///
+    /// Returns:
+    ///
+    /// The distance, or `None` if the position is off the grid or can not reach the zone.
+    pub fn cost_at(&self, grid: &NavGrid, position: Vec2) -> Option<f32> {
+        let cost = self.costs[grid.index(grid.cell_of(position)?)];
+        cost.is_finite().then_some(cost)
+    }
+
+    /// `next_step` is the point an agent at `position` should walk towards to get closer to the
+    /// zone
+    ///
+    /// Arguments:
+    ///
+    /// * `grid`: The grid the field was built on.
+    /// * `position`: The position of the agent.
+    ///
+    /// Returns:
+    ///
+    /// The center of the next cell, or `None` if the agent is inside the zone, off the grid or can
+    /// not reach the zone.
+    pub fn next_step(&self, grid: &NavGrid, position: Vec2) -> Option<Vec2> {
+        let cell = grid.cell_of(position)?;
+        self.next[grid.index(cell)].map(|next| grid.center_of(next))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// `FlowFields` is a resource with the flow field of every zone a crowd is heading to.
+///
+/// Properties:
+///
+/// * `fields`: The flow field of every zone by zone entity.
+/// * `builds`: How many fields have been built, useful to check fields are being reused.
+pub struct FlowFields {
+    fields: BTreeMap<Entity, FlowField>,
+    pub builds: u64,
+}
+
+impl FlowFields {
+    /// `get` gets the flow field leading to a zone
+    pub fn get(&self, zone: Entity) -> Option<&FlowField> {
+        self.fields.get(&zone)
+    }
+
+    /// `len` is the number of flow fields
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// `is_empty` is true when no crowd is using a flow field
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// `next_step` samples the flow field of a zone, see `FlowField::next_step`
+    pub fn next_step(&self, grid: &NavGrid, zone: Entity, position: Vec2) -> Option<Vec2> {
+        self.get(zone)?.next_step(grid, position)
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq)]
+/// `FlowFollower` marks an agent that follows the flow field of `zone` instead of a `NavPath`.
+pub struct FlowFollower {
+    pub zone: Entity,
+}
+
+/// `update_flow_fields` groups agents by the zone they are heading to, builds or rebuilds the flow
+/// field of every zone with a crowd heading to it and marks the agents in those crowds as
+/// `FlowFollower`s. fields nobody follows any more are dropped
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &Agent, Option<&FlowFollower>)> - query containing agents and the field they follow.
+/// * `zones`: Query<(Entity, &Zone, &Transform)> - query containing every zone.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find the zone agents are heading to.
+/// * `grid`: Res<NavGrid> - resource containing the navigation grid.
+/// * `settings`: Res<FlowFieldSettings> - resource containing the crowd size.
+/// * `fields`: ResMut<FlowFields> - resource containing the flow fields.
+/// * `commands`: Commands - This is the command buffer that we use to add and remove `FlowFollower`.
+fn update_flow_fields(
+    agents: Query<(Entity, &Agent, Option<&FlowFollower>)>,
+    zones: Query<(Entity, &Zone, &Transform)>,
+    index: Res<SpatialIndex>,
+    grid: Res<NavGrid>,
+    settings: Res<FlowFieldSettings>,
+    mut fields: ResMut<FlowFields>,
+    mut commands: Commands,
+) {
+    let mut crowds: BTreeMap<Entity, Vec<Entity>> = BTreeMap::new();
+    for (entity, agent, _) in agents.iter() {
+        if let Some(zone) = agent
+            .destination
+            .and_then(|destination| destination_zone(&index, &zones, destination))
+        {
+            crowds.entry(zone).or_default().push(entity);
+        }
+    }
+    crowds.retain(|_, crowd| crowd.len() >= settings.crowd_size);
+
+    fields.fields.retain(|zone, _| crowds.contains_key(zone));
+    for zone_entity in crowds.keys() {
+        let (_, zone, transform) = zones.get(*zone_entity).unwrap();
+        let center = transform.translation.truncate();
+        let current = fields
+            .get(*zone_entity)
+            .is_some_and(|field| field.is_current(&grid, zone, center));
+        if !current {
+            let field = FlowField::build(&grid, *zone_entity, zone, center);
+            fields.fields.insert(*zone_entity, field);
+            fields.builds += 1;
+        }
+    }
+
+    let assigned: BTreeMap<Entity, Entity> = crowds
+        .iter()
+        .flat_map(|(zone, crowd)| crowd.iter().map(move |agent| (*agent, *zone)))
+        .collect();
+    for (entity, _, follower) in agents.iter() {
+        match (assigned.get(&entity), follower) {
+            (Some(zone), Some(follower)) if follower.zone == *zone => {}
+            (Some(zone), _) => {
+                commands.entity(entity).insert(FlowFollower { zone: *zone });
+            }
+            (None, Some(_)) => {
+                commands.entity(entity).remove::<FlowFollower>();
+            }
+            (None, None) => {}
+        }
+    }
+}
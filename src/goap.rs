@@ -0,0 +1,720 @@
+use crate::agent::{Agent, Faction};
+use crate::clock::{SimClock, TimeOfDay};
+use crate::memory::Beliefs;
+use crate::needs::{NeedKind, NeedSettings, Needs};
+use crate::zone::{Queued, Zone, ZoneKind, ZoneRefused};
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashSet, VecDeque};
+
+pub struct GoapPlugin;
+
+impl Plugin for GoapPlugin {
+    /// `build` adds the goal oriented action planner. planning is budgeted by `GoapSettings` so
+    /// many agents can replan without stalling a frame
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GoapSettings>()
+            .init_resource::<PlanQueue>()
+            .init_resource::<GoapActions>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing)
+                    .label(SimLabel::ChooseAction)
+                    .after(SimLabel::UpdateNeeds)
+                    .before(SimLabel::UpdateAgent)
+                    .with_system(sense_need_facts.label(GoapSystem::Sense))
+                    .with_system(run_planner.label(GoapSystem::Plan).after(GoapSystem::Sense))
+                    .with_system(execute_plans.after(GoapSystem::Plan)),
+            );
+    }
+}
+
+#[derive(SystemLabel, Clone, Eq, PartialEq, Debug, Hash)]
+enum GoapSystem {
+    Sense,
+    Plan,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+/// `WorldState` is a set of named facts that are either true or false. facts that are not set are
+/// false.
+pub struct WorldState {
+    facts: BTreeMap<String, bool>,
+}
+
+impl WorldState {
+    /// `with` sets a fact and returns the state, for building states in one expression
+    pub fn with(mut self, fact: impl Into<String>, value: bool) -> Self {
+        self.set(fact, value);
+        self
+    }
+
+    /// `get` gets a fact, `false` if it was never set
+    pub fn get(&self, fact: &str) -> bool {
+        self.facts.get(fact).copied().unwrap_or(false)
+    }
+
+    /// `is_set` checks if a fact was set to `value`, facts that were never set are neither
+    pub fn is_set(&self, fact: &str, value: bool) -> bool {
+        self.facts.get(fact) == Some(&value)
+    }
+
+    /// `set` sets a fact
+    pub fn set(&mut self, fact: impl Into<String>, value: bool) {
+        self.facts.insert(fact.into(), value);
+    }
+
+    /// `satisfies` checks if every fact of `conditions` has the same value in this state
+    pub fn satisfies(&self, conditions: &WorldState) -> bool {
+        conditions
+            .facts
+            .iter()
+            .all(|(fact, value)| self.get(fact) == *value)
+    }
+
+    /// `unsatisfied` counts the facts of `conditions` that have a different value in this state
+    pub fn unsatisfied(&self, conditions: &WorldState) -> usize {
+        conditions
+            .facts
+            .iter()
+            .filter(|(fact, value)| self.get(fact) != **value)
+            .count()
+    }
+
+    /// `apply` sets every fact of `effects` in this state
+    pub fn apply(&mut self, effects: &WorldState) {
+        for (fact, value) in &effects.facts {
+            self.set(fact.clone(), *value);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `GoapAction` is a step a plan can be made of.
+///
+/// Properties:
+///
+/// * `name`: The name of the action.
+/// * `cost`: How expensive the action is, the planner finds the cheapest plan.
+/// * `preconditions`: The facts that have to hold before the action can run.
+/// * `effects`: The facts that hold once the action is done.
+/// * `zone`: The name of the zone the agent has to walk to before the action can run.
+/// * `duration`: How many seconds the action takes once the agent is in the zone.
+pub struct GoapAction {
+    pub name: String,
+    pub cost: f32,
+    pub preconditions: WorldState,
+    pub effects: WorldState,
+    pub zone: Option<String>,
+    pub duration: f32,
+}
+
+impl GoapAction {
+    pub fn new(name: impl Into<String>, cost: f32) -> Self {
+        GoapAction {
+            name: name.into(),
+            cost,
+            preconditions: WorldState::default(),
+            effects: WorldState::default(),
+            zone: None,
+            duration: 0.0,
+        }
+    }
+
+    /// `requires` adds a precondition
+    pub fn requires(mut self, fact: impl Into<String>, value: bool) -> Self {
+        self.preconditions.set(fact, value);
+        self
+    }
+
+    /// `causes` adds an effect
+    pub fn causes(mut self, fact: impl Into<String>, value: bool) -> Self {
+        self.effects.set(fact, value);
+        self
+    }
+
+    /// `at_zone` makes the agent walk to a zone before running the action
+    pub fn at_zone(mut self, zone: impl Into<String>) -> Self {
+        self.zone = Some(zone.into());
+        self
+    }
+
+    /// `taking` sets how many seconds the action takes
+    pub fn taking(mut self, duration: f32) -> Self {
+        self.duration = duration;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// `GoapActions` is a resource with every action plans can be made of. it is empty until a
+/// scenario is spawned, which fills it in with `GoapActions::for_zones`.
+pub struct GoapActions {
+    pub actions: Vec<GoapAction>,
+}
+
+impl GoapActions {
+    /// `for_zones` makes the actions to satisfy every need at the zones of a world. agents work
+    /// at workplaces, buy food and chat at zones serving hunger and social, sleep and wash at zones
+    /// serving energy and hygiene and eat wherever they are. there is an action for every zone
+    /// that fits, needs without a fitting zone can not be planned for. the actions come out the
+    /// same whatever order the zones are in, so saved plans still line up once loaded again.
+    /// actions at zones that are closed or do not let the agent in are not offered to the planner,
+    /// see `run_planner`
+    ///
+    /// Arguments:
+    ///
+    /// * `zones`: The name and kind of every zone.
+    pub fn for_zones<'a>(zones: impl IntoIterator<Item = (&'a str, ZoneKind)>) -> Self {
+        let mut zones: Vec<(&str, ZoneKind)> = zones.into_iter().collect();
+        zones.sort_by_key(|(name, _)| *name);
+        let zones_where = |fits: fn(ZoneKind) -> bool| {
+            zones
+                .iter()
+                .filter(move |(_, kind)| fits(*kind))
+                .map(|(name, _)| *name)
+        };
+
+        let mut actions = Vec::new();
+        for zone in zones_where(|kind| kind == ZoneKind::Workplace) {
+            actions.push(
+                GoapAction::new("work", 3.0)
+                    .at_zone(zone)
+                    .taking(5.0)
+                    .causes("has_money", true),
+            );
+        }
+        for zone in zones_where(|kind| kind.serves(NeedKind::Hunger)) {
+            actions.push(
+                GoapAction::new("buy_food", 1.0)
+                    .at_zone(zone)
+                    .taking(2.0)
+                    .requires("has_money", true)
+                    .causes("has_money", false)
+                    .causes("has_food", true),
+            );
+        }
+        actions.push(
+            GoapAction::new("eat", 1.0)
+                .taking(3.0)
+                .requires("has_food", true)
+                .causes("has_food", false)
+                .causes(need_fact(NeedKind::Hunger), false),
+        );
+        for zone in zones_where(|kind| kind.serves(NeedKind::Energy)) {
+            actions.push(
+                GoapAction::new("sleep", 2.0)
+                    .at_zone(zone)
+                    .taking(10.0)
+                    .causes(need_fact(NeedKind::Energy), false),
+            );
+        }
+        for zone in zones_where(|kind| kind.serves(NeedKind::Social)) {
+            actions.push(
+                GoapAction::new("chat", 2.0)
+                    .at_zone(zone)
+                    .taking(5.0)
+                    .causes(need_fact(NeedKind::Social), false),
+            );
+        }
+        for zone in zones_where(|kind| kind.serves(NeedKind::Hygiene)) {
+            actions.push(
+                GoapAction::new("wash", 1.0)
+                    .at_zone(zone)
+                    .taking(3.0)
+                    .causes(need_fact(NeedKind::Hygiene), false),
+            );
+        }
+        GoapActions { actions }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `Goal` is a state an agent wants to reach.
+///
+/// Properties:
+///
+/// * `name`: The name of the goal.
+/// * `conditions`: The facts that make up the goal.
+/// * `priority`: Higher priority goals are planned for first.
+pub struct Goal {
+    pub name: String,
+    pub conditions: WorldState,
+    pub priority: u32,
+}
+
+impl Goal {
+    pub fn new(name: impl Into<String>, conditions: WorldState, priority: u32) -> Self {
+        Goal {
+            name: name.into(),
+            conditions,
+            priority,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `Plan` is a list of actions found by the planner and how far the agent got with it.
+///
+/// Properties:
+///
+/// * `goal`: The name of the goal the plan reaches.
+/// * `steps`: Indices into `GoapActions::actions`.
+/// * `current`: The step being executed.
+/// * `elapsed`: Seconds spent on the current step since arriving in its zone.
+pub struct Plan {
+    pub goal: String,
+    pub steps: Vec<usize>,
+    pub current: usize,
+    pub elapsed: f32,
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Serialize, Deserialize)]
+/// `GoapAgent` lets an agent plan its actions to reach goals. agents with a `GoapAgent` are
+/// ignored by the utility AI.
+///
+/// Properties:
+///
+/// * `facts`: What is true for the agent right now.
+/// * `goals`: The goals the agent wants to reach.
+/// * `plan`: The plan being executed.
+/// * `needs_plan`: Set when the agent should be queued for planning.
+/// * `retry_in`: Seconds until the agent plans again after the planner found no plan.
+pub struct GoapAgent {
+    pub facts: WorldState,
+    pub goals: Vec<Goal>,
+    pub plan: Option<Plan>,
+    pub needs_plan: bool,
+    #[serde(default)]
+    pub retry_in: f32,
+}
+
+impl Default for GoapAgent {
+    /// a planner that wants all of its needs satisfied, hunger first
+    fn default() -> Self {
+        let goal = |need: NeedKind, priority| {
+            Goal::new(
+                need.name(),
+                WorldState::default().with(need_fact(need), false),
+                priority,
+            )
+        };
+        GoapAgent {
+            facts: WorldState::default(),
+            goals: vec![
+                goal(NeedKind::Hunger, 4),
+                goal(NeedKind::Energy, 3),
+                goal(NeedKind::Social, 2),
+                goal(NeedKind::Hygiene, 1),
+            ],
+            plan: None,
+            needs_plan: true,
+            retry_in: 0.0,
+        }
+    }
+}
+
+impl GoapAgent {
+    /// `current_goal` is the highest priority goal that is not reached yet
+    pub fn current_goal(&self) -> Option<&Goal> {
+        self.goals
+            .iter()
+            .filter(|goal| !self.facts.satisfies(&goal.conditions))
+            .max_by_key(|goal| goal.priority)
+    }
+}
+
+/// `need_fact` is the name of the fact that is true while a need is below its critical threshold
+pub fn need_fact(need: NeedKind) -> String {
+    format!("{}_low", need.name())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `GoapSettings` limits how much planning is done every frame.
+///
+/// Properties:
+///
+/// * `plans_per_frame`: how many agents can plan every frame, the rest wait in the queue.
+/// * `max_expansions`: how many states a single plan may look at before giving up.
+/// * `retry_delay`: how many seconds an agent waits before planning again when no plan was found.
+pub struct GoapSettings {
+    pub plans_per_frame: usize,
+    pub max_expansions: usize,
+    pub retry_delay: f32,
+}
+
+impl Default for GoapSettings {
+    fn default() -> Self {
+        GoapSettings {
+            plans_per_frame: 16,
+            max_expansions: 512,
+            retry_delay: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// `PlanQueue` is the agents waiting for the planner, in order.
+pub struct PlanQueue {
+    queue: VecDeque<Entity>,
+}
+
+impl PlanQueue {
+    /// `len` is the number of agents waiting for a plan
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// `is_empty` is true when no agent is waiting for a plan
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// `SearchNode` is a state the planner reached and how it got there.
+struct SearchNode {
+    state: WorldState,
+    cost: f32,
+    parent: Option<(usize, usize)>,
+}
+
+/// `OpenEntry` orders nodes in the open list by lowest estimated total cost.
+struct OpenEntry {
+    estimate: f32,
+    node: usize,
+}
+
+impl PartialEq for OpenEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenEntry {}
+
+impl PartialOrd for OpenEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed so the binary heap pops the cheapest estimate first, ties go to the oldest node
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+/// `plan` searches for the cheapest list of actions that turns `start` into a state satisfying
+/// `goal`, using A* with the number of unsatisfied goal facts as the heuristic
+///
+/// Arguments:
+///
+/// * `start`: The current facts of the agent.
+/// * `goal`: The facts the plan has to reach.
+/// * `actions`: The actions the plan can use.
+/// * `usable`: Which of the actions can be used right now, the others are left out of the plan.
+/// * `max_expansions`: How many states to look at before giving up.
+///
+/// Returns:
+///
+/// The indices of the actions to run in order, or `None` if no plan was found.
+pub fn plan(
+    start: &WorldState,
+    goal: &WorldState,
+    actions: &[GoapAction],
+    usable: impl Fn(&GoapAction) -> bool,
+    max_expansions: usize,
+) -> Option<Vec<usize>> {
+    let mut nodes = vec![SearchNode {
+        state: start.clone(),
+        cost: 0.0,
+        parent: None,
+    }];
+    let mut open = BinaryHeap::new();
+    open.push(OpenEntry {
+        estimate: start.unsatisfied(goal) as f32,
+        node: 0,
+    });
+    let mut closed: HashSet<WorldState> = HashSet::new();
+
+    while let Some(OpenEntry { node, .. }) = open.pop() {
+        if nodes[node].state.satisfies(goal) {
+            let mut steps = Vec::new();
+            let mut current = node;
+            while let Some((parent, action)) = nodes[current].parent {
+                steps.push(action);
+                current = parent;
+            }
+            steps.reverse();
+            return Some(steps);
+        }
+        if !closed.insert(nodes[node].state.clone()) {
+            continue;
+        }
+        if closed.len() > max_expansions {
+            return None;
+        }
+
+        for (index, action) in actions.iter().enumerate() {
+            if !usable(action) || !nodes[node].state.satisfies(&action.preconditions) {
+                continue;
+            }
+            let mut state = nodes[node].state.clone();
+            state.apply(&action.effects);
+            if closed.contains(&state) {
+                continue;
+            }
+            let cost = nodes[node].cost + action.cost;
+            open.push(OpenEntry {
+                estimate: cost + state.unsatisfied(goal) as f32,
+                node: nodes.len(),
+            });
+            nodes.push(SearchNode {
+                state,
+                cost,
+                parent: Some((node, index)),
+            });
+        }
+    }
+    None
+}
+
+/// `sense_need_facts` sets the `<need>_low` facts from the needs of the agent and queues agents
+/// that need a new plan. agents the planner found no plan for wait `GoapSettings::retry_delay`
+/// seconds before they are queued again
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &mut GoapAgent, Option<&Needs>)> - query containing planning agents.
+/// * `settings`: Res<NeedSettings> - resource containing the need thresholds.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+/// * `queue`: ResMut<PlanQueue> - resource containing the agents waiting for a plan.
+fn sense_need_facts(
+    mut agents: Query<(Entity, &mut GoapAgent, Option<&Needs>)>,
+    settings: Res<NeedSettings>,
+    clock: Res<SimClock>,
+    mut queue: ResMut<PlanQueue>,
+) {
+    for (entity, mut goap, needs) in agents.iter_mut() {
+        if let Some(needs) = needs {
+            for need in NeedKind::ALL {
+                let low = needs.get(need) < settings.get(need).critical;
+                if goap.facts.get(&need_fact(need)) != low {
+                    goap.facts.set(need_fact(need), low);
+                }
+            }
+        }
+
+        // a more important goal came up while executing a plan for another goal
+        let current_goal = goap.current_goal().map(|goal| goal.name.clone());
+        if let Some(plan) = &goap.plan {
+            if current_goal.as_ref() != Some(&plan.goal) {
+                goap.needs_plan = true;
+            }
+        } else if current_goal.is_some() {
+            goap.needs_plan = true;
+        }
+
+        if goap.retry_in > 0.0 {
+            goap.retry_in -= clock.delta_seconds();
+            continue;
+        }
+        if goap.needs_plan && !queue.queue.contains(&entity) {
+            queue.queue.push_back(entity);
+        }
+    }
+}
+
+/// `run_planner` plans for agents at the front of the queue, at most
+/// `GoapSettings::plans_per_frame` every frame. plans only use actions at zones that are open and
+/// let the agent in. agents with `Beliefs` only plan with zones they know about and do not believe
+/// are closed. agents no plan is found for try again after `GoapSettings::retry_delay` seconds
+///
+/// Arguments:
+///
+/// * `agents`: Query<(&mut GoapAgent, Option<&Faction>, Option<&Beliefs>)> - query containing planning agents.
+/// * `zones`: Query<&Zone> - query containing every zone, for agents without `Beliefs`.
+/// * `actions`: Res<GoapActions> - resource containing every action.
+/// * `settings`: Res<GoapSettings> - resource containing the planning budget.
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day, used to leave out closed zones.
+/// * `queue`: ResMut<PlanQueue> - resource containing the agents waiting for a plan.
+fn run_planner(
+    mut agents: Query<(&mut GoapAgent, Option<&Faction>, Option<&Beliefs>)>,
+    zones: Query<&Zone>,
+    actions: Res<GoapActions>,
+    settings: Res<GoapSettings>,
+    time_of_day: Res<TimeOfDay>,
+    mut queue: ResMut<PlanQueue>,
+) {
+    for _ in 0..settings.plans_per_frame {
+        let entity = match queue.queue.pop_front() {
+            Some(entity) => entity,
+            None => break,
+        };
+        let (mut goap, faction, beliefs) = match agents.get_mut(entity) {
+            Ok(agent) => agent,
+            Err(_) => continue,
+        };
+        goap.needs_plan = false;
+
+        let goal = match goap.current_goal() {
+            Some(goal) => goal.clone(),
+            None => {
+                goap.plan = None;
+                continue;
+            }
+        };
+        let enterable =
+            |zone: &Zone| zone.is_open(time_of_day.hours) && zone.access.allows(entity, faction);
+        let usable = |action: &GoapAction| {
+            action.zone.as_ref().is_none_or(|name| match beliefs {
+                Some(beliefs) => beliefs
+                    .zone_named(name)
+                    .is_some_and(|(_, belief)| !belief.closed && enterable(&belief.zone)),
+                None => zones
+                    .iter()
+                    .any(|zone| &zone.name == name && enterable(zone)),
+            })
+        };
+        goap.plan = plan(
+            &goap.facts,
+            &goal.conditions,
+            &actions.actions,
+            usable,
+            settings.max_expansions,
+        )
+        .map(|steps| Plan {
+            goal: goal.name,
+            steps,
+            current: 0,
+            elapsed: 0.0,
+        });
+        if goap.plan.is_none() {
+            goap.retry_in = settings.retry_delay;
+            goap.needs_plan = true;
+        }
+    }
+}
+
+/// `execute_plans` walks agents to the zone of their current step, waits for the step to finish
+/// and applies its effects. steps that clear a `<need>_low` fact bring the need up to its
+/// satisfied level, so the fact is not set again as soon as the needs are sensed. agents with
+/// `Beliefs` go to where they believe the zone is, and plan again in a while when they do not know
+/// it.
+/// agents queueing outside a zone wait to be let in, agents turned away drop their plan and wait
+/// `GoapSettings::retry_delay` seconds before planning again
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &mut Agent, &mut GoapAgent, &Transform, Option<&mut Needs>, Option<&Beliefs>, Option<&Queued>)> - query containing planning agents.
+/// * `zones`: Query<(&Zone, &Transform)> - query containing every zone, for agents without `Beliefs`.
+/// * `refusals`: EventReader<ZoneRefused> - reads the agents zones turned away.
+/// * `actions`: Res<GoapActions> - resource containing every action.
+/// * `goap_settings`: Res<GoapSettings> - resource containing how long to wait after being turned away.
+/// * `settings`: Res<NeedSettings> - resource containing the level every need is satisfied at.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+fn execute_plans(
+    mut agents: Query<(
+        Entity,
+        &mut Agent,
+        &mut GoapAgent,
+        &Transform,
+        Option<&mut Needs>,
+        Option<&Beliefs>,
+        Option<&Queued>,
+    )>,
+    zones: Query<(&Zone, &Transform)>,
+    mut refusals: EventReader<ZoneRefused>,
+    actions: Res<GoapActions>,
+    goap_settings: Res<GoapSettings>,
+    settings: Res<NeedSettings>,
+    clock: Res<SimClock>,
+) {
+    let refused: BTreeSet<Entity> = refusals.iter().map(|refusal| refusal.agent).collect();
+    for (entity, mut agent, mut goap, transform, needs, beliefs, queued) in agents.iter_mut() {
+        let goap = &mut *goap;
+        let plan = match &mut goap.plan {
+            Some(plan) => plan,
+            None => continue,
+        };
+        // plans can outlive the actions they were made from, such as when the scenario changes
+        let action = match plan
+            .steps
+            .get(plan.current)
+            .and_then(|step| actions.actions.get(*step))
+        {
+            Some(action) => action,
+            None => {
+                goap.plan = None;
+                continue;
+            }
+        };
+
+        if let Some(zone_name) = &action.zone {
+            let zone = match beliefs {
+                Some(beliefs) => beliefs
+                    .zone_named(zone_name)
+                    .map(|(_, belief)| (&belief.zone, belief.center)),
+                None => zones
+                    .iter()
+                    .find(|(zone, _)| &zone.name == zone_name)
+                    .map(|(zone, transform)| (zone, transform.translation.truncate())),
+            };
+            match zone {
+                Some(_) if refused.contains(&entity) => {
+                    // the zone turned the agent away, try something else in a while
+                    goap.plan = None;
+                    goap.needs_plan = true;
+                    goap.retry_in = goap_settings.retry_delay;
+                    continue;
+                }
+                Some((zone, center)) => {
+                    if !zone.contains(center, transform.translation.truncate()) {
+                        // the agent is given its destination when it is let in
+                        if agent.destination.is_none() && queued.is_none() {
+                            agent.destination = Some(center);
+                        }
+                        continue;
+                    }
+                }
+                None => {
+                    // the zone is gone or unknown, plan again in a while
+                    goap.plan = None;
+                    goap.needs_plan = true;
+                    goap.retry_in = goap_settings.retry_delay;
+                    continue;
+                }
+            }
+        }
+
+        plan.elapsed += clock.delta_seconds();
+        if plan.elapsed < action.duration {
+            continue;
+        }
+
+        goap.facts.apply(&action.effects);
+        if let Some(mut needs) = needs {
+            for need in NeedKind::ALL {
+                let satisfied = settings.get(need).satisfied;
+                if action.effects.is_set(&need_fact(need), false) && needs.get(need) < satisfied {
+                    needs.set(need, satisfied);
+                }
+            }
+        }
+        plan.current += 1;
+        plan.elapsed = 0.0;
+        if plan.current >= plan.steps.len() {
+            goap.plan = None;
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use crate::agent::Agent;
+use crate::clock::SimClock;
+use crate::needs::{NeedKind, Needs};
+use crate::relationships::{Relationship, SocialGraph};
+use crate::rng::SimRng;
+use crate::spatial::SpatialIndex;
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+/// agents that know each other less than this only greet each other
+pub const GREETING_FAMILIARITY: f32 = 0.1;
+
+/// how many interactions the interaction log keeps
+const LOG_LENGTH: usize = 20;
+
+pub struct InteractionPlugin;
+
+impl Plugin for InteractionPlugin {
+    /// `build` adds the interaction events and the system that starts, holds and ends interactions
+    /// between agents close to each other
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InteractionSettings>()
+            .add_event::<InteractionStarted>()
+            .add_event::<InteractionEnded>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing).with_system(
+                    update_interactions
+                        .label(SimLabel::Interact)
+                        .after(SimLabel::UpdateNeeds)
+                        .after(SimLabel::EnforceZoneRules)
+                        .before(SimLabel::UpdateFlowFields)
+                        .before(SimLabel::PlanPaths),
+                ),
+            );
+    }
+}
+
+pub struct InteractionVisualsPlugin;
+
+impl Plugin for InteractionVisualsPlugin {
+    /// `build` adds a window listing the latest interactions
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InteractionLog>().add_system_set(
+            SystemSet::on_update(GameState::Playing)
+                .with_system(log_interactions)
+                .with_system(render_interaction_log.after(log_interactions)),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// `InteractionKind` is what two agents do together.
+pub enum InteractionKind {
+    /// a quick hello between agents that barely know each other
+    Greeting,
+    /// a chat that keeps agents company
+    Conversation,
+    /// swapping food and goods
+    Trade,
+    /// a falling out, more likely between agents that dislike each other
+    Argument,
+}
+
+impl InteractionKind {
+    /// every kind of interaction
+    pub const ALL: [InteractionKind; 4] = [
+        InteractionKind::Greeting,
+        InteractionKind::Conversation,
+        InteractionKind::Trade,
+        InteractionKind::Argument,
+    ];
+
+    /// `name` is the name of the kind shown in the user interface
+    pub fn name(&self) -> &'static str {
+        match self {
+            InteractionKind::Greeting => "greeting",
+            InteractionKind::Conversation => "conversation",
+            InteractionKind::Trade => "trade",
+            InteractionKind::Argument => "argument",
+        }
+    }
+
+    /// `duration` is how many seconds the interaction lasts
+    pub fn duration(&self) -> f32 {
+        match self {
+            InteractionKind::Greeting => 2.0,
+            InteractionKind::Conversation => 10.0,
+            InteractionKind::Trade => 6.0,
+            InteractionKind::Argument => 5.0,
+        }
+    }
+
+    /// `base_outcome` is how well the interaction goes between agents with no opinion of each
+    /// other, from `-1.0` to `1.0`
+    pub fn base_outcome(&self) -> f32 {
+        match self {
+            InteractionKind::Greeting => 0.3,
+            InteractionKind::Conversation => 0.5,
+            InteractionKind::Trade => 0.3,
+            InteractionKind::Argument => -0.6,
+        }
+    }
+
+    /// `need_changes` is how much the needs of both agents change when the interaction is over
+    pub fn need_changes(&self) -> &'static [(NeedKind, f32)] {
+        match self {
+            InteractionKind::Greeting => &[(NeedKind::Social, 0.05)],
+            InteractionKind::Conversation => &[(NeedKind::Social, 0.25)],
+            InteractionKind::Trade => &[(NeedKind::Hunger, 0.1), (NeedKind::Social, 0.05)],
+            InteractionKind::Argument => &[(NeedKind::Social, -0.1)],
+        }
+    }
+
+    /// `choose` picks what two agents do when they meet. strangers greet each other, agents that
+    /// know each other mostly talk and trade, and argue more the less they like each other
+    ///
+    /// Arguments:
+    ///
+    /// * `relationship`: How the agent starting the interaction feels about the other one, if they have met.
+    /// * `rng`: The random number generator of the simulation.
+    pub fn choose(relationship: Option<&Relationship>, rng: &mut SimRng) -> Self {
+        let relationship = match relationship {
+            Some(relationship) if relationship.familiarity >= GREETING_FAMILIARITY => relationship,
+            _ => return InteractionKind::Greeting,
+        };
+        let weights = [
+            (
+                InteractionKind::Conversation,
+                0.5 + relationship.affinity.max(0.0) * 0.3,
+            ),
+            (InteractionKind::Trade, 0.3 * relationship.trust),
+            (
+                InteractionKind::Argument,
+                (0.1 - relationship.affinity * 0.4).max(0.02),
+            ),
+        ];
+        let mut roll = rng.gen_range(0.0..weights.iter().map(|(_, weight)| weight).sum::<f32>());
+        for (kind, weight) in weights {
+            if roll < weight {
+                return kind;
+            }
+            roll -= weight;
+        }
+        InteractionKind::Conversation
+    }
+}
+
+impl fmt::Display for InteractionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `SocialInteraction` is on both agents taking part in an interaction. the agents stand still
+/// until it is over and then carry on to where they were going.
+///
+/// Properties:
+///
+/// * `kind`: What the agents are doing.
+/// * `partner`: The other agent, an entity of the running simulation written as its id in save files.
+/// * `initiator`: If this agent started the interaction, the outcome is worked out on its side.
+/// * `elapsed`: How many seconds the interaction has lasted.
+/// * `resume`: Where the agent was going when the interaction started.
+pub struct SocialInteraction {
+    pub kind: InteractionKind,
+    #[serde(with = "crate::save::entity_bits")]
+    pub partner: Entity,
+    pub initiator: bool,
+    pub elapsed: f32,
+    pub resume: Option<Vec2>,
+}
+
+impl SocialInteraction {
+    /// `remaining` is how many seconds are left
+    pub fn remaining(&self) -> f32 {
+        (self.kind.duration() - self.elapsed).max(0.0)
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+/// `InteractionCooldown` is how many seconds an agent waits after an interaction before it starts
+/// another one.
+pub struct InteractionCooldown(pub f32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `InteractionStarted` is sent when two agents start an interaction.
+///
+/// Properties:
+///
+/// * `kind`: What the agents are doing.
+/// * `initiator`: The agent that started the interaction.
+/// * `partner`: The other agent.
+pub struct InteractionStarted {
+    pub kind: InteractionKind,
+    pub initiator: Entity,
+    pub partner: Entity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `InteractionEnded` is sent when an interaction is over.
+///
+/// Properties:
+///
+/// * `kind`: What the agents were doing.
+/// * `initiator`: The agent that started the interaction.
+/// * `partner`: The other agent.
+/// * `outcome`: How it went from `-1.0` to `1.0`, `None` if it was broken off because an agent left or was despawned.
+pub struct InteractionEnded {
+    pub kind: InteractionKind,
+    pub initiator: Entity,
+    pub partner: Entity,
+    pub outcome: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `InteractionSettings` is a resource with the tuning of interactions.
+///
+/// Properties:
+///
+/// * `range`: How close agents have to be to start an interaction.
+/// * `break_range`: Interactions are broken off when the agents get further apart than this.
+/// * `chance_per_second`: How likely a free agent with someone in range is to start an interaction every second.
+/// * `cooldown`: How many seconds an agent waits after an interaction before starting another.
+pub struct InteractionSettings {
+    pub range: f32,
+    pub break_range: f32,
+    pub chance_per_second: f32,
+    pub cooldown: f32,
+}
+
+impl Default for InteractionSettings {
+    fn default() -> Self {
+        InteractionSettings {
+            range: 100.0,
+            break_range: 300.0,
+            chance_per_second: 0.1,
+            cooldown: 30.0,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+/// `InteractionLog` is a resource with the latest interactions as they are shown in the
+/// interaction window, newest first.
+pub struct InteractionLog {
+    pub entries: VecDeque<String>,
+}
+
+/// `update_interactions` counts down cooldowns, keeps agents in an interaction standing still, ends
+/// interactions that are over and starts new ones between free agents close to each other. the
+/// outcome of a finished interaction changes the needs of both agents and how they feel about each
+/// other
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &mut Agent, &Transform, Option<&mut SocialInteraction>, Option<&mut InteractionCooldown>, Option<&mut Needs>)> - query containing every agent.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find agents close by.
+/// * `settings`: Res<InteractionSettings> - resource containing the tuning of interactions.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+/// * `rng`: ResMut<SimRng> - resource containing the random number generator, used to start interactions and pick their outcome.
+/// * `graph`: ResMut<SocialGraph> - resource containing the relationships of every agent.
+/// * `started`: EventWriter<InteractionStarted> - sends an event for every interaction started.
+/// * `ended`: EventWriter<InteractionEnded> - sends an event for every interaction that is over.
+/// * `commands`: Commands - This is the command buffer that we use to add and remove interactions and cooldowns.
+fn update_interactions(
+    mut agents: Query<(
+        Entity,
+        &mut Agent,
+        &Transform,
+        Option<&mut SocialInteraction>,
+        Option<&mut InteractionCooldown>,
+        Option<&mut Needs>,
+    )>,
+    index: Res<SpatialIndex>,
+    settings: Res<InteractionSettings>,
+    clock: Res<SimClock>,
+    mut rng: ResMut<SimRng>,
+    mut graph: ResMut<SocialGraph>,
+    mut started: EventWriter<InteractionStarted>,
+    mut ended: EventWriter<InteractionEnded>,
+    mut commands: Commands,
+) {
+    let delta = clock.delta_seconds();
+
+    // agents in an interaction hold on to their destination until it is over
+    let mut ongoing = Vec::new();
+    for (entity, mut agent, _, interaction, cooldown, _) in agents.iter_mut() {
+        if let Some(mut cooldown) = cooldown {
+            cooldown.0 -= delta;
+            if cooldown.0 <= 0.0 {
+                commands.entity(entity).remove::<InteractionCooldown>();
+            }
+        }
+        if let Some(mut interaction) = interaction {
+            interaction.elapsed += delta;
+            if let Some(destination) = agent.destination.take() {
+                interaction.resume = Some(destination);
+            }
+            ongoing.push((entity, *interaction));
+        }
+    }
+
+    let mut finished = HashSet::new();
+    for (entity, interaction) in ongoing.iter() {
+        if finished.contains(entity) {
+            continue;
+        }
+        let partner = interaction.partner;
+        let together = match (agents.get(*entity), agents.get(partner)) {
+            (Ok((_, _, transform, _, _, _)), Ok((_, _, partner_transform, Some(other), _, _))) => {
+                other.partner == *entity
+                    && transform
+                        .translation
+                        .distance(partner_transform.translation)
+                        <= settings.break_range
+            }
+            _ => false,
+        };
+        if together && interaction.elapsed < interaction.kind.duration() {
+            continue;
+        }
+
+        let (initiator, other) = if interaction.initiator {
+            (*entity, partner)
+        } else {
+            (partner, *entity)
+        };
+        let outcome = together.then(|| {
+            let affinity = graph
+                .get(initiator, other)
+                .map_or(0.0, |relationship| relationship.affinity);
+            (interaction.kind.base_outcome() + affinity * 0.3 + rng.gen_range(-0.2..=0.2))
+                .clamp(-1.0, 1.0)
+        });
+        if let Some(outcome) = outcome {
+            graph.record_interaction(initiator, other, outcome);
+        }
+
+        for participant in [*entity, partner] {
+            let (_, mut agent, _, participating, _, needs) = match agents.get_mut(participant) {
+                Ok(agent) => agent,
+                Err(_) => continue,
+            };
+            let participating = match participating {
+                Some(participating) if participant == *entity || together => participating,
+                _ => continue,
+            };
+            agent.destination = participating.resume;
+            if let (Some(mut needs), true) = (needs, outcome.is_some()) {
+                for (need, change) in interaction.kind.need_changes() {
+                    let value = needs.get(*need) + change;
+                    needs.set(*need, value);
+                }
+            }
+            commands
+                .entity(participant)
+                .remove::<SocialInteraction>()
+                .insert(InteractionCooldown(settings.cooldown));
+            finished.insert(participant);
+        }
+        ended.send(InteractionEnded {
+            kind: interaction.kind,
+            initiator,
+            partner: other,
+            outcome,
+        });
+    }
+
+    // free agents start an interaction with the closest free agent in range now and then
+    let mut busy: HashSet<Entity> = ongoing.iter().map(|(entity, _)| *entity).collect();
+    busy.extend(finished);
+    let chance = settings.chance_per_second * delta;
+    let free: Vec<(Entity, Vec2)> = agents
+        .iter()
+        .filter(|(entity, _, _, interaction, cooldown, _)| {
+            interaction.is_none() && cooldown.is_none() && !busy.contains(entity)
+        })
+        .map(|(entity, _, transform, _, _, _)| (entity, transform.translation.truncate()))
+        .collect();
+    let available: HashSet<Entity> = free.iter().map(|(entity, _)| *entity).collect();
+    for (entity, position) in free {
+        if busy.contains(&entity) || rng.gen::<f32>() >= chance {
+            continue;
+        }
+        let partner = index
+            .query_radius(position, settings.range)
+            .filter(|(other, _)| {
+                *other != entity && available.contains(other) && !busy.contains(other)
+            })
+            .min_by(|(a, a_position), (b, b_position)| {
+                a_position
+                    .distance_squared(position)
+                    .total_cmp(&b_position.distance_squared(position))
+                    .then(a.cmp(b))
+            });
+        let partner = match partner {
+            Some((partner, _)) => partner,
+            None => continue,
+        };
+
+        let kind = InteractionKind::choose(graph.get(entity, partner), &mut rng);
+        for (participant, other, initiator) in [(entity, partner, true), (partner, entity, false)] {
+            let resume = agents
+                .get_mut(participant)
+                .ok()
+                .and_then(|(_, mut agent, ..)| agent.destination.take());
+            commands.entity(participant).insert(SocialInteraction {
+                kind,
+                partner: other,
+                initiator,
+                elapsed: 0.0,
+                resume,
+            });
+            busy.insert(participant);
+        }
+        started.send(InteractionStarted {
+            kind,
+            initiator: entity,
+            partner,
+        });
+    }
+}
+
+/// `log_interactions` writes every interaction that started or ended to the log and the
+/// interaction log window
+///
+/// Arguments:
+///
+/// * `started`: EventReader<InteractionStarted> - reads the interactions that started.
+/// * `ended`: EventReader<InteractionEnded> - reads the interactions that are over.
+/// * `agents`: Query<&Agent> - query containing agents, used to get their names.
+/// * `log`: ResMut<InteractionLog> - resource containing the latest interactions.
+fn log_interactions(
+    mut started: EventReader<InteractionStarted>,
+    mut ended: EventReader<InteractionEnded>,
+    agents: Query<&Agent>,
+    mut log: ResMut<InteractionLog>,
+) {
+    let name = |entity: Entity| {
+        agents
+            .get(entity)
+            .map_or_else(|_| "someone".to_string(), |agent| agent.name.clone())
+    };
+    let mut entries = Vec::new();
+    for event in started.iter() {
+        entries.push(format!(
+            "{} started a {} with {}",
+            name(event.initiator),
+            event.kind,
+            name(event.partner)
+        ));
+    }
+    for event in ended.iter() {
+        entries.push(match event.outcome {
+            Some(outcome) => format!(
+                "{} and {} finished a {} ({:+.2})",
+                name(event.initiator),
+                name(event.partner),
+                event.kind,
+                outcome
+            ),
+            None => format!(
+                "{} and {} broke off a {}",
+                name(event.initiator),
+                name(event.partner),
+                event.kind
+            ),
+        });
+    }
+    for entry in entries {
+        info!("{}", entry);
+        log.entries.push_front(entry);
+    }
+    log.entries.truncate(LOG_LENGTH);
+}
+
+/// `render_interaction_log` renders a window with the latest interactions
+///
+/// Arguments:
+///
+/// * `log`: Res<InteractionLog> - resource containing the latest interactions.
+/// * `egui_context`: ResMut<EguiContext> - resource containing the context for the Egui user interface.
+fn render_interaction_log(log: Res<InteractionLog>, mut egui_context: ResMut<EguiContext>) {
+    egui::Window::new("Interactions").show(egui_context.ctx_mut(), |ui| {
+        if log.entries.is_empty() {
+            ui.label("nobody has met yet");
+        }
+        for entry in log.entries.iter() {
+            ui.label(entry);
+        }
+    });
+}
@@ -1,17 +1,128 @@
-mod actions;
-mod agent;
-mod camera;
-mod loading;
-mod menu;
-mod windows;
-mod zone;
+//! A data driven npc simulation built on bevy.
+//!
+//! The simulation itself lives in [`SimulationPlugin`] and can run headless on top of
+//! `MinimalPlugins`. [`GamePlugin`] adds the window, sprites, camera and egui windows on top.
+//!
+//! ```
+//! use bevy::prelude::*;
+//! use npc_sim_bevy::prelude::*;
+//!
+//! let mut app = App::new();
+//! app.add_plugins(MinimalPlugins).add_plugin(SimulationPlugin);
+//!
+//! let zone = spawn_zone(&mut app.world, "market", Vec2::ZERO, 500.0, 500.0);
+//! let agent = spawn_agent(&mut app.world, "anna", Vec2::new(10.0, 10.0));
+//! app.update();
+//!
+//! let mapping = app.world.resource::<AgentZoneMapping>();
+//! assert!(mapping.agents_in(zone).contains(&agent));
+//! ```
+
+// the `Bundle` derive of bevy 0.7 forgets every field, including the ones without drop glue
+#![allow(clippy::forget_non_drop)]
+// system parameters such as queries get long tuple types, which is how bevy is meant to be used
+#![allow(clippy::type_complexity)]
+// systems take one parameter for every resource and query they use
+#![allow(clippy::too_many_arguments)]
+
+pub mod actions;
+pub mod agent;
+pub mod behaviour;
+pub mod camera;
+pub mod clock;
+pub mod editor;
+pub mod flow_field;
+pub mod goap;
+pub mod interaction;
+pub mod loading;
+pub mod memory;
+pub mod menu;
+pub mod navigation;
+pub mod needs;
+pub mod perception;
+pub mod relationships;
+pub mod rng;
+pub mod rumour;
+pub mod save;
+pub mod scenario;
+pub mod schedule;
+pub mod spatial;
+pub mod steering;
+pub mod utility;
+pub mod windows;
+pub mod zone;
+
+/// The types most users of the simulation need, `use npc_sim_bevy::prelude::*` to get them all.
+pub mod prelude {
+    pub use crate::agent::{spawn_agent, Agent, AgentBundle, Faction};
+    pub use crate::behaviour::{
+        BehaviourNode, BehaviourTree, BehaviourTreeAsset, BehaviourTreeHandle, Blackboard,
+        BlackboardValue, Status,
+    };
+    pub use crate::clock::{SimClock, TimeOfDay};
+    pub use crate::flow_field::{FlowField, FlowFieldSettings, FlowFields, FlowFollower};
+    pub use crate::goap::{Goal, GoapAction, GoapActions, GoapAgent, GoapSettings, WorldState};
+    pub use crate::interaction::{
+        InteractionCooldown, InteractionEnded, InteractionKind, InteractionLog,
+        InteractionSettings, InteractionStarted, SocialInteraction,
+    };
+    pub use crate::memory::{
+        Beliefs, Episode, EpisodeEvent, EpisodicMemory, LearnZones, MemorySettings, ZoneBelief,
+    };
+    pub use crate::navigation::{NavGrid, NavPath, Obstacle, ObstacleBundle, PathCache};
+    pub use crate::needs::{Amenity, NeedKind, NeedSettings, Needs};
+    pub use crate::perception::{Perceived, Perception};
+    pub use crate::relationships::{Relationship, RelationshipKind, SocialGraph, SocialSettings};
+    pub use crate::rng::SimRng;
+    pub use crate::rumour::{
+        ExportRumourMetrics, Fact, KnownRumour, MetricsStatus, Reach, RumourHeard, RumourMetrics,
+        RumourSettings, RumourSpread, RumourSummary, Rumours, StartRumour,
+    };
+    pub use crate::save::{
+        LoadSimulation, SaveFile, SaveSimulation, SaveStatus, SavedAgent, SavedZone, SAVE_VERSION,
+    };
+    pub use crate::scenario::{
+        ActiveScenario, AgentDefinition, BrainDefinition, ObstacleDefinition, Scenario,
+    };
+    pub use crate::schedule::{DailySchedule, ScheduleAction, ScheduleBlock, ScheduledActivity};
+    pub use crate::spatial::SpatialIndex;
+    pub use crate::steering::{MaxAcceleration, MaxSpeed, SteeringSettings, Velocity};
+    pub use crate::utility::{
+        ActionTarget, Activity, Consideration, ResponseCurve, ScoredConsideration, UtilityAction,
+        UtilityActions, UtilityBrain, UtilityContext,
+    };
+    pub use crate::zone::{
+        spawn_zone, spawn_zone_definitions, AccessDefinition, AgentEnteredZone, AgentExitedZone,
+        AgentZoneMapping, OpeningHours, PointInShape, Queued, Refusal, Zone, ZoneAccess,
+        ZoneBundle, ZoneColour, ZoneDefinition, ZoneKind, ZoneQueues, ZoneRefused, ZoneShape,
+    };
+    pub use crate::{GamePlugin, GameState, SimLabel, SimStage, SimulationPlugin, VisualsPlugin};
+}
 
 use crate::actions::ActionsPlugin;
-use crate::agent::AgentPlugin;
+use crate::agent::{AgentPlugin, AgentVisualsPlugin};
+use crate::behaviour::{BehaviourAssetPlugin, BehaviourTreePlugin};
 use crate::camera::CameraPlugin;
+use crate::clock::{run_ticks, ClockPlugin, ClockVisualsPlugin, SimClock};
+use crate::editor::ZoneEditorPlugin;
+use crate::flow_field::FlowFieldPlugin;
+use crate::goap::GoapPlugin;
+use crate::interaction::{InteractionPlugin, InteractionVisualsPlugin};
+use crate::memory::MemoryPlugin;
 use crate::menu::MenuPlugin;
+use crate::navigation::{NavigationPlugin, NavigationVisualsPlugin};
+use crate::needs::NeedsPlugin;
+use crate::perception::{PerceptionPlugin, PerceptionVisualsPlugin};
+use crate::relationships::RelationshipPlugin;
+use crate::rng::RngPlugin;
+use crate::rumour::{RumourPlugin, RumourVisualsPlugin};
+use crate::save::{SavePlugin, SaveVisualsPlugin};
+use crate::scenario::ScenarioPlugin;
+use crate::spatial::SpatialPlugin;
+use crate::steering::SteeringPlugin;
+use crate::utility::UtilityPlugin;
 use crate::windows::UiPlugin;
-use crate::zone::ZonePlugin;
+use crate::zone::{ZonePlugin, ZoneVisualsPlugin};
 use bevy::app::App;
 #[cfg(debug_assertions)]
 use bevy::diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin};
@@ -24,8 +135,8 @@ use bevy_prototype_lyon::plugin::ShapePlugin;
 // Or https://github.com/bevyengine/bevy/blob/main/examples/ecs/state.rs
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 /// the different `GameState` that can be one of three values: `Loading`, `Playing`, or
-/// `Menu`.
-enum GameState {
+/// `Menu`. the simulation systems only run in `Playing`.
+pub enum GameState {
     // During the loading State the LoadingPlugin will load our assets
     Loading,
     // During this State the actual game logic is executed
@@ -34,6 +145,141 @@ enum GameState {
     Menu,
 }
 
+#[derive(SystemLabel, Clone, Eq, PartialEq, Debug, Hash)]
+/// `SimLabel` labels the simulation systems so other systems can be ordered around them with
+/// `.before(SimLabel::UpdateAgent)` or `.after(SimLabel::UpdateZones)`.
+///
+/// They all run in `SimStage` while in `GameState::Playing`.
+pub enum SimLabel {
+    /// `advance_time_of_day`, moves the in-world clock forward
+    AdvanceClock,
+    /// `decay_needs` and `satisfy_needs`, lowers and refills the needs of agents
+    UpdateNeeds,
+    /// `choose_actions`, `tick_behaviour_trees` and the GOAP planner, lets agents pick what to do
+    /// next. runs before `UpdateAgent`
+    ChooseAction,
+    /// `rebuild_nav_grid`, blocks the cells covered by obstacles when they change
+    UpdateNavGrid,
+    /// `enforce_zone_rules`, turns agents away from closed and restricted zones and queues them
+    /// outside full ones. runs after `ChooseAction`
+    EnforceZoneRules,
+    /// `update_flow_fields`, builds flow fields for crowds heading to the same zone. runs after
+    /// `ChooseAction` and `UpdateNavGrid`
+    UpdateFlowFields,
+    /// `plan_paths`, finds paths around obstacles to the destination of agents not following a
+    /// flow field. runs after `ChooseAction`
+    PlanPaths,
+    /// `steer_agents`, turns paths into velocities while avoiding other agents and obstacles. runs
+    /// after `PlanPaths`
+    Steer,
+    /// `update_agent`, moves agents by their velocity and checks if they have arrived
+    UpdateAgent,
+    /// `index_zones`, puts zones that were added, moved or changed in the `SpatialIndex` before
+    /// anything looks them up. runs before `UpdateNeeds` and `ChooseAction`
+    IndexZones,
+    /// `update_spatial_index`, moves agents that moved in the `SpatialIndex`. runs after
+    /// `UpdateAgent`
+    UpdateSpatialIndex,
+    /// `update_zones`, works out which agents are in which zone and sends `AgentEnteredZone` and
+    /// `AgentExitedZone` events. runs after `UpdateAgent`
+    UpdateZones,
+    /// `get_to_know_neighbours`, makes agents that spend time near each other more familiar in the
+    /// `SocialGraph`. runs after `UpdateSpatialIndex`
+    UpdateRelationships,
+    /// `update_interactions`, starts and ends interactions between agents close to each other and
+    /// holds them still while they last. runs after `EnforceZoneRules`
+    Interact,
+    /// `observe_zones`, updates what agents believe about the zones they can see. runs before
+    /// `ChooseAction`
+    Observe,
+    /// `record_episodes`, adds what happened this tick to the memories of agents. runs after
+    /// `UpdateZones` and `Interact`
+    Remember,
+    /// `start_rumours` and `spread_rumours`, starts rumours and passes them on between agents
+    /// that finish an interaction. runs after `Interact` and `EnforceZoneRules`
+    SpreadRumours,
+    /// `update_perception`, works out what agents with a `Perception` can see and hear. runs
+    /// before `Observe`
+    Perceive,
+}
+
+#[derive(StageLabel, Clone, Eq, PartialEq, Debug, Hash)]
+/// `SimStage` is the stage the simulation systems run in, right after `CoreStage::Update`. it runs
+/// once for every tick of the `SimClock`, which can be no times or many times in a frame.
+pub struct SimStage;
+
+/// `SimulationPlugin` runs agents and zones without a window, renderer or egui.
+///
+/// It works on top of `MinimalPlugins` so simulations can run on servers, CI boxes and in tests.
+/// If no `GameState` has been added yet the simulation starts straight in `GameState::Playing`.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        if app.world.get_resource::<State<GameState>>().is_none() {
+            app.add_state(GameState::Playing);
+        }
+
+        app.add_stage_after(
+            CoreStage::Update,
+            SimStage,
+            SystemStage::parallel().with_run_criteria(run_ticks),
+        )
+        // `on_update` sets only stop looping in stages that also run the state driver
+        .add_system_set_to_stage(SimStage, State::<GameState>::get_driver())
+        .add_plugin(AgentPlugin)
+        .add_plugin(ZonePlugin)
+        .add_plugin(ClockPlugin)
+        .add_plugin(RngPlugin)
+        .add_plugin(NeedsPlugin)
+        .add_plugin(UtilityPlugin)
+        .add_plugin(BehaviourTreePlugin)
+        .add_plugin(GoapPlugin)
+        .add_plugin(NavigationPlugin)
+        .add_plugin(FlowFieldPlugin)
+        .add_plugin(SteeringPlugin)
+        .add_plugin(SpatialPlugin)
+        .add_plugin(RelationshipPlugin)
+        .add_plugin(InteractionPlugin)
+        .add_plugin(PerceptionPlugin)
+        .add_plugin(MemoryPlugin)
+        .add_plugin(RumourPlugin)
+        .add_plugin(ScenarioPlugin)
+        .add_plugin(SavePlugin);
+    }
+}
+
+/// `VisualsPlugin` is the visual layer on top of `SimulationPlugin`: asset loading, the menu,
+/// sprites and shapes, the camera and the egui windows. It needs `DefaultPlugins`.
+pub struct VisualsPlugin;
+
+impl Plugin for VisualsPlugin {
+    fn build(&self, app: &mut App) {
+        // a window shows the simulation as it happens, headless runs go as fast as they can
+        app.world
+            .get_resource_or_insert_with(SimClock::default)
+            .real_time = true;
+
+        app.add_plugin(UiPlugin)
+            .add_plugin(loading::LoadingPlugin)
+            .add_plugin(BehaviourAssetPlugin)
+            .add_plugin(AgentVisualsPlugin)
+            .add_plugin(MenuPlugin)
+            .add_plugin(EguiPlugin)
+            .add_plugin(ActionsPlugin)
+            .add_plugin(ShapePlugin)
+            .add_plugin(ZoneVisualsPlugin)
+            .add_plugin(ZoneEditorPlugin)
+            .add_plugin(SaveVisualsPlugin)
+            .add_plugin(ClockVisualsPlugin)
+            .add_plugin(InteractionVisualsPlugin)
+            .add_plugin(RumourVisualsPlugin)
+            .add_plugin(NavigationVisualsPlugin)
+            .add_plugin(PerceptionVisualsPlugin)
+            .add_plugin(CameraPlugin);
+    }
+}
+
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
@@ -46,19 +292,12 @@ impl Plugin for GamePlugin {
     /// * `app`: &mut App - this is the main application object.
     fn build(&self, app: &mut App) {
         app.add_state(GameState::Loading)
-            .add_plugin(UiPlugin)
-            .add_plugin(loading::LoadingPlugin)
-            .add_plugin(AgentPlugin)
-            .add_plugin(MenuPlugin)
-            .add_plugin(EguiPlugin)
-            .add_plugin(ActionsPlugin)
-            .add_plugin(ShapePlugin)
-            .add_plugin(ZonePlugin)
-            .add_plugin(CameraPlugin);
+            .add_plugin(SimulationPlugin)
+            .add_plugin(VisualsPlugin);
 
         #[cfg(debug_assertions)]
         {
-            app.add_plugin(FrameTimeDiagnosticsPlugin::default())
+            app.add_plugin(FrameTimeDiagnosticsPlugin)
                 .add_plugin(LogDiagnosticsPlugin::default());
         }
     }
@@ -1,6 +1,9 @@
+use crate::behaviour::BehaviourTreeAsset;
+use crate::scenario::{ScenarioAsset, ScenarioLoader};
 use crate::GameState;
 use bevy::prelude::*;
-use bevy_asset_loader::{AssetCollection, AssetLoader};
+use bevy::utils::HashMap;
+use bevy_asset_loader::{AssetCollection, AssetLoader, DynamicAsset};
 
 pub struct LoadingPlugin;
 
@@ -9,9 +12,18 @@ pub struct LoadingPlugin;
 /// If interested, take a look at https://bevy-cheatbook.github.io/features/assets.html
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
+        app.add_asset::<ScenarioAsset>()
+            .init_asset_loader::<ScenarioLoader>();
+
         AssetLoader::new(GameState::Loading)
             .with_collection::<FontAssets>()
             .with_collection::<TextureAssets>()
+            .with_collection::<BehaviourAssets>()
+            .with_collection::<ScenarioAssets>()
+            .add_dynamic_assets(HashMap::from_iter([(
+                "scenarios".to_string(),
+                scenario_files(),
+            )]))
             .continue_to_state(GameState::Menu)
             .build(app);
     }
@@ -43,3 +55,50 @@ pub struct TextureAssets {
     #[asset(path = "textures/bevy.png")]
     pub texture_bevy: Handle<Image>,
 }
+
+#[derive(AssetCollection)]
+/// `BehaviourAssets` contains the behaviour trees designers wrote in `assets/behaviours`.
+///
+/// Properties:
+///
+/// * `villager`: a tree for agents that eat, sleep and chat with other agents.
+pub struct BehaviourAssets {
+    #[asset(path = "behaviours/villager.bt.ron")]
+    pub villager: Handle<BehaviourTreeAsset>,
+}
+
+#[derive(AssetCollection)]
+/// `ScenarioAssets` contains the scenarios in `assets/scenarios`, the menu lets the user pick one.
+///
+/// Properties:
+///
+/// * `scenarios`: every `.scenario.ron` and `.scenario.json` file, see `scenario_files`.
+pub struct ScenarioAssets {
+    #[asset(key = "scenarios", collection(typed))]
+    pub scenarios: Vec<Handle<ScenarioAsset>>,
+}
+
+/// `scenario_files` is where `ScenarioAssets` are loaded from. every scenario in
+/// `assets/scenarios` is found on its own, except in the browser where folders can not be listed
+/// and `assets/scenarios/scenarios.ron` has to name them
+#[cfg(not(target_arch = "wasm32"))]
+fn scenario_files() -> DynamicAsset {
+    DynamicAsset::Folder {
+        path: "scenarios".to_string(),
+    }
+}
+
+/// `scenario_files` is where `ScenarioAssets` are loaded from. every scenario in
+/// `assets/scenarios` is found on its own, except in the browser where folders can not be listed
+/// and `assets/scenarios/scenarios.ron` has to name them
+#[cfg(target_arch = "wasm32")]
+fn scenario_files() -> DynamicAsset {
+    let names: Vec<String> = ron::from_str(include_str!("../assets/scenarios/scenarios.ron"))
+        .expect("assets/scenarios/scenarios.ron is a list of file names");
+    DynamicAsset::Files {
+        paths: names
+            .into_iter()
+            .map(|name| format!("scenarios/{}", name))
+            .collect(),
+    }
+}
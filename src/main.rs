@@ -3,6 +3,7 @@
 
 use bevy::prelude::{App, ClearColor, Color, Msaa, WindowDescriptor};
 use bevy::DefaultPlugins;
+use npc_sim_bevy::scenario::{ActiveScenario, Scenario};
 use npc_sim_bevy::GamePlugin;
 
 /// this is the main function that runs the application
@@ -10,6 +11,8 @@ fn main() {
     App::new()
         .insert_resource(Msaa { samples: 1 })
         .insert_resource(ClearColor(Color::rgb(0.4, 0.4, 0.4)))
+        // the village is run unless another scenario is picked in the menu
+        .insert_resource(ActiveScenario(Some(Scenario::village())))
         .insert_resource(WindowDescriptor {
             width: 800.,
             height: 600.,
@@ -0,0 +1,603 @@
+use crate::agent::Agent;
+use crate::clock::{SimClock, TimeOfDay, DAY_LENGTH_SECONDS};
+use crate::interaction::{InteractionEnded, InteractionKind, InteractionStarted};
+use crate::navigation::NavGrid;
+use crate::needs::Amenity;
+use crate::perception::{facing, Perceived, Perception};
+use crate::spatial::SpatialIndex;
+use crate::utility::ZoneInfo;
+use crate::zone::{ancestors, AgentEnteredZone, Zone};
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// how strong the memory of taking part in an interaction starts out
+const MET_STRENGTH: f32 = 1.0;
+/// how strong the memory of walking into a zone starts out
+const VISITED_STRENGTH: f32 = 0.8;
+/// how strong the memory of watching other agents interact starts out
+const WITNESSED_STRENGTH: f32 = 0.6;
+
+pub struct MemoryPlugin;
+
+impl Plugin for MemoryPlugin {
+    /// `build` adds the systems that let agents see zones, remember what happens to them and
+    /// slowly forget it again
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MemorySettings>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        observe_zones
+                            .label(SimLabel::Observe)
+                            .after(SimLabel::IndexZones)
+                            .before(SimLabel::ChooseAction),
+                    )
+                    .with_system(
+                        record_episodes
+                            .label(SimLabel::Remember)
+                            .after(SimLabel::UpdateZones)
+                            .after(SimLabel::UpdateSpatialIndex)
+                            .after(SimLabel::Interact),
+                    ),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `EpisodeEvent` is something that happened to an agent or in front of it.
+pub enum EpisodeEvent {
+    /// the agent took part in an interaction with another agent
+    Met {
+        #[serde(with = "crate::save::entity_bits")]
+        agent: Entity,
+        kind: InteractionKind,
+    },
+    /// the agent walked into a zone
+    Visited {
+        #[serde(with = "crate::save::entity_bits")]
+        zone: Entity,
+    },
+    /// the agent watched two other agents finish an interaction
+    Witnessed {
+        #[serde(with = "crate::save::entity_bits")]
+        initiator: Entity,
+        #[serde(with = "crate::save::entity_bits")]
+        partner: Entity,
+        kind: InteractionKind,
+        outcome: f32,
+    },
+}
+
+impl EpisodeEvent {
+    /// `entities` are the agents and zones the event is about
+    pub fn entities(&self) -> Vec<Entity> {
+        match *self {
+            EpisodeEvent::Met { agent, .. } => vec![agent],
+            EpisodeEvent::Visited { zone } => vec![zone],
+            EpisodeEvent::Witnessed {
+                initiator, partner, ..
+            } => vec![initiator, partner],
+        }
+    }
+
+    /// `strength` is how strong the memory of the event starts out
+    pub fn strength(&self) -> f32 {
+        match self {
+            EpisodeEvent::Met { .. } => MET_STRENGTH,
+            EpisodeEvent::Visited { .. } => VISITED_STRENGTH,
+            EpisodeEvent::Witnessed { .. } => WITNESSED_STRENGTH,
+        }
+    }
+
+    /// `describe` writes the event out for the user interface
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: Gets the name of an agent or zone, `None` if it is gone.
+    pub fn describe(&self, name: impl Fn(Entity) -> Option<String>) -> String {
+        let name = |entity| name(entity).unwrap_or_else(|| "someone".to_string());
+        match *self {
+            EpisodeEvent::Met { agent, kind } => format!("had a {} with {}", kind, name(agent)),
+            EpisodeEvent::Visited { zone } => format!("went to {}", name(zone)),
+            EpisodeEvent::Witnessed {
+                initiator,
+                partner,
+                kind,
+                outcome,
+            } => format!(
+                "saw {} and {} have a {} ({:+.2})",
+                name(initiator),
+                name(partner),
+                kind,
+                outcome
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `Episode` is one thing an agent remembers.
+///
+/// Properties:
+///
+/// * `tick`: The tick of the `SimClock` it happened on.
+/// * `event`: What happened.
+/// * `strength`: How well the agent remembers it, fading from the strength of the event down to nothing.
+pub struct Episode {
+    pub tick: u64,
+    pub event: EpisodeEvent,
+    pub strength: f32,
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Serialize, Deserialize)]
+/// `EpisodicMemory` is what an agent remembers happening, oldest first. memories fade with time
+/// and the weakest one is forgotten when there is no room for a new one.
+///
+/// Properties:
+///
+/// * `episodes`: The remembered episodes, oldest first.
+/// * `capacity`: How many episodes the agent can remember at once.
+pub struct EpisodicMemory {
+    pub episodes: Vec<Episode>,
+    pub capacity: usize,
+}
+
+impl Default for EpisodicMemory {
+    fn default() -> Self {
+        EpisodicMemory {
+            episodes: Vec::new(),
+            capacity: 64,
+        }
+    }
+}
+
+impl EpisodicMemory {
+    /// `remember` adds an episode. meeting the same agent or visiting the same zone again
+    /// refreshes the old memory instead of adding another one
+    ///
+    /// Arguments:
+    ///
+    /// * `event`: What happened.
+    /// * `tick`: The tick it happened on.
+    pub fn remember(&mut self, event: EpisodeEvent, tick: u64) {
+        let repeat = |old: &EpisodeEvent| match (old, &event) {
+            (EpisodeEvent::Met { agent: a, .. }, EpisodeEvent::Met { agent: b, .. }) => a == b,
+            (EpisodeEvent::Visited { zone: a }, EpisodeEvent::Visited { zone: b }) => a == b,
+            _ => false,
+        };
+        self.episodes.retain(|episode| !repeat(&episode.event));
+        if self.episodes.len() >= self.capacity {
+            let weakest = self
+                .episodes
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.strength.total_cmp(&b.strength))
+                .map(|(index, _)| index);
+            match weakest {
+                Some(index) => {
+                    self.episodes.remove(index);
+                }
+                None => return,
+            }
+        }
+        self.episodes.push(Episode {
+            tick,
+            event,
+            strength: event.strength(),
+        });
+    }
+
+    /// `fade` weakens every memory and forgets the ones that got too weak
+    ///
+    /// Arguments:
+    ///
+    /// * `amount`: How much strength every memory loses.
+    /// * `forget_below`: Memories weaker than this are forgotten.
+    pub fn fade(&mut self, amount: f32, forget_below: f32) {
+        for episode in self.episodes.iter_mut() {
+            episode.strength -= amount;
+        }
+        self.episodes
+            .retain(|episode| episode.strength >= forget_below);
+    }
+
+    /// `recent` gets the newest episodes, newest first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &Episode> {
+        self.episodes.iter().rev().take(count)
+    }
+
+    /// `has_met` checks if the agent remembers an interaction with another agent
+    pub fn has_met(&self, agent: Entity) -> bool {
+        self.episodes.iter().any(
+            |episode| matches!(episode.event, EpisodeEvent::Met { agent: met, .. } if met == agent),
+        )
+    }
+
+    /// `last_visit` is the tick the agent last remembers walking into a zone
+    pub fn last_visit(&self, zone: Entity) -> Option<u64> {
+        self.episodes
+            .iter()
+            .rev()
+            .find(|episode| matches!(episode.event, EpisodeEvent::Visited { zone: visited } if visited == zone))
+            .map(|episode| episode.tick)
+    }
+
+    /// `map_entities` swaps every entity in the memory for another one, such as when a saved
+    /// memory is loaded into a new world. episodes about entities that are gone are forgotten
+    ///
+    /// Arguments:
+    ///
+    /// * `map`: Gets the new entity for an old one, `None` if the entity is gone.
+    pub fn map_entities(&mut self, map: impl Fn(Entity) -> Option<Entity>) {
+        self.episodes.retain_mut(|episode| {
+            let event = &mut episode.event;
+            let entities: Vec<&mut Entity> = match event {
+                EpisodeEvent::Met { agent, .. } => vec![agent],
+                EpisodeEvent::Visited { zone } => vec![zone],
+                EpisodeEvent::Witnessed {
+                    initiator, partner, ..
+                } => vec![initiator, partner],
+            };
+            for entity in entities {
+                match map(*entity) {
+                    Some(mapped) => *entity = mapped,
+                    None => return false,
+                }
+            }
+            true
+        });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `ZoneBelief` is what an agent believes about a zone, as it was the last time the agent saw it.
+///
+/// Properties:
+///
+/// * `zone`: The zone as it was seen.
+/// * `center`: Where the zone was.
+/// * `amenity`: The needs the zone refilled, if any.
+/// * `seen`: The tick of the `SimClock` the agent last saw the zone on.
+/// * `closed`: If the agent heard the zone is closed since it last saw it open.
+pub struct ZoneBelief {
+    pub zone: Zone,
+    pub center: Vec2,
+    pub amenity: Option<Amenity>,
+    pub seen: u64,
+    pub closed: bool,
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Default)]
+/// `Beliefs` are the zones an agent knows about. the AI only sends an agent to zones it believes
+/// in, and plans with what the zones were like when the agent last saw them. agents without
+/// `Beliefs`, such as ones spawned from code, know every zone as it is.
+///
+/// Properties:
+///
+/// * `zones`: What the agent believes about every zone it knows, by zone entity.
+pub struct Beliefs {
+    pub zones: BTreeMap<Entity, ZoneBelief>,
+}
+
+impl Beliefs {
+    /// `knows` checks if the agent knows about a zone
+    pub fn knows(&self, zone: Entity) -> bool {
+        self.zones.contains_key(&zone)
+    }
+
+    /// `get` gets what the agent believes about a zone
+    pub fn get(&self, zone: Entity) -> Option<&ZoneBelief> {
+        self.zones.get(&zone)
+    }
+
+    /// `zone_named` finds a zone the agent knows by its name
+    ///
+    /// Returns:
+    ///
+    /// The zone entity and what the agent believes about it.
+    pub fn zone_named(&self, name: &str) -> Option<(Entity, &ZoneBelief)> {
+        self.zones
+            .iter()
+            .find(|(_, belief)| belief.zone.name == name)
+            .map(|(entity, belief)| (*entity, belief))
+    }
+
+    /// `observe` updates what the agent believes about a zone it can see, what the agent sees
+    /// counts for more than what it heard. the agent only stops believing a zone is closed once it
+    /// sees the zone open
+    ///
+    /// Arguments:
+    ///
+    /// * `entity`: The zone entity.
+    /// * `zone`: The zone.
+    /// * `center`: Where the zone is.
+    /// * `amenity`: The needs the zone refills, if any.
+    /// * `hours`: The hour of the day the zone is seen at.
+    /// * `tick`: The tick the zone is seen on.
+    pub fn observe(
+        &mut self,
+        entity: Entity,
+        zone: &Zone,
+        center: Vec2,
+        amenity: Option<&Amenity>,
+        hours: f32,
+        tick: u64,
+    ) {
+        match self.zones.get_mut(&entity) {
+            Some(belief) => {
+                if belief.zone != *zone {
+                    belief.zone = zone.clone();
+                }
+                belief.center = center;
+                if belief.amenity.as_ref() != amenity {
+                    belief.amenity = amenity.cloned();
+                }
+                belief.seen = tick;
+                if zone.is_open(hours) {
+                    belief.closed = false;
+                }
+            }
+            None => {
+                self.zones.insert(
+                    entity,
+                    ZoneBelief {
+                        zone: zone.clone(),
+                        center,
+                        amenity: amenity.cloned(),
+                        seen: tick,
+                        closed: false,
+                    },
+                );
+            }
+        }
+    }
+
+    /// `forget` forgets a zone, such as when the agent finds it is no longer there
+    pub fn forget(&mut self, zone: Entity) {
+        self.zones.remove(&zone);
+    }
+
+    /// `zone_infos` is every zone the agent knows as the utility AI sees them
+    pub fn zone_infos(&self) -> Vec<ZoneInfo<'_>> {
+        self.zones
+            .iter()
+            .map(|(entity, belief)| ZoneInfo {
+                entity: *entity,
+                zone: &belief.zone,
+                center: belief.center,
+                amenity: belief.amenity.as_ref(),
+                closed: belief.closed,
+                ancestors: ancestors(&belief.zone, |parent| {
+                    self.zones.get(&parent).map(|belief| &belief.zone)
+                })
+                .into_iter()
+                .map(|(_, zone)| zone)
+                .collect(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Default)]
+/// `LearnZones` are zones an agent knows about without having seen them, such as the ones in the
+/// village it grew up in. `observe_zones` adds them to the `Beliefs` of the agent and removes the
+/// component.
+pub struct LearnZones(pub Vec<Entity>);
+
+#[derive(Debug, Clone, PartialEq)]
+/// `MemorySettings` is a resource with the tuning of memories and beliefs.
+///
+/// Properties:
+///
+/// * `sight_range`: How far away agents without a `Perception` see zones and other agents, and how far away interactions are witnessed.
+/// * `decay_rate`: How much strength memories lose every second.
+/// * `forget_below`: Memories weaker than this are forgotten.
+pub struct MemorySettings {
+    pub sight_range: f32,
+    pub decay_rate: f32,
+    pub forget_below: f32,
+}
+
+impl Default for MemorySettings {
+    /// memories fade over about a day
+    fn default() -> Self {
+        MemorySettings {
+            sight_range: 400.0,
+            decay_rate: 1.0 / DAY_LENGTH_SECONDS,
+            forget_below: 0.1,
+        }
+    }
+}
+
+/// `observe_zones` updates the beliefs of agents about every zone they can see, and forgets zones
+/// that are no longer where an agent expected them to be. zones in `LearnZones` are added as they
+/// are. agents with a `Perception` only see what it lets them, the rest see every zone in range
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &mut Beliefs, &Transform, Option<&LearnZones>, Option<(&Perception, &Perceived)>)> - query containing agents with beliefs and what they perceive.
+/// * `zones`: Query<(Entity, &Zone, &Transform, Option<&Amenity>)> - query containing every zone.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find the zones in range.
+/// * `grid`: Res<NavGrid> - resource containing the navigation grid, used to check if obstacles hide where a zone should be.
+/// * `settings`: Res<MemorySettings> - resource containing how far agents can see.
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day, used to see if zones are open.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to timestamp what is seen.
+/// * `commands`: Commands - This is the command buffer that we use to remove `LearnZones` once it is learned.
+fn observe_zones(
+    mut agents: Query<(
+        Entity,
+        &mut Beliefs,
+        &Transform,
+        Option<&LearnZones>,
+        Option<(&Perception, &Perceived)>,
+    )>,
+    zones: Query<(Entity, &Zone, &Transform, Option<&Amenity>)>,
+    index: Res<SpatialIndex>,
+    grid: Res<NavGrid>,
+    settings: Res<MemorySettings>,
+    time_of_day: Res<TimeOfDay>,
+    clock: Res<SimClock>,
+    mut commands: Commands,
+) {
+    for (entity, mut beliefs, transform, learn, perception) in agents.iter_mut() {
+        let position = transform.translation.truncate();
+        if let Some(LearnZones(learn)) = learn {
+            for (zone_entity, zone, zone_transform, amenity) in
+                learn.iter().filter_map(|zone| zones.get(*zone).ok())
+            {
+                let center = zone_transform.translation.truncate();
+                beliefs.observe(
+                    zone_entity,
+                    zone,
+                    center,
+                    amenity,
+                    time_of_day.hours,
+                    clock.tick,
+                );
+            }
+            commands.entity(entity).remove::<LearnZones>();
+        }
+
+        let reach = Vec2::splat(settings.sight_range);
+        let candidates: Vec<Entity> = match perception {
+            Some((_, perceived)) => perceived.seen.iter().copied().collect(),
+            None => index
+                .zones_in_rect(position - reach, position + reach)
+                .collect(),
+        };
+        for (zone_entity, zone, zone_transform, amenity) in candidates
+            .into_iter()
+            .filter_map(|zone| zones.get(zone).ok())
+        {
+            let center = zone_transform.translation.truncate();
+            let (min, max) = zone.bounds(center);
+            let seen = match perception {
+                Some(_) => true,
+                None => position.clamp(min, max).distance(position) <= settings.sight_range,
+            };
+            if seen {
+                beliefs.observe(
+                    zone_entity,
+                    zone,
+                    center,
+                    amenity,
+                    time_of_day.hours,
+                    clock.tick,
+                );
+            }
+        }
+
+        // the agent can see where the zone should be, but it was not seen there
+        let gone: Vec<Entity> = beliefs
+            .zones
+            .iter()
+            .filter(|(_, belief)| {
+                let in_sight = match perception {
+                    Some((perception, _)) => {
+                        perception.sees(position, facing(transform), belief.center, &grid)
+                    }
+                    None => belief.center.distance(position) <= settings.sight_range,
+                };
+                in_sight && belief.seen != clock.tick
+            })
+            .map(|(zone_entity, _)| *zone_entity)
+            .collect();
+        for zone_entity in gone {
+            beliefs.forget(zone_entity);
+        }
+    }
+}
+
+/// `record_episodes` adds the zones agents walk into, the interactions they take part in and the
+/// interactions they watch to their memories, and fades old memories. agents with a `Perception`
+/// only witness interactions they saw or heard one of the agents in
+///
+/// Arguments:
+///
+/// * `memories`: Query<(&mut EpisodicMemory, Option<&Perceived>), With<Agent>> - query containing agents with a memory and what they perceive.
+/// * `entered`: EventReader<AgentEnteredZone> - reads the zones agents walked into.
+/// * `started`: EventReader<InteractionStarted> - reads the interactions that started.
+/// * `ended`: EventReader<InteractionEnded> - reads the interactions that are over.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find the agents watching an interaction.
+/// * `settings`: Res<MemorySettings> - resource containing how far agents can see and how quickly they forget.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to timestamp episodes.
+fn record_episodes(
+    mut memories: Query<(&mut EpisodicMemory, Option<&Perceived>), With<Agent>>,
+    mut entered: EventReader<AgentEnteredZone>,
+    mut started: EventReader<InteractionStarted>,
+    mut ended: EventReader<InteractionEnded>,
+    index: Res<SpatialIndex>,
+    settings: Res<MemorySettings>,
+    clock: Res<SimClock>,
+) {
+    for (mut memory, _) in memories.iter_mut() {
+        memory.fade(
+            settings.decay_rate * clock.delta_seconds(),
+            settings.forget_below,
+        );
+    }
+
+    for event in entered.iter() {
+        if let Ok((mut memory, _)) = memories.get_mut(event.agent) {
+            memory.remember(EpisodeEvent::Visited { zone: event.zone }, clock.tick);
+        }
+    }
+    for event in started.iter() {
+        for (agent, other) in [
+            (event.initiator, event.partner),
+            (event.partner, event.initiator),
+        ] {
+            if let Ok((mut memory, _)) = memories.get_mut(agent) {
+                memory.remember(
+                    EpisodeEvent::Met {
+                        agent: other,
+                        kind: event.kind,
+                    },
+                    clock.tick,
+                );
+            }
+        }
+    }
+    for event in ended.iter() {
+        let outcome = match event.outcome {
+            Some(outcome) => outcome,
+            None => continue,
+        };
+        let position = match index.position(event.initiator) {
+            Some(position) => position,
+            None => continue,
+        };
+        let mut witnesses: Vec<Entity> = index
+            .query_radius(position, settings.sight_range)
+            .map(|(witness, _)| witness)
+            .filter(|witness| *witness != event.initiator && *witness != event.partner)
+            .collect();
+        witnesses.sort();
+        for witness in witnesses {
+            if let Ok((mut memory, perceived)) = memories.get_mut(witness) {
+                let noticed = perceived.is_none_or(|perceived| {
+                    perceived.perceives(event.initiator) || perceived.perceives(event.partner)
+                });
+                if !noticed {
+                    continue;
+                }
+                memory.remember(
+                    EpisodeEvent::Witnessed {
+                        initiator: event.initiator,
+                        partner: event.partner,
+                        kind: event.kind,
+                        outcome,
+                    },
+                    clock.tick,
+                );
+            }
+        }
+    }
+}
@@ -1,17 +1,70 @@
+use crate::loading::ScenarioAssets;
+use crate::scenario::{ActiveScenario, ScenarioAsset};
 use crate::GameState;
 use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
 
 pub struct MenuPlugin;
 
-/// This plugin is responsible for the game menu (containing only one button...)
+/// This plugin is responsible for the game menu, where a scenario is picked to start the simulation with
 /// The menu is only drawn during the State `GameState::Menu` and is removed when that state is exited
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(GameState::Menu).with_system(go));
+        app.add_system_set(SystemSet::on_update(GameState::Menu).with_system(render_menu));
     }
 }
 
-/// placeholder
-fn go(mut state: ResMut<State<GameState>>) {
-    state.set(GameState::Playing).unwrap();
+/// `render_menu` lists every scenario in `ScenarioAssets`. picking a valid one makes it the
+/// `ActiveScenario` and starts the simulation, invalid ones show what is wrong with them
+///
+/// Arguments:
+///
+/// * `scenarios`: Res<ScenarioAssets> - resource containing the handles of the scenario files.
+/// * `assets`: Res<Assets<ScenarioAsset>> - resource containing the loaded scenarios.
+/// * `state`: ResMut<State<GameState>> - the state that is set to `GameState::Playing` once a scenario is picked.
+/// * `egui_context`: ResMut<EguiContext> - resource containing the context for the Egui user interface.
+/// * `commands`: Commands - This is the command buffer that we use to insert the picked scenario.
+fn render_menu(
+    scenarios: Res<ScenarioAssets>,
+    assets: Res<Assets<ScenarioAsset>>,
+    mut state: ResMut<State<GameState>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut commands: Commands,
+) {
+    egui::Window::new("Scenarios").show(egui_context.ctx_mut(), |ui| {
+        let mut loaded: Vec<&ScenarioAsset> = scenarios
+            .scenarios
+            .iter()
+            .filter_map(|handle| assets.get(handle))
+            .collect();
+        loaded.sort_by(|a, b| a.path.cmp(&b.path));
+        if loaded.is_empty() {
+            ui.label("there are no scenarios in assets/scenarios");
+        }
+
+        for asset in loaded {
+            ui.separator();
+            match &asset.scenario {
+                Ok(scenario) => {
+                    ui.strong(&scenario.name);
+                    if !scenario.description.is_empty() {
+                        ui.label(&scenario.description);
+                    }
+                    ui.label(format!(
+                        "{} zones, {} agents",
+                        scenario.zones.len(),
+                        scenario.agents.len()
+                    ));
+                    if ui.button("Start").clicked() {
+                        commands.insert_resource(ActiveScenario(Some(scenario.clone())));
+                        state.set(GameState::Playing).unwrap();
+                    }
+                }
+                Err(problems) => {
+                    ui.strong(&asset.path);
+                    ui.colored_label(egui::Color32::RED, problems);
+                }
+            }
+        }
+    });
 }
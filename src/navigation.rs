@@ -0,0 +1,580 @@
+use crate::agent::Agent;
+use crate::flow_field::FlowFollower;
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    /// `build` adds the navigation grid built from `Obstacle`s and the systems that find paths
+    /// around them for every agent with a destination
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NavGrid>()
+            .init_resource::<PathCache>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        rebuild_nav_grid
+                            .label(SimLabel::UpdateNavGrid)
+                            .before(SimLabel::PlanPaths),
+                    )
+                    .with_system(
+                        plan_paths
+                            .label(SimLabel::PlanPaths)
+                            .after(SimLabel::ChooseAction)
+                            .before(SimLabel::UpdateAgent),
+                    ),
+            )
+            // removals are only reported until the end of the frame, which can pass without a tick
+            .add_system_to_stage(CoreStage::PostUpdate, forget_removed_obstacles);
+    }
+}
+
+pub struct NavigationVisualsPlugin;
+
+impl Plugin for NavigationVisualsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(attach_obstacle_shapes),
+        );
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+/// `Obstacle` is a rectangle centered on the translation of the entity that agents walk around.
+///
+/// Properties:
+///
+/// * `width`: The width of the obstacle.
+/// * `height`: The height of the obstacle.
+pub struct Obstacle {
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Bundle)]
+/// `ObstacleBundle` is everything the navigation grid needs to treat an entity as an obstacle.
+pub struct ObstacleBundle {
+    pub obstacle: Obstacle,
+    #[bundle]
+    pub transform: TransformBundle,
+}
+
+impl ObstacleBundle {
+    /// `new` creates an obstacle bundle centered on `position`
+    pub fn new(position: Vec2, width: f32, height: f32) -> Self {
+        ObstacleBundle {
+            obstacle: Obstacle { width, height },
+            transform: TransformBundle::from_transform(Transform::from_translation(
+                position.extend(0.5),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `NavGrid` is a grid over the world marking which cells are blocked by obstacles.
+///
+/// Properties:
+///
+/// * `cell_size`: The width and height of a cell.
+/// * `origin`: The world position of the bottom left corner of the grid.
+/// * `size`: The number of cells along x and y.
+/// * `blocked`: Whether each cell is blocked, row by row.
+/// * `generation`: Goes up every time the grid is rebuilt, paths from older generations are stale.
+pub struct NavGrid {
+    pub cell_size: f32,
+    pub origin: Vec2,
+    pub size: IVec2,
+    blocked: Vec<bool>,
+    generation: u64,
+}
+
+impl Default for NavGrid {
+    /// a grid of 100 unit cells covering -10000..10000 on both axes
+    fn default() -> Self {
+        NavGrid::new(Vec2::splat(-10000.0), IVec2::splat(200), 100.0)
+    }
+}
+
+impl NavGrid {
+    pub fn new(origin: Vec2, size: IVec2, cell_size: f32) -> Self {
+        NavGrid {
+            cell_size,
+            origin,
+            size,
+            blocked: vec![false; (size.x * size.y) as usize],
+            generation: 0,
+        }
+    }
+
+    /// `generation` goes up every time obstacles change
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// `cell_of` gets the cell a world position is in, `None` if it is outside the grid
+    pub fn cell_of(&self, position: Vec2) -> Option<IVec2> {
+        let cell = ((position - self.origin) / self.cell_size)
+            .floor()
+            .as_ivec2();
+        self.in_bounds(cell).then_some(cell)
+    }
+
+    /// `center_of` gets the world position of the center of a cell
+    pub fn center_of(&self, cell: IVec2) -> Vec2 {
+        self.origin + (cell.as_vec2() + Vec2::splat(0.5)) * self.cell_size
+    }
+
+    /// `in_bounds` checks if a cell is inside the grid
+    pub fn in_bounds(&self, cell: IVec2) -> bool {
+        cell.x >= 0 && cell.y >= 0 && cell.x < self.size.x && cell.y < self.size.y
+    }
+
+    /// `index` is the position of a cell in row by row storage, cells must be in bounds
+    pub fn index(&self, cell: IVec2) -> usize {
+        (cell.y * self.size.x + cell.x) as usize
+    }
+
+    /// `is_blocked` checks if a cell is blocked, cells outside the grid are blocked
+    pub fn is_blocked(&self, cell: IVec2) -> bool {
+        !self.in_bounds(cell) || self.blocked[self.index(cell)]
+    }
+
+    /// `rebuild` clears the grid and blocks every cell touched by an obstacle
+    ///
+    /// Arguments:
+    ///
+    /// * `obstacles`: The center and obstacle of every obstacle.
+    pub fn rebuild<'a>(&mut self, obstacles: impl IntoIterator<Item = (Vec2, &'a Obstacle)>) {
+        self.blocked.iter_mut().for_each(|blocked| *blocked = false);
+        for (center, obstacle) in obstacles {
+            let half = Vec2::new(obstacle.width, obstacle.height) / 2.0;
+            let min = ((center - half - self.origin) / self.cell_size)
+                .floor()
+                .as_ivec2()
+                .max(IVec2::ZERO);
+            let max = ((center + half - self.origin) / self.cell_size)
+                .floor()
+                .as_ivec2()
+                .min(self.size - IVec2::ONE);
+            for y in min.y..=max.y {
+                for x in min.x..=max.x {
+                    let index = self.index(IVec2::new(x, y));
+                    self.blocked[index] = true;
+                }
+            }
+        }
+        self.generation += 1;
+    }
+
+    /// `neighbours` are the walkable cells around a cell, diagonals only if they do not cut a
+    /// blocked corner, with the cost of moving to them
+    pub fn neighbours(&self, cell: IVec2) -> impl Iterator<Item = (IVec2, f32)> + '_ {
+        const DIRECTIONS: [(i32, i32); 8] = [
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ];
+        DIRECTIONS.into_iter().filter_map(move |(x, y)| {
+            let direction = IVec2::new(x, y);
+            let next = cell + direction;
+            if self.is_blocked(next) {
+                return None;
+            }
+            if direction.x != 0 && direction.y != 0 {
+                let cuts_corner = self.is_blocked(cell + IVec2::new(direction.x, 0))
+                    || self.is_blocked(cell + IVec2::new(0, direction.y));
+                if cuts_corner {
+                    return None;
+                }
+                return Some((next, std::f32::consts::SQRT_2));
+            }
+            Some((next, 1.0))
+        })
+    }
+
+    /// `line_of_sight` checks if the straight line between two points only crosses walkable cells
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        let steps = (from.distance(to) / (self.cell_size / 4.0)).ceil().max(1.0) as usize;
+        (0..=steps).all(|step| {
+            let point = from.lerp(to, step as f32 / steps as f32);
+            match self.cell_of(point) {
+                Some(cell) => !self.is_blocked(cell),
+                None => false,
+            }
+        })
+    }
+
+    /// `first_obstruction` finds where the straight line between two points first crosses a blocked
+    /// cell. unlike `line_of_sight` the space outside the grid and the cells the points are in do
+    /// not block it, so agents standing next to a wall can still see away from it
+    ///
+    /// Returns:
+    ///
+    /// The first blocked point along the line, `None` if nothing is in the way.
+    pub fn first_obstruction(&self, from: Vec2, to: Vec2) -> Option<Vec2> {
+        let steps = (from.distance(to) / (self.cell_size / 4.0)).ceil().max(1.0) as usize;
+        let ends = [self.cell_of(from), self.cell_of(to)];
+        (0..=steps)
+            .map(|step| from.lerp(to, step as f32 / steps as f32))
+            .find(|point| match self.cell_of(*point) {
+                Some(cell) => !ends.contains(&Some(cell)) && self.is_blocked(cell),
+                None => false,
+            })
+    }
+
+    /// `find_path` runs A* between two cells
+    ///
+    /// Arguments:
+    ///
+    /// * `start`: The cell to start in, may be blocked so agents can leave obstacles.
+    /// * `goal`: The cell to reach.
+    ///
+    /// Returns:
+    ///
+    /// The cells from `start` to `goal`, or `None` if the goal can not be reached.
+    pub fn find_path(&self, start: IVec2, goal: IVec2) -> Option<Vec<IVec2>> {
+        if !self.in_bounds(start) || self.is_blocked(goal) {
+            return None;
+        }
+
+        let heuristic = |cell: IVec2| {
+            let d = (goal - cell).abs();
+            let (low, high) = (d.x.min(d.y) as f32, d.x.max(d.y) as f32);
+            high - low + low * std::f32::consts::SQRT_2
+        };
+
+        let mut open = BinaryHeap::new();
+        let cells = (self.size.x * self.size.y) as usize;
+        let mut cost = vec![f32::INFINITY; cells];
+        let mut came_from: Vec<Option<IVec2>> = vec![None; cells];
+        cost[self.index(start)] = 0.0;
+        open.push(OpenCell {
+            estimate: heuristic(start),
+            cell: start,
+        });
+
+        while let Some(OpenCell { cell, estimate }) = open.pop() {
+            if cell == goal {
+                let mut path = vec![cell];
+                let mut current = cell;
+                while let Some(previous) = came_from[self.index(current)] {
+                    path.push(previous);
+                    current = previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let current_cost = cost[self.index(cell)];
+            if estimate > current_cost + heuristic(cell) {
+                // a cheaper way to this cell was found after it was queued
+                continue;
+            }
+            for (next, step_cost) in self.neighbours(cell) {
+                let next_cost = current_cost + step_cost;
+                let next_index = self.index(next);
+                if next_cost < cost[next_index] {
+                    cost[next_index] = next_cost;
+                    came_from[next_index] = Some(cell);
+                    open.push(OpenCell {
+                        estimate: next_cost + heuristic(next),
+                        cell: next,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// `smooth` removes every waypoint that can be skipped by walking in a straight line
+    pub fn smooth(&self, waypoints: Vec<Vec2>) -> Vec<Vec2> {
+        if waypoints.len() <= 2 {
+            return waypoints;
+        }
+        let mut smoothed = vec![waypoints[0]];
+        let mut anchor = 0;
+        for index in 2..waypoints.len() {
+            if !self.line_of_sight(waypoints[anchor], waypoints[index]) {
+                anchor = index - 1;
+                smoothed.push(waypoints[anchor]);
+            }
+        }
+        smoothed.push(waypoints[waypoints.len() - 1]);
+        smoothed
+    }
+}
+
+/// `OpenCell` orders cells in the open list by lowest estimated total cost.
+pub(crate) struct OpenCell {
+    pub(crate) estimate: f32,
+    pub(crate) cell: IVec2,
+}
+
+impl PartialEq for OpenCell {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OpenCell {}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed so the binary heap pops the cheapest estimate first
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then_with(|| (other.cell.x, other.cell.y).cmp(&(self.cell.x, self.cell.y)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// `PathCache` remembers paths between cells so agents walking the same way share the search.
+/// it is cleared every time the `NavGrid` is rebuilt.
+///
+/// Properties:
+///
+/// * `paths`: Smoothed waypoints by start and goal cell, `None` if there is no path.
+/// * `hits`: How many paths were served from the cache.
+/// * `misses`: How many paths had to be searched.
+pub struct PathCache {
+    paths: HashMap<(IVec2, IVec2), Option<Vec<Vec2>>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl PathCache {
+    /// `clear` forgets every path
+    pub fn clear(&mut self) {
+        self.paths.clear();
+    }
+
+    /// `len` is the number of cached paths
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// `is_empty` is true when no path is cached
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// `find_path` gets the waypoints from `from` to `to`, searching the grid if the path is not
+    /// cached yet
+    ///
+    /// Arguments:
+    ///
+    /// * `grid`: The grid to search.
+    /// * `from`: Where the path starts.
+    /// * `to`: Where the path ends, this is always the last waypoint.
+    ///
+    /// Returns:
+    ///
+    /// The waypoints to walk through, or `None` if either point is off the grid or unreachable.
+    pub fn find_path(&mut self, grid: &NavGrid, from: Vec2, to: Vec2) -> Option<Vec<Vec2>> {
+        let start = grid.cell_of(from)?;
+        let goal = grid.cell_of(to)?;
+
+        let cells = match self.paths.get(&(start, goal)) {
+            Some(cached) => {
+                self.hits += 1;
+                cached.clone()
+            }
+            None => {
+                self.misses += 1;
+                let waypoints = grid.find_path(start, goal).map(|cells| {
+                    grid.smooth(cells.into_iter().map(|cell| grid.center_of(cell)).collect())
+                });
+                self.paths.insert((start, goal), waypoints.clone());
+                waypoints
+            }
+        }?;
+
+        // the first waypoint is the center of the start cell, which the agent is already in
+        let mut waypoints: Vec<Vec2> = cells.into_iter().skip(1).collect();
+        waypoints.pop();
+        waypoints.push(to);
+        Some(waypoints)
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq)]
+/// `NavPath` is the route an agent follows to its destination.
+///
+/// Properties:
+///
+/// * `goal`: The destination the path was found for.
+/// * `waypoints`: The points still to walk through, the last one is the goal.
+/// * `generation`: The `NavGrid` generation the path was found on.
+pub struct NavPath {
+    pub goal: Vec2,
+    pub waypoints: Vec<Vec2>,
+    pub generation: u64,
+}
+
+impl NavPath {
+    /// `next_waypoint` is the point the agent should walk towards
+    pub fn next_waypoint(&self) -> Option<Vec2> {
+        self.waypoints.first().copied()
+    }
+}
+
+/// `rebuild_nav_grid` rebuilds the grid and clears the path cache whenever an obstacle is added,
+/// moved or changed
+///
+/// Arguments:
+///
+/// * `obstacles`: Query<(&Obstacle, &Transform)> - query containing every obstacle.
+/// * `changed`: Query<(), Or<(Changed<Obstacle>, Changed<Transform>)>, With<Obstacle>> - query containing obstacles that changed since the last tick.
+/// * `grid`: ResMut<NavGrid> - resource containing the navigation grid.
+/// * `cache`: ResMut<PathCache> - resource containing the cached paths.
+fn rebuild_nav_grid(
+    obstacles: Query<(&Obstacle, &Transform)>,
+    changed: Query<(), (With<Obstacle>, Or<(Changed<Obstacle>, Changed<Transform>)>)>,
+    grid: ResMut<NavGrid>,
+    cache: ResMut<PathCache>,
+) {
+    if changed.is_empty() {
+        return;
+    }
+    rebuild(&obstacles, grid, cache);
+}
+
+/// `forget_removed_obstacles` rebuilds the grid and clears the path cache when an obstacle is
+/// removed. it runs every frame since removals are only reported until the end of the frame
+///
+/// Arguments:
+///
+/// * `obstacles`: Query<(&Obstacle, &Transform)> - query containing every obstacle.
+/// * `removed`: RemovedComponents<Obstacle> - obstacles removed this frame.
+/// * `grid`: ResMut<NavGrid> - resource containing the navigation grid.
+/// * `cache`: ResMut<PathCache> - resource containing the cached paths.
+fn forget_removed_obstacles(
+    obstacles: Query<(&Obstacle, &Transform)>,
+    removed: RemovedComponents<Obstacle>,
+    grid: ResMut<NavGrid>,
+    cache: ResMut<PathCache>,
+) {
+    if removed.iter().next().is_none() {
+        return;
+    }
+    rebuild(&obstacles, grid, cache);
+}
+
+/// `rebuild` builds the grid again from every obstacle and clears the path cache
+///
+/// Arguments:
+///
+/// * `obstacles`: &Query<(&Obstacle, &Transform)> - query containing every obstacle.
+/// * `grid`: ResMut<NavGrid> - resource containing the navigation grid.
+/// * `cache`: ResMut<PathCache> - resource containing the cached paths.
+fn rebuild(
+    obstacles: &Query<(&Obstacle, &Transform)>,
+    mut grid: ResMut<NavGrid>,
+    mut cache: ResMut<PathCache>,
+) {
+    grid.rebuild(
+        obstacles
+            .iter()
+            .map(|(obstacle, transform)| (transform.translation.truncate(), obstacle)),
+    );
+    cache.clear();
+}
+
+/// `plan_paths` finds a path for every agent whose destination changed or whose path was found
+/// on an outdated grid, and removes the paths of agents without a destination or following a
+/// flow field. agents whose destination can not be reached on the grid walk in a straight line
+/// instead
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &Agent, &Transform, Option<&NavPath>), Without<FlowFollower>> - query containing agents and their paths.
+/// * `followers`: Query<Entity, (With<FlowFollower>, With<NavPath>)> - query containing flow field followers that still have a path.
+/// * `grid`: Res<NavGrid> - resource containing the navigation grid.
+/// * `cache`: ResMut<PathCache> - resource containing the cached paths.
+/// * `commands`: Commands - This is the command buffer that we use to add and remove paths.
+fn plan_paths(
+    agents: Query<(Entity, &Agent, &Transform, Option<&NavPath>), Without<FlowFollower>>,
+    followers: Query<Entity, (With<FlowFollower>, With<NavPath>)>,
+    grid: Res<NavGrid>,
+    mut cache: ResMut<PathCache>,
+    mut commands: Commands,
+) {
+    for entity in followers.iter() {
+        commands.entity(entity).remove::<NavPath>();
+    }
+    for (entity, agent, transform, path) in agents.iter() {
+        let destination = match agent.destination {
+            Some(destination) => destination,
+            None => {
+                if path.is_some() {
+                    commands.entity(entity).remove::<NavPath>();
+                }
+                continue;
+            }
+        };
+        if let Some(path) = path {
+            if path.goal == destination && path.generation == grid.generation() {
+                continue;
+            }
+        }
+
+        let waypoints = cache
+            .find_path(&grid, transform.translation.truncate(), destination)
+            .unwrap_or_else(|| vec![destination]);
+        commands.entity(entity).insert(NavPath {
+            goal: destination,
+            waypoints,
+            generation: grid.generation(),
+        });
+    }
+}
+
+/// `attach_obstacle_shapes` draws every obstacle that is not drawn yet as a dark rectangle
+///
+/// Arguments:
+///
+/// * `obstacles`: Query<(Entity, &Obstacle, &Transform), Without<Path>> - query containing obstacles without a shape.
+/// * `commands`: Commands - This is the command buffer that we use to add the shape to the obstacle.
+fn attach_obstacle_shapes(
+    obstacles: Query<(Entity, &Obstacle, &Transform), Without<Path>>,
+    mut commands: Commands,
+) {
+    for (entity, obstacle, transform) in obstacles.iter() {
+        let shape = shapes::Rectangle {
+            extents: Vec2::new(obstacle.width, obstacle.height),
+            origin: RectangleOrigin::Center,
+        };
+
+        commands
+            .entity(entity)
+            .insert_bundle(GeometryBuilder::build_as(
+                &shape,
+                DrawMode::Outlined {
+                    fill_mode: FillMode::color(Color::DARK_GRAY),
+                    outline_mode: StrokeMode::new(Color::BLACK, 10.0),
+                },
+                *transform,
+            ));
+    }
+}
@@ -0,0 +1,219 @@
+use crate::agent::Agent;
+use crate::clock::SimClock;
+use crate::spatial::SpatialIndex;
+use crate::zone::Zone;
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub struct NeedsPlugin;
+
+impl Plugin for NeedsPlugin {
+    /// `build` adds the systems that make needs decay over time and refill them inside amenities
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NeedSettings>().add_system_set_to_stage(
+            SimStage,
+            SystemSet::on_update(GameState::Playing)
+                .with_system(
+                    decay_needs
+                        .label(SimLabel::UpdateNeeds)
+                        .label(NeedsSystem::Decay),
+                )
+                .with_system(
+                    satisfy_needs
+                        .label(SimLabel::UpdateNeeds)
+                        .after(NeedsSystem::Decay),
+                ),
+        );
+    }
+}
+
+#[derive(SystemLabel, Clone, Eq, PartialEq, Debug, Hash)]
+/// `NeedsSystem` orders needs being refilled after they decay, so the result of a tick does not
+/// depend on which system the scheduler happens to run first
+enum NeedsSystem {
+    Decay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+/// `NeedKind` is one of the things an agent needs to stay happy.
+pub enum NeedKind {
+    Hunger,
+    Energy,
+    Social,
+    Hygiene,
+}
+
+impl NeedKind {
+    /// every need in the order they are stored in `Needs`
+    pub const ALL: [NeedKind; 4] = [
+        NeedKind::Hunger,
+        NeedKind::Energy,
+        NeedKind::Social,
+        NeedKind::Hygiene,
+    ];
+
+    /// `name` is the name of the need shown in the ui
+    pub fn name(&self) -> &'static str {
+        match self {
+            NeedKind::Hunger => "hunger",
+            NeedKind::Energy => "energy",
+            NeedKind::Social => "social",
+            NeedKind::Hygiene => "hygiene",
+        }
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq)]
+/// `Needs` stores how satisfied an agent is for every `NeedKind`, from `0.0` (desperate) to `1.0`
+/// (fully satisfied).
+///
+/// Properties:
+///
+/// * `values`: the level of every need, indexed in the order of `NeedKind::ALL`.
+pub struct Needs {
+    values: [f32; 4],
+}
+
+impl Default for Needs {
+    fn default() -> Self {
+        Needs { values: [1.0; 4] }
+    }
+}
+
+impl Needs {
+    /// `get` gets the level of a need
+    ///
+    /// Arguments:
+    ///
+    /// * `need`: The need to get.
+    pub fn get(&self, need: NeedKind) -> f32 {
+        self.values[need as usize]
+    }
+
+    /// `set` sets the level of a need, clamped to `0.0..=1.0`
+    ///
+    /// Arguments:
+    ///
+    /// * `need`: The need to set.
+    /// * `value`: The new level of the need.
+    pub fn set(&mut self, need: NeedKind, value: f32) {
+        self.values[need as usize] = value.clamp(0.0, 1.0);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `NeedConfig` controls how a single need behaves.
+///
+/// Properties:
+///
+/// * `decay_rate`: how much of the need is lost every second.
+/// * `critical`: below this level the agent goes looking for a way to satisfy the need.
+/// * `satisfied`: the level at which the agent stops satisfying the need.
+pub struct NeedConfig {
+    pub decay_rate: f32,
+    pub critical: f32,
+    pub satisfied: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `NeedSettings` is a resource with the `NeedConfig` of every need, indexed in the order of
+/// `NeedKind::ALL`.
+pub struct NeedSettings {
+    configs: [NeedConfig; 4],
+}
+
+impl Default for NeedSettings {
+    fn default() -> Self {
+        let config = |decay_rate| NeedConfig {
+            decay_rate,
+            critical: 0.3,
+            satisfied: 0.95,
+        };
+        NeedSettings {
+            configs: [config(0.01), config(0.005), config(0.008), config(0.004)],
+        }
+    }
+}
+
+impl NeedSettings {
+    /// `get` gets the config of a need
+    pub fn get(&self, need: NeedKind) -> &NeedConfig {
+        &self.configs[need as usize]
+    }
+
+    /// `get_mut` gets the config of a need so it can be changed
+    pub fn get_mut(&mut self, need: NeedKind) -> &mut NeedConfig {
+        &mut self.configs[need as usize]
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Serialize, Deserialize)]
+/// `Amenity` marks a zone as a place where agents can satisfy needs. the `ZoneKind` of the zone is
+/// what agents go there for, the amenity is how quickly their needs refill once inside.
+///
+/// Properties:
+///
+/// * `provides`: the needs that are satisfied while inside the zone.
+/// * `rate`: how much of each need is restored every second.
+pub struct Amenity {
+    pub provides: Vec<NeedKind>,
+    pub rate: f32,
+}
+
+/// `decay_needs` lowers every need of every agent according to its decay rate
+///
+/// Arguments:
+///
+/// * `needs_query`: Query<&mut Needs> - query containing the needs of agents.
+/// * `settings`: Res<NeedSettings> - resource containing the decay rates.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+fn decay_needs(
+    mut needs_query: Query<&mut Needs>,
+    settings: Res<NeedSettings>,
+    clock: Res<SimClock>,
+) {
+    for mut needs in needs_query.iter_mut() {
+        for need in NeedKind::ALL {
+            let value = needs.get(need) - settings.get(need).decay_rate * clock.delta_seconds();
+            needs.set(need, value);
+        }
+    }
+}
+
+/// `satisfy_needs` restores the needs of agents standing inside an amenity that provides them,
+/// looking up the zones around every agent in the `SpatialIndex`
+///
+/// Arguments:
+///
+/// * `agents`: Query<(&mut Needs, &Transform), With<Agent>> - query containing agents and their needs.
+/// * `amenities`: Query<(&Zone, &Amenity, &Transform)> - query containing every amenity zone.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find the zones agents stand in.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+fn satisfy_needs(
+    mut agents: Query<(&mut Needs, &Transform), With<Agent>>,
+    amenities: Query<(&Zone, &Amenity, &Transform)>,
+    index: Res<SpatialIndex>,
+    clock: Res<SimClock>,
+) {
+    for (mut needs, agent_transform) in agents.iter_mut() {
+        let position = agent_transform.translation.truncate();
+        for zone_entity in index.zones_at(position) {
+            let (zone, amenity, zone_transform) = match amenities.get(zone_entity) {
+                Ok(amenity) => amenity,
+                Err(_) => continue,
+            };
+            if !zone.contains(zone_transform.translation.truncate(), position) {
+                continue;
+            }
+            for need in &amenity.provides {
+                let value = needs.get(*need) + amenity.rate * clock.delta_seconds();
+                needs.set(*need, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::navigation::NavGrid;
+use crate::spatial::SpatialIndex;
+use crate::windows::UiStates;
+use crate::zone::Zone;
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// how many rays the vision cone of the selected agent is drawn with
+const CONE_RAYS: usize = 32;
+
+/// the radius of the markers drawn on everything the selected agent perceives
+const MARKER_RADIUS: f32 = 30.0;
+
+pub struct PerceptionPlugin;
+
+impl Plugin for PerceptionPlugin {
+    /// `build` adds the system that works out what every agent with a `Perception` can see and
+    /// hear
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            SimStage,
+            SystemSet::on_update(GameState::Playing).with_system(
+                update_perception
+                    .label(SimLabel::Perceive)
+                    .after(SimLabel::IndexZones)
+                    .before(SimLabel::Observe)
+                    .before(SimLabel::ChooseAction),
+            ),
+        );
+    }
+}
+
+pub struct PerceptionVisualsPlugin;
+
+impl Plugin for PerceptionVisualsPlugin {
+    /// `build` adds the overlay showing what the selected agents perceive
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Playing).with_system(draw_perception));
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `Perception` is how far and where an agent can see and hear. agents without one see everything
+/// within the `sight_range` of the `MemorySettings` in every direction.
+///
+/// Properties:
+///
+/// * `view_distance`: How far the agent can see.
+/// * `field_of_view`: How wide the agent can see in degrees, centered on the way it is facing.
+/// * `hearing_radius`: How far away the agent hears other agents, in every direction and through walls.
+pub struct Perception {
+    pub view_distance: f32,
+    pub field_of_view: f32,
+    pub hearing_radius: f32,
+}
+
+impl Default for Perception {
+    fn default() -> Self {
+        Perception {
+            view_distance: 400.0,
+            field_of_view: 120.0,
+            hearing_radius: 150.0,
+        }
+    }
+}
+
+impl Perception {
+    /// `in_view` checks if a point is close enough and inside the field of view, ignoring walls
+    ///
+    /// Arguments:
+    ///
+    /// * `eye`: Where the agent is.
+    /// * `facing`: The way the agent is facing.
+    /// * `target`: The point to look at.
+    pub fn in_view(&self, eye: Vec2, facing: Vec2, target: Vec2) -> bool {
+        let offset = target - eye;
+        if offset.length() > self.view_distance {
+            return false;
+        }
+        offset.length_squared() <= f32::EPSILON
+            || facing.angle_between(offset).abs().to_degrees() <= self.field_of_view / 2.0
+    }
+
+    /// `sees` checks if a point is in view and no obstacle is in the way
+    ///
+    /// Arguments:
+    ///
+    /// * `eye`: Where the agent is.
+    /// * `facing`: The way the agent is facing.
+    /// * `target`: The point to look at.
+    /// * `grid`: The navigation grid marking where the obstacles are.
+    pub fn sees(&self, eye: Vec2, facing: Vec2, target: Vec2, grid: &NavGrid) -> bool {
+        self.in_view(eye, facing, target) && grid.first_obstruction(eye, target).is_none()
+    }
+
+    /// `hears` checks if a point is close enough to be heard
+    pub fn hears(&self, eye: Vec2, target: Vec2) -> bool {
+        eye.distance(target) <= self.hearing_radius
+    }
+
+    /// `validate` checks that every distance and the field of view make sense
+    ///
+    /// Returns:
+    ///
+    /// A description of every problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (parameter, value) in [
+            ("view_distance", self.view_distance),
+            ("hearing_radius", self.hearing_radius),
+        ] {
+            if value < 0.0 {
+                problems.push(format!(
+                    "a {} of {}, it can not be below 0",
+                    parameter, value
+                ));
+            }
+        }
+        if !(0.0..=360.0).contains(&self.field_of_view) {
+            problems.push(format!(
+                "a field_of_view of {}, it has to be from 0 to 360 degrees",
+                self.field_of_view
+            ));
+        }
+        problems
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Default)]
+/// `Perceived` is everything an agent with a `Perception` noticed this tick, worked out again
+/// every tick.
+///
+/// Properties:
+///
+/// * `seen`: The agents and zones the agent can see.
+/// * `heard`: The agents the agent can hear, seen or not.
+pub struct Perceived {
+    pub seen: BTreeSet<Entity>,
+    pub heard: BTreeSet<Entity>,
+}
+
+impl Perceived {
+    /// `perceives` checks if the agent saw or heard an entity
+    pub fn perceives(&self, entity: Entity) -> bool {
+        self.seen.contains(&entity) || self.heard.contains(&entity)
+    }
+}
+
+/// `facing` is the way an agent turned by `update_agent` is facing
+pub fn facing(transform: &Transform) -> Vec2 {
+    (transform.rotation * Vec3::X).truncate()
+}
+
+/// `update_perception` works out which agents and zones every agent with a `Perception` can see
+/// and which agents it can hear. zones are seen when the point of them closest to the agent is in
+/// sight
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &Perception, &mut Perceived, &Transform)> - query containing agents that perceive.
+/// * `zones`: Query<(Entity, &Zone, &Transform)> - query containing every zone.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find the agents and zones nearby.
+/// * `grid`: Res<NavGrid> - resource containing the navigation grid, used to check if obstacles are in the way.
+fn update_perception(
+    mut agents: Query<(Entity, &Perception, &mut Perceived, &Transform)>,
+    zones: Query<(Entity, &Zone, &Transform)>,
+    index: Res<SpatialIndex>,
+    grid: Res<NavGrid>,
+) {
+    for (entity, perception, mut perceived, transform) in agents.iter_mut() {
+        let eye = transform.translation.truncate();
+        let facing = facing(transform);
+        perceived.seen.clear();
+        perceived.heard.clear();
+
+        let radius = perception.view_distance.max(perception.hearing_radius);
+        for (other, position) in index.query_radius(eye, radius) {
+            if other == entity {
+                continue;
+            }
+            if perception.sees(eye, facing, position, &grid) {
+                perceived.seen.insert(other);
+            }
+            if perception.hears(eye, position) {
+                perceived.heard.insert(other);
+            }
+        }
+
+        let reach = Vec2::splat(perception.view_distance);
+        for (zone_entity, zone, zone_transform) in index
+            .zones_in_rect(eye - reach, eye + reach)
+            .filter_map(|zone| zones.get(zone).ok())
+        {
+            let (min, max) = zone.bounds(zone_transform.translation.truncate());
+            if perception.sees(eye, facing, eye.clamp(min, max), &grid) {
+                perceived.seen.insert(zone_entity);
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+/// `PerceptionOverlay` marks the shapes showing what the selected agents perceive, they are drawn
+/// again every frame.
+struct PerceptionOverlay;
+
+/// `draw_perception` draws the vision cone, cut short by obstacles, and the hearing radius of
+/// every selected agent, with a marker on everything it saw or heard
+///
+/// Arguments:
+///
+/// * `ui_states`: Res<UiStates> - resource containing the selected agents.
+/// * `agents`: Query<(&Perception, &Perceived, &Transform)> - query containing agents that perceive.
+/// * `positions`: Query<&Transform> - query containing the position of every entity, used to mark what was perceived.
+/// * `overlays`: Query<Entity, With<PerceptionOverlay>> - query containing the shapes drawn last frame.
+/// * `grid`: Res<NavGrid> - resource containing the navigation grid, used to cut the vision cone short.
+/// * `commands`: Commands - This is the command buffer that we use to spawn and despawn the shapes.
+fn draw_perception(
+    ui_states: Res<UiStates>,
+    agents: Query<(&Perception, &Perceived, &Transform)>,
+    positions: Query<&Transform>,
+    overlays: Query<Entity, With<PerceptionOverlay>>,
+    grid: Res<NavGrid>,
+    mut commands: Commands,
+) {
+    for overlay in overlays.iter() {
+        commands.entity(overlay).despawn();
+    }
+
+    for (perception, perceived, transform) in ui_states
+        .agents
+        .iter()
+        .filter_map(|entity| agents.get(*entity).ok())
+    {
+        let eye = transform.translation.truncate();
+        let heading = facing(transform);
+        let heading = heading.y.atan2(heading.x);
+        let field_of_view = perception.field_of_view.to_radians();
+        let mut points = vec![Vec2::ZERO];
+        points.extend((0..=CONE_RAYS).map(|ray| {
+            let angle =
+                heading - field_of_view / 2.0 + field_of_view * ray as f32 / CONE_RAYS as f32;
+            let end = eye + Vec2::new(angle.cos(), angle.sin()) * perception.view_distance;
+            grid.first_obstruction(eye, end).unwrap_or(end) - eye
+        }));
+        let overlay_transform = Transform::from_translation(eye.extend(10.0));
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &shapes::Polygon {
+                    points,
+                    closed: true,
+                },
+                DrawMode::Outlined {
+                    fill_mode: FillMode::color(Color::rgba(1.0, 1.0, 0.0, 0.15)),
+                    outline_mode: StrokeMode::new(Color::YELLOW, 4.0),
+                },
+                overlay_transform,
+            ))
+            .insert(PerceptionOverlay);
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &shapes::Circle {
+                    radius: perception.hearing_radius,
+                    center: Vec2::ZERO,
+                },
+                DrawMode::Stroke(StrokeMode::new(Color::CYAN, 4.0)),
+                overlay_transform,
+            ))
+            .insert(PerceptionOverlay);
+
+        let seen = perceived.seen.iter().map(|entity| (entity, Color::YELLOW));
+        let heard = perceived
+            .heard
+            .difference(&perceived.seen)
+            .map(|entity| (entity, Color::CYAN));
+        for (entity, colour) in seen.chain(heard) {
+            if let Ok(position) = positions.get(*entity) {
+                commands
+                    .spawn_bundle(GeometryBuilder::build_as(
+                        &shapes::Circle {
+                            radius: MARKER_RADIUS,
+                            center: Vec2::ZERO,
+                        },
+                        DrawMode::Stroke(StrokeMode::new(colour, 6.0)),
+                        Transform::from_translation(position.translation.truncate().extend(10.0)),
+                    ))
+                    .insert(PerceptionOverlay);
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use crate::agent::Agent;
+use crate::clock::SimClock;
+use crate::spatial::SpatialIndex;
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// how much more familiar two agents get from one interaction
+pub const INTERACTION_FAMILIARITY: f32 = 0.05;
+/// how much the affinity of two agents changes from an interaction that went as well or as badly
+/// as it can
+pub const INTERACTION_AFFINITY: f32 = 0.1;
+/// how much the trust of two agents changes from an interaction that went as well or as badly as
+/// it can
+pub const INTERACTION_TRUST: f32 = 0.05;
+
+/// the affinity an acquaintance has to reach to become a friend
+pub const FRIEND_AFFINITY: f32 = 0.5;
+/// the familiarity an acquaintance has to reach to become a friend
+pub const FRIEND_FAMILIARITY: f32 = 0.3;
+/// the affinity below which an acquaintance becomes a rival
+pub const RIVAL_AFFINITY: f32 = -0.5;
+
+pub struct RelationshipPlugin;
+
+impl Plugin for RelationshipPlugin {
+    /// `build` adds the social graph and the system that makes agents who spend time near each
+    /// other more familiar
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SocialGraph>()
+            .init_resource::<SocialSettings>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing).with_system(
+                    get_to_know_neighbours
+                        .label(SimLabel::UpdateRelationships)
+                        .after(SimLabel::UpdateSpatialIndex),
+                ),
+            )
+            // removals are only reported until the end of the frame, which can pass without a tick
+            .add_system_to_stage(CoreStage::PostUpdate, forget_removed_agents);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+/// `RelationshipKind` is what one agent is to another.
+pub enum RelationshipKind {
+    /// someone the agent has met
+    #[default]
+    Acquaintance,
+    /// someone the agent likes and knows well
+    Friend,
+    /// a relative, agents stay family no matter how they get on
+    Family,
+    /// someone the agent has fallen out with
+    Rival,
+}
+
+impl RelationshipKind {
+    /// `name` is the name of the kind shown in the user interface
+    pub fn name(&self) -> &'static str {
+        match self {
+            RelationshipKind::Acquaintance => "acquaintance",
+            RelationshipKind::Friend => "friend",
+            RelationshipKind::Family => "family",
+            RelationshipKind::Rival => "rival",
+        }
+    }
+}
+
+impl fmt::Display for RelationshipKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `Relationship` is how one agent feels about another. relationships go one way, the other agent
+/// can feel differently.
+///
+/// Properties:
+///
+/// * `kind`: What the other agent is to this one.
+/// * `affinity`: How much the agent likes the other one, from `-1.0` to `1.0`.
+/// * `familiarity`: How well the agent knows the other one, from `0.0` to `1.0`.
+/// * `trust`: How much the agent believes the other one, from `0.0` to `1.0`.
+pub struct Relationship {
+    pub kind: RelationshipKind,
+    pub affinity: f32,
+    pub familiarity: f32,
+    pub trust: f32,
+}
+
+impl Default for Relationship {
+    /// a stranger the agent has no opinion of yet
+    fn default() -> Self {
+        Relationship {
+            kind: RelationshipKind::Acquaintance,
+            affinity: 0.0,
+            familiarity: 0.0,
+            trust: 0.5,
+        }
+    }
+}
+
+impl Relationship {
+    /// `strength` is how much the relationship matters to the agent, strong likes and dislikes of
+    /// agents it knows well matter the most
+    pub fn strength(&self) -> f32 {
+        self.affinity.abs() * (0.5 + self.familiarity * 0.5)
+    }
+
+    /// `update_kind` turns acquaintances into friends or rivals and back as the affinity and
+    /// familiarity change. family stays family
+    pub fn update_kind(&mut self) {
+        if self.kind == RelationshipKind::Family {
+            return;
+        }
+        self.kind = if self.affinity >= FRIEND_AFFINITY && self.familiarity >= FRIEND_FAMILIARITY {
+            RelationshipKind::Friend
+        } else if self.affinity <= RIVAL_AFFINITY {
+            RelationshipKind::Rival
+        } else {
+            RelationshipKind::Acquaintance
+        };
+    }
+
+    /// `validate` checks that every value is in its range
+    ///
+    /// Returns:
+    ///
+    /// A description of every problem found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !(-1.0..=1.0).contains(&self.affinity) {
+            problems.push(format!(
+                "an affinity of {}, it goes from -1 to 1",
+                self.affinity
+            ));
+        }
+        for (name, value) in [("familiarity", self.familiarity), ("trust", self.trust)] {
+            if !(0.0..=1.0).contains(&value) {
+                problems.push(format!("a {} of {}, it goes from 0 to 1", name, value));
+            }
+        }
+        problems
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq)]
+/// `SocialGraph` is a resource with the relationship of every agent with every agent it knows.
+/// relationships are kept in order so the graph is walked the same way every run.
+///
+/// Properties:
+///
+/// * `relationships`: How every agent feels about every agent it knows.
+pub struct SocialGraph {
+    relationships: BTreeMap<Entity, BTreeMap<Entity, Relationship>>,
+}
+
+impl SocialGraph {
+    /// `get` is how `from` feels about `to`, `None` if they have not met
+    pub fn get(&self, from: Entity, to: Entity) -> Option<&Relationship> {
+        self.relationships.get(&from)?.get(&to)
+    }
+
+    /// `get_or_insert` is how `from` feels about `to`, meeting them first if they have not met
+    pub fn get_or_insert(&mut self, from: Entity, to: Entity) -> &mut Relationship {
+        self.relationships
+            .entry(from)
+            .or_default()
+            .entry(to)
+            .or_default()
+    }
+
+    /// `set` replaces how `from` feels about `to`
+    pub fn set(&mut self, from: Entity, to: Entity, relationship: Relationship) {
+        self.relationships
+            .entry(from)
+            .or_default()
+            .insert(to, relationship);
+    }
+
+    /// `relationships_of` iterates over every agent `agent` knows, with how it feels about them
+    pub fn relationships_of(&self, agent: Entity) -> impl Iterator<Item = (Entity, &Relationship)> {
+        self.relationships
+            .get(&agent)
+            .into_iter()
+            .flatten()
+            .map(|(other, relationship)| (*other, relationship))
+    }
+
+    /// `of_kind` iterates over every agent that is a `kind` to `agent`
+    pub fn of_kind(
+        &self,
+        agent: Entity,
+        kind: RelationshipKind,
+    ) -> impl Iterator<Item = Entity> + '_ {
+        self.relationships_of(agent)
+            .filter(move |(_, relationship)| relationship.kind == kind)
+            .map(|(other, _)| other)
+    }
+
+    /// `friends_of` iterates over every friend of `agent`
+    pub fn friends_of(&self, agent: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.of_kind(agent, RelationshipKind::Friend)
+    }
+
+    /// `top_relationships` are the relationships that matter most to an agent
+    ///
+    /// Arguments:
+    ///
+    /// * `agent`: The agent whose relationships to look at.
+    /// * `count`: How many relationships to return at most.
+    ///
+    /// Returns:
+    ///
+    /// The other agents with how `agent` feels about them, strongest first.
+    pub fn top_relationships(&self, agent: Entity, count: usize) -> Vec<(Entity, &Relationship)> {
+        let mut relationships: Vec<(Entity, &Relationship)> =
+            self.relationships_of(agent).collect();
+        relationships.sort_by(|(_, a), (_, b)| b.strength().total_cmp(&a.strength()));
+        relationships.truncate(count);
+        relationships
+    }
+
+    /// `within_range` finds every agent that is a `kind` to `agent` and within `range` of it
+    ///
+    /// Arguments:
+    ///
+    /// * `agent`: The agent to search around.
+    /// * `kind`: The kind of relationship to look for.
+    /// * `range`: How far from the agent to search.
+    /// * `index`: The spatial index, used to find where the agents are.
+    ///
+    /// Returns:
+    ///
+    /// The agents found, closest first. empty if `agent` is not in the index.
+    pub fn within_range(
+        &self,
+        agent: Entity,
+        kind: RelationshipKind,
+        range: f32,
+        index: &SpatialIndex,
+    ) -> Vec<Entity> {
+        let center = match index.position(agent) {
+            Some(center) => center,
+            None => return Vec::new(),
+        };
+        let mut found: Vec<(Entity, f32)> = index
+            .query_radius(center, range)
+            .filter(|(other, _)| {
+                *other != agent
+                    && self
+                        .get(agent, *other)
+                        .is_some_and(|relationship| relationship.kind == kind)
+            })
+            .map(|(other, position)| (other, position.distance_squared(center)))
+            .collect();
+        found.sort_by(|(a, a_distance), (b, b_distance)| {
+            a_distance.total_cmp(b_distance).then(a.cmp(b))
+        });
+        found.into_iter().map(|(other, _)| other).collect()
+    }
+
+    /// `friends_within` finds every friend of `agent` within `range` of it, closest first
+    pub fn friends_within(&self, agent: Entity, range: f32, index: &SpatialIndex) -> Vec<Entity> {
+        self.within_range(agent, RelationshipKind::Friend, range, index)
+    }
+
+    /// `record_interaction` changes how two agents feel about each other after they interacted,
+    /// both get more familiar and like and trust each other more or less depending on how it went
+    ///
+    /// Arguments:
+    ///
+    /// * `a`: One of the agents.
+    /// * `b`: The other agent.
+    /// * `outcome`: How the interaction went, from `-1.0` for as badly as it can to `1.0` for as well as it can.
+    pub fn record_interaction(&mut self, a: Entity, b: Entity, outcome: f32) {
+        let outcome = outcome.clamp(-1.0, 1.0);
+        for (from, to) in [(a, b), (b, a)] {
+            let relationship = self.get_or_insert(from, to);
+            relationship.familiarity =
+                (relationship.familiarity + INTERACTION_FAMILIARITY).min(1.0);
+            relationship.affinity =
+                (relationship.affinity + outcome * INTERACTION_AFFINITY).clamp(-1.0, 1.0);
+            relationship.trust = (relationship.trust + outcome * INTERACTION_TRUST).clamp(0.0, 1.0);
+            relationship.update_kind();
+        }
+    }
+
+    /// `remove` forgets an agent and every relationship with it
+    pub fn remove(&mut self, agent: Entity) {
+        self.relationships.remove(&agent);
+        for relationships in self.relationships.values_mut() {
+            relationships.remove(&agent);
+        }
+        self.relationships
+            .retain(|_, relationships| !relationships.is_empty());
+    }
+
+    /// `relationships` lists every relationship as the agent, the agent it is about and the
+    /// relationship, in order
+    pub fn relationships(&self) -> Vec<(Entity, Entity, Relationship)> {
+        self.relationships
+            .iter()
+            .flat_map(|(from, relationships)| {
+                relationships
+                    .iter()
+                    .map(move |(to, relationship)| (*from, *to, *relationship))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `SocialSettings` is a resource with the tuning of how agents get to know each other.
+///
+/// Properties:
+///
+/// * `meet_range`: Agents closer than this get to know each other.
+/// * `familiarity_rate`: How much more familiar agents in range get every second.
+pub struct SocialSettings {
+    pub meet_range: f32,
+    pub familiarity_rate: f32,
+}
+
+impl Default for SocialSettings {
+    fn default() -> Self {
+        SocialSettings {
+            meet_range: 150.0,
+            familiarity_rate: 0.002,
+        }
+    }
+}
+
+/// `get_to_know_neighbours` makes every agent a little more familiar with the agents around it
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &Transform), With<Agent>> - query containing every agent.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find agents close by.
+/// * `settings`: Res<SocialSettings> - resource containing how agents get to know each other.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+/// * `graph`: ResMut<SocialGraph> - resource containing the relationships of every agent.
+fn get_to_know_neighbours(
+    agents: Query<(Entity, &Transform), With<Agent>>,
+    index: Res<SpatialIndex>,
+    settings: Res<SocialSettings>,
+    clock: Res<SimClock>,
+    mut graph: ResMut<SocialGraph>,
+) {
+    let gain = settings.familiarity_rate * clock.delta_seconds();
+    for (entity, transform) in agents.iter() {
+        for (other, _) in index.query_radius(transform.translation.truncate(), settings.meet_range)
+        {
+            if other == entity || !agents.contains(other) {
+                continue;
+            }
+            let relationship = graph.get_or_insert(entity, other);
+            relationship.familiarity = (relationship.familiarity + gain).min(1.0);
+            relationship.update_kind();
+        }
+    }
+}
+
+/// `forget_removed_agents` takes the agents that were removed out of the social graph
+///
+/// Arguments:
+///
+/// * `removed`: RemovedComponents<Agent> - agents removed this frame.
+/// * `graph`: ResMut<SocialGraph> - resource containing the relationships of every agent.
+fn forget_removed_agents(removed: RemovedComponents<Agent>, mut graph: ResMut<SocialGraph>) {
+    for entity in removed.iter() {
+        graph.remove(entity);
+    }
+}
@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// the seed used when a scenario does not pick one
+pub const DEFAULT_SEED: u64 = 0;
+
+pub struct RngPlugin;
+
+impl Plugin for RngPlugin {
+    /// `build` adds the random number generator shared by the simulation systems
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimRng>();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `SimRng` is a resource with the random number generator of the simulation. simulation systems
+/// that need randomness take `ResMut<SimRng>` instead of using `rand::random` or `thread_rng`, so
+/// a run with the same seed and scenario always gives the same result. it works with everything in
+/// `rand::Rng`, such as `rng.gen_range(0.0..1.0)`.
+///
+/// Properties:
+///
+/// * `seed`: The seed the generator started from.
+/// * `rng`: The generator, ChaCha8 gives the same numbers on every platform.
+pub struct SimRng {
+    seed: u64,
+    rng: ChaCha8Rng,
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        SimRng::new(DEFAULT_SEED)
+    }
+}
+
+impl SimRng {
+    /// `new` creates a generator from a seed
+    pub fn new(seed: u64) -> Self {
+        SimRng {
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// `seed` is the seed the generator started from
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// `state` is where the generator is, enough to carry on from the same place later
+    pub fn state(&self) -> RngState {
+        RngState {
+            seed: self.seed,
+            position: self.rng.get_word_pos(),
+        }
+    }
+
+    /// `from_state` creates a generator that carries on where `state` was taken
+    pub fn from_state(state: RngState) -> Self {
+        let mut rng = SimRng::new(state.seed);
+        rng.rng.set_word_pos(state.position);
+        rng
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// `RngState` is where a `SimRng` is, as it is written in save files.
+///
+/// Properties:
+///
+/// * `seed`: The seed the generator started from.
+/// * `position`: How many 32 bit words the generator has given out since it was seeded.
+pub struct RngState {
+    pub seed: u64,
+    pub position: u128,
+}
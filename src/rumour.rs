@@ -0,0 +1,844 @@
+use crate::agent::Agent;
+use crate::clock::{SimClock, TimeOfDay};
+use crate::interaction::{InteractionEnded, InteractionKind};
+use crate::memory::Beliefs;
+use crate::needs::Amenity;
+use crate::relationships::{Relationship, SocialGraph};
+use crate::rng::SimRng;
+use crate::zone::{Refusal, Zone, ZoneRefused};
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// how much an agent that hears someone stole something likes the thief less, at full confidence
+pub const THEFT_AFFINITY: f32 = 0.3;
+
+/// how much an agent that hears someone stole something trusts the thief less, at full confidence
+pub const THEFT_TRUST: f32 = 0.3;
+
+/// the folder the rumour window exports metrics to
+pub const DEFAULT_METRICS_FOLDER: &str = "metrics";
+
+pub struct RumourPlugin;
+
+impl Plugin for RumourPlugin {
+    /// `build` adds the systems that start rumours, pass them on when agents interact and export
+    /// how far they spread
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RumourSettings>()
+            .init_resource::<RumourMetrics>()
+            .init_resource::<MetricsStatus>()
+            .add_event::<StartRumour>()
+            .add_event::<RumourHeard>()
+            .add_event::<ExportRumourMetrics>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        start_rumours
+                            .label(SimLabel::SpreadRumours)
+                            .after(SimLabel::EnforceZoneRules),
+                    )
+                    .with_system(
+                        spread_rumours
+                            .label(SimLabel::SpreadRumours)
+                            .after(start_rumours)
+                            .after(SimLabel::Interact),
+                    ),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing).with_system(export_rumour_metrics),
+            );
+    }
+}
+
+pub struct RumourVisualsPlugin;
+
+impl Plugin for RumourVisualsPlugin {
+    /// `build` adds a window showing how far every rumour spread
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(render_rumour_window),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `Fact` is something agents can tell each other, true or not.
+pub enum Fact {
+    /// the zone is closed
+    ZoneClosed {
+        #[serde(with = "crate::save::entity_bits")]
+        zone: Entity,
+    },
+    /// the zone can be found at a position
+    ZoneAt {
+        #[serde(with = "crate::save::entity_bits")]
+        zone: Entity,
+        position: Vec2,
+    },
+    /// the agent stole something
+    Stole {
+        #[serde(with = "crate::save::entity_bits")]
+        agent: Entity,
+    },
+}
+
+impl Fact {
+    /// `describe` writes the fact out for the user interface and the metrics
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: Gets the name of an agent or zone, `None` if it is gone.
+    pub fn describe(&self, name: impl Fn(Entity) -> Option<String>) -> String {
+        let name = |entity| name(entity).unwrap_or_else(|| "somewhere".to_string());
+        match *self {
+            Fact::ZoneClosed { zone } => format!("{} is closed", name(zone)),
+            Fact::ZoneAt { zone, position } => {
+                format!("{} is at {:.0},{:.0}", name(zone), position.x, position.y)
+            }
+            Fact::Stole { agent } => format!("{} stole something", name(agent)),
+        }
+    }
+
+    /// `distort` is the fact as someone gets it wrong: the blame or the closed zone moves to
+    /// someone or somewhere else, or the zone moves
+    ///
+    /// Arguments:
+    ///
+    /// * `rng`: The random number generator of the simulation.
+    /// * `zones`: The zones a closed zone can be mixed up with.
+    /// * `agents`: The agents a thief can be mixed up with.
+    /// * `location_error`: How far a zone can move.
+    pub fn distort(
+        &self,
+        rng: &mut SimRng,
+        zones: &[Entity],
+        agents: &[Entity],
+        location_error: f32,
+    ) -> Fact {
+        match *self {
+            Fact::ZoneClosed { .. } if !zones.is_empty() => Fact::ZoneClosed {
+                zone: zones[rng.gen_range(0..zones.len())],
+            },
+            Fact::ZoneAt { zone, position } => Fact::ZoneAt {
+                zone,
+                position: position
+                    + Vec2::new(
+                        rng.gen_range(-location_error..=location_error),
+                        rng.gen_range(-location_error..=location_error),
+                    ),
+            },
+            Fact::Stole { .. } if !agents.is_empty() => Fact::Stole {
+                agent: agents[rng.gen_range(0..agents.len())],
+            },
+            fact => fact,
+        }
+    }
+
+    /// `map_entities` swaps the entity the fact is about for another one
+    ///
+    /// Arguments:
+    ///
+    /// * `map`: Gets the new entity for an old one, `None` if the entity is gone.
+    ///
+    /// Returns:
+    ///
+    /// The fact about the new entity, `None` if it is gone.
+    pub fn map_entities(&self, map: impl Fn(Entity) -> Option<Entity>) -> Option<Fact> {
+        Some(match *self {
+            Fact::ZoneClosed { zone } => Fact::ZoneClosed { zone: map(zone)? },
+            Fact::ZoneAt { zone, position } => Fact::ZoneAt {
+                zone: map(zone)?,
+                position,
+            },
+            Fact::Stole { agent } => Fact::Stole { agent: map(agent)? },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `KnownRumour` is a rumour as one agent knows it.
+///
+/// Properties:
+///
+/// * `fact`: What the agent was told, which can differ from how the rumour started.
+/// * `confidence`: How much the agent believes it, from `0.0` to `1.0`.
+/// * `hops`: How many agents it went through to get here, `0` for the agent that started it.
+/// * `heard`: The tick of the `SimClock` the agent first heard it on.
+/// * `distorted`: If it got changed on the way.
+pub struct KnownRumour {
+    pub fact: Fact,
+    pub confidence: f32,
+    pub hops: u32,
+    pub heard: u64,
+    pub distorted: bool,
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Default, Serialize, Deserialize)]
+/// `Rumours` are the rumours an agent knows. only agents with `Rumours` start and pass them on.
+///
+/// Properties:
+///
+/// * `known`: Every rumour the agent knows, by the id it has in the `RumourMetrics`.
+pub struct Rumours {
+    pub known: BTreeMap<u64, KnownRumour>,
+}
+
+impl Rumours {
+    /// `get` gets a rumour the agent knows
+    pub fn get(&self, id: u64) -> Option<&KnownRumour> {
+        self.known.get(&id)
+    }
+
+    /// `knows_fact` checks if the agent knows a rumour telling the fact
+    pub fn knows_fact(&self, fact: &Fact) -> bool {
+        self.known.values().any(|rumour| rumour.fact == *fact)
+    }
+
+    /// `map_entities` swaps every entity the rumours are about for another one, such as when saved
+    /// rumours are loaded into a new world. rumours about entities that are gone are forgotten
+    ///
+    /// Arguments:
+    ///
+    /// * `map`: Gets the new entity for an old one, `None` if the entity is gone.
+    pub fn map_entities(&mut self, map: impl Fn(Entity) -> Option<Entity>) {
+        self.known
+            .retain(|_, rumour| match rumour.fact.map_entities(&map) {
+                Some(fact) => {
+                    rumour.fact = fact;
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `Reach` is an agent a rumour got to.
+///
+/// Properties:
+///
+/// * `agent`: The name of the agent.
+/// * `tick`: The tick the agent heard the rumour on.
+/// * `hops`: How many agents the rumour went through to get there.
+/// * `confidence`: How much the agent believed it.
+/// * `distorted`: If it got changed on the way.
+/// * `fact`: The rumour as the agent heard it.
+pub struct Reach {
+    pub agent: String,
+    pub tick: u64,
+    pub hops: u32,
+    pub confidence: f32,
+    pub distorted: bool,
+    pub fact: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `RumourSpread` is how a rumour spread through the population.
+///
+/// Properties:
+///
+/// * `id`: The id of the rumour.
+/// * `fact`: The rumour as it started.
+/// * `origin`: The name of the agent that started it.
+/// * `started`: The tick it started on.
+/// * `reached`: Every agent that heard it, in the order they heard it, starting with the origin.
+pub struct RumourSpread {
+    pub id: u64,
+    pub fact: String,
+    pub origin: String,
+    pub started: u64,
+    pub reached: Vec<Reach>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `RumourSummary` is how far and how fast a rumour spread.
+///
+/// Properties:
+///
+/// * `id`: The id of the rumour.
+/// * `fact`: The rumour as it started.
+/// * `origin`: The name of the agent that started it.
+/// * `reached`: How many agents heard it, the origin included.
+/// * `fraction`: The part of the population that heard it.
+/// * `max_hops`: The most agents it went through to get to someone.
+/// * `distorted`: How many agents heard it changed.
+/// * `seconds_to_half`: How long it took to reach half the population, `None` if it has not yet.
+/// * `seconds_to_last`: How long it took to reach the last agent that heard it.
+pub struct RumourSummary {
+    pub id: u64,
+    pub fact: String,
+    pub origin: String,
+    pub reached: usize,
+    pub fraction: f32,
+    pub max_hops: u32,
+    pub distorted: usize,
+    pub seconds_to_half: Option<f32>,
+    pub seconds_to_last: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+/// `RumourMetrics` is a resource recording every agent every rumour reached, for the rumour window
+/// and for exporting.
+///
+/// Properties:
+///
+/// * `spreads`: Every rumour, the index is the id of the rumour.
+pub struct RumourMetrics {
+    pub spreads: Vec<RumourSpread>,
+}
+
+impl RumourMetrics {
+    /// `start` records a new rumour
+    ///
+    /// Arguments:
+    ///
+    /// * `fact`: The rumour as it starts.
+    /// * `origin`: The name of the agent starting it.
+    /// * `tick`: The tick it starts on.
+    ///
+    /// Returns:
+    ///
+    /// The id of the rumour.
+    pub fn start(&mut self, fact: String, origin: String, tick: u64) -> u64 {
+        let id = self.spreads.len() as u64;
+        self.spreads.push(RumourSpread {
+            id,
+            fact: fact.clone(),
+            origin: origin.clone(),
+            started: tick,
+            reached: vec![Reach {
+                agent: origin,
+                tick,
+                hops: 0,
+                confidence: 1.0,
+                distorted: false,
+                fact,
+            }],
+        });
+        id
+    }
+
+    /// `record` records that a rumour reached another agent
+    pub fn record(&mut self, id: u64, reach: Reach) {
+        if let Some(spread) = self.spreads.get_mut(id as usize) {
+            spread.reached.push(reach);
+        }
+    }
+
+    /// `summaries` works out how far and how fast every rumour spread
+    ///
+    /// Arguments:
+    ///
+    /// * `population`: How many agents could have heard the rumours.
+    /// * `step`: How many seconds a tick is.
+    pub fn summaries(&self, population: usize, step: f32) -> Vec<RumourSummary> {
+        let seconds = |spread: &RumourSpread, reach: &Reach| {
+            reach.tick.saturating_sub(spread.started) as f32 * step
+        };
+        self.spreads
+            .iter()
+            .map(|spread| RumourSummary {
+                id: spread.id,
+                fact: spread.fact.clone(),
+                origin: spread.origin.clone(),
+                reached: spread.reached.len(),
+                fraction: spread.reached.len() as f32 / population.max(1) as f32,
+                max_hops: spread
+                    .reached
+                    .iter()
+                    .map(|reach| reach.hops)
+                    .max()
+                    .unwrap_or(0),
+                distorted: spread
+                    .reached
+                    .iter()
+                    .filter(|reach| reach.distorted)
+                    .count(),
+                seconds_to_half: population
+                    .div_ceil(2)
+                    .checked_sub(1)
+                    .and_then(|index| spread.reached.get(index))
+                    .map(|reach| seconds(spread, reach)),
+                seconds_to_last: spread
+                    .reached
+                    .last()
+                    .map_or(0.0, |reach| seconds(spread, reach)),
+            })
+            .collect()
+    }
+
+    /// `reach_csv` writes a row for every agent every rumour reached
+    ///
+    /// Arguments:
+    ///
+    /// * `step`: How many seconds a tick is.
+    pub fn reach_csv(&self, step: f32) -> String {
+        let mut csv = String::from("rumour,agent,seconds,hops,confidence,distorted,fact\n");
+        for spread in self.spreads.iter() {
+            for reach in spread.reached.iter() {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{}\n",
+                    spread.id,
+                    csv_field(&reach.agent),
+                    reach.tick.saturating_sub(spread.started) as f32 * step,
+                    reach.hops,
+                    reach.confidence,
+                    reach.distorted,
+                    csv_field(&reach.fact)
+                ));
+            }
+        }
+        csv
+    }
+
+    /// `summary_csv` writes a row for every rumour with how far and how fast it spread
+    ///
+    /// Arguments:
+    ///
+    /// * `population`: How many agents could have heard the rumours.
+    /// * `step`: How many seconds a tick is.
+    pub fn summary_csv(&self, population: usize, step: f32) -> String {
+        let mut csv = String::from(
+            "rumour,fact,origin,reached,fraction,max_hops,distorted,seconds_to_half,seconds_to_last\n",
+        );
+        for summary in self.summaries(population, step) {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                summary.id,
+                csv_field(&summary.fact),
+                csv_field(&summary.origin),
+                summary.reached,
+                summary.fraction,
+                summary.max_hops,
+                summary.distorted,
+                summary
+                    .seconds_to_half
+                    .map_or_else(String::new, |seconds| seconds.to_string()),
+                summary.seconds_to_last
+            ));
+        }
+        csv
+    }
+
+    /// `write_csv` writes `rumour_reach.csv` and `rumour_summary.csv` to a folder, making it if it
+    /// does not exist
+    ///
+    /// Arguments:
+    ///
+    /// * `folder`: The folder to write to.
+    /// * `population`: How many agents could have heard the rumours.
+    /// * `step`: How many seconds a tick is.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write_csv(
+        &self,
+        folder: impl AsRef<Path>,
+        population: usize,
+        step: f32,
+    ) -> anyhow::Result<()> {
+        let folder = folder.as_ref();
+        std::fs::create_dir_all(folder)?;
+        std::fs::write(folder.join("rumour_reach.csv"), self.reach_csv(step))?;
+        std::fs::write(
+            folder.join("rumour_summary.csv"),
+            self.summary_csv(population, step),
+        )?;
+        Ok(())
+    }
+
+    /// `write_csv` always fails in the browser, there is no file system to write to
+    #[cfg(target_arch = "wasm32")]
+    pub fn write_csv(
+        &self,
+        _folder: impl AsRef<Path>,
+        _population: usize,
+        _step: f32,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("exporting is not supported in the browser")
+    }
+}
+
+/// `csv_field` quotes a field if it has to be
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `RumourSettings` is a resource with the tuning of rumours.
+///
+/// Properties:
+///
+/// * `share_chance`: How likely an agent is to pass on each rumour it knows when an interaction goes well.
+/// * `distortion_chance`: How likely a rumour is to get changed every time it is passed on.
+/// * `min_confidence`: Agents do not believe or pass on rumours they believe less than this.
+/// * `location_error`: How far a distorted rumour can move a zone.
+pub struct RumourSettings {
+    pub share_chance: f32,
+    pub distortion_chance: f32,
+    pub min_confidence: f32,
+    pub location_error: f32,
+}
+
+impl Default for RumourSettings {
+    fn default() -> Self {
+        RumourSettings {
+            share_chance: 0.5,
+            distortion_chance: 0.1,
+            min_confidence: 0.1,
+            location_error: 300.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `StartRumour` is sent to make an agent start a rumour. agents that were turned away from a
+/// closed zone start one about it on their own.
+///
+/// Properties:
+///
+/// * `agent`: The agent starting the rumour, it needs `Rumours`.
+/// * `fact`: What the rumour says.
+pub struct StartRumour {
+    pub agent: Entity,
+    pub fact: Fact,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `RumourHeard` is sent when an agent hears a rumour for the first time.
+///
+/// Properties:
+///
+/// * `id`: The id of the rumour.
+/// * `teller`: The agent that passed it on.
+/// * `listener`: The agent that heard it.
+/// * `rumour`: The rumour as the listener knows it.
+pub struct RumourHeard {
+    pub id: u64,
+    pub teller: Entity,
+    pub listener: Entity,
+    pub rumour: KnownRumour,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `ExportRumourMetrics` is sent to write the `RumourMetrics` to CSV files, see
+/// `RumourMetrics::write_csv`.
+pub struct ExportRumourMetrics {
+    pub folder: String,
+}
+
+#[derive(Default, Debug, Clone)]
+/// `MetricsStatus` is a resource with the result of the last export, shown in the rumour window.
+pub struct MetricsStatus {
+    pub message: String,
+}
+
+/// `name_of` gets the name of an agent or zone
+fn name_of(
+    agents: &Query<(Entity, &Agent)>,
+    zones: &Query<&Zone>,
+    entity: Entity,
+) -> Option<String> {
+    agents
+        .get(entity)
+        .map(|(_, agent)| agent.name.clone())
+        .or_else(|_| zones.get(entity).map(|zone| zone.name.clone()))
+        .ok()
+}
+
+/// `start_rumours` starts a rumour for every `StartRumour` event and for every agent turned away
+/// from a closed zone, unless the agent already knows it
+///
+/// Arguments:
+///
+/// * `starts`: EventReader<StartRumour> - reads the rumours to start.
+/// * `refused`: EventReader<ZoneRefused> - reads the agents that were turned away from zones.
+/// * `rumours`: Query<&mut Rumours> - query containing agents that know rumours.
+/// * `agents`: Query<(Entity, &Agent)> - query containing every agent, used to name them.
+/// * `zones`: Query<&Zone> - query containing every zone, used to name them.
+/// * `metrics`: ResMut<RumourMetrics> - resource recording every rumour.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to timestamp rumours.
+fn start_rumours(
+    mut starts: EventReader<StartRumour>,
+    mut refused: EventReader<ZoneRefused>,
+    mut rumours: Query<&mut Rumours>,
+    agents: Query<(Entity, &Agent)>,
+    zones: Query<&Zone>,
+    mut metrics: ResMut<RumourMetrics>,
+    clock: Res<SimClock>,
+) {
+    let closed = refused
+        .iter()
+        .filter(|refusal| refusal.reason == Refusal::Closed)
+        .map(|refusal| StartRumour {
+            agent: refusal.agent,
+            fact: Fact::ZoneClosed { zone: refusal.zone },
+        });
+    let started: Vec<StartRumour> = starts.iter().copied().chain(closed).collect();
+    for StartRumour { agent, fact } in started {
+        let mut known = match rumours.get_mut(agent) {
+            Ok(known) if !known.knows_fact(&fact) => known,
+            _ => continue,
+        };
+        let origin = name_of(&agents, &zones, agent).unwrap_or_default();
+        let description = fact.describe(|entity| name_of(&agents, &zones, entity));
+        let id = metrics.start(description, origin, clock.tick);
+        known.known.insert(
+            id,
+            KnownRumour {
+                fact,
+                confidence: 1.0,
+                hops: 0,
+                heard: clock.tick,
+                distorted: false,
+            },
+        );
+    }
+}
+
+/// `spread_rumours` lets agents that finish an interaction on good terms pass on the rumours they
+/// believe. listeners believe a rumour as much as the teller did times how much they trust the
+/// teller, hearing it again makes them more sure. rumours can get distorted on the way
+///
+/// Arguments:
+///
+/// * `ended`: EventReader<InteractionEnded> - reads the interactions that are over.
+/// * `rumours`: Query<&mut Rumours> - query containing agents that know rumours.
+/// * `beliefs`: Query<&mut Beliefs> - query containing what agents believe about zones, changed by rumours about zones.
+/// * `agents`: Query<(Entity, &Agent)> - query containing every agent, used to name them and to mix up thieves.
+/// * `zones`: Query<&Zone> - query containing every zone, used to name them.
+/// * `amenities`: Query<(Entity, &Transform, Option<&Amenity>), With<Zone>> - query containing every zone, used to learn zones and to mix up closed zones.
+/// * `graph`: ResMut<SocialGraph> - resource containing how much agents trust each other, changed by rumours about thieves.
+/// * `rng`: ResMut<SimRng> - resource containing the random number generator, used to share and distort rumours.
+/// * `settings`: Res<RumourSettings> - resource containing the tuning of rumours.
+/// * `metrics`: ResMut<RumourMetrics> - resource recording every agent every rumour reached.
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day, used when learning zones.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to timestamp rumours.
+/// * `heard`: EventWriter<RumourHeard> - sends an event for every agent that hears a rumour for the first time.
+fn spread_rumours(
+    mut ended: EventReader<InteractionEnded>,
+    mut rumours: Query<&mut Rumours>,
+    mut beliefs: Query<&mut Beliefs>,
+    agents: Query<(Entity, &Agent)>,
+    zones: Query<&Zone>,
+    amenities: Query<(Entity, &Transform, Option<&Amenity>), With<Zone>>,
+    mut graph: ResMut<SocialGraph>,
+    mut rng: ResMut<SimRng>,
+    settings: Res<RumourSettings>,
+    mut metrics: ResMut<RumourMetrics>,
+    time_of_day: Res<TimeOfDay>,
+    clock: Res<SimClock>,
+    mut heard: EventWriter<RumourHeard>,
+) {
+    let mut zone_entities: Vec<Entity> = amenities.iter().map(|(entity, ..)| entity).collect();
+    zone_entities.sort();
+    let mut agent_entities: Vec<Entity> = agents.iter().map(|(entity, _)| entity).collect();
+    agent_entities.sort();
+
+    for event in ended.iter() {
+        if event.outcome.is_none() || event.kind == InteractionKind::Argument {
+            continue;
+        }
+
+        // both agents tell what they knew before the interaction, not what they just heard
+        let mut told = Vec::new();
+        for (teller, listener) in [
+            (event.initiator, event.partner),
+            (event.partner, event.initiator),
+        ] {
+            if let Ok(known) = rumours.get(teller) {
+                for (id, rumour) in known.known.iter() {
+                    if rumour.confidence >= settings.min_confidence
+                        && rng.gen::<f32>() < settings.share_chance
+                    {
+                        told.push((teller, listener, *id, *rumour));
+                    }
+                }
+            }
+        }
+
+        for (teller, listener, id, rumour) in told {
+            let trust = graph
+                .get(listener, teller)
+                .map_or(Relationship::default().trust, |relationship| {
+                    relationship.trust
+                });
+            let confidence = rumour.confidence * trust;
+            let mut known = match rumours.get_mut(listener) {
+                Ok(known) => known,
+                Err(_) => continue,
+            };
+            if let Some(existing) = known.known.get_mut(&id) {
+                existing.confidence = 1.0 - (1.0 - existing.confidence) * (1.0 - confidence);
+                continue;
+            }
+            if confidence < settings.min_confidence {
+                continue;
+            }
+
+            let mut fact = rumour.fact;
+            let mut distorted = rumour.distorted;
+            if rng.gen::<f32>() < settings.distortion_chance {
+                fact = fact.distort(
+                    &mut rng,
+                    &zone_entities,
+                    &agent_entities,
+                    settings.location_error,
+                );
+                distorted |= fact != rumour.fact;
+            }
+            let rumour = KnownRumour {
+                fact,
+                confidence,
+                hops: rumour.hops + 1,
+                heard: clock.tick,
+                distorted,
+            };
+            known.known.insert(id, rumour);
+            metrics.record(
+                id,
+                Reach {
+                    agent: name_of(&agents, &zones, listener).unwrap_or_default(),
+                    tick: clock.tick,
+                    hops: rumour.hops,
+                    confidence,
+                    distorted,
+                    fact: fact.describe(|entity| name_of(&agents, &zones, entity)),
+                },
+            );
+
+            match fact {
+                Fact::Stole { agent } if agent != listener => {
+                    let relationship = graph.get_or_insert(listener, agent);
+                    relationship.affinity =
+                        (relationship.affinity - THEFT_AFFINITY * confidence).clamp(-1.0, 1.0);
+                    relationship.trust =
+                        (relationship.trust - THEFT_TRUST * confidence).clamp(0.0, 1.0);
+                    relationship.update_kind();
+                }
+                Fact::ZoneClosed { zone } => {
+                    if let Ok(mut beliefs) = beliefs.get_mut(listener) {
+                        if let Some(belief) = beliefs.zones.get_mut(&zone) {
+                            belief.closed = true;
+                        }
+                    }
+                }
+                Fact::ZoneAt { zone, position } => {
+                    if let (Ok(mut beliefs), Ok(known_zone), Ok((_, _, amenity))) = (
+                        beliefs.get_mut(listener),
+                        zones.get(zone),
+                        amenities.get(zone),
+                    ) {
+                        if !beliefs.knows(zone) {
+                            beliefs.observe(
+                                zone,
+                                known_zone,
+                                position,
+                                amenity,
+                                time_of_day.hours,
+                                clock.tick,
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+            heard.send(RumourHeard {
+                id,
+                teller,
+                listener,
+                rumour,
+            });
+        }
+    }
+}
+
+/// `export_rumour_metrics` writes the `RumourMetrics` to CSV files for every
+/// `ExportRumourMetrics` event
+///
+/// Arguments:
+///
+/// * `exports`: EventReader<ExportRumourMetrics> - reads the folders to export to.
+/// * `population`: Query<(), With<Rumours>> - query containing every agent that can hear rumours.
+/// * `metrics`: Res<RumourMetrics> - resource recording every rumour.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to turn ticks into seconds.
+/// * `status`: ResMut<MetricsStatus> - resource containing the result of the export.
+fn export_rumour_metrics(
+    mut exports: EventReader<ExportRumourMetrics>,
+    population: Query<(), With<Rumours>>,
+    metrics: Res<RumourMetrics>,
+    clock: Res<SimClock>,
+    mut status: ResMut<MetricsStatus>,
+) {
+    for ExportRumourMetrics { folder } in exports.iter() {
+        let message =
+            match metrics.write_csv(folder, population.iter().count(), clock.delta_seconds()) {
+                Ok(()) => format!("exported rumour metrics to {}", folder),
+                Err(error) => format!("could not export rumour metrics to {}: {}", folder, error),
+            };
+        info!("{}", message);
+        status.message = message;
+    }
+}
+
+/// `render_rumour_window` renders a window with how far and how fast every rumour spread and a
+/// button to export the metrics
+///
+/// Arguments:
+///
+/// * `metrics`: Res<RumourMetrics> - resource recording every rumour.
+/// * `population`: Query<(), With<Rumours>> - query containing every agent that can hear rumours.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to turn ticks into seconds.
+/// * `status`: Res<MetricsStatus> - resource containing the result of the last export.
+/// * `exports`: EventWriter<ExportRumourMetrics> - sends an event when the export button is clicked.
+/// * `egui_context`: ResMut<EguiContext> - resource containing the context for the Egui user interface.
+fn render_rumour_window(
+    metrics: Res<RumourMetrics>,
+    population: Query<(), With<Rumours>>,
+    clock: Res<SimClock>,
+    status: Res<MetricsStatus>,
+    mut exports: EventWriter<ExportRumourMetrics>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    let population = population.iter().count();
+    egui::Window::new("Rumours").show(egui_context.ctx_mut(), |ui| {
+        if metrics.spreads.is_empty() {
+            ui.label("no rumours yet");
+        }
+        for summary in metrics.summaries(population, clock.delta_seconds()) {
+            ui.strong(format!("\"{}\" from {}", summary.fact, summary.origin));
+            ui.label(format!(
+                "reached {}/{} in {:.0}s, {} hops, {} distorted",
+                summary.reached,
+                population,
+                summary.seconds_to_last,
+                summary.max_hops,
+                summary.distorted
+            ));
+        }
+        if ui.button("Export metrics").clicked() {
+            exports.send(ExportRumourMetrics {
+                folder: DEFAULT_METRICS_FOLDER.to_string(),
+            });
+        }
+        if !status.message.is_empty() {
+            ui.label(&status.message);
+        }
+    });
+}
@@ -0,0 +1,1012 @@
+use crate::agent::{Agent, AgentBundle, Faction};
+use crate::behaviour::{BehaviourTree, BehaviourTreeHandle};
+use crate::clock::{SimClock, TimeOfDay};
+use crate::editor::ZoneEditor;
+use crate::flow_field::FlowFields;
+use crate::goap::{GoapActions, GoapAgent, PlanQueue};
+use crate::interaction::{InteractionCooldown, SocialInteraction};
+use crate::memory::{Beliefs, EpisodicMemory, ZoneBelief};
+use crate::navigation::{Obstacle, ObstacleBundle, PathCache};
+use crate::needs::{Amenity, NeedKind, Needs};
+use crate::perception::{Perceived, Perception};
+use crate::relationships::{Relationship, SocialGraph};
+use crate::rng::{RngState, SimRng, DEFAULT_SEED};
+use crate::rumour::{RumourMetrics, Rumours};
+use crate::scenario::ObstacleDefinition;
+use crate::schedule::DailySchedule;
+use crate::steering::{MaxAcceleration, MaxSpeed, Velocity};
+use crate::utility::{Activity, UtilityBrain};
+use crate::windows::UiStates;
+use crate::zone::{
+    AgentZoneMapping, OpeningHours, Queued, Zone, ZoneAccess, ZoneBundle, ZoneColour, ZoneKind,
+    ZoneQueues, ZoneShape,
+};
+use crate::GameState;
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+/// the version of the save files written by this build, bump it and add a migration to
+/// `MIGRATIONS` whenever `SaveFile` changes
+pub const SAVE_VERSION: u32 = 9;
+
+/// the file the save window writes to and reads from until another path is typed in
+pub const DEFAULT_SAVE_PATH: &str = "saves/simulation.save.json";
+
+/// `MIGRATIONS` upgrades old save files one version at a time, `MIGRATIONS[0]` turns a version 1
+/// file into a version 2 file and so on. they work on the JSON of the file before it is read, so
+/// the old versions of `SaveFile` do not have to be kept around
+const MIGRATIONS: &[fn(&mut Value)] = &[
+    add_tick_and_rng,
+    add_day,
+    add_schedules,
+    add_relationships,
+    add_interactions,
+    add_memories,
+    add_rumours,
+    add_perception,
+];
+
+/// `add_tick_and_rng` upgrades version 1 files, which were written before the simulation had a
+/// tick counter and a random number generator. both start from scratch
+fn add_tick_and_rng(value: &mut Value) {
+    value["tick"] = Value::from(0);
+    // `Value` can not hold the u128 position, so the default state is written out by hand
+    value["rng"] = serde_json::json!({ "seed": DEFAULT_SEED, "position": 0 });
+}
+
+/// `add_day` upgrades version 2 files, which were written before the clock counted days. they
+/// carry on from the first day
+fn add_day(value: &mut Value) {
+    value["day"] = Value::from(0);
+}
+
+/// `add_schedules` upgrades version 3 files, which were written before agents had schedules. the
+/// agents carry on without one
+fn add_schedules(value: &mut Value) {
+    if let Some(agents) = value["agents"].as_array_mut() {
+        for agent in agents {
+            agent["schedule"] = Value::Null;
+        }
+    }
+}
+
+/// `add_relationships` upgrades version 4 files, which were written before agents knew each
+/// other. everyone starts as strangers
+fn add_relationships(value: &mut Value) {
+    value["relationships"] = Value::Array(Vec::new());
+}
+
+/// `add_interactions` upgrades version 5 files, which were written before agents interacted.
+/// nobody is in the middle of an interaction or waiting to start the next one
+fn add_interactions(value: &mut Value) {
+    if let Some(agents) = value["agents"].as_array_mut() {
+        for agent in agents {
+            agent["interaction"] = Value::Null;
+            agent["interaction_cooldown"] = Value::Null;
+        }
+    }
+}
+
+/// `add_memories` upgrades version 6 files, which were written before agents had memories and
+/// beliefs. agents keep knowing every zone as it is, like they did before
+fn add_memories(value: &mut Value) {
+    if let Some(agents) = value["agents"].as_array_mut() {
+        for agent in agents {
+            agent["memory"] = Value::Null;
+            agent["beliefs"] = Value::Null;
+        }
+    }
+}
+
+/// `add_rumours` upgrades version 7 files, which were written before agents told each other
+/// rumours. nobody knows a rumour and no zone is believed to be closed
+fn add_rumours(value: &mut Value) {
+    if let Some(agents) = value["agents"].as_array_mut() {
+        for agent in agents {
+            agent["rumours"] = Value::Null;
+            if let Some(beliefs) = agent["beliefs"].as_array_mut() {
+                for belief in beliefs {
+                    belief["closed"] = Value::from(false);
+                }
+            }
+        }
+    }
+    value["rumours"] = serde_json::json!({ "spreads": [] });
+}
+
+/// `add_perception` upgrades version 8 files, which were written before agents had a field of
+/// view. agents carry on seeing everything around them
+fn add_perception(value: &mut Value) {
+    if let Some(agents) = value["agents"].as_array_mut() {
+        for agent in agents {
+            agent["perception"] = Value::Null;
+        }
+    }
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    /// `build` saves and loads the simulation when a `SaveSimulation` or `LoadSimulation` event is
+    /// sent
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SaveStatus>()
+            .add_event::<SaveSimulation>()
+            .add_event::<LoadSimulation>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(handle_save_requests.exclusive_system()),
+            );
+    }
+}
+
+pub struct SaveVisualsPlugin;
+
+impl Plugin for SaveVisualsPlugin {
+    /// `build` adds the window with the save and load buttons
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(render_save_window),
+        );
+    }
+}
+
+/// `entity_bits` writes an entity as a number in save files. the number is only used to find the
+/// entity again when the file is loaded, loaded entities get new ids
+pub mod entity_bits {
+    use bevy::prelude::Entity;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(entity.to_bits())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+        Ok(Entity::from_bits(u64::deserialize(deserializer)?))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `SaveSimulation` asks for the simulation to be saved to `path` at the start of the next frame.
+pub struct SaveSimulation {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `LoadSimulation` asks for the simulation to be replaced by the save file at `path` at the start
+/// of the next frame.
+pub struct LoadSimulation {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `SaveStatus` is a resource with the file the save window uses and what happened to the last
+/// save or load.
+///
+/// Properties:
+///
+/// * `path`: The file the save window writes to and reads from.
+/// * `message`: The result of the last save or load, empty before the first one.
+pub struct SaveStatus {
+    pub path: String,
+    pub message: String,
+}
+
+impl Default for SaveStatus {
+    fn default() -> Self {
+        SaveStatus {
+            path: DEFAULT_SAVE_PATH.to_string(),
+            message: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `SavedAgent` is an agent and what its brain was doing when the simulation was saved.
+///
+/// Properties:
+///
+/// * `id`: The entity of the agent when it was saved, other parts of the file refer to the agent by it.
+/// * `name`: The name of the agent.
+/// * `position`: Where the agent was.
+/// * `rotation`: Which way the agent was facing.
+/// * `destination`: Where the agent was going.
+/// * `needs`: The level of every need, if the agent had needs.
+/// * `velocity`: How the agent was moving, if it could move.
+/// * `max_speed`: The fastest the agent can move, if it had a limit.
+/// * `max_acceleration`: How quickly the agent can change its velocity, if it had a limit.
+/// * `faction`: The faction the agent belongs to, if any.
+/// * `brain`: The state of the utility AI, if the agent had one.
+/// * `activity`: The name of the action the agent was doing, if it had an `Activity`.
+/// * `activity_target`: The id of the zone the action was taking the agent to.
+/// * `goap`: The facts, goals and plan of the agent if it uses the GOAP planner.
+/// * `behaviour_tree`: The tree and the state of its nodes if the agent uses a behaviour tree.
+/// * `behaviour_tree_path`: The `.bt.ron` file the tree came from, so edits to it are picked up again.
+/// * `queued`: The id of the zone the agent was queueing for and where it was going.
+/// * `schedule`: The daily routine of the agent, if it has one.
+/// * `interaction`: The interaction the agent was taking part in, its partner written as its id.
+/// * `interaction_cooldown`: How many seconds the agent had to wait before its next interaction.
+/// * `memory`: What the agent remembered, with agents and zones written as their ids.
+/// * `beliefs`: The zones the agent knew about, `None` if the agent knew every zone.
+/// * `rumours`: The rumours the agent knew, with agents and zones written as their ids.
+/// * `perception`: How far and where the agent could see and hear, if it had a field of view.
+pub struct SavedAgent {
+    pub id: u64,
+    pub name: String,
+    pub position: Vec2,
+    pub rotation: Quat,
+    pub destination: Option<Vec2>,
+    pub needs: Option<BTreeMap<NeedKind, f32>>,
+    pub velocity: Option<Vec2>,
+    pub max_speed: Option<f32>,
+    pub max_acceleration: Option<f32>,
+    pub faction: Option<String>,
+    pub brain: Option<UtilityBrain>,
+    pub activity: Option<String>,
+    pub activity_target: Option<u64>,
+    pub goap: Option<GoapAgent>,
+    pub behaviour_tree: Option<BehaviourTree>,
+    pub behaviour_tree_path: Option<String>,
+    pub queued: Option<(u64, Vec2)>,
+    pub schedule: Option<DailySchedule>,
+    pub interaction: Option<SocialInteraction>,
+    pub interaction_cooldown: Option<f32>,
+    pub memory: Option<EpisodicMemory>,
+    pub beliefs: Option<Vec<SavedBelief>>,
+    pub rumours: Option<Rumours>,
+    pub perception: Option<Perception>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `SavedAccess` is who may enter a zone, with agents written as their ids.
+pub enum SavedAccess {
+    Public,
+    AllowList {
+        agents: Vec<u64>,
+        factions: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `SavedZone` is a zone as it was when the simulation was saved.
+///
+/// Properties:
+///
+/// * `id`: The entity of the zone when it was saved, other parts of the file refer to the zone by it.
+/// * `name`: The name of the zone.
+/// * `position`: The center of the zone.
+/// * `shape`: The area the zone covers.
+/// * `kind`: What the zone is for.
+/// * `capacity`: How many agents fit in the zone.
+/// * `hours`: When the zone is open.
+/// * `access`: Who may enter the zone.
+/// * `parent`: The id of the zone this zone is part of.
+/// * `colour`: The colour of the zone as red, green, blue and alpha.
+/// * `amenity`: The needs the zone satisfies.
+pub struct SavedZone {
+    pub id: u64,
+    pub name: String,
+    pub position: Vec2,
+    pub shape: ZoneShape,
+    pub kind: ZoneKind,
+    pub capacity: Option<usize>,
+    pub hours: Option<OpeningHours>,
+    pub access: SavedAccess,
+    pub parent: Option<u64>,
+    pub colour: Option<[f32; 4]>,
+    pub amenity: Option<Amenity>,
+}
+
+impl SavedZone {
+    /// `new` writes down a zone
+    ///
+    /// Arguments:
+    ///
+    /// * `entity`: The zone entity.
+    /// * `zone`: The zone.
+    /// * `position`: The center of the zone.
+    /// * `colour`: The colour of the zone, if it has its own.
+    /// * `amenity`: The needs the zone satisfies, if any.
+    pub fn new(
+        entity: Entity,
+        zone: &Zone,
+        position: Vec2,
+        colour: Option<&ZoneColour>,
+        amenity: Option<&Amenity>,
+    ) -> Self {
+        SavedZone {
+            id: entity.to_bits(),
+            name: zone.name.clone(),
+            position,
+            shape: zone.shape.clone(),
+            kind: zone.kind,
+            capacity: zone.capacity,
+            hours: zone.hours,
+            access: match &zone.access {
+                ZoneAccess::Public => SavedAccess::Public,
+                ZoneAccess::AllowList { agents, factions } => SavedAccess::AllowList {
+                    agents: agents.iter().map(|agent| agent.to_bits()).collect(),
+                    factions: factions.clone(),
+                },
+            },
+            parent: zone.parent.map(Entity::to_bits),
+            colour: colour.map(|colour| colour.0.as_rgba_f32()),
+            amenity: amenity.cloned(),
+        }
+    }
+
+    /// `zone` turns the saved zone back into a zone
+    ///
+    /// Arguments:
+    ///
+    /// * `entity_of`: Gets the new entity of an agent or zone by its id, `None` if it is gone.
+    pub fn zone(&self, entity_of: impl Fn(&u64) -> Option<Entity>) -> Zone {
+        Zone {
+            name: self.name.clone(),
+            shape: self.shape.clone(),
+            kind: self.kind,
+            capacity: self.capacity,
+            hours: self.hours,
+            access: match &self.access {
+                SavedAccess::Public => ZoneAccess::Public,
+                SavedAccess::AllowList { agents, factions } => ZoneAccess::AllowList {
+                    agents: agents.iter().filter_map(&entity_of).collect(),
+                    factions: factions.clone(),
+                },
+            },
+            parent: self.parent.as_ref().and_then(&entity_of),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `SavedBelief` is what an agent believed about a zone when the simulation was saved.
+///
+/// Properties:
+///
+/// * `zone`: The zone as the agent last saw it, its id is the zone entity when it was saved.
+/// * `seen`: The tick the agent last saw the zone on.
+/// * `closed`: If the agent had heard the zone is closed since it last saw it.
+pub struct SavedBelief {
+    pub zone: SavedZone,
+    pub seen: u64,
+    pub closed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `SaveFile` is everything needed to carry on a simulation where it was saved. files are written
+/// as JSON and upgraded with `MIGRATIONS` when they were saved by an older version.
+///
+/// Properties:
+///
+/// * `version`: The `SAVE_VERSION` the file was written with.
+/// * `tick`: How many ticks the simulation had run.
+/// * `rng`: Where the random number generator of the simulation was.
+/// * `day`: How many in-world days had passed.
+/// * `time_of_day`: The hour of the in-world clock.
+/// * `agents`: Every agent.
+/// * `zones`: Every zone.
+/// * `obstacles`: Every obstacle.
+/// * `memberships`: Every agent in every zone as agent id, zone id and seconds spent inside, in the order they entered.
+/// * `queues`: The ids of the agents waiting outside every full zone, first in line first.
+/// * `admitted`: The agents that were let into a zone but had not walked in yet, as agent id and zone id.
+/// * `relationships`: How every agent feels about every agent it knows, as agent id, the id of the other agent and the relationship.
+/// * `rumours`: Every agent every rumour reached.
+pub struct SaveFile {
+    pub version: u32,
+    pub tick: u64,
+    pub rng: RngState,
+    pub day: u32,
+    pub time_of_day: f32,
+    pub agents: Vec<SavedAgent>,
+    pub zones: Vec<SavedZone>,
+    pub obstacles: Vec<ObstacleDefinition>,
+    pub memberships: Vec<(u64, u64, f32)>,
+    pub queues: Vec<(u64, Vec<u64>)>,
+    pub admitted: Vec<(u64, u64)>,
+    pub relationships: Vec<(u64, u64, Relationship)>,
+    pub rumours: RumourMetrics,
+}
+
+impl SaveFile {
+    /// `capture` saves the agents, zones, obstacles, zone memberships, relationships, rumours,
+    /// clock and random number generator of a world. agents need an `Agent` and a `Transform`, the
+    /// rest of the components of an `AgentBundle` are saved if the agent has them
+    ///
+    /// Arguments:
+    ///
+    /// * `world`: The world to save.
+    pub fn capture(world: &mut World) -> Self {
+        let mut agents_query = world.query::<(
+            Entity,
+            (
+                &Agent,
+                &Transform,
+                Option<&Needs>,
+                Option<&Velocity>,
+                Option<&MaxSpeed>,
+                Option<&MaxAcceleration>,
+            ),
+            (Option<&UtilityBrain>, Option<&Activity>, Option<&Faction>),
+            (
+                Option<&GoapAgent>,
+                Option<&BehaviourTree>,
+                Option<&BehaviourTreeHandle>,
+                Option<&Queued>,
+                Option<&DailySchedule>,
+            ),
+            (
+                Option<&SocialInteraction>,
+                Option<&InteractionCooldown>,
+                Option<&EpisodicMemory>,
+                Option<&Beliefs>,
+                Option<&Rumours>,
+                Option<&Perception>,
+            ),
+        )>();
+        let asset_server = world.get_resource::<AssetServer>();
+        let mut agents: Vec<SavedAgent> = agents_query
+            .iter(world)
+            .map(
+                |(
+                    entity,
+                    (agent, transform, needs, velocity, max_speed, max_acceleration),
+                    (brain, activity, faction),
+                    (goap, tree, tree_handle, queued, schedule),
+                    (interaction, cooldown, memory, beliefs, rumours, perception),
+                )| SavedAgent {
+                    id: entity.to_bits(),
+                    name: agent.name.clone(),
+                    position: transform.translation.truncate(),
+                    rotation: transform.rotation,
+                    destination: agent.destination,
+                    needs: needs.map(|needs| {
+                        NeedKind::ALL
+                            .iter()
+                            .map(|need| (*need, needs.get(*need)))
+                            .collect()
+                    }),
+                    velocity: velocity.map(|velocity| velocity.0),
+                    max_speed: max_speed.map(|max_speed| max_speed.0),
+                    max_acceleration: max_acceleration.map(|max_acceleration| max_acceleration.0),
+                    faction: faction.map(|faction| faction.0.clone()),
+                    brain: brain.cloned(),
+                    activity: activity.map(|activity| activity.action.clone()),
+                    activity_target: activity
+                        .and_then(|activity| activity.target)
+                        .map(Entity::to_bits),
+                    goap: goap.cloned(),
+                    behaviour_tree: tree.cloned(),
+                    behaviour_tree_path: tree_handle.and_then(|handle| {
+                        let path = asset_server?.get_handle_path(&handle.0)?;
+                        Some(path.path().to_string_lossy().into_owned())
+                    }),
+                    queued: queued.map(|queued| (queued.zone.to_bits(), queued.destination)),
+                    schedule: schedule.cloned(),
+                    interaction: interaction.copied(),
+                    interaction_cooldown: cooldown.map(|cooldown| cooldown.0),
+                    memory: memory.cloned(),
+                    beliefs: beliefs.map(|beliefs| {
+                        beliefs
+                            .zones
+                            .iter()
+                            .map(|(zone, belief)| SavedBelief {
+                                zone: SavedZone::new(
+                                    *zone,
+                                    &belief.zone,
+                                    belief.center,
+                                    None,
+                                    belief.amenity.as_ref(),
+                                ),
+                                seen: belief.seen,
+                                closed: belief.closed,
+                            })
+                            .collect()
+                    }),
+                    rumours: rumours.cloned(),
+                    perception: perception.copied(),
+                },
+            )
+            .collect();
+
+        let mut zones_query = world.query::<(
+            Entity,
+            &Zone,
+            &Transform,
+            Option<&ZoneColour>,
+            Option<&Amenity>,
+        )>();
+        let mut zones: Vec<SavedZone> = zones_query
+            .iter(world)
+            .map(|(entity, zone, transform, colour, amenity)| {
+                SavedZone::new(
+                    entity,
+                    zone,
+                    transform.translation.truncate(),
+                    colour,
+                    amenity,
+                )
+            })
+            .collect();
+
+        // entities are stored in no particular order, sorting keeps files of the same world alike
+        agents.sort_by_key(|agent| agent.id);
+        zones.sort_by_key(|zone| zone.id);
+
+        let mut obstacles_query = world.query::<(&Obstacle, &Transform)>();
+        let obstacles = obstacles_query
+            .iter(world)
+            .map(|(obstacle, transform)| ObstacleDefinition {
+                position: transform.translation.truncate(),
+                width: obstacle.width,
+                height: obstacle.height,
+            })
+            .collect();
+
+        let mut memberships: Vec<(u64, u64, f32)> = world
+            .get_resource::<AgentZoneMapping>()
+            .map(|mapping| {
+                mapping
+                    .memberships()
+                    .into_iter()
+                    .map(|(agent, zone, dwell)| (agent.to_bits(), zone.to_bits(), dwell))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // a stable sort keeps the order agents entered each zone in
+        memberships.sort_by_key(|(_, zone, _)| *zone);
+        let (mut queues, mut admitted): (Vec<(u64, Vec<u64>)>, Vec<(u64, u64)>) = world
+            .get_resource::<ZoneQueues>()
+            .map(|queues| {
+                (
+                    queues
+                        .queues()
+                        .map(|(zone, agents)| {
+                            (zone.to_bits(), agents.map(Entity::to_bits).collect())
+                        })
+                        .collect(),
+                    queues
+                        .admitted()
+                        .map(|(agent, zone)| (agent.to_bits(), zone.to_bits()))
+                        .collect(),
+                )
+            })
+            .unwrap_or_default();
+        queues.sort_by_key(|(zone, _)| *zone);
+        admitted.sort();
+        let mut relationships: Vec<(u64, u64, Relationship)> = world
+            .get_resource::<SocialGraph>()
+            .map(|graph| {
+                graph
+                    .relationships()
+                    .into_iter()
+                    .map(|(from, to, relationship)| (from.to_bits(), to.to_bits(), relationship))
+                    .collect()
+            })
+            .unwrap_or_default();
+        relationships.sort_by_key(|(from, to, _)| (*from, *to));
+
+        SaveFile {
+            version: SAVE_VERSION,
+            tick: world
+                .get_resource::<SimClock>()
+                .map(|clock| clock.tick)
+                .unwrap_or_default(),
+            day: world
+                .get_resource::<SimClock>()
+                .map(|clock| clock.day)
+                .unwrap_or_default(),
+            rng: world
+                .get_resource::<SimRng>()
+                .cloned()
+                .unwrap_or_default()
+                .state(),
+            time_of_day: world
+                .get_resource::<TimeOfDay>()
+                .copied()
+                .unwrap_or_default()
+                .hours,
+            agents,
+            zones,
+            obstacles,
+            memberships,
+            queues,
+            admitted,
+            relationships,
+            rumours: world
+                .get_resource::<RumourMetrics>()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// `restore` replaces every agent, zone and obstacle of a world with the ones in the file and
+    /// puts the zone memberships, relationships, the clock and the random number generator back
+    /// as they were
+    ///
+    /// Arguments:
+    ///
+    /// * `world`: The world to load the file into.
+    ///
+    /// Returns:
+    ///
+    /// The new entity of every agent and zone by the id it has in the file.
+    pub fn restore(&self, world: &mut World) -> HashMap<u64, Entity> {
+        let mut old_query =
+            world.query_filtered::<Entity, Or<(With<Agent>, With<Zone>, With<Obstacle>)>>();
+        let old: Vec<Entity> = old_query.iter(world).collect();
+        for entity in old {
+            world.entity_mut(entity).despawn_recursive();
+        }
+
+        let ids: HashMap<u64, Entity> = self
+            .agents
+            .iter()
+            .map(|agent| agent.id)
+            .chain(self.zones.iter().map(|zone| zone.id))
+            .map(|id| (id, world.spawn().id()))
+            .collect();
+        let entity_of = |id: &u64| ids.get(id).copied();
+
+        let asset_server = world.get_resource::<AssetServer>().cloned();
+        for saved in self.agents.iter() {
+            // only the parts of the bundle the agent had are put back
+            let bundle = AgentBundle::new(saved.name.clone(), saved.position);
+            let mut transform = bundle.transform;
+            transform.local.rotation = saved.rotation;
+            let mut agent = bundle.agent;
+            agent.destination = saved.destination;
+
+            let mut entity = world.entity_mut(ids[&saved.id]);
+            entity.insert(agent).insert_bundle(transform);
+            if let Some(levels) = &saved.needs {
+                let mut needs = bundle.needs;
+                for (need, level) in levels.iter() {
+                    needs.set(*need, *level);
+                }
+                entity.insert(needs);
+            }
+            if let Some(velocity) = saved.velocity {
+                entity.insert(Velocity(velocity));
+            }
+            if let Some(max_speed) = saved.max_speed {
+                entity.insert(MaxSpeed(max_speed));
+            }
+            if let Some(max_acceleration) = saved.max_acceleration {
+                entity.insert(MaxAcceleration(max_acceleration));
+            }
+            if let Some(brain) = &saved.brain {
+                entity.insert(brain.clone());
+            }
+            if let Some(action) = &saved.activity {
+                entity.insert(Activity {
+                    action: action.clone(),
+                    target: saved.activity_target.as_ref().and_then(entity_of),
+                });
+            }
+            if let Some(faction) = &saved.faction {
+                entity.insert(Faction(faction.clone()));
+            }
+            if let Some(goap) = &saved.goap {
+                entity.insert(goap.clone());
+            }
+            if let Some(tree) = &saved.behaviour_tree {
+                let mut tree = tree.clone();
+                tree.map_entities(|old| entity_of(&old.to_bits()));
+                entity.insert(tree);
+            }
+            if let (Some(path), Some(asset_server)) = (&saved.behaviour_tree_path, &asset_server) {
+                entity.insert(BehaviourTreeHandle(asset_server.load(path.as_str())));
+            }
+            if let Some((zone, destination)) = saved.queued {
+                if let Some(zone) = entity_of(&zone) {
+                    entity.insert(Queued { zone, destination });
+                }
+            }
+            if let Some(schedule) = &saved.schedule {
+                entity.insert(schedule.clone());
+            }
+            if let Some(interaction) = saved.interaction {
+                if let Some(partner) = entity_of(&interaction.partner.to_bits()) {
+                    entity.insert(SocialInteraction {
+                        partner,
+                        ..interaction
+                    });
+                }
+            }
+            if let Some(cooldown) = saved.interaction_cooldown {
+                entity.insert(InteractionCooldown(cooldown));
+            }
+            if let Some(memory) = &saved.memory {
+                let mut memory = memory.clone();
+                memory.map_entities(|old| entity_of(&old.to_bits()));
+                entity.insert(memory);
+            }
+            if let Some(saved_beliefs) = &saved.beliefs {
+                // beliefs about zones that were already gone when saving are lost
+                let mut beliefs = Beliefs::default();
+                for belief in saved_beliefs.iter() {
+                    if let Some(zone) = entity_of(&belief.zone.id) {
+                        beliefs.zones.insert(
+                            zone,
+                            ZoneBelief {
+                                zone: belief.zone.zone(entity_of),
+                                center: belief.zone.position,
+                                amenity: belief.zone.amenity.clone(),
+                                seen: belief.seen,
+                                closed: belief.closed,
+                            },
+                        );
+                    }
+                }
+                entity.insert(beliefs);
+            }
+            if let Some(rumours) = &saved.rumours {
+                let mut rumours = rumours.clone();
+                rumours.map_entities(|old| entity_of(&old.to_bits()));
+                entity.insert(rumours);
+            }
+            if let Some(perception) = saved.perception {
+                // what the agent perceived is worked out again on the next tick
+                entity.insert(perception).insert(Perceived::default());
+            }
+        }
+
+        for saved in self.zones.iter() {
+            let mut bundle =
+                ZoneBundle::with_shape(saved.name.clone(), saved.position, saved.shape.clone());
+            bundle.zone = saved.zone(entity_of);
+
+            let mut entity = world.entity_mut(ids[&saved.id]);
+            entity.insert_bundle(bundle);
+            if let Some([r, g, b, a]) = saved.colour {
+                entity.insert(ZoneColour(Color::rgba(r, g, b, a)));
+            }
+            if let Some(amenity) = &saved.amenity {
+                entity.insert(amenity.clone());
+            }
+        }
+
+        for obstacle in self.obstacles.iter() {
+            world.spawn().insert_bundle(ObstacleBundle::new(
+                obstacle.position,
+                obstacle.width,
+                obstacle.height,
+            ));
+        }
+
+        let parents = self
+            .zones
+            .iter()
+            .filter_map(|zone| Some((entity_of(&zone.id)?, entity_of(zone.parent.as_ref()?)?)))
+            .collect();
+        let memberships: Vec<(Entity, Entity, f32)> = self
+            .memberships
+            .iter()
+            .filter_map(|(agent, zone, dwell)| Some((entity_of(agent)?, entity_of(zone)?, *dwell)))
+            .collect();
+        world.insert_resource(AgentZoneMapping::restore(memberships, parents));
+        // the steps of saved plans point into the actions made for the saved zones
+        world.insert_resource(GoapActions::for_zones(
+            self.zones
+                .iter()
+                .map(|zone| (zone.name.as_str(), zone.kind)),
+        ));
+        world.insert_resource(ZoneQueues::restore(
+            self.queues.iter().filter_map(|(zone, agents)| {
+                Some((
+                    entity_of(zone)?,
+                    agents.iter().filter_map(entity_of).collect(),
+                ))
+            }),
+            self.admitted
+                .iter()
+                .filter_map(|(agent, zone)| Some((entity_of(agent)?, entity_of(zone)?))),
+        ));
+        let mut graph = SocialGraph::default();
+        for (from, to, relationship) in self.relationships.iter() {
+            if let (Some(from), Some(to)) = (entity_of(from), entity_of(to)) {
+                graph.set(from, to, *relationship);
+            }
+        }
+        world.insert_resource(graph);
+        world.insert_resource(self.rumours.clone());
+        world.insert_resource(TimeOfDay {
+            hours: self.time_of_day,
+        });
+        let mut clock = world.get_resource_or_insert_with(SimClock::default);
+        clock.tick = self.tick;
+        clock.day = self.day;
+        world.insert_resource(SimRng::from_state(self.rng));
+
+        // everything below refers to entities that are gone and is rebuilt as the simulation runs
+        world.insert_resource(PlanQueue::default());
+        world.insert_resource(FlowFields::default());
+        if let Some(mut cache) = world.get_resource_mut::<PathCache>() {
+            cache.clear();
+        }
+        if let Some(mut ui_states) = world.get_resource_mut::<UiStates>() {
+            ui_states.agents.clear();
+        }
+        if let Some(mut editor) = world.get_resource_mut::<ZoneEditor>() {
+            editor.selected = None;
+            editor.drag = None;
+        }
+
+        ids
+    }
+
+    /// `state_hash` hashes everything in the file, two worlds with the same hash are in the same
+    /// state. runs with the same seed and scenario have the same hash after the same number of ticks
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(self).unwrap().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// `to_json` writes the file as pretty printed JSON
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// `from_json` reads a save file, upgrading it first if it was written by an older version
+    ///
+    /// Arguments:
+    ///
+    /// * `json`: The contents of the file.
+    ///
+    /// Returns:
+    ///
+    /// The save file, or why it can not be read.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let mut value: Value = serde_json::from_str(json)?;
+        migrate(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// `write` writes the file to `path`, making the folders it goes in if they do not exist
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// `write` always fails in the browser, there is no file system to write to
+    #[cfg(target_arch = "wasm32")]
+    pub fn write(&self, _path: impl AsRef<Path>) -> anyhow::Result<()> {
+        anyhow::bail!("saving is not supported in the browser")
+    }
+
+    /// `read` reads the save file at `path`, see `from_json`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        SaveFile::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    /// `read` always fails in the browser, there is no file system to read from
+    #[cfg(target_arch = "wasm32")]
+    pub fn read(_path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        anyhow::bail!("loading is not supported in the browser")
+    }
+}
+
+/// `migrate` upgrades the JSON of a save file to `SAVE_VERSION`
+///
+/// Arguments:
+///
+/// * `value`: The JSON of the save file, changed in place.
+///
+/// Returns:
+///
+/// Why the file can not be upgraded, if it can not.
+pub fn migrate(value: &mut Value) -> anyhow::Result<()> {
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow::anyhow!("the file has no version, it is not a save file"))?;
+    if version == 0 || version > SAVE_VERSION as u64 {
+        anyhow::bail!(
+            "the file is version {}, this build reads save files up to version {}",
+            version,
+            SAVE_VERSION
+        );
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        migration(value);
+        value["version"] = Value::from(from as u64 + 2);
+    }
+    Ok(())
+}
+
+/// `handle_save_requests` saves and loads the simulation for every `SaveSimulation` and
+/// `LoadSimulation` event, it needs the whole world so it runs on its own
+///
+/// Arguments:
+///
+/// * `world`: &mut World - the world to save or load into.
+fn handle_save_requests(world: &mut World) {
+    let saves: Vec<SaveSimulation> = world
+        .resource_mut::<Events<SaveSimulation>>()
+        .drain()
+        .collect();
+    let loads: Vec<LoadSimulation> = world
+        .resource_mut::<Events<LoadSimulation>>()
+        .drain()
+        .collect();
+
+    for SaveSimulation { path } in saves {
+        let message = match SaveFile::capture(world).write(&path) {
+            Ok(()) => format!("saved to {}", path),
+            Err(error) => format!("could not save to {}: {}", path, error),
+        };
+        info!("{}", message);
+        world.resource_mut::<SaveStatus>().message = message;
+    }
+    for LoadSimulation { path } in loads {
+        let message = match SaveFile::read(&path) {
+            Ok(save) => {
+                save.restore(world);
+                format!("loaded {}", path)
+            }
+            Err(error) => format!("could not load {}: {}", path, error),
+        };
+        info!("{}", message);
+        world.resource_mut::<SaveStatus>().message = message;
+    }
+}
+
+/// `render_save_window` draws the window to save the simulation to a file and load it back
+///
+/// Arguments:
+///
+/// * `status`: ResMut<SaveStatus> - resource containing the save file path and the result of the last save or load.
+/// * `saves`: EventWriter<SaveSimulation> - sends an event when the save button is pressed.
+/// * `loads`: EventWriter<LoadSimulation> - sends an event when the load button is pressed.
+/// * `egui_context`: ResMut<EguiContext> - resource containing the context for the Egui user interface.
+fn render_save_window(
+    mut status: ResMut<SaveStatus>,
+    mut saves: EventWriter<SaveSimulation>,
+    mut loads: EventWriter<LoadSimulation>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    egui::Window::new("Save").show(egui_context.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label("file");
+            ui.text_edit_singleline(&mut status.path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                saves.send(SaveSimulation {
+                    path: status.path.clone(),
+                });
+            }
+            if ui.button("Load").clicked() {
+                loads.send(LoadSimulation {
+                    path: status.path.clone(),
+                });
+            }
+        });
+        if !status.message.is_empty() {
+            ui.label(&status.message);
+        }
+    });
+}
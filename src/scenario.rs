@@ -0,0 +1,652 @@
+use crate::agent::{Agent, AgentBundle, Faction};
+use crate::behaviour::BehaviourTreeHandle;
+use crate::clock::{SimClock, TimeOfDay};
+use crate::goap::{GoapActions, GoapAgent};
+use crate::memory::{Beliefs, EpisodicMemory, LearnZones};
+use crate::navigation::{Obstacle, ObstacleBundle};
+use crate::needs::NeedKind;
+use crate::perception::{Perceived, Perception};
+use crate::relationships::{Relationship, RelationshipKind, SocialGraph};
+use crate::rng::SimRng;
+use crate::rumour::{Fact, KnownRumour, RumourMetrics, Rumours};
+use crate::schedule::DailySchedule;
+use crate::zone::{spawn_zone_definitions, validate_zone_definitions, Zone, ZoneDefinition};
+use crate::GameState;
+use bevy::asset::{AssetLoader, BoxedFuture, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    /// `build` spawns the `ActiveScenario` when the simulation starts. nothing is spawned unless a
+    /// scenario is picked, by inserting `ActiveScenario(Some(..))` before `GameState::Playing` is
+    /// entered
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveScenario>().add_system_set(
+            SystemSet::on_enter(GameState::Playing).with_system(spawn_active_scenario),
+        );
+    }
+}
+
+/// the village scenario, also shipped in `assets/scenarios`
+const VILLAGE_SCENARIO: &str = include_str!("../assets/scenarios/village.scenario.ron");
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// `BrainDefinition` is what decides what an agent does.
+pub enum BrainDefinition {
+    /// the utility AI scores every action and picks the best one
+    #[default]
+    Utility,
+    /// the GOAP planner chains actions to satisfy the needs of the agent
+    Goap,
+    /// the behaviour tree in a `.bt.ron` file, relative to the assets folder
+    BehaviourTree(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `AgentDefinition` is an agent as it is written in a scenario file.
+///
+/// Properties:
+///
+/// * `name`: The name of the agent, unique within a scenario.
+/// * `position`: Where the agent starts.
+/// * `needs`: The level the needs of the agent start at, needs that are left out start full.
+/// * `faction`: The faction the agent belongs to, if any.
+/// * `brain`: What decides what the agent does, the utility AI by default.
+/// * `max_speed`: The fastest the agent can move, the `MaxSpeed` default if left out.
+/// * `max_acceleration`: How quickly the agent can change its velocity, the `MaxAcceleration` default if left out.
+/// * `think_interval`: How many seconds the utility AI waits between choices, the `UtilityBrain` default if left out.
+/// * `schedule`: The daily routine of the agent, followed exactly. agents without one get the `DailySchedule` default varied a little.
+/// * `knows`: The names of the zones the agent knows about when the simulation starts, every zone of the scenario if left out. other zones have to be found first.
+/// * `perception`: How far and where the agent can see and hear, the `Perception` default if left out.
+pub struct AgentDefinition {
+    pub name: String,
+    pub position: Vec2,
+    #[serde(default)]
+    pub needs: BTreeMap<NeedKind, f32>,
+    #[serde(default)]
+    pub faction: Option<String>,
+    #[serde(default)]
+    pub brain: BrainDefinition,
+    #[serde(default)]
+    pub max_speed: Option<f32>,
+    #[serde(default)]
+    pub max_acceleration: Option<f32>,
+    #[serde(default)]
+    pub think_interval: Option<f32>,
+    #[serde(default)]
+    pub schedule: Option<DailySchedule>,
+    #[serde(default)]
+    pub knows: Option<Vec<String>>,
+    #[serde(default)]
+    pub perception: Option<Perception>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `ObstacleDefinition` is an obstacle as it is written in a scenario file.
+pub struct ObstacleDefinition {
+    pub position: Vec2,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `RelationshipDefinition` is a relationship between two agents as it is written in a scenario
+/// file, both agents feel the same way about each other.
+///
+/// Properties:
+///
+/// * `agents`: The names of the two agents.
+/// * `kind`: What the agents are to each other.
+/// * `affinity`: How much they like each other, from -1 to 1.
+/// * `familiarity`: How well they know each other, from 0 to 1.
+/// * `trust`: How much they believe each other, from 0 to 1, the `Relationship` default if left out.
+pub struct RelationshipDefinition {
+    pub agents: (String, String),
+    #[serde(default)]
+    pub kind: RelationshipKind,
+    #[serde(default)]
+    pub affinity: f32,
+    #[serde(default)]
+    pub familiarity: f32,
+    #[serde(default)]
+    pub trust: Option<f32>,
+}
+
+impl RelationshipDefinition {
+    /// `relationship` is how each of the agents feels about the other
+    pub fn relationship(&self) -> Relationship {
+        Relationship {
+            kind: self.kind,
+            affinity: self.affinity,
+            familiarity: self.familiarity,
+            trust: self.trust.unwrap_or(Relationship::default().trust),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `FactDefinition` is a fact as it is written in a scenario file, naming the zone or agent it is
+/// about.
+pub enum FactDefinition {
+    /// the zone is closed
+    ZoneClosed(String),
+    /// the zone can be found where it is
+    ZoneAt(String),
+    /// the agent stole something
+    Stole(String),
+}
+
+impl FactDefinition {
+    /// `subject` is the name of the zone or agent the fact is about
+    pub fn subject(&self) -> &str {
+        match self {
+            FactDefinition::ZoneClosed(name)
+            | FactDefinition::ZoneAt(name)
+            | FactDefinition::Stole(name) => name,
+        }
+    }
+
+    /// `is_about_zone` checks if the fact names a zone rather than an agent
+    pub fn is_about_zone(&self) -> bool {
+        !matches!(self, FactDefinition::Stole(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `RumourDefinition` is a rumour an agent knows when the simulation starts, as it is written in a
+/// scenario file.
+///
+/// Properties:
+///
+/// * `agent`: The name of the agent that starts the rumour.
+/// * `fact`: What the rumour says.
+pub struct RumourDefinition {
+    pub agent: String,
+    pub fact: FactDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `Scenario` is the world a simulation starts with.
+///
+/// Properties:
+///
+/// * `name`: The name of the scenario shown in the menu.
+/// * `description`: What the scenario is about.
+/// * `time_of_day`: The hour the simulation starts at, the `TimeOfDay` default if left out.
+/// * `seed`: The seed of the `SimRng`, runs with the same seed and scenario always play out the same.
+/// * `zones`: The zones in the world.
+/// * `agents`: The agents in the world.
+/// * `obstacles`: The obstacles agents walk around.
+/// * `relationships`: The agents that know each other when the simulation starts.
+/// * `rumours`: The rumours agents start spreading when the simulation starts.
+pub struct Scenario {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub time_of_day: Option<f32>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub zones: Vec<ZoneDefinition>,
+    #[serde(default)]
+    pub agents: Vec<AgentDefinition>,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleDefinition>,
+    #[serde(default)]
+    pub relationships: Vec<RelationshipDefinition>,
+    #[serde(default)]
+    pub rumours: Vec<RumourDefinition>,
+}
+
+impl Scenario {
+    /// `from_ron` reads and validates a scenario written in RON
+    pub fn from_ron(ron: &str) -> Result<Self, String> {
+        let scenario: Scenario = ron::from_str(ron).map_err(|error| error.to_string())?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// `from_json` reads and validates a scenario written in JSON
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let scenario: Scenario = serde_json::from_str(json).map_err(|error| error.to_string())?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// `village` is the scenario the game starts with, a cafe, a home and a workshop around a wall
+    pub fn village() -> Self {
+        Scenario::from_ron(VILLAGE_SCENARIO).expect("the village scenario is valid")
+    }
+
+    /// `validate` checks that the scenario can be spawned
+    ///
+    /// Returns:
+    ///
+    /// Every problem found, one per line.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if self.name.is_empty() {
+            problems.push("the scenario has no name".to_string());
+        }
+        if let Some(hours) = self.time_of_day {
+            if !(0.0..24.0).contains(&hours) {
+                problems.push(format!(
+                    "the scenario starts at hour {}, it has to be from 0 up to 24",
+                    hours
+                ));
+            }
+        }
+
+        problems.extend(validate_zone_definitions(&self.zones, |name| {
+            self.agents.iter().any(|agent| agent.name == name)
+        }));
+
+        for (index, agent) in self.agents.iter().enumerate() {
+            let name = &agent.name;
+            if name.is_empty() {
+                problems.push(format!("agent number {} has no name", index + 1));
+            }
+            if self.agents[..index].iter().any(|other| &other.name == name) {
+                problems.push(format!("there is more than one agent named \"{}\"", name));
+            }
+            for (need, level) in agent.needs.iter() {
+                if !(0.0..=1.0).contains(level) {
+                    problems.push(format!(
+                        "agent \"{}\" starts with {} {}, needs go from 0 to 1",
+                        name,
+                        need.name(),
+                        level
+                    ));
+                }
+            }
+            for (parameter, value) in [
+                ("max_speed", agent.max_speed),
+                ("max_acceleration", agent.max_acceleration),
+                ("think_interval", agent.think_interval),
+            ] {
+                if value.is_some_and(|value| value <= 0.0) {
+                    problems.push(format!(
+                        "agent \"{}\" has a {} of {}, it has to be above 0",
+                        name,
+                        parameter,
+                        value.unwrap()
+                    ));
+                }
+            }
+            if let Some(schedule) = &agent.schedule {
+                for problem in schedule.validate() {
+                    problems.push(format!(
+                        "agent \"{}\" has a schedule where {}",
+                        name, problem
+                    ));
+                }
+            }
+            for problem in agent.perception.iter().flat_map(Perception::validate) {
+                problems.push(format!("agent \"{}\" has {}", name, problem));
+            }
+            for zone in agent.knows.iter().flatten() {
+                if !self.zones.iter().any(|definition| &definition.name == zone) {
+                    problems.push(format!(
+                        "agent \"{}\" knows the zone \"{}\", there is no zone with that name",
+                        name, zone
+                    ));
+                }
+            }
+            if let BrainDefinition::BehaviourTree(path) = &agent.brain {
+                if !path.ends_with(".bt.ron") {
+                    problems.push(format!(
+                        "agent \"{}\" uses the behaviour tree \"{}\", behaviour tree files end in .bt.ron",
+                        name, path
+                    ));
+                }
+            }
+        }
+
+        for (index, obstacle) in self.obstacles.iter().enumerate() {
+            if obstacle.width <= 0.0 || obstacle.height <= 0.0 {
+                problems.push(format!(
+                    "obstacle number {} is {} by {}, both have to be above 0",
+                    index + 1,
+                    obstacle.width,
+                    obstacle.height
+                ));
+            }
+        }
+
+        for definition in self.relationships.iter() {
+            let (a, b) = &definition.agents;
+            for name in [a, b] {
+                if !self.agents.iter().any(|agent| &agent.name == name) {
+                    problems.push(format!(
+                        "the relationship between \"{}\" and \"{}\" names \"{}\", there is no agent with that name",
+                        a, b, name
+                    ));
+                }
+            }
+            if a == b {
+                problems.push(format!("agent \"{}\" has a relationship with itself", a));
+            }
+            for problem in definition.relationship().validate() {
+                problems.push(format!(
+                    "the relationship between \"{}\" and \"{}\" has {}",
+                    a, b, problem
+                ));
+            }
+        }
+
+        for definition in self.rumours.iter() {
+            if !self
+                .agents
+                .iter()
+                .any(|agent| agent.name == definition.agent)
+            {
+                problems.push(format!(
+                    "a rumour is started by \"{}\", there is no agent with that name",
+                    definition.agent
+                ));
+            }
+            let subject = definition.fact.subject();
+            if definition.fact.is_about_zone() {
+                if !self.zones.iter().any(|zone| zone.name == subject) {
+                    problems.push(format!(
+                        "a rumour started by \"{}\" is about the zone \"{}\", there is no zone with that name",
+                        definition.agent, subject
+                    ));
+                }
+            } else if !self.agents.iter().any(|agent| agent.name == subject) {
+                problems.push(format!(
+                    "a rumour started by \"{}\" is about the agent \"{}\", there is no agent with that name",
+                    definition.agent, subject
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+
+    /// `spawn` spawns every agent, zone and obstacle of the scenario. the scenario has to be valid.
+    /// agents start with an empty memory and learn the zones they know about on the first tick,
+    /// the rumours of the scenario are recorded in the metrics as starting on `tick`
+    ///
+    /// Arguments:
+    ///
+    /// * `commands`: The command buffer to spawn the scenario with.
+    /// * `asset_server`: Loads the behaviour trees of agents, agents keep the utility AI without one.
+    /// * `rng`: Varies the schedules of agents that do not have their own.
+    /// * `graph`: The social graph the relationships of the scenario are added to.
+    /// * `metrics`: The rumour metrics the rumours of the scenario are added to.
+    /// * `tick`: The tick of the `SimClock` the scenario is spawned on.
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        asset_server: Option<&AssetServer>,
+        rng: &mut SimRng,
+        graph: &mut SocialGraph,
+        metrics: &mut RumourMetrics,
+        tick: u64,
+    ) {
+        let mut agents = BTreeMap::new();
+        for definition in self.agents.iter() {
+            let mut bundle = AgentBundle::new(definition.name.clone(), definition.position);
+            for (need, level) in definition.needs.iter() {
+                bundle.needs.set(*need, *level);
+            }
+            if let Some(max_speed) = definition.max_speed {
+                bundle.max_speed.0 = max_speed;
+            }
+            if let Some(max_acceleration) = definition.max_acceleration {
+                bundle.max_acceleration.0 = max_acceleration;
+            }
+            if let Some(think_interval) = definition.think_interval {
+                bundle.brain.think_interval = think_interval;
+            }
+
+            let schedule = match &definition.schedule {
+                Some(schedule) => schedule.clone(),
+                None => DailySchedule::default().varied(rng),
+            };
+
+            let mut entity_commands = commands.spawn_bundle(bundle);
+            entity_commands
+                .insert(schedule)
+                .insert(EpisodicMemory::default())
+                .insert(Beliefs::default())
+                .insert(definition.perception.unwrap_or_default())
+                .insert(Perceived::default());
+            if let Some(faction) = &definition.faction {
+                entity_commands.insert(Faction(faction.clone()));
+            }
+            match (&definition.brain, asset_server) {
+                (BrainDefinition::Utility, _) => {}
+                (BrainDefinition::Goap, _) => {
+                    entity_commands.insert(GoapAgent::default());
+                }
+                (BrainDefinition::BehaviourTree(path), Some(asset_server)) => {
+                    entity_commands.insert(BehaviourTreeHandle(asset_server.load(path.as_str())));
+                }
+                (BrainDefinition::BehaviourTree(path), None) => {
+                    warn!(
+                        "agent \"{}\" uses the utility AI, there is no asset server to load \"{}\" with",
+                        definition.name, path
+                    );
+                }
+            }
+            agents.insert(definition.name.as_str(), entity_commands.id());
+        }
+
+        let zones = spawn_zone_definitions(commands, &self.zones, |name| agents.get(name).copied())
+            .expect("the zones of a valid scenario can be spawned");
+
+        for definition in self.agents.iter() {
+            let known = self
+                .zones
+                .iter()
+                .zip(&zones)
+                .filter(|(zone, _)| {
+                    definition
+                        .knows
+                        .as_ref()
+                        .is_none_or(|knows| knows.contains(&zone.name))
+                })
+                .map(|(_, entity)| *entity)
+                .collect();
+            commands
+                .entity(agents[definition.name.as_str()])
+                .insert(LearnZones(known));
+        }
+
+        let mut rumours: BTreeMap<&str, Rumours> = BTreeMap::new();
+        for definition in self.rumours.iter() {
+            let subject = definition.fact.subject();
+            let zone = || {
+                self.zones
+                    .iter()
+                    .zip(&zones)
+                    .find(|(zone, _)| zone.name == subject)
+                    .expect("the zones of rumours in a valid scenario exist")
+            };
+            let fact = match &definition.fact {
+                FactDefinition::ZoneClosed(_) => Fact::ZoneClosed { zone: *zone().1 },
+                FactDefinition::ZoneAt(_) => {
+                    let (zone, entity) = zone();
+                    Fact::ZoneAt {
+                        zone: *entity,
+                        position: zone.position,
+                    }
+                }
+                FactDefinition::Stole(_) => Fact::Stole {
+                    agent: agents[subject],
+                },
+            };
+            let description = fact.describe(|_| Some(subject.to_string()));
+            let id = metrics.start(description, definition.agent.clone(), tick);
+            rumours
+                .entry(definition.agent.as_str())
+                .or_default()
+                .known
+                .insert(
+                    id,
+                    KnownRumour {
+                        fact,
+                        confidence: 1.0,
+                        hops: 0,
+                        heard: tick,
+                        distorted: false,
+                    },
+                );
+        }
+        for definition in self.agents.iter() {
+            let name = definition.name.as_str();
+            commands
+                .entity(agents[name])
+                .insert(rumours.remove(name).unwrap_or_default());
+        }
+
+        for definition in self.relationships.iter() {
+            let a = agents[definition.agents.0.as_str()];
+            let b = agents[definition.agents.1.as_str()];
+            graph.set(a, b, definition.relationship());
+            graph.set(b, a, definition.relationship());
+        }
+
+        for obstacle in self.obstacles.iter() {
+            commands.spawn_bundle(ObstacleBundle::new(
+                obstacle.position,
+                obstacle.width,
+                obstacle.height,
+            ));
+        }
+    }
+}
+
+#[derive(Default)]
+/// `ActiveScenario` is a resource with the scenario spawned when the simulation starts, `None`
+/// starts with an empty world.
+pub struct ActiveScenario(pub Option<Scenario>);
+
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "b1d3c9a2-5e47-4f0b-9a8c-2d6e1f3a7b54"]
+/// `ScenarioAsset` is a scenario file loaded by the `AssetServer`. files that can not be read or
+/// are not valid are still loaded with their problems, so the menu can show them instead of the
+/// loading screen waiting forever.
+///
+/// Properties:
+///
+/// * `path`: The file the scenario was loaded from.
+/// * `scenario`: The scenario, or what is wrong with the file.
+pub struct ScenarioAsset {
+    pub path: String,
+    pub scenario: Result<Scenario, String>,
+}
+
+#[derive(Default)]
+/// `ScenarioLoader` loads `.scenario.ron` and `.scenario.json` files into `ScenarioAsset`s.
+pub struct ScenarioLoader;
+
+impl AssetLoader for ScenarioLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let path = load_context.path().display().to_string();
+            let scenario = std::str::from_utf8(bytes)
+                .map_err(|error| error.to_string())
+                .and_then(|text| {
+                    if path.ends_with(".json") {
+                        Scenario::from_json(text)
+                    } else {
+                        Scenario::from_ron(text)
+                    }
+                });
+            if let Err(problems) = &scenario {
+                error!("scenario {} is not valid:\n{}", path, problems);
+            }
+            load_context.set_default_asset(LoadedAsset::new(ScenarioAsset { path, scenario }));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scenario.ron", "scenario.json"]
+    }
+}
+
+/// `spawn_active_scenario` spawns the world of the `ActiveScenario`, if one was picked, and gives
+/// planners actions at its zones. entering `GameState::Playing` again only spawns anything if a
+/// different scenario was picked since, which then replaces the world of the one before
+///
+/// Arguments:
+///
+/// * `scenario`: Res<ActiveScenario> - resource containing the scenario to spawn.
+/// * `spawned_before`: Local<bool> - if a scenario was spawned already, its world is despawned before the next one is spawned.
+/// * `previous`: Query<Entity, Or<(With<Agent>, With<Zone>, With<Obstacle>)>> - query containing the world of the scenario spawned before.
+/// * `time_of_day`: ResMut<TimeOfDay> - resource containing the time of day, set to the start of the scenario.
+/// * `rng`: ResMut<SimRng> - resource containing the random number generator, seeded by the scenario and used to vary schedules.
+/// * `graph`: ResMut<SocialGraph> - resource containing the relationships of every agent, filled in from the scenario.
+/// * `metrics`: ResMut<RumourMetrics> - resource recording every rumour, filled in from the scenario.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to timestamp the rumours of the scenario.
+/// * `asset_server`: Option<Res<AssetServer>> - used to load behaviour trees, missing when running headless.
+/// * `commands`: Commands - This is the command buffer that we use to spawn entities and insert the `GoapActions`.
+fn spawn_active_scenario(
+    scenario: Res<ActiveScenario>,
+    mut spawned_before: Local<bool>,
+    previous: Query<Entity, Or<(With<Agent>, With<Zone>, With<Obstacle>)>>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    mut rng: ResMut<SimRng>,
+    mut graph: ResMut<SocialGraph>,
+    mut metrics: ResMut<RumourMetrics>,
+    clock: Res<SimClock>,
+    asset_server: Option<Res<AssetServer>>,
+    mut commands: Commands,
+) {
+    if !scenario.is_changed() {
+        return;
+    }
+    if *spawned_before {
+        for entity in previous.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        *graph = SocialGraph::default();
+        *metrics = RumourMetrics::default();
+    }
+    let scenario = match &scenario.0 {
+        Some(scenario) => scenario,
+        None => return,
+    };
+    *spawned_before = true;
+    if let Some(hours) = scenario.time_of_day {
+        time_of_day.hours = hours;
+    }
+    if let Some(seed) = scenario.seed {
+        *rng = SimRng::new(seed);
+    }
+    commands.insert_resource(GoapActions::for_zones(
+        scenario
+            .zones
+            .iter()
+            .map(|zone| (zone.name.as_str(), zone.kind)),
+    ));
+    scenario.spawn(
+        &mut commands,
+        asset_server.as_deref(),
+        &mut rng,
+        &mut graph,
+        &mut metrics,
+        clock.tick,
+    );
+}
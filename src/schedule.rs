@@ -0,0 +1,220 @@
+use crate::rng::SimRng;
+use crate::utility::{
+    ActionTarget, Consideration, ResponseCurve, ScoredConsideration, UtilityAction, UtilityContext,
+};
+use crate::zone::ZoneKind;
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// the most hours the start and end of a block move when a schedule is varied for an agent
+pub const SCHEDULE_VARIATION_HOURS: f32 = 0.75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// `ScheduledActivity` is what a schedule says an agent should be doing at some time of day.
+pub enum ScheduledActivity {
+    /// sleeping at home
+    Sleep,
+    /// working at a workplace
+    Work,
+    /// free time, spent in parks and shops
+    Leisure,
+}
+
+impl ScheduledActivity {
+    /// every scheduled activity
+    pub const ALL: [ScheduledActivity; 3] = [
+        ScheduledActivity::Sleep,
+        ScheduledActivity::Work,
+        ScheduledActivity::Leisure,
+    ];
+
+    /// `name` is the name of the utility action that follows the activity, also shown as the
+    /// activity of the agent
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScheduledActivity::Sleep => "bedtime",
+            ScheduledActivity::Work => "work",
+            ScheduledActivity::Leisure => "leisure",
+        }
+    }
+
+    /// `zone_kinds` are the kinds of zone the activity takes place in
+    pub fn zone_kinds(&self) -> &'static [ZoneKind] {
+        match self {
+            ScheduledActivity::Sleep => &[ZoneKind::Home],
+            ScheduledActivity::Work => &[ZoneKind::Workplace],
+            ScheduledActivity::Leisure => &[ZoneKind::Park, ZoneKind::Shop],
+        }
+    }
+}
+
+impl fmt::Display for ScheduledActivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `ScheduleBlock` is a stretch of the day set aside for an activity.
+///
+/// Properties:
+///
+/// * `activity`: What the agent should be doing.
+/// * `start`: The hour the block starts.
+/// * `end`: The hour the block ends, blocks that end before they start run past midnight.
+pub struct ScheduleBlock {
+    pub activity: ScheduledActivity,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl ScheduleBlock {
+    /// `contains` checks if an hour of the day falls inside the block
+    pub fn contains(&self, hours: f32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&hours)
+        } else {
+            hours >= self.start || hours < self.end
+        }
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Serialize, Deserialize)]
+/// `DailySchedule` is the daily routine of an agent. agents with the utility AI follow it unless a
+/// need gets urgent, time that is not in any block is leisure time.
+///
+/// Properties:
+///
+/// * `blocks`: The blocks of the day, the first block containing an hour wins.
+pub struct DailySchedule {
+    pub blocks: Vec<ScheduleBlock>,
+}
+
+impl Default for DailySchedule {
+    /// sleep at home from 22:00 to 07:00 and work from 09:00 to 17:00
+    fn default() -> Self {
+        DailySchedule {
+            blocks: vec![
+                ScheduleBlock {
+                    activity: ScheduledActivity::Sleep,
+                    start: 22.0,
+                    end: 7.0,
+                },
+                ScheduleBlock {
+                    activity: ScheduledActivity::Work,
+                    start: 9.0,
+                    end: 17.0,
+                },
+            ],
+        }
+    }
+}
+
+impl DailySchedule {
+    /// `activity_at` is what the agent should be doing at an hour of the day
+    pub fn activity_at(&self, hours: f32) -> ScheduledActivity {
+        self.blocks
+            .iter()
+            .find(|block| block.contains(hours))
+            .map_or(ScheduledActivity::Leisure, |block| block.activity)
+    }
+
+    /// `varied` moves the start and end of every block by up to `SCHEDULE_VARIATION_HOURS`, so
+    /// agents sharing a schedule do not all get up and leave at the same moment
+    ///
+    /// Arguments:
+    ///
+    /// * `rng`: The random number generator of the simulation.
+    ///
+    /// Returns:
+    ///
+    /// A copy of the schedule with the blocks moved.
+    pub fn varied(&self, rng: &mut SimRng) -> Self {
+        let mut vary = |hours: f32| {
+            (hours + rng.gen_range(-SCHEDULE_VARIATION_HOURS..=SCHEDULE_VARIATION_HOURS))
+                .rem_euclid(24.0)
+        };
+        DailySchedule {
+            blocks: self
+                .blocks
+                .iter()
+                .map(|block| ScheduleBlock {
+                    activity: block.activity,
+                    start: vary(block.start),
+                    end: vary(block.end),
+                })
+                .collect(),
+        }
+    }
+
+    /// `validate` checks that every block starts and ends within the day
+    ///
+    /// Returns:
+    ///
+    /// A description of every problem found.
+    pub fn validate(&self) -> Vec<String> {
+        self.blocks
+            .iter()
+            .filter(|block| {
+                !(0.0..24.0).contains(&block.start) || !(0.0..24.0).contains(&block.end)
+            })
+            .map(|block| {
+                format!(
+                    "the {} block runs from {} to {}, hours go from 0 up to 24",
+                    block.activity, block.start, block.end
+                )
+            })
+            .collect()
+    }
+}
+
+/// `ScheduleAction` sends the agent to the nearest open zone where its scheduled activity takes
+/// place, while the schedule says so.
+pub struct ScheduleAction {
+    pub activity: ScheduledActivity,
+    pub considerations: Vec<ScoredConsideration>,
+}
+
+impl ScheduleAction {
+    /// `new` creates an action that is only worth doing while the activity is scheduled, and then
+    /// beats idling but loses to urgent needs
+    ///
+    /// Arguments:
+    ///
+    /// * `activity`: The scheduled activity to follow.
+    pub fn new(activity: ScheduledActivity) -> Self {
+        ScheduleAction {
+            activity,
+            considerations: vec![ScoredConsideration::new(
+                Consideration::Scheduled(activity),
+                ResponseCurve::Linear {
+                    slope: 0.6,
+                    offset: 0.0,
+                },
+            )],
+        }
+    }
+}
+
+impl UtilityAction for ScheduleAction {
+    fn name(&self) -> &str {
+        self.activity.name()
+    }
+
+    fn considerations(&self) -> &[ScoredConsideration] {
+        &self.considerations
+    }
+
+    fn target(&self, context: &UtilityContext) -> Option<ActionTarget> {
+        context.schedule?;
+        context
+            .nearest_zone(|info| {
+                self.activity.zone_kinds().contains(&info.zone.kind)
+                    && info.is_open(context.time_of_day * 24.0)
+                    && info.allows(context.agent, context.faction)
+            })
+            .map(|info| ActionTarget::Zone(info.entity, info.center))
+    }
+}
@@ -0,0 +1,375 @@
+use crate::agent::Agent;
+use crate::zone::Zone;
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+
+pub struct SpatialPlugin;
+
+impl Plugin for SpatialPlugin {
+    /// `build` adds the spatial index and the systems that keep it in sync with the transforms of
+    /// agents and zones
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialIndex>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        index_zones
+                            .label(SimLabel::IndexZones)
+                            .before(SimLabel::UpdateNeeds)
+                            .before(SimLabel::ChooseAction),
+                    )
+                    .with_system(
+                        update_spatial_index
+                            .label(SimLabel::UpdateSpatialIndex)
+                            .after(SimLabel::UpdateAgent)
+                            .before(SimLabel::UpdateZones),
+                    ),
+            )
+            // removals are only reported until the end of the frame, which can pass without a tick
+            .add_system_to_stage(CoreStage::PostUpdate, forget_removed_entities);
+    }
+}
+
+#[derive(Debug, Clone)]
+/// `SpatialIndex` is a spatial hash of agents and zones. the world is split into square cells and
+/// every agent is stored in the cell its position is in, every zone in each cell its bounding box
+/// touches. lookups only have to check the few cells around the point of interest instead of every
+/// entity.
+///
+/// Properties:
+///
+/// * `cell_size`: The width and height of a cell.
+/// * `points`: The agents in every cell with their positions.
+/// * `positions`: The position of every agent, used to find the agent when it moves.
+/// * `areas`: The zones touching every cell.
+/// * `large_zones`: The zones spanning more than `MAX_CELL_SPAN` cells, checked on every lookup.
+/// * `bounds`: The bottom left and top right corner of every zone.
+pub struct SpatialIndex {
+    cell_size: f32,
+    points: HashMap<IVec2, Vec<(Entity, Vec2)>>,
+    positions: HashMap<Entity, Vec2>,
+    areas: HashMap<IVec2, Vec<Entity>>,
+    large_zones: BTreeSet<Entity>,
+    bounds: HashMap<Entity, (Vec2, Vec2)>,
+}
+
+/// `MAX_CELL_SPAN` is how many cells wide or high a box can be before it stops being split into
+/// cells. zones this large are kept aside, and lookups this large check every occupied cell
+pub const MAX_CELL_SPAN: i32 = 256;
+
+impl Default for SpatialIndex {
+    /// 250 unit cells, a bit more than the distance agents look around them
+    fn default() -> Self {
+        SpatialIndex::new(250.0)
+    }
+}
+
+impl SpatialIndex {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialIndex {
+            cell_size,
+            points: HashMap::new(),
+            positions: HashMap::new(),
+            areas: HashMap::new(),
+            large_zones: BTreeSet::new(),
+            bounds: HashMap::new(),
+        }
+    }
+
+    /// `cell_of` gets the cell a position is in
+    pub fn cell_of(&self, position: Vec2) -> IVec2 {
+        (position / self.cell_size).floor().as_ivec2()
+    }
+
+    /// `cells` iterates over every cell overlapping the box from `min` to `max`
+    ///
+    /// Returns:
+    ///
+    /// The cells, or `None` if the box is not finite or spans more than `MAX_CELL_SPAN` cells.
+    fn cells(&self, min: Vec2, max: Vec2) -> Option<impl Iterator<Item = IVec2>> {
+        if !min.is_finite() || !max.is_finite() {
+            return None;
+        }
+        let (min, max) = (
+            (min / self.cell_size).floor(),
+            (max / self.cell_size).floor(),
+        );
+        let span = max - min;
+        if span.x > MAX_CELL_SPAN as f32 || span.y > MAX_CELL_SPAN as f32 {
+            return None;
+        }
+        let (min, max) = (min.as_ivec2(), max.as_ivec2());
+        Some((min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y))))
+    }
+
+    /// `cells_or_occupied` is the cells overlapping a box, or every cell in `map` when the box is
+    /// too large to split into cells. boxes with a `NaN` corner overlap nothing
+    fn cells_or_occupied<T>(&self, min: Vec2, max: Vec2, map: &HashMap<IVec2, T>) -> Vec<IVec2> {
+        if min.is_nan() || max.is_nan() {
+            return Vec::new();
+        }
+        match self.cells(min, max) {
+            Some(cells) => cells.collect(),
+            None => {
+                // sorted so large lookups still find things in the same order every run
+                let mut cells: Vec<IVec2> = map.keys().copied().collect();
+                cells.sort_by_key(|cell| (cell.y, cell.x));
+                cells
+            }
+        }
+    }
+
+    /// `len` is the number of agents in the index
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// `is_empty` is true when there are no agents in the index
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// `position` is where an agent was when it was last indexed
+    pub fn position(&self, entity: Entity) -> Option<Vec2> {
+        self.positions.get(&entity).copied()
+    }
+
+    /// `insert` adds an agent to the index or moves it if it is already in there
+    ///
+    /// Arguments:
+    ///
+    /// * `entity`: The agent entity.
+    /// * `position`: Where the agent is.
+    pub fn insert(&mut self, entity: Entity, position: Vec2) {
+        let cell = self.cell_of(position);
+        if let Some(previous) = self.positions.insert(entity, position) {
+            let previous_cell = self.cell_of(previous);
+            if previous_cell == cell {
+                let entries = self.points.get_mut(&cell).unwrap();
+                let entry = entries.iter_mut().find(|(e, _)| *e == entity).unwrap();
+                entry.1 = position;
+                return;
+            }
+            self.remove_point(previous_cell, entity);
+        }
+        self.points
+            .entry(cell)
+            .or_default()
+            .push((entity, position));
+    }
+
+    /// `remove` takes an agent out of the index
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(position) = self.positions.remove(&entity) {
+            let cell = self.cell_of(position);
+            self.remove_point(cell, entity);
+        }
+    }
+
+    fn remove_point(&mut self, cell: IVec2, entity: Entity) {
+        if let Some(entries) = self.points.get_mut(&cell) {
+            entries.retain(|(e, _)| *e != entity);
+            if entries.is_empty() {
+                self.points.remove(&cell);
+            }
+        }
+    }
+
+    /// `query_rect` finds every agent inside a box
+    ///
+    /// Arguments:
+    ///
+    /// * `min`: The bottom left corner of the box.
+    /// * `max`: The top right corner of the box.
+    ///
+    /// Returns:
+    ///
+    /// The agents in the box with their positions.
+    pub fn query_rect(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = (Entity, Vec2)> + '_ {
+        self.cells_or_occupied(min, max, &self.points)
+            .into_iter()
+            .filter_map(|cell| self.points.get(&cell))
+            .flatten()
+            .filter(move |(_, position)| {
+                position.x >= min.x
+                    && position.x <= max.x
+                    && position.y >= min.y
+                    && position.y <= max.y
+            })
+            .copied()
+    }
+
+    /// `query_radius` finds every agent within `radius` of `center`
+    ///
+    /// Arguments:
+    ///
+    /// * `center`: The center of the circle to search.
+    /// * `radius`: The radius of the circle to search.
+    ///
+    /// Returns:
+    ///
+    /// The agents in the circle with their positions.
+    pub fn query_radius(
+        &self,
+        center: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = (Entity, Vec2)> + '_ {
+        self.query_rect(center - Vec2::splat(radius), center + Vec2::splat(radius))
+            .filter(move |(_, position)| position.distance_squared(center) <= radius * radius)
+    }
+
+    /// `nearest` finds the agent closest to `center` within `radius`
+    pub fn nearest(&self, center: Vec2, radius: f32) -> Option<(Entity, Vec2)> {
+        self.query_radius(center, radius).min_by(|(_, a), (_, b)| {
+            a.distance_squared(center)
+                .total_cmp(&b.distance_squared(center))
+        })
+    }
+
+    /// `insert_zone` adds a zone to the index or moves it if it is already in there. zones with a
+    /// bounding box that is not finite are left out, zones spanning more than `MAX_CELL_SPAN`
+    /// cells are kept aside instead of being stored in every cell
+    ///
+    /// Arguments:
+    ///
+    /// * `entity`: The zone entity.
+    /// * `min`: The bottom left corner of the bounding box of the zone.
+    /// * `max`: The top right corner of the bounding box of the zone.
+    pub fn insert_zone(&mut self, entity: Entity, min: Vec2, max: Vec2) {
+        self.remove_zone(entity);
+        if !min.is_finite() || !max.is_finite() {
+            return;
+        }
+        match self.cells(min, max) {
+            Some(cells) => {
+                for cell in cells.collect::<Vec<_>>() {
+                    self.areas.entry(cell).or_default().push(entity);
+                }
+            }
+            None => {
+                self.large_zones.insert(entity);
+            }
+        }
+        self.bounds.insert(entity, (min, max));
+    }
+
+    /// `remove_zone` takes a zone out of the index
+    pub fn remove_zone(&mut self, entity: Entity) {
+        if let Some((min, max)) = self.bounds.remove(&entity) {
+            if self.large_zones.remove(&entity) {
+                return;
+            }
+            for cell in self
+                .cells(min, max)
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+            {
+                if let Some(entries) = self.areas.get_mut(&cell) {
+                    entries.retain(|e| *e != entity);
+                    if entries.is_empty() {
+                        self.areas.remove(&cell);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `zones_at` finds the zones whose bounding box contains a point. the point may still be
+    /// outside the zone itself, check it with `Zone::contains`
+    pub fn zones_at(&self, point: Vec2) -> impl Iterator<Item = Entity> + '_ {
+        self.areas
+            .get(&self.cell_of(point))
+            .into_iter()
+            .flatten()
+            .chain(self.large_zones.iter())
+            .copied()
+            .filter(move |entity| {
+                let (min, max) = self.bounds[entity];
+                point.x >= min.x && point.x <= max.x && point.y >= min.y && point.y <= max.y
+            })
+    }
+
+    /// `zones_in_rect` finds the zones whose bounding box overlaps a box
+    ///
+    /// Arguments:
+    ///
+    /// * `min`: The bottom left corner of the box.
+    /// * `max`: The top right corner of the box.
+    ///
+    /// Returns:
+    ///
+    /// The zones, each once and in the order of their entities.
+    pub fn zones_in_rect(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = Entity> + '_ {
+        let mut zones: BTreeSet<Entity> = self
+            .cells_or_occupied(min, max, &self.areas)
+            .into_iter()
+            .filter_map(|cell| self.areas.get(&cell))
+            .flatten()
+            .copied()
+            .collect();
+        zones.extend(self.large_zones.iter().copied());
+        zones.into_iter().filter(move |entity| {
+            let (zone_min, zone_max) = self.bounds[entity];
+            zone_min.x <= max.x && zone_max.x >= min.x && zone_min.y <= max.y && zone_max.y >= min.y
+        })
+    }
+}
+
+/// `update_spatial_index` moves agents that moved in the index
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &Transform), (With<Agent>, Changed<Transform>)> - query containing agents that moved.
+/// * `index`: ResMut<SpatialIndex> - resource containing the spatial index.
+fn update_spatial_index(
+    agents: Query<(Entity, &Transform), (With<Agent>, Changed<Transform>)>,
+    mut index: ResMut<SpatialIndex>,
+) {
+    for (entity, transform) in agents.iter() {
+        index.insert(entity, transform.translation.truncate());
+    }
+}
+
+/// `index_zones` moves zones that were added, moved or changed in the index. zones only change
+/// between ticks, so they are indexed at the start of the tick and found the tick they appear
+///
+/// Arguments:
+///
+/// * `zones`: Query<(Entity, &Zone, &Transform), Or<(Changed<Zone>, Changed<Transform>)>> - query containing zones that moved or changed.
+/// * `index`: ResMut<SpatialIndex> - resource containing the spatial index.
+fn index_zones(
+    zones: Query<(Entity, &Zone, &Transform), Or<(Changed<Zone>, Changed<Transform>)>>,
+    mut index: ResMut<SpatialIndex>,
+) {
+    for (entity, zone, transform) in zones.iter() {
+        let (min, max) = zone.bounds(transform.translation.truncate());
+        index.insert_zone(entity, min, max);
+    }
+}
+
+/// `forget_removed_entities` takes the agents and zones that were removed out of the index
+///
+/// Arguments:
+///
+/// * `removed_agents`: RemovedComponents<Agent> - agents removed this frame.
+/// * `removed_zones`: RemovedComponents<Zone> - zones removed this frame.
+/// * `index`: ResMut<SpatialIndex> - resource containing the spatial index.
+fn forget_removed_entities(
+    removed_agents: RemovedComponents<Agent>,
+    removed_zones: RemovedComponents<Zone>,
+    mut index: ResMut<SpatialIndex>,
+) {
+    for entity in removed_agents.iter() {
+        index.remove(entity);
+    }
+    for entity in removed_zones.iter() {
+        index.remove_zone(entity);
+    }
+}
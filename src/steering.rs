@@ -0,0 +1,372 @@
+use crate::agent::Agent;
+use crate::clock::SimClock;
+use crate::flow_field::{FlowFields, FlowFollower};
+use crate::navigation::{NavGrid, NavPath, Obstacle};
+use crate::spatial::SpatialIndex;
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+pub struct SteeringPlugin;
+
+impl Plugin for SteeringPlugin {
+    /// `build` adds the system that turns the path of every agent into a velocity while keeping
+    /// agents apart and away from obstacles
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SteeringSettings>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing).with_system(
+                    steer_agents
+                        .label(SimLabel::Steer)
+                        .after(SimLabel::PlanPaths)
+                        .after(SimLabel::UpdateFlowFields)
+                        .before(SimLabel::UpdateAgent),
+                ),
+            );
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy, Default, PartialEq)]
+/// `Velocity` is how fast and in which direction an agent is moving, in units per second.
+pub struct Velocity(pub Vec2);
+
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+/// `MaxSpeed` is the fastest an agent can move, in units per second.
+pub struct MaxSpeed(pub f32);
+
+impl Default for MaxSpeed {
+    fn default() -> Self {
+        MaxSpeed(1000.0)
+    }
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+/// `MaxAcceleration` is how quickly an agent can change its velocity, in units per second squared.
+pub struct MaxAcceleration(pub f32);
+
+impl Default for MaxAcceleration {
+    fn default() -> Self {
+        MaxAcceleration(4000.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// `SteeringSettings` is a resource with the tuning shared by every agent.
+///
+/// Properties:
+///
+/// * `radius`: The radius of the circle every agent takes up.
+/// * `arrival_radius`: How close an agent has to get to its destination to have arrived.
+/// * `slowing_radius`: The distance from the destination at which agents start to slow down.
+/// * `waypoint_radius`: How close an agent has to get to a waypoint before heading to the next one.
+/// * `separation_radius`: Agents closer than this push each other apart.
+/// * `neighbour_radius`: Agents closer than this are checked for collisions.
+/// * `time_horizon`: How many seconds ahead collisions with other agents are avoided.
+/// * `obstacle_margin`: Agents closer than this to an obstacle steer away from it.
+pub struct SteeringSettings {
+    pub radius: f32,
+    pub arrival_radius: f32,
+    pub slowing_radius: f32,
+    pub waypoint_radius: f32,
+    pub separation_radius: f32,
+    pub neighbour_radius: f32,
+    pub time_horizon: f32,
+    pub obstacle_margin: f32,
+}
+
+impl Default for SteeringSettings {
+    fn default() -> Self {
+        SteeringSettings {
+            radius: 10.0,
+            arrival_radius: 10.0,
+            slowing_radius: 300.0,
+            waypoint_radius: 50.0,
+            separation_radius: 40.0,
+            neighbour_radius: 300.0,
+            time_horizon: 1.0,
+            obstacle_margin: 60.0,
+        }
+    }
+}
+
+/// `seek` is the velocity that heads straight for `target` at full speed
+///
+/// Arguments:
+///
+/// * `position`: The position of the agent.
+/// * `target`: The point to head for.
+/// * `max_speed`: The speed of the agent.
+pub fn seek(position: Vec2, target: Vec2, max_speed: f32) -> Vec2 {
+    (target - position).normalize_or_zero() * max_speed
+}
+
+/// `arrive` is like `seek` but slows down inside `slowing_radius` so the agent stops on the
+/// target instead of overshooting it
+///
+/// Arguments:
+///
+/// * `position`: The position of the agent.
+/// * `target`: The point to stop on.
+/// * `max_speed`: The speed of the agent.
+/// * `slowing_radius`: The distance from the target at which to start slowing down.
+pub fn arrive(position: Vec2, target: Vec2, max_speed: f32, slowing_radius: f32) -> Vec2 {
+    let offset = target - position;
+    let distance = offset.length();
+    let speed = max_speed * (distance / slowing_radius).min(1.0);
+    offset.normalize_or_zero() * speed
+}
+
+/// `separation` pushes an agent away from every neighbour closer than `radius`, harder the
+/// closer they are
+///
+/// Arguments:
+///
+/// * `position`: The position of the agent.
+/// * `neighbours`: The positions of the other agents.
+/// * `radius`: The distance at which agents start pushing each other.
+/// * `max_speed`: The speed of the agent.
+pub fn separation(
+    position: Vec2,
+    neighbours: impl Iterator<Item = Vec2>,
+    radius: f32,
+    max_speed: f32,
+) -> Vec2 {
+    neighbours
+        .filter_map(|neighbour| {
+            let offset = position - neighbour;
+            let distance = offset.length();
+            (distance < radius).then(|| {
+                // agents standing on top of each other are pushed apart along x
+                let away = offset.try_normalize().unwrap_or(Vec2::X);
+                away * max_speed * (1.0 - distance / radius)
+            })
+        })
+        .fold(Vec2::ZERO, |total, push| total + push)
+}
+
+/// `avoid_obstacles` steers an agent away from obstacles it is heading into
+///
+/// Arguments:
+///
+/// * `position`: The position of the agent.
+/// * `velocity`: The velocity the agent wants to move with.
+/// * `obstacles`: The center and obstacle of every obstacle.
+/// * `margin`: How far from an obstacle the agent starts steering away.
+/// * `max_speed`: The speed of the agent.
+pub fn avoid_obstacles<'a>(
+    position: Vec2,
+    velocity: Vec2,
+    obstacles: impl Iterator<Item = (Vec2, &'a Obstacle)>,
+    margin: f32,
+    max_speed: f32,
+) -> Vec2 {
+    obstacles
+        .filter_map(|(center, obstacle)| {
+            let half = Vec2::new(obstacle.width, obstacle.height) / 2.0;
+            let closest = position.clamp(center - half, center + half);
+            let offset = position - closest;
+            let distance = offset.length();
+            if distance >= margin {
+                return None;
+            }
+            let away = match offset.try_normalize() {
+                Some(away) => away,
+                // inside the obstacle, leave through the nearest side
+                None => {
+                    let local = position - center;
+                    if half.x - local.x.abs() < half.y - local.y.abs() {
+                        Vec2::new(local.x.signum(), 0.0)
+                    } else {
+                        Vec2::new(0.0, local.y.signum())
+                    }
+                }
+            };
+            // agents walking along or away from the obstacle are left alone
+            if distance > 0.0 && velocity.dot(away) >= 0.0 {
+                return None;
+            }
+            Some(away * max_speed * (1.0 - distance / margin))
+        })
+        .fold(Vec2::ZERO, |total, push| total + push)
+}
+
+/// `avoid_agents` is a reciprocal velocity obstacle in the spirit of ORCA. for every neighbour it
+/// predicts the closest approach within `time_horizon` seconds and, if the agents would overlap,
+/// changes the velocity by half of what is needed to keep them apart. the neighbour does the other
+/// half, so two agents never both dodge the same way
+///
+/// Arguments:
+///
+/// * `position`: The position of the agent.
+/// * `velocity`: The velocity the agent wants to move with.
+/// * `neighbours`: The position and velocity of the other agents.
+/// * `radius`: The radius of every agent.
+/// * `time_horizon`: How many seconds ahead to look for collisions.
+pub fn avoid_agents(
+    position: Vec2,
+    velocity: Vec2,
+    neighbours: impl Iterator<Item = (Vec2, Vec2)>,
+    radius: f32,
+    time_horizon: f32,
+) -> Vec2 {
+    let combined_radius = radius * 2.0;
+    neighbours
+        .filter_map(|(neighbour_position, neighbour_velocity)| {
+            let relative_position = neighbour_position - position;
+            let relative_velocity = velocity - neighbour_velocity;
+            let closing_speed = relative_velocity.length_squared();
+            if closing_speed <= f32::EPSILON {
+                return None;
+            }
+            let time = relative_position.dot(relative_velocity) / closing_speed;
+            if time <= 0.0 || time > time_horizon {
+                return None;
+            }
+            // where the neighbour will be relative to the agent at the closest approach
+            let miss = relative_position - relative_velocity * time;
+            let miss_distance = miss.length();
+            if miss_distance >= combined_radius {
+                return None;
+            }
+            // head on collisions are dodged to the right, which is the other way for the neighbour
+            let away = (-miss)
+                .try_normalize()
+                .unwrap_or_else(|| -relative_velocity.perp().normalize());
+            Some(away * (combined_radius - miss_distance) / time * 0.5)
+        })
+        .fold(Vec2::ZERO, |total, push| total + push)
+}
+
+/// `apply_steering` moves `velocity` towards `desired`, limited by the acceleration and speed of
+/// the agent
+///
+/// Arguments:
+///
+/// * `velocity`: The current velocity.
+/// * `desired`: The velocity the agent wants.
+/// * `max_speed`: The speed of the agent.
+/// * `max_acceleration`: The acceleration of the agent.
+/// * `delta`: The seconds since the last frame.
+pub fn apply_steering(
+    velocity: Vec2,
+    desired: Vec2,
+    max_speed: f32,
+    max_acceleration: f32,
+    delta: f32,
+) -> Vec2 {
+    let steering =
+        (desired.clamp_length_max(max_speed) - velocity).clamp_length_max(max_acceleration * delta);
+    (velocity + steering).clamp_length_max(max_speed)
+}
+
+/// `steer_agents` works out the velocity of every agent. agents seek the next waypoint of their
+/// `NavPath` or flow field and arrive at their destination, then keep their distance from other
+/// agents and obstacles. agents without a destination brake but still get out of the way
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &Agent, &Transform, &mut Velocity, &MaxSpeed, &MaxAcceleration, Option<&mut NavPath>, Option<&FlowFollower>)> - query containing agents and how they move.
+/// * `obstacles`: Query<(&Obstacle, &Transform)> - query containing every obstacle.
+/// * `grid`: Res<NavGrid> - resource containing the navigation grid the paths are on.
+/// * `flow_fields`: Res<FlowFields> - resource containing the flow fields of crowds.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index used to find neighbours.
+/// * `settings`: Res<SteeringSettings> - resource containing the steering tuning.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+fn steer_agents(
+    mut agents: Query<(
+        Entity,
+        &Agent,
+        &Transform,
+        &mut Velocity,
+        &MaxSpeed,
+        &MaxAcceleration,
+        Option<&mut NavPath>,
+        Option<&FlowFollower>,
+    )>,
+    obstacles: Query<(&Obstacle, &Transform)>,
+    grid: Res<NavGrid>,
+    flow_fields: Res<FlowFields>,
+    index: Res<SpatialIndex>,
+    settings: Res<SteeringSettings>,
+    clock: Res<SimClock>,
+) {
+    let snapshot: HashMap<Entity, (Vec2, Vec2)> = agents
+        .iter()
+        .map(|(entity, _, transform, velocity, ..)| {
+            (entity, (transform.translation.truncate(), velocity.0))
+        })
+        .collect();
+
+    for (entity, agent, transform, mut velocity, max_speed, max_acceleration, path, follower) in
+        agents.iter_mut()
+    {
+        let position = transform.translation.truncate();
+        let max_speed = max_speed.0;
+
+        let mut desired = match agent.destination {
+            Some(destination) => {
+                let mut path = path.filter(|path| path.goal == destination);
+                if let Some(path) = path.as_mut() {
+                    let reached = path.waypoints.len() > 1
+                        && path.waypoints[0].distance(position) <= settings.waypoint_radius;
+                    if reached {
+                        path.waypoints.remove(0);
+                    }
+                }
+                let waypoint = match follower {
+                    Some(follower) => flow_fields.next_step(&grid, follower.zone, position),
+                    None => path
+                        .as_ref()
+                        .and_then(|path| path.next_waypoint())
+                        .filter(|waypoint| *waypoint != destination),
+                };
+                match waypoint {
+                    Some(waypoint) => seek(position, waypoint, max_speed),
+                    None => arrive(position, destination, max_speed, settings.slowing_radius),
+                }
+            }
+            None => Vec2::ZERO,
+        };
+
+        let neighbours: Vec<(Vec2, Vec2)> = index
+            .query_radius(position, settings.neighbour_radius)
+            .filter(|(other, _)| *other != entity)
+            .filter_map(|(other, _)| snapshot.get(&other).copied())
+            .collect();
+        desired += separation(
+            position,
+            neighbours.iter().map(|(position, _)| *position),
+            settings.separation_radius,
+            max_speed,
+        );
+        desired += avoid_obstacles(
+            position,
+            desired,
+            obstacles
+                .iter()
+                .map(|(obstacle, transform)| (transform.translation.truncate(), obstacle)),
+            settings.obstacle_margin,
+            max_speed,
+        );
+        desired += avoid_agents(
+            position,
+            desired,
+            neighbours.into_iter(),
+            settings.radius,
+            settings.time_horizon,
+        );
+
+        velocity.0 = apply_steering(
+            velocity.0,
+            desired,
+            max_speed,
+            max_acceleration.0,
+            clock.delta_seconds(),
+        );
+    }
+}
@@ -0,0 +1,611 @@
+use crate::agent::{Agent, Faction};
+use crate::behaviour::BehaviourTree;
+use crate::clock::{SimClock, TimeOfDay};
+use crate::goap::GoapAgent;
+use crate::memory::Beliefs;
+use crate::needs::{Amenity, NeedKind, NeedSettings, Needs};
+use crate::schedule::{DailySchedule, ScheduleAction, ScheduledActivity};
+use crate::zone::{ancestors, Zone};
+use crate::{GameState, SimLabel, SimStage};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub struct UtilityPlugin;
+
+impl Plugin for UtilityPlugin {
+    /// `build` adds the default utility actions and the system that lets every agent with a
+    /// `UtilityBrain` pick the best scoring action
+    ///
+    /// Arguments:
+    ///
+    /// * `app`: The application instance.
+    fn build(&self, app: &mut App) {
+        app.insert_resource(UtilityActions::default_actions())
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing).with_system(
+                    choose_actions
+                        .label(SimLabel::ChooseAction)
+                        .after(SimLabel::UpdateNeeds)
+                        .after(SimLabel::AdvanceClock)
+                        .before(SimLabel::UpdateAgent),
+                ),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `ResponseCurve` turns the input of a consideration, between `0.0` and `1.0`, into a score
+/// between `0.0` and `1.0`.
+pub enum ResponseCurve {
+    /// `slope * x + offset`
+    Linear { slope: f32, offset: f32 },
+    /// `slope * x^exponent + offset`
+    Polynomial {
+        slope: f32,
+        exponent: f32,
+        offset: f32,
+    },
+    /// an s-curve centered on `midpoint`, a negative `steepness` flips the curve
+    Logistic { steepness: f32, midpoint: f32 },
+    /// `1.0` between `start` and `end` and `0.0` everywhere else, wraps around if `start > end`
+    Window { start: f32, end: f32 },
+}
+
+impl ResponseCurve {
+    /// `evaluate` runs the curve for an input
+    ///
+    /// Arguments:
+    ///
+    /// * `x`: The input, clamped to `0.0..=1.0`.
+    ///
+    /// Returns:
+    ///
+    /// The score, clamped to `0.0..=1.0`.
+    pub fn evaluate(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        let y = match *self {
+            ResponseCurve::Linear { slope, offset } => slope * x + offset,
+            ResponseCurve::Polynomial {
+                slope,
+                exponent,
+                offset,
+            } => slope * x.powf(exponent) + offset,
+            ResponseCurve::Logistic {
+                steepness,
+                midpoint,
+            } => 1.0 / (1.0 + (-steepness * (x - midpoint)).exp()),
+            ResponseCurve::Window { start, end } => {
+                let inside = if start <= end {
+                    x >= start && x <= end
+                } else {
+                    x >= start || x <= end
+                };
+                if inside {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `Consideration` is one input to the score of an action, always normalized to `0.0..=1.0`.
+pub enum Consideration {
+    /// the level of a need of the agent, `1.0` if the agent has no needs
+    Need(NeedKind),
+    /// the distance to the target of the action divided by `max_distance`
+    DistanceToTarget { max_distance: f32 },
+    /// how far through the day it is, `0.0` at midnight
+    TimeOfDay,
+    /// `1.0` while the schedule of the agent says to do an activity, `0.0` otherwise or without a
+    /// schedule
+    Scheduled(ScheduledActivity),
+    /// a fixed input, useful as a base score
+    Constant(f32),
+}
+
+impl Consideration {
+    /// `input` gets the normalized input of the consideration
+    ///
+    /// Arguments:
+    ///
+    /// * `context`: What the agent knows about itself and the world.
+    /// * `target`: Where the action would take the agent.
+    pub fn input(&self, context: &UtilityContext, target: &ActionTarget) -> f32 {
+        let input = match *self {
+            Consideration::Need(need) => context.needs.map_or(1.0, |needs| needs.get(need)),
+            Consideration::DistanceToTarget { max_distance } => match target.position() {
+                Some(position) => context.position.distance(position) / max_distance,
+                None => 0.0,
+            },
+            Consideration::TimeOfDay => context.time_of_day,
+            Consideration::Scheduled(activity) => match context.schedule {
+                Some(schedule) if schedule.activity_at(context.time_of_day * 24.0) == activity => {
+                    1.0
+                }
+                _ => 0.0,
+            },
+            Consideration::Constant(value) => value,
+        };
+        input.clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `ScoredConsideration` is a consideration with the curve used to score it.
+pub struct ScoredConsideration {
+    pub consideration: Consideration,
+    pub curve: ResponseCurve,
+}
+
+impl ScoredConsideration {
+    pub fn new(consideration: Consideration, curve: ResponseCurve) -> Self {
+        ScoredConsideration {
+            consideration,
+            curve,
+        }
+    }
+
+    /// `score` scores the consideration for a target
+    pub fn score(&self, context: &UtilityContext, target: &ActionTarget) -> f32 {
+        self.curve
+            .evaluate(self.consideration.input(context, target))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `ActionTarget` is where an action wants to take the agent.
+pub enum ActionTarget {
+    /// the agent stays where it is
+    Stay,
+    /// the agent walks to the center of a zone
+    Zone(Entity, Vec2),
+    /// the agent walks to a point in the world
+    Position(Vec2),
+}
+
+impl ActionTarget {
+    /// `position` is the point the agent walks to, `None` if it stays
+    pub fn position(&self) -> Option<Vec2> {
+        match *self {
+            ActionTarget::Stay => None,
+            ActionTarget::Zone(_, position) | ActionTarget::Position(position) => Some(position),
+        }
+    }
+
+    /// `zone` is the zone the agent walks to, if any
+    pub fn zone(&self) -> Option<Entity> {
+        match *self {
+            ActionTarget::Zone(zone, _) => Some(zone),
+            _ => None,
+        }
+    }
+}
+
+/// `ZoneInfo` is what an agent knows of a zone while choosing an action.
+pub struct ZoneInfo<'a> {
+    pub entity: Entity,
+    pub zone: &'a Zone,
+    pub center: Vec2,
+    pub amenity: Option<&'a Amenity>,
+    /// the agent heard the zone is closed
+    pub closed: bool,
+    /// the zones the zone is part of, innermost first
+    pub ancestors: Vec<&'a Zone>,
+}
+
+impl<'a> ZoneInfo<'a> {
+    /// `is_open` checks if the zone and every zone it is part of are open at an hour of the day,
+    /// and that the agent has not heard otherwise
+    pub fn is_open(&self, hours: f32) -> bool {
+        !self.closed
+            && std::iter::once(self.zone)
+                .chain(self.ancestors.iter().copied())
+                .all(|zone| zone.is_open(hours))
+    }
+
+    /// `allows` checks if an agent may enter the zone and every zone it is part of
+    pub fn allows(&self, agent: Entity, faction: Option<&Faction>) -> bool {
+        std::iter::once(self.zone)
+            .chain(self.ancestors.iter().copied())
+            .all(|zone| zone.access.allows(agent, faction))
+    }
+}
+
+/// `UtilityContext` is everything an action can use to score itself for one agent.
+pub struct UtilityContext<'a> {
+    pub agent: Entity,
+    pub position: Vec2,
+    /// the name of the action the agent is doing, empty before its first choice
+    pub activity: &'a str,
+    pub needs: Option<&'a Needs>,
+    /// the thresholds of every need
+    pub need_settings: &'a NeedSettings,
+    pub faction: Option<&'a Faction>,
+    pub schedule: Option<&'a DailySchedule>,
+    /// how far through the day it is, `0.0` at midnight
+    pub time_of_day: f32,
+    pub zones: &'a [ZoneInfo<'a>],
+}
+
+impl<'a> UtilityContext<'a> {
+    /// `nearest_zone` finds the closest zone matching a filter
+    ///
+    /// Arguments:
+    ///
+    /// * `filter`: Which zones to consider.
+    pub fn nearest_zone(&self, filter: impl Fn(&ZoneInfo) -> bool) -> Option<&ZoneInfo<'a>> {
+        self.zones
+            .iter()
+            .filter(|info| filter(info))
+            .min_by(|a, b| {
+                a.center
+                    .distance(self.position)
+                    .total_cmp(&b.center.distance(self.position))
+            })
+    }
+}
+
+/// `combine_scores` multiplies scores together, compensating for the number of scores so actions
+/// with many considerations are not punished for it
+///
+/// Arguments:
+///
+/// * `scores`: The scores to combine, each between `0.0` and `1.0`.
+pub fn combine_scores(scores: impl IntoIterator<Item = f32>) -> f32 {
+    let scores: Vec<f32> = scores.into_iter().collect();
+    if scores.is_empty() {
+        return 0.0;
+    }
+    let modification = 1.0 - 1.0 / scores.len() as f32;
+    scores
+        .into_iter()
+        .map(|score| score + (1.0 - score) * modification * score)
+        .product()
+}
+
+/// `UtilityAction` is something an agent can decide to do. implement it to add actions from other
+/// crates and register them with `UtilityActions::add`.
+pub trait UtilityAction: Send + Sync + 'static {
+    /// the name of the action, also used as the activity of the agent
+    fn name(&self) -> &str;
+
+    /// the considerations that make up the score of the action
+    fn considerations(&self) -> &[ScoredConsideration];
+
+    /// where the action takes the agent, `None` if the action is not possible right now
+    fn target(&self, context: &UtilityContext) -> Option<ActionTarget>;
+
+    /// multiplies the score of the action
+    fn weight(&self) -> f32 {
+        1.0
+    }
+
+    /// `score` scores the action for a target, by default the combined score of every
+    /// consideration multiplied by the weight
+    fn score(&self, context: &UtilityContext, target: &ActionTarget) -> f32 {
+        combine_scores(
+            self.considerations()
+                .iter()
+                .map(|consideration| consideration.score(context, target)),
+        ) * self.weight()
+    }
+}
+
+/// `NeedAction` sends the agent to the nearest open zone that serves a need and lets the agent in.
+/// the action gets urgent once the need drops below its `critical` level in the `NeedSettings`,
+/// and an agent that took it keeps at it until the need is back up to its `satisfied` level.
+///
+/// Properties:
+///
+/// * `name`: The name of the action.
+/// * `need`: The need the action satisfies.
+/// * `steepness`: How sharply the action gets urgent around the critical level of the need.
+/// * `considerations`: Every other consideration of the action, the need is scored on top of them.
+pub struct NeedAction {
+    pub name: String,
+    pub need: NeedKind,
+    pub steepness: f32,
+    pub considerations: Vec<ScoredConsideration>,
+}
+
+impl NeedAction {
+    /// `new` creates an action that gets more urgent the lower the need is and less attractive the
+    /// further away the amenity is
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the action.
+    /// * `need`: The need the action satisfies.
+    pub fn new(name: impl Into<String>, need: NeedKind) -> Self {
+        NeedAction {
+            name: name.into(),
+            need,
+            steepness: 12.0,
+            considerations: vec![ScoredConsideration::new(
+                Consideration::DistanceToTarget {
+                    max_distance: 20000.0,
+                },
+                ResponseCurve::Linear {
+                    slope: -0.5,
+                    offset: 1.0,
+                },
+            )],
+        }
+    }
+
+    /// `urgency` scores the need of the agent. it is centered on the critical level of the need,
+    /// but while the agent is already seeing to the need it stays at `1.0` until the need is
+    /// satisfied and then drops to `0.0`
+    ///
+    /// Arguments:
+    ///
+    /// * `context`: What the agent knows about itself and the world.
+    /// * `target`: Where the action would take the agent.
+    pub fn urgency(&self, context: &UtilityContext, target: &ActionTarget) -> f32 {
+        let config = context.need_settings.get(self.need);
+        let level = Consideration::Need(self.need).input(context, target);
+        if context.activity == self.name {
+            return if level < config.satisfied { 1.0 } else { 0.0 };
+        }
+        ResponseCurve::Logistic {
+            steepness: -self.steepness,
+            midpoint: config.critical,
+        }
+        .evaluate(level)
+    }
+}
+
+impl UtilityAction for NeedAction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn considerations(&self) -> &[ScoredConsideration] {
+        &self.considerations
+    }
+
+    fn score(&self, context: &UtilityContext, target: &ActionTarget) -> f32 {
+        combine_scores(
+            std::iter::once(self.urgency(context, target)).chain(
+                self.considerations
+                    .iter()
+                    .map(|consideration| consideration.score(context, target)),
+            ),
+        ) * self.weight()
+    }
+
+    fn target(&self, context: &UtilityContext) -> Option<ActionTarget> {
+        context
+            .nearest_zone(|info| {
+                info.zone.kind.serves(self.need)
+                    && info.is_open(context.time_of_day * 24.0)
+                    && info.allows(context.agent, context.faction)
+            })
+            .map(|info| ActionTarget::Zone(info.entity, info.center))
+    }
+}
+
+/// `IdleAction` keeps the agent where it is, its score is the bar every other action has to beat.
+pub struct IdleAction {
+    pub considerations: Vec<ScoredConsideration>,
+}
+
+impl Default for IdleAction {
+    fn default() -> Self {
+        IdleAction {
+            considerations: vec![ScoredConsideration::new(
+                Consideration::Constant(0.1),
+                ResponseCurve::Linear {
+                    slope: 1.0,
+                    offset: 0.0,
+                },
+            )],
+        }
+    }
+}
+
+impl UtilityAction for IdleAction {
+    fn name(&self) -> &str {
+        "idle"
+    }
+
+    fn considerations(&self) -> &[ScoredConsideration] {
+        &self.considerations
+    }
+
+    fn target(&self, _context: &UtilityContext) -> Option<ActionTarget> {
+        Some(ActionTarget::Stay)
+    }
+}
+
+#[derive(Default)]
+/// `UtilityActions` is a resource with every action agents can choose from.
+pub struct UtilityActions {
+    actions: Vec<Box<dyn UtilityAction>>,
+}
+
+impl UtilityActions {
+    /// `default_actions` has an action for every need, every scheduled activity and the idle
+    /// action
+    pub fn default_actions() -> Self {
+        let mut actions = UtilityActions::default();
+        actions
+            .add(NeedAction::new("eat", NeedKind::Hunger))
+            .add(NeedAction::new("sleep", NeedKind::Energy))
+            .add(NeedAction::new("socialise", NeedKind::Social))
+            .add(NeedAction::new("wash", NeedKind::Hygiene));
+        for activity in ScheduledActivity::ALL {
+            actions.add(ScheduleAction::new(activity));
+        }
+        actions.add(IdleAction::default());
+        actions
+    }
+
+    /// `add` registers a new action
+    ///
+    /// Arguments:
+    ///
+    /// * `action`: The action to add.
+    pub fn add(&mut self, action: impl UtilityAction) -> &mut Self {
+        self.actions.push(Box::new(action));
+        self
+    }
+
+    /// `iter` iterates over every registered action
+    pub fn iter(&self) -> impl Iterator<Item = &dyn UtilityAction> {
+        self.actions.iter().map(|action| action.as_ref())
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Serialize, Deserialize)]
+/// `UtilityBrain` lets an agent choose its own actions.
+///
+/// Properties:
+///
+/// * `think_interval`: how many seconds between choosing actions.
+/// * `inertia`: multiplies the score of the current action so agents do not flip between actions.
+/// * `next_think`: seconds until the agent chooses again.
+pub struct UtilityBrain {
+    pub think_interval: f32,
+    pub inertia: f32,
+    pub next_think: f32,
+}
+
+impl Default for UtilityBrain {
+    fn default() -> Self {
+        UtilityBrain {
+            think_interval: 1.0,
+            inertia: 1.25,
+            next_think: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Component, Clone, PartialEq, Default)]
+/// `Activity` is what the agent is currently doing.
+///
+/// Properties:
+///
+/// * `action`: the name of the chosen action, empty before the first choice.
+/// * `target`: the zone the action takes the agent to, if any.
+pub struct Activity {
+    pub action: String,
+    pub target: Option<Entity>,
+}
+
+/// `choose_actions` scores every action for every agent whose brain is ready to think and applies
+/// the best one to the destination and activity of the agent. agents with `Beliefs` only consider
+/// the zones they know about. agents with a `BehaviourTree` or a `GoapAgent` are driven by their
+/// tree or planner instead
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &mut Agent, &mut UtilityBrain, &mut Activity, &Transform, Option<&Needs>, Option<&Faction>, Option<&DailySchedule>, Option<&Beliefs>), (Without<BehaviourTree>, Without<GoapAgent>)> - query containing agents with a brain.
+/// * `zones`: Query<(Entity, &Zone, &Transform, Option<&Amenity>)> - query containing every zone, for agents without `Beliefs`.
+/// * `actions`: Res<UtilityActions> - resource containing every action.
+/// * `need_settings`: Res<NeedSettings> - resource containing the thresholds of every need.
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+fn choose_actions(
+    mut agents: Query<
+        (
+            Entity,
+            &mut Agent,
+            &mut UtilityBrain,
+            &mut Activity,
+            &Transform,
+            Option<&Needs>,
+            Option<&Faction>,
+            Option<&DailySchedule>,
+            Option<&Beliefs>,
+        ),
+        (Without<BehaviourTree>, Without<GoapAgent>),
+    >,
+    zones: Query<(Entity, &Zone, &Transform, Option<&Amenity>)>,
+    actions: Res<UtilityActions>,
+    need_settings: Res<NeedSettings>,
+    time_of_day: Res<TimeOfDay>,
+    clock: Res<SimClock>,
+) {
+    let zone_infos: Vec<ZoneInfo> = zones
+        .iter()
+        .map(|(entity, zone, transform, amenity)| ZoneInfo {
+            entity,
+            zone,
+            center: transform.translation.truncate(),
+            amenity,
+            closed: false,
+            ancestors: ancestors(zone, |parent| {
+                zones.get(parent).ok().map(|(_, zone, _, _)| zone)
+            })
+            .into_iter()
+            .map(|(_, zone)| zone)
+            .collect(),
+        })
+        .collect();
+
+    for (
+        entity,
+        mut agent,
+        mut brain,
+        mut activity,
+        transform,
+        needs,
+        faction,
+        schedule,
+        beliefs,
+    ) in agents.iter_mut()
+    {
+        brain.next_think -= clock.delta_seconds();
+        if brain.next_think > 0.0 {
+            continue;
+        }
+        brain.next_think = brain.think_interval;
+
+        let believed;
+        let zones: &[ZoneInfo] = match beliefs {
+            Some(beliefs) => {
+                believed = beliefs.zone_infos();
+                &believed
+            }
+            None => &zone_infos,
+        };
+        let context = UtilityContext {
+            agent: entity,
+            position: transform.translation.truncate(),
+            activity: &activity.action,
+            needs,
+            need_settings: &need_settings,
+            faction,
+            schedule,
+            time_of_day: time_of_day.fraction(),
+            zones,
+        };
+
+        let best = actions
+            .iter()
+            .filter_map(|action| {
+                let target = action.target(&context)?;
+                let mut score = action.score(&context, &target);
+                if action.name() == activity.action {
+                    score *= brain.inertia;
+                }
+                Some((action, target, score))
+            })
+            .max_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+
+        if let Some((action, target, _)) = best {
+            if action.name() == activity.action && target.zone() == activity.target {
+                continue;
+            }
+            activity.action = action.name().to_string();
+            activity.target = target.zone();
+            agent.destination = target.position();
+        }
+    }
+}
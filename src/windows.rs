@@ -1,8 +1,24 @@
 use crate::agent::Agent;
+use crate::clock::{SimClock, TimeOfDay};
+use crate::interaction::SocialInteraction;
+use crate::memory::{Beliefs, EpisodicMemory};
+use crate::needs::{NeedKind, Needs};
+use crate::perception::{Perceived, Perception};
+use crate::relationships::{Relationship, SocialGraph};
+use crate::rumour::Rumours;
+use crate::schedule::DailySchedule;
+use crate::utility::Activity;
+use crate::zone::Zone;
 use crate::GameState;
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContext};
 
+/// how many relationships the agent window shows
+const TOP_RELATIONSHIPS: usize = 5;
+
+/// how many of the newest memories the agent window shows
+const RECENT_EPISODES: usize = 5;
+
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
@@ -28,19 +44,198 @@ pub struct UiStates {
 /// Arguments:
 ///
 /// * `ui_states`: ResMut<UiStates> - resource containing a list of entities that are being rendered in the user interface.
-/// * `agents`: Query<(&mut Agent, &mut Transform)> - query containing agents and their transforms.
+/// * `agents`: Query<(&mut Agent, &mut Transform, Option<&Needs>, Option<&Activity>, Option<&DailySchedule>, Option<&SocialInteraction>)> - query containing agents, their transforms, needs, activity, schedule and interaction.
+/// * `minds`: Query<(Option<&EpisodicMemory>, Option<&Beliefs>, Option<&Rumours>)> - query containing what agents remember, the zones they know and the rumours they heard.
+/// * `senses`: Query<(&Perception, &Perceived)> - query containing what agents can see and hear.
+/// * `zones`: Query<&Zone> - query containing every zone, used to name the zones agents remember.
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day, used to show what the schedule says.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to show how long ago things happened.
+/// * `graph`: Res<SocialGraph> - resource containing the relationships of every agent.
 /// * `egui_context`: ResMut<EguiContext> - resource containing the context for the Egui user interface.
 fn render_ui(
     ui_states: ResMut<UiStates>,
-    mut agents: Query<(&mut Agent, &mut Transform)>,
+    mut agents: Query<(
+        &mut Agent,
+        &mut Transform,
+        Option<&Needs>,
+        Option<&Activity>,
+        Option<&DailySchedule>,
+        Option<&SocialInteraction>,
+    )>,
+    minds: Query<(Option<&EpisodicMemory>, Option<&Beliefs>, Option<&Rumours>)>,
+    senses: Query<(&Perception, &Perceived)>,
+    zones: Query<&Zone>,
+    time_of_day: Res<TimeOfDay>,
+    clock: Res<SimClock>,
+    graph: Res<SocialGraph>,
     mut egui_context: ResMut<EguiContext>,
 ) {
     for entity in ui_states.agents.iter() {
+        let relationships: Vec<(String, Relationship)> = graph
+            .top_relationships(*entity, TOP_RELATIONSHIPS)
+            .into_iter()
+            .filter_map(|(other, relationship)| {
+                let (other, ..) = agents.get(other).ok()?;
+                Some((other.name.clone(), *relationship))
+            })
+            .collect();
+        let interaction = agents
+            .get(*entity)
+            .ok()
+            .and_then(|(.., interaction)| interaction.copied())
+            .map(|interaction| {
+                let partner = agents
+                    .get(interaction.partner)
+                    .map_or_else(|_| "someone".to_string(), |(other, ..)| other.name.clone());
+                (interaction, partner)
+            });
+        let (memory, beliefs, rumours) = minds.get(*entity).unwrap_or_default();
+        let name = |entity: Entity| {
+            agents
+                .get(entity)
+                .map(|(agent, ..)| agent.name.clone())
+                .or_else(|_| zones.get(entity).map(|zone| zone.name.clone()))
+                .ok()
+        };
+        let memories: Vec<String> = memory
+            .into_iter()
+            .flat_map(|memory| memory.recent(RECENT_EPISODES))
+            .map(|episode| {
+                format!(
+                    "{} ({:.0}s ago)",
+                    episode.event.describe(name),
+                    clock.seconds_since(episode.tick)
+                )
+            })
+            .collect();
+        let known_zones: Option<Vec<String>> = beliefs.map(|beliefs| {
+            beliefs
+                .zones
+                .values()
+                .map(|belief| {
+                    format!(
+                        "{} ({}, seen {:.0}s ago{})",
+                        belief.zone.name,
+                        belief.zone.kind.name(),
+                        clock.seconds_since(belief.seen),
+                        if belief.closed { ", heard closed" } else { "" }
+                    )
+                })
+                .collect()
+        });
+        let senses = senses.get(*entity).ok().map(|(perception, perceived)| {
+            let names = |entities: Vec<&Entity>| -> String {
+                let names: Vec<String> = entities
+                    .into_iter()
+                    .filter_map(|entity| name(*entity))
+                    .collect();
+                if names.is_empty() {
+                    "nothing".to_string()
+                } else {
+                    names.join(", ")
+                }
+            };
+            (
+                *perception,
+                names(perceived.seen.iter().collect()),
+                names(perceived.heard.difference(&perceived.seen).collect()),
+            )
+        });
+        let heard: Vec<String> = rumours
+            .into_iter()
+            .flat_map(|rumours| rumours.known.values())
+            .map(|rumour| {
+                format!(
+                    "{} (confidence {:.2}, {} hops)",
+                    rumour.fact.describe(name),
+                    rumour.confidence,
+                    rumour.hops
+                )
+            })
+            .collect();
+
         egui::Window::new("Agent Info").show(egui_context.ctx_mut(), |ui| {
-            let (mut agent, mut agent_transform): (Mut<Agent>, Mut<Transform>) =
-                agents.get_mut(*entity).unwrap();
+            let (mut agent, mut agent_transform, needs, activity, schedule, _): (
+                Mut<Agent>,
+                Mut<Transform>,
+                Option<&Needs>,
+                Option<&Activity>,
+                Option<&DailySchedule>,
+                Option<&SocialInteraction>,
+            ) = agents.get_mut(*entity).unwrap();
 
             ui.strong(format!("agent {}", agent.name));
+            if let Some(activity) = activity {
+                ui.label(format!("activity: {}", activity.action));
+            }
+            if let Some((interaction, partner)) = &interaction {
+                ui.label(format!(
+                    "{} with {} ({:.0}s left)",
+                    interaction.kind,
+                    partner,
+                    interaction.remaining()
+                ));
+            }
+            if let Some(schedule) = schedule {
+                ui.collapsing("schedule", |ui| {
+                    ui.label(format!("now: {}", schedule.activity_at(time_of_day.hours)));
+                    for block in schedule.blocks.iter() {
+                        ui.label(format!(
+                            "{} {:05.2} - {:05.2}",
+                            block.activity, block.start, block.end
+                        ));
+                    }
+                });
+            }
+
+            if !relationships.is_empty() {
+                ui.collapsing("relationships", |ui| {
+                    for (name, relationship) in relationships.iter() {
+                        ui.label(format!("{} ({})", name, relationship.kind));
+                        ui.label(format!(
+                            "affinity {:+.2} familiarity {:.2} trust {:.2}",
+                            relationship.affinity, relationship.familiarity, relationship.trust
+                        ));
+                    }
+                });
+            }
+
+            if !memories.is_empty() {
+                ui.collapsing("memories", |ui| {
+                    for memory in memories.iter() {
+                        ui.label(memory);
+                    }
+                });
+            }
+            if let Some(known_zones) = &known_zones {
+                ui.collapsing("known zones", |ui| {
+                    if known_zones.is_empty() {
+                        ui.label("none yet");
+                    }
+                    for zone in known_zones.iter() {
+                        ui.label(zone);
+                    }
+                });
+            }
+            if let Some((perception, seen, only_heard)) = &senses {
+                ui.collapsing("perception", |ui| {
+                    ui.label(format!(
+                        "sees {:.0} units over {:.0} degrees, hears {:.0} units",
+                        perception.view_distance,
+                        perception.field_of_view,
+                        perception.hearing_radius
+                    ));
+                    ui.label(format!("seen: {}", seen));
+                    ui.label(format!("only heard: {}", only_heard));
+                });
+            }
+            if !heard.is_empty() {
+                ui.collapsing("rumours", |ui| {
+                    for rumour in heard.iter() {
+                        ui.label(rumour);
+                    }
+                });
+            }
 
             ui.collapsing("position", |ui| {
                 ui.label(format!(
@@ -59,6 +254,18 @@ fn render_ui(
                 }
             });
 
+            if let Some(needs) = needs {
+                ui.collapsing("needs", |ui| {
+                    for need in NeedKind::ALL {
+                        ui.add(egui::ProgressBar::new(needs.get(need)).text(format!(
+                            "{} {:.0}%",
+                            need.name(),
+                            needs.get(need) * 100.0
+                        )));
+                    }
+                });
+            }
+
             ui.collapsing("agent", |ui| {
                 let mut destination_toggled = agent.destination.is_some();
                 let _destination_toggle =
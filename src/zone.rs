@@ -1,137 +1,1657 @@
-use crate::agent::Agent;
+use crate::agent::{Agent, Faction};
+use crate::clock::{SimClock, TimeOfDay};
+use crate::needs::{Amenity, NeedKind};
+use crate::spatial::SpatialIndex;
 
-use crate::GameState;
+use crate::{GameState, SimLabel, SimStage};
 use bevy::prelude::*;
 
 use bevy_prototype_lyon::prelude::*;
 
-use itertools::Itertools;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::hash::{Hash, Hasher};
 
 pub struct ZonePlugin;
 
 impl Plugin for ZonePlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(AgentZoneMapping::default())
-            .add_system_set(SystemSet::on_enter(GameState::Playing).with_system(spawn_test_zone))
-            .add_system_set(SystemSet::on_update(GameState::Playing).with_system(update_zones));
+            .add_event::<AgentEnteredZone>()
+            .add_event::<AgentExitedZone>()
+            .add_event::<ZoneRefused>()
+            .init_resource::<ZoneQueues>()
+            .add_system_set_to_stage(
+                SimStage,
+                SystemSet::on_update(GameState::Playing)
+                    .with_system(
+                        enforce_zone_rules
+                            .label(SimLabel::EnforceZoneRules)
+                            .after(SimLabel::ChooseAction)
+                            .before(SimLabel::UpdateFlowFields)
+                            .before(SimLabel::PlanPaths),
+                    )
+                    .with_system(
+                        update_zones
+                            .label(SimLabel::UpdateZones)
+                            .after(SimLabel::UpdateAgent)
+                            .after(SimLabel::UpdateSpatialIndex),
+                    ),
+            );
     }
 }
 
+pub struct ZoneVisualsPlugin;
+
+impl Plugin for ZoneVisualsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Playing).with_system(attach_zone_shapes),
+        );
+    }
+}
+
+/// `PointInShape` is implemented by every shape a zone can take. points are relative to the
+/// center of the shape.
+pub trait PointInShape {
+    /// `contains_point` checks if a point is inside the shape
+    fn contains_point(&self, point: Vec2) -> bool;
+
+    /// `bounds` is the bottom left and top right corner of a box around the whole shape
+    fn bounds(&self) -> (Vec2, Vec2);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `ZoneShape` is the area a zone covers, relative to the center of the zone.
+pub enum ZoneShape {
+    /// an axis aligned rectangle centered on the zone
+    Rectangle { width: f32, height: f32 },
+    /// a circle centered on the zone
+    Circle { radius: f32 },
+    /// a simple polygon, its points can be in either winding order
+    Polygon { points: Vec<Vec2> },
+    /// every point inside any of the shapes
+    Union(Vec<ZoneShape>),
+    /// every point inside `base` but not inside any of `cuts`
+    Difference {
+        base: Box<ZoneShape>,
+        cuts: Vec<ZoneShape>,
+    },
+}
+
+/// `CIRCLE_SEGMENTS` is how many straight sides circles are drawn with
+const CIRCLE_SEGMENTS: usize = 48;
+
+impl PointInShape for ZoneShape {
+    fn contains_point(&self, point: Vec2) -> bool {
+        match self {
+            ZoneShape::Rectangle { width, height } => {
+                point.x.abs() <= width / 2.0 && point.y.abs() <= height / 2.0
+            }
+            ZoneShape::Circle { radius } => point.length_squared() <= radius * radius,
+            ZoneShape::Polygon { points } => {
+                // even-odd rule, count how many edges a ray going right from the point crosses
+                let mut inside = false;
+                for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+                    if (a.y > point.y) != (b.y > point.y)
+                        && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                    {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+            ZoneShape::Union(shapes) => shapes.iter().any(|shape| shape.contains_point(point)),
+            ZoneShape::Difference { base, cuts } => {
+                base.contains_point(point) && !cuts.iter().any(|cut| cut.contains_point(point))
+            }
+        }
+    }
+
+    fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            ZoneShape::Rectangle { width, height } => {
+                let half = Vec2::new(*width, *height) / 2.0;
+                (-half, half)
+            }
+            ZoneShape::Circle { radius } => (-Vec2::splat(*radius), Vec2::splat(*radius)),
+            ZoneShape::Polygon { points } => points.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), point| (min.min(*point), max.max(*point)),
+            ),
+            ZoneShape::Union(shapes) => shapes.iter().map(PointInShape::bounds).fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), (shape_min, shape_max)| (min.min(shape_min), max.max(shape_max)),
+            ),
+            ZoneShape::Difference { base, .. } => base.bounds(),
+        }
+    }
+}
+
+impl ZoneShape {
+    /// `problem` describes what is wrong with a shape that can not cover any area
+    ///
+    /// Returns:
+    ///
+    /// The end of a sentence about the shape, or `None` if the shape is fine.
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            ZoneShape::Rectangle { width, height } if !width.is_finite() || !height.is_finite() => {
+                Some(format!(
+                    "is a {} by {} rectangle, both have to be finite",
+                    width, height
+                ))
+            }
+            ZoneShape::Rectangle { width, height } if *width <= 0.0 || *height <= 0.0 => {
+                Some(format!(
+                    "is a {} by {} rectangle, both have to be above 0",
+                    width, height
+                ))
+            }
+            ZoneShape::Circle { radius } if !(*radius > 0.0 && radius.is_finite()) => {
+                Some(format!(
+                    "is a circle with radius {}, it has to be finite and above 0",
+                    radius
+                ))
+            }
+            ZoneShape::Polygon { points } if points.len() < 3 => Some(format!(
+                "is a polygon with {} points, it needs at least 3",
+                points.len()
+            )),
+            ZoneShape::Polygon { points } if points.iter().any(|point| !point.is_finite()) => {
+                Some("is a polygon with a point that is not finite".to_string())
+            }
+            ZoneShape::Polygon { points } if signed_area(points).abs() <= f32::EPSILON => {
+                Some("is a polygon with no area, its points are all on one line".to_string())
+            }
+            ZoneShape::Union(shapes) if shapes.is_empty() => {
+                Some("is a union of no shapes".to_string())
+            }
+            ZoneShape::Union(shapes) => shapes.iter().find_map(ZoneShape::problem),
+            ZoneShape::Difference { base, cuts } => base
+                .problem()
+                .or_else(|| cuts.iter().find_map(ZoneShape::problem)),
+            _ => None,
+        }
+    }
+
+    /// `outlines` turns the shape into closed outlines for drawing. filled areas wind counter
+    /// clockwise and holes clockwise. the outlines of unions and differences are traced around
+    /// the area they cover, so they never cross or overlap and can be filled with either fill rule
+    pub fn outlines(&self) -> Vec<Vec<Vec2>> {
+        match self {
+            ZoneShape::Rectangle { width, height } => {
+                let half = Vec2::new(*width, *height) / 2.0;
+                vec![vec![
+                    Vec2::new(-half.x, -half.y),
+                    Vec2::new(half.x, -half.y),
+                    Vec2::new(half.x, half.y),
+                    Vec2::new(-half.x, half.y),
+                ]]
+            }
+            ZoneShape::Circle { radius } => vec![(0..CIRCLE_SEGMENTS)
+                .map(|index| {
+                    let angle = index as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                    Vec2::new(angle.cos(), angle.sin()) * *radius
+                })
+                .collect()],
+            ZoneShape::Polygon { points } => {
+                let mut points = points.clone();
+                if signed_area(&points) < 0.0 {
+                    points.reverse();
+                }
+                vec![points]
+            }
+            ZoneShape::Union(_) | ZoneShape::Difference { .. } => {
+                let drawn = self.drawn();
+                let mut edges = Vec::new();
+                drawn.part_edges(&mut edges);
+                trace_boundary(&drawn, &edges)
+            }
+        }
+    }
+
+    /// `drawn` is the shape as it is drawn, with circles swapped for the polygons they are drawn
+    /// with, so what is inside the shape matches its outlines
+    fn drawn(&self) -> ZoneShape {
+        match self {
+            ZoneShape::Circle { .. } => ZoneShape::Polygon {
+                points: self.outlines().remove(0),
+            },
+            ZoneShape::Union(shapes) => ZoneShape::Union(shapes.iter().map(Self::drawn).collect()),
+            ZoneShape::Difference { base, cuts } => ZoneShape::Difference {
+                base: Box::new(base.drawn()),
+                cuts: cuts.iter().map(Self::drawn).collect(),
+            },
+            shape => shape.clone(),
+        }
+    }
+
+    /// `part_edges` collects the edges of the outlines of every rectangle, circle and polygon the
+    /// shape is made of
+    fn part_edges(&self, edges: &mut Vec<(Vec2, Vec2)>) {
+        match self {
+            ZoneShape::Union(shapes) => shapes.iter().for_each(|shape| shape.part_edges(edges)),
+            ZoneShape::Difference { base, cuts } => {
+                base.part_edges(edges);
+                cuts.iter().for_each(|cut| cut.part_edges(edges));
+            }
+            shape => {
+                for outline in shape.outlines() {
+                    edges.extend(
+                        outline
+                            .iter()
+                            .copied()
+                            .zip(outline.iter().copied().cycle().skip(1))
+                            .filter(|(a, b)| a != b),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// `BOUNDARY_TOLERANCE` is how close two points have to be to count as the same point when tracing
+/// the outline of a shape
+const BOUNDARY_TOLERANCE: f32 = 0.01;
+
+/// `BOUNDARY_PROBE` is how far to either side of an edge to look to see if the edge is on the
+/// outline of a shape
+const BOUNDARY_PROBE: f32 = 0.1;
+
+/// `trace_boundary` traces the outline of a shape made of other shapes. the edges of the parts
+/// are split wherever they cross or touch, and only the pieces with the shape on one side and not
+/// the other are kept, turned so the shape is on their left. the pieces are then joined into loops
+///
+/// Arguments:
+///
+/// * `shape`: The shape, with circles already swapped for polygons.
+/// * `edges`: The edges of the outlines of every part of the shape.
+///
+/// Returns:
+///
+/// The outlines, counter clockwise around filled areas and clockwise around holes.
+fn trace_boundary(shape: &ZoneShape, edges: &[(Vec2, Vec2)]) -> Vec<Vec<Vec2>> {
+    let mut pieces: Vec<(Vec2, Vec2)> = Vec::new();
+    for &(a, b) in edges {
+        let direction = b - a;
+        let length_squared = direction.length_squared();
+        let mut splits = vec![0.0, 1.0];
+        for &(c, d) in edges {
+            let other = d - c;
+            let denominator = direction.perp_dot(other);
+            if denominator != 0.0 {
+                let t = (c - a).perp_dot(other) / denominator;
+                let u = (c - a).perp_dot(direction) / denominator;
+                if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+                    splits.push(t);
+                }
+            }
+            // edges that touch or run along this one split it where they end
+            for point in [c, d] {
+                let t = (point - a).dot(direction) / length_squared;
+                if t > 0.0 && t < 1.0 && (a + direction * t).distance(point) <= BOUNDARY_TOLERANCE {
+                    splits.push(t);
+                }
+            }
+        }
+        splits.sort_by(f32::total_cmp);
+
+        let normal = direction.perp().normalize();
+        for (from, to) in splits.iter().zip(splits.iter().skip(1)) {
+            let (start, end) = (a + direction * *from, a + direction * *to);
+            if start.distance(end) <= BOUNDARY_TOLERANCE {
+                continue;
+            }
+            let middle = (start + end) / 2.0;
+            let left = shape.contains_point(middle + normal * BOUNDARY_PROBE);
+            let right = shape.contains_point(middle - normal * BOUNDARY_PROBE);
+            let piece = match (left, right) {
+                (true, false) => (start, end),
+                (false, true) => (end, start),
+                _ => continue,
+            };
+            // parts sharing an edge on the outline would add it twice
+            let duplicate = pieces.iter().any(|(other_start, other_end)| {
+                other_start.distance(piece.0) <= BOUNDARY_TOLERANCE
+                    && other_end.distance(piece.1) <= BOUNDARY_TOLERANCE
+            });
+            if !duplicate {
+                pieces.push(piece);
+            }
+        }
+    }
+
+    let mut outlines = Vec::new();
+    while let Some((start, mut end)) = pieces.pop() {
+        let mut outline = vec![start];
+        while end.distance(start) > BOUNDARY_TOLERANCE {
+            outline.push(end);
+            match pieces
+                .iter()
+                .position(|(next, _)| next.distance(end) <= BOUNDARY_TOLERANCE)
+            {
+                Some(next) => end = pieces.swap_remove(next).1,
+                None => break,
+            }
+        }
+        if outline.len() >= 3 {
+            outlines.push(outline);
+        }
+    }
+    outlines
+}
+
+/// `signed_area` is the area of a polygon with the shoelace formula, negative for clockwise polygons
+fn signed_area(points: &[Vec2]) -> f32 {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.perp_dot(*b))
+        .sum::<f32>()
+        / 2.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+/// `ZoneKind` is what a zone is for.
+pub enum ZoneKind {
+    /// where agents sleep and wash
+    Home,
+    /// where agents go to work
+    Workplace,
+    /// shops, cafes and markets, where agents eat and meet people
+    Shop,
+    /// open spaces where agents meet people
+    #[default]
+    Park,
+    /// where agents rest and get cleaned up
+    Hospital,
+    /// areas only some agents may enter
+    Restricted,
+}
+
+impl ZoneKind {
+    /// every kind of zone
+    pub const ALL: [ZoneKind; 6] = [
+        ZoneKind::Home,
+        ZoneKind::Workplace,
+        ZoneKind::Shop,
+        ZoneKind::Park,
+        ZoneKind::Hospital,
+        ZoneKind::Restricted,
+    ];
+
+    /// `name` is the name of the kind shown in the ui
+    pub fn name(&self) -> &'static str {
+        match self {
+            ZoneKind::Home => "home",
+            ZoneKind::Workplace => "workplace",
+            ZoneKind::Shop => "shop",
+            ZoneKind::Park => "park",
+            ZoneKind::Hospital => "hospital",
+            ZoneKind::Restricted => "restricted",
+        }
+    }
+
+    /// `purposes` are the needs agents go to this kind of zone for
+    pub fn purposes(&self) -> &'static [NeedKind] {
+        match self {
+            ZoneKind::Home => &[NeedKind::Energy, NeedKind::Hygiene],
+            ZoneKind::Workplace => &[],
+            ZoneKind::Shop => &[NeedKind::Hunger, NeedKind::Social],
+            ZoneKind::Park => &[NeedKind::Social],
+            ZoneKind::Hospital => &[NeedKind::Energy, NeedKind::Hygiene],
+            ZoneKind::Restricted => &[],
+        }
+    }
+
+    /// `serves` checks if agents go to this kind of zone to satisfy a need
+    pub fn serves(&self, need: NeedKind) -> bool {
+        self.purposes().contains(&need)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// `OpeningHours` is when a zone lets agents in.
+///
+/// Properties:
+///
+/// * `open`: The hour the zone opens.
+/// * `close`: The hour the zone closes, before `open` for zones that are open overnight.
+pub struct OpeningHours {
+    pub open: f32,
+    pub close: f32,
+}
+
+impl OpeningHours {
+    /// `is_open` checks if the zone is open at an hour of the day
+    pub fn is_open(&self, hours: f32) -> bool {
+        if self.open <= self.close {
+            hours >= self.open && hours < self.close
+        } else {
+            hours >= self.open || hours < self.close
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// `ZoneAccess` is who may enter a zone.
+pub enum ZoneAccess {
+    /// every agent may enter
+    Public,
+    /// only the listed agents and the members of the listed factions may enter
+    AllowList {
+        agents: Vec<Entity>,
+        factions: Vec<String>,
+    },
+}
+
+impl ZoneAccess {
+    /// `allows` checks if an agent may enter
+    ///
+    /// Arguments:
+    ///
+    /// * `agent`: The agent entity.
+    /// * `faction`: The faction of the agent, if it has one.
+    pub fn allows(&self, agent: Entity, faction: Option<&Faction>) -> bool {
+        match self {
+            ZoneAccess::Public => true,
+            ZoneAccess::AllowList { agents, factions } => {
+                agents.contains(&agent)
+                    || faction.is_some_and(|faction| factions.contains(&faction.0))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// `Refusal` is why a zone did not let an agent in.
+pub enum Refusal {
+    /// the zone is outside its opening hours
+    Closed,
+    /// the agent is not on the allow-list of the zone
+    NotAllowed,
+    /// the zone is at capacity, the agent can queue until someone leaves
+    Full,
+}
+
 #[derive(Debug, Component, Clone, PartialEq)]
-/// `Zone` is a struct that contains a `name` field of type `String`, a `height` field of type `f32`,
-/// and a `width` field of type `f32`. this stores a rectangle shaped zone.
+/// `Zone` is a named area of the world, its `shape` is centered on the translation of the entity.
 ///
 /// Properties:
 ///
 /// * `name`: The name of the zone.
-/// * `height`: The height of the zone.
-/// * `width`: The width of the zone.
+/// * `shape`: The area the zone covers.
+/// * `kind`: What the zone is for.
+/// * `capacity`: How many agents fit in the zone, `None` for no limit.
+/// * `hours`: When the zone is open, `None` for always.
+/// * `access`: Who may enter the zone.
+/// * `parent`: The zone this zone is part of, such as the building a room is in.
 pub struct Zone {
     pub name: String,
-    pub height: f32,
-    pub width: f32,
+    pub shape: ZoneShape,
+    pub kind: ZoneKind,
+    pub capacity: Option<usize>,
+    pub hours: Option<OpeningHours>,
+    pub access: ZoneAccess,
+    pub parent: Option<Entity>,
+}
+
+impl Zone {
+    /// `contains` checks if a point is inside the zone
+    ///
+    /// Arguments:
+    ///
+    /// * `center`: The position of the center of the zone.
+    /// * `point`: The point to check.
+    ///
+    /// Returns:
+    ///
+    /// true if the point is inside the shape of the zone.
+    pub fn contains(&self, center: Vec2, point: Vec2) -> bool {
+        self.shape.contains_point(point - center)
+    }
+
+    /// `bounds` is the bottom left and top right corner of a box around the zone
+    ///
+    /// Arguments:
+    ///
+    /// * `center`: The position of the center of the zone.
+    pub fn bounds(&self, center: Vec2) -> (Vec2, Vec2) {
+        let (min, max) = self.shape.bounds();
+        (center + min, center + max)
+    }
+
+    /// `is_open` checks if the zone is open at an hour of the day
+    pub fn is_open(&self, hours: f32) -> bool {
+        self.hours.is_none_or(|opening| opening.is_open(hours))
+    }
+
+    /// `admits` checks if an agent may walk into the zone right now
+    ///
+    /// Arguments:
+    ///
+    /// * `agent`: The agent entity.
+    /// * `faction`: The faction of the agent, if it has one.
+    /// * `hours`: The hour of the day.
+    /// * `occupancy`: How many agents are already in the zone.
+    ///
+    /// Returns:
+    ///
+    /// Why the agent is refused, or `Ok` if it may enter.
+    pub fn admits(
+        &self,
+        agent: Entity,
+        faction: Option<&Faction>,
+        hours: f32,
+        occupancy: usize,
+    ) -> Result<(), Refusal> {
+        if !self.access.allows(agent, faction) {
+            return Err(Refusal::NotAllowed);
+        }
+        if !self.is_open(hours) {
+            return Err(Refusal::Closed);
+        }
+        if self.capacity.is_some_and(|capacity| occupancy >= capacity) {
+            return Err(Refusal::Full);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Bundle)]
+/// `ZoneBundle` is everything the simulation needs to treat an entity as a zone.
+/// the zone is centered on the translation of `transform`.
+///
+/// Properties:
+///
+/// * `zone`: The zone component.
+/// * `transform`: The position of the center of the zone.
+pub struct ZoneBundle {
+    pub zone: Zone,
+    #[bundle]
+    pub transform: TransformBundle,
+}
+
+impl ZoneBundle {
+    /// `new` creates a rectangle shaped zone bundle centered on `position`
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the zone.
+    /// * `position`: The center of the zone.
+    /// * `width`: The width of the zone.
+    /// * `height`: The height of the zone.
+    pub fn new(name: impl Into<String>, position: Vec2, width: f32, height: f32) -> Self {
+        ZoneBundle::with_shape(name, position, ZoneShape::Rectangle { width, height })
+    }
+
+    /// `with_shape` creates a zone bundle of any shape centered on `position`
+    ///
+    /// Arguments:
+    ///
+    /// * `name`: The name of the zone.
+    /// * `position`: The center of the zone.
+    /// * `shape`: The area the zone covers, relative to `position`.
+    pub fn with_shape(name: impl Into<String>, position: Vec2, shape: ZoneShape) -> Self {
+        ZoneBundle {
+            zone: Zone {
+                name: name.into(),
+                shape,
+                kind: ZoneKind::default(),
+                capacity: None,
+                hours: None,
+                access: ZoneAccess::Public,
+                parent: None,
+            },
+            transform: TransformBundle::from_transform(Transform::from_translation(
+                position.extend(0.0),
+            )),
+        }
+    }
+
+    /// `with_kind` sets what the zone is for, zones are parks by default
+    pub fn with_kind(mut self, kind: ZoneKind) -> Self {
+        self.zone.kind = kind;
+        self
+    }
+
+    /// `with_capacity` limits how many agents fit in the zone
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.zone.capacity = Some(capacity);
+        self
+    }
+
+    /// `with_hours` only lets agents in between `open` and `close`
+    pub fn with_hours(mut self, open: f32, close: f32) -> Self {
+        self.zone.hours = Some(OpeningHours { open, close });
+        self
+    }
+
+    /// `with_access` sets who may enter the zone
+    pub fn with_access(mut self, access: ZoneAccess) -> Self {
+        self.zone.access = access;
+        self
+    }
+
+    /// `with_parent` makes the zone part of another zone, agents in the zone are also counted as
+    /// being in the parent and have to follow its rules too
+    pub fn with_parent(mut self, parent: Entity) -> Self {
+        self.zone.parent = Some(parent);
+        self
+    }
+}
+
+/// `ancestors` follows the parents of a zone outwards, a room gives its building, then its
+/// district and so on. a zone that is its own ancestor stops the walk instead of looping forever
+///
+/// Arguments:
+///
+/// * `zone`: The zone to start from, it is not part of the result.
+/// * `get`: Looks up a zone by entity.
+///
+/// Returns:
+///
+/// The ancestors of the zone, innermost first.
+pub fn ancestors<'a>(
+    zone: &'a Zone,
+    get: impl Fn(Entity) -> Option<&'a Zone>,
+) -> Vec<(Entity, &'a Zone)> {
+    let mut ancestors: Vec<(Entity, &Zone)> = Vec::new();
+    let mut parent = zone.parent;
+    while let Some(entity) = parent {
+        if ancestors.iter().any(|(ancestor, _)| *ancestor == entity) {
+            break;
+        }
+        match get(entity) {
+            Some(zone) => {
+                ancestors.push((entity, zone));
+                parent = zone.parent;
+            }
+            None => break,
+        }
+    }
+    ancestors
+}
+
+/// `spawn_zone` spawns a new zone straight into the world, useful outside of systems such as in
+/// tests or when setting up an `App`. inside systems use `ZoneBundle` with `Commands`.
+///
+/// Arguments:
+///
+/// * `world`: The world to spawn the zone in.
+/// * `name`: The name of the zone.
+/// * `position`: The center of the zone.
+/// * `width`: The width of the zone.
+/// * `height`: The height of the zone.
+///
+/// Returns:
+///
+/// The entity of the new zone.
+pub fn spawn_zone(
+    world: &mut World,
+    name: impl Into<String>,
+    position: Vec2,
+    width: f32,
+    height: f32,
+) -> Entity {
+    world
+        .spawn()
+        .insert_bundle(ZoneBundle::new(name, position, width, height))
+        .id()
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+/// `ZoneColour` is the colour a zone is filled with, zones without one are given a colour picked
+/// from their name when they are first drawn.
+pub struct ZoneColour(pub Color);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+/// `AccessDefinition` is a `ZoneAccess` as it is written to files, with agents named instead of
+/// referred to by entity.
+pub enum AccessDefinition {
+    #[default]
+    Public,
+    AllowList {
+        #[serde(default)]
+        agents: Vec<String>,
+        #[serde(default)]
+        factions: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// `ZoneDefinition` is a zone as it is written to and read from files. other zones and agents are
+/// referred to by name, so the file does not depend on entity ids.
+///
+/// Properties:
+///
+/// * `name`: The name of the zone, unique within a file.
+/// * `position`: The center of the zone.
+/// * `shape`: The area the zone covers, relative to `position`.
+/// * `kind`: What the zone is for, parks by default.
+/// * `capacity`: How many agents fit in the zone, no limit by default.
+/// * `hours`: When the zone is open, always by default.
+/// * `access`: Who may enter the zone, everyone by default.
+/// * `parent`: The name of the zone this zone is part of.
+/// * `colour`: The colour the zone is filled with as rgba, random by default.
+/// * `amenity`: How the zone refills the needs of agents inside it, if it does.
+pub struct ZoneDefinition {
+    pub name: String,
+    pub position: Vec2,
+    pub shape: ZoneShape,
+    #[serde(default)]
+    pub kind: ZoneKind,
+    #[serde(default)]
+    pub capacity: Option<usize>,
+    #[serde(default)]
+    pub hours: Option<OpeningHours>,
+    #[serde(default)]
+    pub access: AccessDefinition,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub colour: Option<[f32; 4]>,
+    #[serde(default)]
+    pub amenity: Option<Amenity>,
+}
+
+impl ZoneDefinition {
+    /// `from_zone` describes a zone in the world
+    ///
+    /// Arguments:
+    ///
+    /// * `zone`: The zone.
+    /// * `position`: The center of the zone.
+    /// * `colour`: The colour of the zone, if it has one.
+    /// * `name_of`: Looks up the name of a zone or agent entity, entities without a name are left out.
+    ///
+    /// Returns:
+    ///
+    /// The definition of the zone, without an amenity. set `amenity` if the zone has one.
+    pub fn from_zone(
+        zone: &Zone,
+        position: Vec2,
+        colour: Option<&ZoneColour>,
+        name_of: impl Fn(Entity) -> Option<String>,
+    ) -> Self {
+        ZoneDefinition {
+            name: zone.name.clone(),
+            position,
+            shape: zone.shape.clone(),
+            kind: zone.kind,
+            capacity: zone.capacity,
+            hours: zone.hours,
+            access: match &zone.access {
+                ZoneAccess::Public => AccessDefinition::Public,
+                ZoneAccess::AllowList { agents, factions } => AccessDefinition::AllowList {
+                    agents: agents.iter().filter_map(|agent| name_of(*agent)).collect(),
+                    factions: factions.clone(),
+                },
+            },
+            parent: zone.parent.and_then(&name_of),
+            colour: colour.map(|colour| colour.0.as_rgba_f32()),
+            amenity: None,
+        }
+    }
+}
+
+/// `validate_zone_definitions` checks that a set of zone definitions can be spawned together
+///
+/// Arguments:
+///
+/// * `definitions`: The zones to check.
+/// * `agent_exists`: Checks if there is an agent with a name, for allow-lists.
+///
+/// Returns:
+///
+/// A sentence for every problem found, empty if the definitions are fine.
+pub fn validate_zone_definitions(
+    definitions: &[ZoneDefinition],
+    agent_exists: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (index, definition) in definitions.iter().enumerate() {
+        let name = &definition.name;
+        if name.is_empty() {
+            problems.push(format!("zone number {} has no name", index + 1));
+        }
+        if definitions[..index].iter().any(|other| &other.name == name) {
+            problems.push(format!("there is more than one zone named \"{}\"", name));
+        }
+        if let Some(problem) = definition.shape.problem() {
+            problems.push(format!("zone \"{}\" {}", name, problem));
+        }
+        if definition.capacity == Some(0) {
+            problems.push(format!(
+                "zone \"{}\" has a capacity of 0 so nobody can enter it",
+                name
+            ));
+        }
+        if let Some(hours) = definition.hours {
+            if !(0.0..=24.0).contains(&hours.open) || !(0.0..=24.0).contains(&hours.close) {
+                problems.push(format!(
+                    "zone \"{}\" has opening hours outside of 0 to 24",
+                    name
+                ));
+            }
+        }
+        match &definition.parent {
+            Some(parent) if parent == name => {
+                problems.push(format!("zone \"{}\" is part of itself", name));
+            }
+            Some(parent) if !definitions.iter().any(|other| &other.name == parent) => {
+                problems.push(format!(
+                    "zone \"{}\" is part of \"{}\" but there is no zone with that name",
+                    name, parent
+                ));
+            }
+            _ => {}
+        }
+        if let AccessDefinition::AllowList { agents, .. } = &definition.access {
+            for agent in agents.iter().filter(|agent| !agent_exists(agent)) {
+                problems.push(format!(
+                    "zone \"{}\" allows \"{}\" in but there is no agent with that name",
+                    name, agent
+                ));
+            }
+        }
+        if let Some(amenity) = &definition.amenity {
+            if amenity.provides.is_empty() || amenity.rate <= 0.0 {
+                problems.push(format!(
+                    "the amenity of zone \"{}\" has to provide at least one need at a rate above 0",
+                    name
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// `spawn_zone_definitions` checks a set of zone definitions and spawns them. nothing is spawned if
+/// any definition is wrong
+///
+/// Arguments:
+///
+/// * `commands`: The command buffer to spawn the zones with.
+/// * `definitions`: The zones to spawn.
+/// * `agent_named`: Looks up an agent by name, for allow-lists.
+///
+/// Returns:
+///
+/// The new zone entities in the order of `definitions`, or what is wrong with the definitions.
+pub fn spawn_zone_definitions(
+    commands: &mut Commands,
+    definitions: &[ZoneDefinition],
+    agent_named: impl Fn(&str) -> Option<Entity>,
+) -> anyhow::Result<Vec<Entity>> {
+    let problems = validate_zone_definitions(definitions, |agent| agent_named(agent).is_some());
+    if !problems.is_empty() {
+        anyhow::bail!(problems.join("\n"));
+    }
+
+    // spawn first so parents can be set no matter the order of the definitions
+    let entities: Vec<Entity> = definitions.iter().map(|_| commands.spawn().id()).collect();
+    let entity_named = |name: &str| {
+        definitions
+            .iter()
+            .position(|definition| definition.name == name)
+            .map(|index| entities[index])
+    };
+    for (definition, entity) in definitions.iter().zip(&entities) {
+        let mut bundle = ZoneBundle::with_shape(
+            definition.name.clone(),
+            definition.position,
+            definition.shape.clone(),
+        )
+        .with_kind(definition.kind);
+        bundle.zone.capacity = definition.capacity;
+        bundle.zone.hours = definition.hours;
+        bundle.zone.parent = definition.parent.as_deref().and_then(&entity_named);
+        bundle.zone.access = match &definition.access {
+            AccessDefinition::Public => ZoneAccess::Public,
+            AccessDefinition::AllowList { agents, factions } => ZoneAccess::AllowList {
+                agents: agents
+                    .iter()
+                    .filter_map(|agent| agent_named(agent))
+                    .collect(),
+                factions: factions.clone(),
+            },
+        };
+        let mut entity_commands = commands.entity(*entity);
+        entity_commands.insert_bundle(bundle);
+        if let Some([r, g, b, a]) = definition.colour {
+            entity_commands.insert(ZoneColour(Color::rgba(r, g, b, a)));
+        }
+        if let Some(amenity) = &definition.amenity {
+            entity_commands.insert(amenity.clone());
+        }
+    }
+    Ok(entities)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// `AgentEnteredZone` is sent when an agent walks into a zone, or spawns inside it.
+///
+/// Properties:
+///
+/// * `agent`: The agent entity.
+/// * `zone`: The zone entity.
+pub struct AgentEnteredZone {
+    pub agent: Entity,
+    pub zone: Entity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// `AgentExitedZone` is sent when an agent walks out of a zone, or when the agent or the zone is
+/// despawned.
+///
+/// Properties:
+///
+/// * `agent`: The agent entity.
+/// * `zone`: The zone entity.
+/// * `dwell`: How many seconds the agent spent in the zone.
+pub struct AgentExitedZone {
+    pub agent: Entity,
+    pub zone: Entity,
+    pub dwell: f32,
 }
 
 #[derive(Default, Debug, Clone)]
-/// `AgentZoneMapping` is a `HashMap` that maps an `Entity` to a `Vec` of `Entity`s.
+/// `AgentZoneMapping` is the current membership of every zone, kept up to date by `update_zones`
+/// as agents enter and leave zones. it also knows which zones are part of which, so agents in a
+/// room can be found through the building and district the room is in.
 ///
 /// Properties:
 ///
-/// * `map`: This is a HashMap that maps an zones to a list of agents.
+/// * `zone_agents`: The agents in every zone, in the order they entered.
+/// * `agent_zones`: The zones every agent is in, with how many seconds it has been there.
+/// * `parents`: The parent of every zone that has one.
 pub struct AgentZoneMapping {
-    map: HashMap<Entity, Vec<Entity>>,
+    zone_agents: BTreeMap<Entity, Vec<Entity>>,
+    agent_zones: BTreeMap<Entity, BTreeMap<Entity, f32>>,
+    parents: BTreeMap<Entity, Entity>,
+}
+
+impl AgentZoneMapping {
+    /// `agents_in` gets the agents that are in a zone
+    ///
+    /// Arguments:
+    ///
+    /// * `zone`: The zone entity.
+    ///
+    /// Returns:
+    ///
+    /// The agents in the zone, empty if the zone is not known.
+    pub fn agents_in(&self, zone: Entity) -> &[Entity] {
+        self.zone_agents
+            .get(&zone)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// `zones_containing` gets every zone an agent is in
+    ///
+    /// Arguments:
+    ///
+    /// * `agent`: The agent entity.
+    pub fn zones_containing(&self, agent: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.agent_zones
+            .get(&agent)
+            .into_iter()
+            .flat_map(|zones| zones.keys().copied())
+    }
+
+    /// `is_in` checks if an agent is in a zone
+    pub fn is_in(&self, agent: Entity, zone: Entity) -> bool {
+        self.agent_zones
+            .get(&agent)
+            .is_some_and(|zones| zones.contains_key(&zone))
+    }
+
+    /// `dwell_time` gets how many seconds an agent has been in a zone
+    ///
+    /// Returns:
+    ///
+    /// The seconds since the agent entered the zone, or `None` if it is not in the zone.
+    pub fn dwell_time(&self, agent: Entity, zone: Entity) -> Option<f32> {
+        self.agent_zones.get(&agent)?.get(&zone).copied()
+    }
+
+    /// `zones` gets every zone that is tracked by the mapping
+    pub fn zones(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.zone_agents.keys().copied()
+    }
+
+    /// `parent` gets the zone a zone is part of
+    pub fn parent(&self, zone: Entity) -> Option<Entity> {
+        self.parents.get(&zone).copied()
+    }
+
+    /// `ancestors` gets the parent of a zone, the parent of that parent and so on, innermost first
+    pub fn ancestors(&self, zone: Entity) -> Vec<Entity> {
+        let mut ancestors = Vec::new();
+        let mut parent = self.parent(zone);
+        while let Some(entity) = parent {
+            if entity == zone || ancestors.contains(&entity) {
+                break;
+            }
+            ancestors.push(entity);
+            parent = self.parent(entity);
+        }
+        ancestors
+    }
+
+    /// `children` gets the zones that are directly part of a zone
+    pub fn children(&self, zone: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.parents
+            .iter()
+            .filter(move |(_, parent)| **parent == zone)
+            .map(|(child, _)| *child)
+    }
+
+    /// `descendants` gets every zone that is part of a zone, however deeply nested
+    pub fn descendants(&self, zone: Entity) -> Vec<Entity> {
+        let mut descendants: Vec<Entity> = Vec::new();
+        let mut open = vec![zone];
+        while let Some(entity) = open.pop() {
+            for child in self.children(entity) {
+                if child != zone && !descendants.contains(&child) {
+                    descendants.push(child);
+                    open.push(child);
+                }
+            }
+        }
+        descendants
+    }
+
+    /// `agents_within` gets the agents in a zone or in any zone that is part of it, so the agents
+    /// in a district include the agents in its buildings and their rooms
+    ///
+    /// Returns:
+    ///
+    /// Every agent once, the agents directly in the zone first.
+    pub fn agents_within(&self, zone: Entity) -> Vec<Entity> {
+        let mut agents = self.agents_in(zone).to_vec();
+        for descendant in self.descendants(zone) {
+            for agent in self.agents_in(descendant) {
+                if !agents.contains(agent) {
+                    agents.push(*agent);
+                }
+            }
+        }
+        agents
+    }
+
+    /// `is_within` checks if an agent is in a zone or in any zone that is part of it
+    pub fn is_within(&self, agent: Entity, zone: Entity) -> bool {
+        self.zones_containing(agent)
+            .any(|inside| inside == zone || self.ancestors(inside).contains(&zone))
+    }
+
+    /// `enclosing_zones` gets every zone an agent is in together with every zone those are part
+    /// of, so an agent in a room is also in the building and district
+    pub fn enclosing_zones(&self, agent: Entity) -> Vec<Entity> {
+        let mut zones: Vec<Entity> = Vec::new();
+        for zone in self.zones_containing(agent) {
+            for zone in std::iter::once(zone).chain(self.ancestors(zone)) {
+                if !zones.contains(&zone) {
+                    zones.push(zone);
+                }
+            }
+        }
+        zones
+    }
+
+    /// `memberships` lists every agent in every zone with how many seconds it has been there, in
+    /// the order the agents entered each zone
+    pub fn memberships(&self) -> Vec<(Entity, Entity, f32)> {
+        self.zone_agents
+            .iter()
+            .flat_map(|(zone, agents)| {
+                agents.iter().map(move |agent| {
+                    let dwell = self.dwell_time(*agent, *zone).unwrap_or(0.0);
+                    (*agent, *zone, dwell)
+                })
+            })
+            .collect()
+    }
+
+    /// `restore` builds the membership of every zone from a list made by `memberships`, used when
+    /// loading a saved simulation
+    ///
+    /// Arguments:
+    ///
+    /// * `memberships`: Every agent in every zone with its dwell time, in the order they entered.
+    /// * `parents`: The parent of every zone that has one.
+    pub(crate) fn restore(
+        memberships: impl IntoIterator<Item = (Entity, Entity, f32)>,
+        parents: BTreeMap<Entity, Entity>,
+    ) -> Self {
+        let mut mapping = AgentZoneMapping {
+            parents,
+            ..AgentZoneMapping::default()
+        };
+        for (agent, zone, dwell) in memberships {
+            mapping.enter(agent, zone);
+            mapping
+                .agent_zones
+                .entry(agent)
+                .or_default()
+                .insert(zone, dwell);
+        }
+        mapping
+    }
+
+    /// `enter` puts an agent in a zone
+    fn enter(&mut self, agent: Entity, zone: Entity) {
+        self.zone_agents.entry(zone).or_default().push(agent);
+        self.agent_zones.entry(agent).or_default().insert(zone, 0.0);
+    }
+
+    /// `exit` takes an agent out of a zone
+    ///
+    /// Returns:
+    ///
+    /// How many seconds the agent was in the zone.
+    fn exit(&mut self, agent: Entity, zone: Entity) -> f32 {
+        if let Some(agents) = self.zone_agents.get_mut(&zone) {
+            agents.retain(|other| *other != agent);
+        }
+        let zones = self.agent_zones.get_mut(&agent);
+        let dwell = zones.and_then(|zones| zones.remove(&zone)).unwrap_or(0.0);
+        if self.agent_zones.get(&agent).is_some_and(BTreeMap::is_empty) {
+            self.agent_zones.remove(&agent);
+        }
+        dwell
+    }
 }
 
-/// For each zone, check if any agents are in it. If they are, add them to the zone's list of agents
+/// `destination_zone` finds the zone an agent is heading to. agents are sent to the center of
+/// zones, so when zones overlap the one centered closest to the destination wins. only the zones
+/// the `SpatialIndex` has around the destination are checked
 ///
 /// Arguments:
 ///
-/// * `zones`: Query<(Entity, &Zone)> - get all zone entities.
-/// * `agents`: Query<Entity, With<Agent>> - get all agent entities.
-/// * `transform_q`: Query<&Transform>  - transform to query with entities
-/// * `zone_mapping`: This is the resource that we created earlier.
-fn update_zones(
-    zones: Query<(Entity, &Zone)>,
-    agents: Query<Entity, With<Agent>>,
-    transform_q: Query<&Transform>,
-    mut zone_mapping: ResMut<AgentZoneMapping>,
+/// * `index`: The spatial index, used to find the zones around the destination.
+/// * `zones`: Every zone with its transform.
+/// * `destination`: The destination of the agent.
+pub fn destination_zone(
+    index: &SpatialIndex,
+    zones: &Query<(Entity, &Zone, &Transform)>,
+    destination: Vec2,
+) -> Option<Entity> {
+    index
+        .zones_at(destination)
+        .filter_map(|entity| zones.get(entity).ok())
+        .map(|(entity, zone, transform)| (entity, zone, transform.translation.truncate()))
+        .filter(|(_, zone, center)| zone.contains(*center, destination))
+        .min_by(|(_, _, a), (_, _, b)| {
+            a.distance_squared(destination)
+                .total_cmp(&b.distance_squared(destination))
+        })
+        .map(|(entity, _, _)| entity)
+}
+
+/// how close to the edge of a zone an agent gets before the zone decides to let it in or not
+pub const ZONE_DOOR_DISTANCE: f32 = 150.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// `ZoneRefused` is sent when a closed zone or a zone the agent is not allowed in turns it away.
+///
+/// Properties:
+///
+/// * `agent`: The agent entity.
+/// * `zone`: The zone entity.
+/// * `reason`: Why the agent was refused.
+pub struct ZoneRefused {
+    pub agent: Entity,
+    pub zone: Entity,
+    pub reason: Refusal,
+}
+
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+/// `Queued` marks an agent waiting outside a full zone.
+///
+/// Properties:
+///
+/// * `zone`: The zone the agent is waiting for.
+/// * `destination`: Where the agent was going, given back once it is let in.
+pub struct Queued {
+    pub zone: Entity,
+    pub destination: Vec2,
+}
+
+#[derive(Debug, Clone, Default)]
+/// `ZoneQueues` is a resource with the agents waiting outside every full zone, first come first
+/// served.
+///
+/// Properties:
+///
+/// * `queues`: The agents waiting outside every zone.
+/// * `admitted`: The zone every agent that was let in but has not walked in yet is heading for.
+pub struct ZoneQueues {
+    queues: BTreeMap<Entity, VecDeque<Entity>>,
+    admitted: BTreeMap<Entity, Entity>,
+}
+
+impl ZoneQueues {
+    /// `queue` gets the agents waiting outside a zone, the first one is let in next
+    pub fn queue(&self, zone: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.queues.get(&zone).into_iter().flatten().copied()
+    }
+
+    /// `queues` gets every zone with agents waiting outside it
+    pub fn queues(&self) -> impl Iterator<Item = (Entity, impl Iterator<Item = Entity> + '_)> + '_ {
+        self.queues
+            .iter()
+            .map(|(zone, agents)| (*zone, agents.iter().copied()))
+    }
+
+    /// `admitted` gets every agent that was let in but has not walked in yet, with its zone
+    pub fn admitted(&self) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.admitted.iter().map(|(agent, zone)| (*agent, *zone))
+    }
+
+    /// `restore` builds the queues from what `queues` and `admitted` returned, used when loading
+    /// a saved simulation
+    pub(crate) fn restore(
+        queues: impl IntoIterator<Item = (Entity, Vec<Entity>)>,
+        admitted: impl IntoIterator<Item = (Entity, Entity)>,
+    ) -> Self {
+        ZoneQueues {
+            queues: queues
+                .into_iter()
+                .map(|(zone, agents)| (zone, agents.into_iter().collect()))
+                .collect(),
+            admitted: admitted.into_iter().collect(),
+        }
+    }
+}
+
+/// `entering` gets the zones an agent walks into when it walks into `zone`, the zone itself and
+/// every zone it is part of that the agent is not already in
+///
+/// Arguments:
+///
+/// * `agent`: The agent entity.
+/// * `zone_entity`: The zone entity.
+/// * `zone`: The zone the agent is heading for.
+/// * `zones`: Every zone, used to look up the parents of the zone.
+/// * `mapping`: The agents in every zone.
+fn entering<'a>(
+    agent: Entity,
+    zone_entity: Entity,
+    zone: &'a Zone,
+    zones: &'a Query<(Entity, &Zone, &Transform)>,
+    mapping: &AgentZoneMapping,
+) -> Vec<(Entity, &'a Zone)> {
+    std::iter::once((zone_entity, zone))
+        .chain(ancestors(zone, |entity| {
+            zones.get(entity).ok().map(|(_, zone, _)| zone)
+        }))
+        .filter(|(entity, _)| !mapping.is_within(agent, *entity))
+        .collect()
+}
+
+/// `admission` checks the rules of every zone an agent walks into. the rules of a building apply
+/// to every room in it, so a closed building keeps its rooms closed too
+///
+/// Arguments:
+///
+/// * `agent`: The agent entity.
+/// * `faction`: The faction of the agent, if it has one.
+/// * `entering`: The zones the agent walks into, see `entering`.
+/// * `hours`: The hour of the day.
+/// * `mapping`: The agents in every zone.
+/// * `occupancy`: How many agents are in or were let into every zone so far, filled in as needed.
+///
+/// Returns:
+///
+/// Why the agent is refused, refusals that waiting will not fix win over `Refusal::Full`.
+fn admission(
+    agent: Entity,
+    faction: Option<&Faction>,
+    entering: &[(Entity, &Zone)],
+    hours: f32,
+    mapping: &AgentZoneMapping,
+    occupancy: &mut BTreeMap<Entity, usize>,
+) -> Result<(), Refusal> {
+    let mut full = false;
+    for (entity, zone) in entering {
+        let count = *occupancy
+            .entry(*entity)
+            .or_insert_with(|| mapping.agents_within(*entity).len());
+        match zone.admits(agent, faction, hours, count) {
+            Ok(()) => {}
+            Err(Refusal::Full) => full = true,
+            Err(reason) => return Err(reason),
+        }
+    }
+    if full {
+        return Err(Refusal::Full);
+    }
+    Ok(())
+}
+
+/// `enforce_zone_rules` stops agents at the edge of zones that do not let them in. agents heading
+/// for a closed zone or a zone they are not allowed in give up on it, agents heading for a full zone
+/// queue outside until enough agents leave. agents leave the queue by being given another
+/// destination. the rules of every zone a zone is part of are checked too
+///
+/// Arguments:
+///
+/// * `agents`: Query<(Entity, &mut Agent, &Transform, Option<&Faction>, Option<&Queued>)> - query containing agents.
+/// * `zones`: Query<(Entity, &Zone, &Transform)> - query containing every zone.
+/// * `index`: Res<SpatialIndex> - resource containing the spatial index, used to find the zone agents are heading to.
+/// * `mapping`: Res<AgentZoneMapping> - resource containing the agents in every zone and how zones nest.
+/// * `time_of_day`: Res<TimeOfDay> - resource containing the time of day.
+/// * `queues`: ResMut<ZoneQueues> - resource containing the queue outside every zone.
+/// * `refused`: EventWriter<ZoneRefused> - sends an event for every agent turned away.
+/// * `commands`: Commands - This is the command buffer that we use to add and remove `Queued`.
+fn enforce_zone_rules(
+    mut agents: Query<(
+        Entity,
+        &mut Agent,
+        &Transform,
+        Option<&Faction>,
+        Option<&Queued>,
+    )>,
+    zones: Query<(Entity, &Zone, &Transform)>,
+    index: Res<SpatialIndex>,
+    mapping: Res<AgentZoneMapping>,
+    time_of_day: Res<TimeOfDay>,
+    mut queues: ResMut<ZoneQueues>,
+    mut refused: EventWriter<ZoneRefused>,
+    mut commands: Commands,
 ) {
-    let zone_mappings = &mut zone_mapping.map;
-    let zone_list: Vec<Entity> = zones.iter().map(|(e, _)| e).collect_vec();
-    let agent_with_transform: Vec<(Entity, &Transform)> = agents
-        .iter()
-        .map(|e| (e, transform_q.get(e).unwrap()))
-        .collect_vec();
+    // agents that were let in keep their place until they walk in or go somewhere else
+    let ZoneQueues { queues, admitted } = &mut *queues;
+    admitted.retain(|agent_entity, zone_entity| {
+        !mapping.is_within(*agent_entity, *zone_entity)
+            && agents.get(*agent_entity).is_ok_and(|(_, agent, _, _, _)| {
+                agent
+                    .destination
+                    .and_then(|destination| destination_zone(&index, &zones, destination))
+                    == Some(*zone_entity)
+            })
+    });
+    let mut occupancy: BTreeMap<Entity, usize> = BTreeMap::new();
+    for (agent_entity, zone_entity) in admitted.iter() {
+        let (_, zone, _) = zones.get(*zone_entity).unwrap();
+        for (entity, _) in entering(*agent_entity, *zone_entity, zone, &zones, &mapping) {
+            *occupancy
+                .entry(entity)
+                .or_insert_with(|| mapping.agents_within(entity).len()) += 1;
+        }
+    }
+
+    // agents given somewhere else to go while they were waiting leave the queue, behaviour trees
+    // and planners sending them to the zone they wait for do not make them lose their place
+    let mut left = BTreeSet::new();
+    for (agent_entity, agent, _, _, queued) in agents.iter() {
+        if let (Some(queued), Some(destination)) = (queued, agent.destination) {
+            if destination_zone(&index, &zones, destination) != Some(queued.zone) {
+                commands.entity(agent_entity).remove::<Queued>();
+                left.insert(agent_entity);
+            }
+        }
+    }
 
-    for entity in &zone_list {
-        zone_mappings.entry(*entity).or_insert(vec![]);
+    // let queued agents in while there is room, in the order they arrived
+    for (zone_entity, queue) in queues.iter_mut() {
+        queue.retain(|agent| {
+            !left.contains(agent)
+                && agents.get(*agent).is_ok_and(|(_, _, _, _, queued)| {
+                    queued.is_some_and(|queued| queued.zone == *zone_entity)
+                })
+        });
+        let zone = match zones.get(*zone_entity) {
+            Ok((_, zone, _)) => zone,
+            Err(_) => {
+                queue.clear();
+                continue;
+            }
+        };
+        while let Some(agent_entity) = queue.front().copied() {
+            let (_, mut agent, _, faction, queued) = agents.get_mut(agent_entity).unwrap();
+            let entering = entering(agent_entity, *zone_entity, zone, &zones, &mapping);
+            match admission(
+                agent_entity,
+                faction,
+                &entering,
+                time_of_day.hours,
+                &mapping,
+                &mut occupancy,
+            ) {
+                Ok(()) => {
+                    agent.destination = Some(queued.unwrap().destination);
+                    admitted.insert(agent_entity, *zone_entity);
+                    for (entity, _) in &entering {
+                        *occupancy.get_mut(entity).unwrap() += 1;
+                    }
+                }
+                Err(Refusal::Full) => break,
+                Err(reason) => refused.send(ZoneRefused {
+                    agent: agent_entity,
+                    zone: *zone_entity,
+                    reason,
+                }),
+            }
+            commands.entity(agent_entity).remove::<Queued>();
+            queue.pop_front();
+        }
     }
+    queues.retain(|_, queue| !queue.is_empty());
 
-    for (zone_entity, _agents) in zone_mappings.clone().iter() {
-        if !zone_list.contains(zone_entity) {
-            zone_mappings.remove(zone_entity);
+    for (agent_entity, mut agent, transform, faction, queued) in agents.iter_mut() {
+        let destination = match agent.destination {
+            // agents that were let in already took their place
+            Some(_) if admitted.contains_key(&agent_entity) => continue,
+            Some(destination) => destination,
+            None => continue,
+        };
+        if queued.is_some() && !left.contains(&agent_entity) {
+            // behaviour trees and planners keep sending the agent to the zone it waits for
+            agent.destination = None;
             continue;
         }
+        let zone_entity = destination_zone(&index, &zones, destination);
+        let zone_entity = match zone_entity {
+            Some(zone_entity) => zone_entity,
+            None => continue,
+        };
+        if mapping.is_within(agent_entity, zone_entity) {
+            continue;
+        }
+        let (_, zone, _) = zones.get(zone_entity).unwrap();
+        let entering = entering(agent_entity, zone_entity, zone, &zones, &mapping);
+        // the agent is stopped at the door of the outermost zone it is not in yet
+        let position = transform.translation.truncate();
+        let at_door = entering.iter().any(|(entity, zone)| {
+            let (_, _, zone_transform) = zones.get(*entity).unwrap();
+            let (min, max) = zone.bounds(zone_transform.translation.truncate());
+            position.clamp(min, max).distance(position) <= ZONE_DOOR_DISTANCE
+        });
+        if !at_door {
+            continue;
+        }
+
+        // nobody gets to skip the queue, even if someone just left
+        let admits = match admission(
+            agent_entity,
+            faction,
+            &entering,
+            time_of_day.hours,
+            &mapping,
+            &mut occupancy,
+        ) {
+            Ok(()) if queues.contains_key(&zone_entity) => Err(Refusal::Full),
+            admits => admits,
+        };
+        match admits {
+            Ok(()) => {
+                admitted.insert(agent_entity, zone_entity);
+                for (entity, _) in &entering {
+                    *occupancy.get_mut(entity).unwrap() += 1;
+                }
+            }
+            Err(Refusal::Full) => {
+                agent.destination = None;
+                commands.entity(agent_entity).insert(Queued {
+                    zone: zone_entity,
+                    destination,
+                });
+                queues
+                    .entry(zone_entity)
+                    .or_default()
+                    .push_back(agent_entity);
+            }
+            Err(reason) => {
+                agent.destination = None;
+                refused.send(ZoneRefused {
+                    agent: agent_entity,
+                    zone: zone_entity,
+                    reason,
+                });
+            }
+        }
     }
+}
 
-    for (zone_entity, zone) in zones.iter() {
-        for (agent_entity, agent_transform) in &agent_with_transform {
-            let zone_transform = transform_q.get(zone_entity).unwrap();
+/// `update_zones` works out which zones every agent is in, using the `SpatialIndex` to only check
+/// the zones near the agent. agents that walked into or out of a zone since the last frame are
+/// moved in the `AgentZoneMapping` and an `AgentEnteredZone` or `AgentExitedZone` event is sent,
+/// agents that stayed have their dwell time increased. the parent of every zone is copied into the
+/// mapping so it can answer questions about nested zones
+///
+/// Arguments:
+///
+/// * `zones`: Query<(Entity, &Zone, &Transform)> - get all zones and their positions.
+/// * `agents`: Query<(Entity, &Transform), With<Agent>> - get all agents and their positions.
+/// * `index`: Res<SpatialIndex> - the spatial index used to find the zones near an agent.
+/// * `clock`: Res<SimClock> - resource containing the simulation clock, used to get the length of a tick.
+/// * `zone_mapping`: ResMut<AgentZoneMapping> - resource containing the membership of every zone.
+/// * `entered`: EventWriter<AgentEnteredZone> - sends an event for every agent entering a zone.
+/// * `exited`: EventWriter<AgentExitedZone> - sends an event for every agent leaving a zone.
+fn update_zones(
+    zones: Query<(Entity, &Zone, &Transform)>,
+    agents: Query<(Entity, &Transform), With<Agent>>,
+    index: Res<SpatialIndex>,
+    clock: Res<SimClock>,
+    mut zone_mapping: ResMut<AgentZoneMapping>,
+    mut entered: EventWriter<AgentEnteredZone>,
+    mut exited: EventWriter<AgentExitedZone>,
+) {
+    // agents and zones that were despawned leave every zone they were part of
+    let gone: Vec<(Entity, Entity)> = zone_mapping
+        .agent_zones
+        .iter()
+        .flat_map(|(agent, zones)| zones.keys().map(move |zone| (*agent, *zone)))
+        .filter(|(agent, zone)| !agents.contains(*agent) || !zones.contains(*zone))
+        .collect();
+    for (agent, zone) in gone {
+        let dwell = zone_mapping.exit(agent, zone);
+        exited.send(AgentExitedZone { agent, zone, dwell });
+    }
+    zone_mapping
+        .zone_agents
+        .retain(|zone_entity, _| zones.contains(*zone_entity));
+    for (entity, _, _) in zones.iter() {
+        zone_mapping.zone_agents.entry(entity).or_default();
+    }
 
-            let scale_x: f32 = zone.height;
-            let scale_y: f32 = zone.width;
-            let pos_x: f32 = agent_transform.translation.x;
-            let pos_y: f32 = agent_transform.translation.y;
+    zone_mapping.parents = zones
+        .iter()
+        .filter_map(|(entity, zone, _)| zone.parent.map(|parent| (entity, parent)))
+        .collect();
+
+    for (agent, agent_transform) in agents.iter() {
+        let position = agent_transform.translation.truncate();
+        let inside: Vec<Entity> = index
+            .zones_at(position)
+            .filter(|zone_entity| {
+                zones
+                    .get(*zone_entity)
+                    .is_ok_and(|(_, zone, zone_transform)| {
+                        zone.contains(zone_transform.translation.truncate(), position)
+                    })
+            })
+            .collect();
 
-            if zone_transform.translation.x >= pos_x - scale_x / 2.0
-                && zone_transform.translation.x <= pos_x + scale_x / 2.0
-                && zone_transform.translation.y >= pos_y - scale_y / 2.0
-                && zone_transform.translation.y <= pos_y + scale_y / 2.0
+        let left: Vec<Entity> = zone_mapping
+            .zones_containing(agent)
+            .filter(|zone| !inside.contains(zone))
+            .collect();
+        for zone in left {
+            let dwell = zone_mapping.exit(agent, zone);
+            exited.send(AgentExitedZone { agent, zone, dwell });
+        }
+
+        for zone in inside {
+            match zone_mapping
+                .agent_zones
+                .get_mut(&agent)
+                .and_then(|zones| zones.get_mut(&zone))
             {
-                println!("{:?} is in {:?}", agent_entity, zone.name);
-                zone_mappings
-                    .get_mut(&zone_entity)
-                    .unwrap()
-                    .push(*agent_entity)
+                Some(dwell) => *dwell += clock.delta_seconds(),
+                None => {
+                    zone_mapping.enter(agent, zone);
+                    entered.send(AgentEnteredZone { agent, zone });
+                }
             }
         }
     }
 }
 
-/// It creates a zone, spawns it, and adds it to the `AgentZoneMapping` resource
+/// `attach_zone_shapes` draws every zone that is not drawn yet and redraws zones that were
+/// edited. zones without a `ZoneColour` are given a colour picked from their name
 ///
 /// Arguments:
 ///
-/// * `commands`: Commands - This is the command buffer that we use to spawn entities.
-/// * `zones`: ResMut<AgentZoneMapping>
-fn spawn_test_zone(mut commands: Commands, mut zones: ResMut<AgentZoneMapping>) {
-    let zone: Zone = Zone {
-        name: "TEST".parse().unwrap(),
-        height: 1000.0,
-        width: 1000.0,
-    };
-    let shape = shapes::Rectangle {
-        extents: Vec2::new(zone.width, zone.height),
-        origin: RectangleOrigin::Center,
-    };
+/// * `zones`: Query<(Entity, &Zone, &Transform, Option<&ZoneColour>), Or<(Without<Path>, Changed<Zone>, Changed<ZoneColour>)>> - query containing zones that need drawing.
+/// * `commands`: Commands - This is the command buffer that we use to add the shape to the zone.
+fn attach_zone_shapes(
+    zones: Query<
+        (Entity, &Zone, &Transform, Option<&ZoneColour>),
+        Or<(Without<Path>, Changed<Zone>, Changed<ZoneColour>)>,
+    >,
+    mut commands: Commands,
+) {
+    for (entity, zone, transform, colour) in zones.iter() {
+        let colour = match colour {
+            Some(colour) => colour.0,
+            None => {
+                // picked from the name so zones keep their colour from run to run
+                let mut hasher = DefaultHasher::new();
+                zone.name.hash(&mut hasher);
+                let colour = Color::hsla((hasher.finish() % 360) as f32, 1.0, 0.5, 0.3);
+                commands.entity(entity).insert(ZoneColour(colour));
+                colour
+            }
+        };
+        let builder =
+            zone.shape
+                .outlines()
+                .into_iter()
+                .fold(GeometryBuilder::new(), |builder, points| {
+                    builder.add(&shapes::Polygon {
+                        points,
+                        closed: true,
+                    })
+                });
 
-    let zone_entity = commands
-        .spawn_bundle(GeometryBuilder::build_as(
-            &shape,
+        commands.entity(entity).insert_bundle(builder.build(
             DrawMode::Outlined {
-                fill_mode: FillMode::color(Color::hsla(
-                    rand::random::<f32>() * 360.0,
-                    1.0,
-                    0.5,
-                    0.3,
-                )),
+                fill_mode: FillMode {
+                    // outlines never cross, so holes are inside an even number of them
+                    options: FillOptions::even_odd(),
+                    color: colour,
+                },
                 outline_mode: StrokeMode::new(Color::BLACK, 10.0),
             },
-            Transform::default(),
-        ))
-        .insert(zone)
-        .id();
-
-    (*zones).map.insert(zone_entity, vec![]);
+            *transform,
+        ));
+    }
 }
@@ -0,0 +1,2133 @@
+use bevy::ecs::event::Events;
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+use npc_sim_bevy::prelude::*;
+use npc_sim_bevy::utility::{combine_scores, IdleAction, NeedAction};
+
+fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins).add_plugin(SimulationPlugin);
+    app
+}
+
+/// `village_app` is a headless app that spawns the village scenario
+fn village_app() -> App {
+    let mut app = headless_app();
+    app.insert_resource(ActiveScenario(Some(Scenario::village())));
+    app
+}
+
+#[test]
+fn simulation_runs_without_a_window() {
+    // nothing is spawned until a scenario is picked
+    let mut empty = headless_app();
+    empty.update();
+    let mut agents = empty.world.query::<&Agent>();
+    assert_eq!(agents.iter(&empty.world).count(), 0);
+
+    let mut app = village_app();
+
+    for _ in 0..10 {
+        app.update();
+    }
+
+    // the village scenario is spawned when entering `GameState::Playing`, jane keeps the utility AI
+    // as there is no asset server to load her behaviour tree with
+    let mut agents = app.world.query::<&Agent>();
+    assert_eq!(agents.iter(&app.world).count(), 3);
+    let mut zones = app.world.query::<&Zone>();
+    assert_eq!(zones.iter(&app.world).count(), 4);
+}
+
+#[test]
+fn spawned_agents_are_mapped_to_zones() {
+    let mut app = headless_app();
+    let market = spawn_zone(
+        &mut app.world,
+        "market",
+        Vec2::new(5000.0, 0.0),
+        200.0,
+        200.0,
+    );
+    let inside = spawn_agent(&mut app.world, "anna", Vec2::new(5050.0, 0.0));
+    let outside = spawn_agent(&mut app.world, "bob", Vec2::new(-5000.0, 0.0));
+
+    app.update();
+
+    let mapping = app.world.resource::<AgentZoneMapping>();
+    assert!(mapping.agents_in(market).contains(&inside));
+    assert!(!mapping.agents_in(market).contains(&outside));
+    assert!(mapping.zones_containing(inside).any(|zone| zone == market));
+}
+
+#[test]
+fn hungry_agents_head_for_food() {
+    let mut app = village_app();
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(-10000.0, 0.0));
+    app.world
+        .get_mut::<Needs>(agent)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.1);
+
+    app.update();
+
+    let agent = app.world.get::<Agent>(agent).unwrap();
+    // the nearest amenity providing food is the test cafe
+    assert_eq!(agent.destination, Some(Vec2::new(3000.0, 0.0)));
+}
+
+#[test]
+fn behaviour_trees_walk_agents_to_zones() {
+    let mut app = headless_app();
+    spawn_zone(&mut app.world, "park", Vec2::new(0.0, 8000.0), 400.0, 400.0);
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(0.0, 6000.0));
+    let root = BehaviourNode::from_ron(
+        r#"Sequence([
+            MoveToZone("park"),
+            SetBlackboard(key: "arrived", value: Bool(true)),
+        ])"#,
+    )
+    .unwrap();
+    app.world.entity_mut(agent).insert(BehaviourTree::new(root));
+
+    app.update();
+
+    assert_eq!(
+        app.world.get::<Agent>(agent).unwrap().destination,
+        Some(Vec2::new(0.0, 8000.0))
+    );
+
+    app.world.get_mut::<Transform>(agent).unwrap().translation = Vec3::new(0.0, 8000.0, 1.0);
+    app.update();
+
+    let tree = app.world.get::<BehaviourTree>(agent).unwrap();
+    assert_eq!(tree.status, Status::Success);
+    assert_eq!(
+        tree.blackboard.get("arrived"),
+        Some(&BlackboardValue::Bool(true))
+    );
+}
+
+#[test]
+fn shipped_behaviour_trees_parse() {
+    let villager: BehaviourTreeAsset =
+        ron::from_str(include_str!("../assets/behaviours/villager.bt.ron")).unwrap();
+    assert!(villager.root.node_count() > 1);
+}
+
+#[test]
+fn planners_chain_actions_to_reach_goals() {
+    let mut app = village_app();
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(0.0, -6000.0));
+    app.world.entity_mut(agent).insert(GoapAgent::default());
+    app.world
+        .get_mut::<Needs>(agent)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.1);
+
+    app.update();
+
+    let goap = app.world.get::<GoapAgent>(agent).unwrap();
+    let plan = goap.plan.as_ref().expect("hungry agents plan to eat");
+    let actions = app.world.resource::<GoapActions>();
+    let names: Vec<&str> = plan
+        .steps
+        .iter()
+        .map(|step| actions.actions[*step].name.as_str())
+        .collect();
+    assert_eq!(names, ["work", "buy_food", "eat"]);
+    // the village has one workplace, where the first step happens
+    assert_eq!(
+        app.world.get::<Agent>(agent).unwrap().destination,
+        Some(Vec2::new(0.0, -3000.0))
+    );
+}
+
+#[test]
+fn planners_back_off_and_eating_satisfies_hunger() {
+    let mut app = headless_app();
+    app.update();
+    // without a workplace there is no way to pay for food
+    app.world
+        .insert_resource(GoapActions::for_zones([("kiosk", ZoneKind::Shop)]));
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(0.0, -6000.0));
+    app.world.entity_mut(agent).insert(GoapAgent::default());
+    app.world
+        .get_mut::<Needs>(agent)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.1);
+    app.update();
+
+    let retry_delay = app.world.resource::<GoapSettings>().retry_delay;
+    let goap = app.world.get::<GoapAgent>(agent).unwrap();
+    assert_eq!(goap.plan, None);
+    assert_eq!(goap.retry_in, retry_delay);
+    app.update();
+    assert!(app
+        .world
+        .resource::<npc_sim_bevy::goap::PlanQueue>()
+        .is_empty());
+    assert!(app.world.get::<GoapAgent>(agent).unwrap().retry_in < retry_delay);
+
+    // once anna has food she eats it, and stays fed instead of planning to eat again
+    app.world
+        .get_mut::<GoapAgent>(agent)
+        .unwrap()
+        .facts
+        .set("has_food", true);
+    let step = app.world.resource::<SimClock>().delta_seconds();
+    for _ in 0..((retry_delay + 3.0) / step).ceil() as usize + 2 {
+        app.update();
+    }
+    let satisfied = app
+        .world
+        .resource::<NeedSettings>()
+        .get(NeedKind::Hunger)
+        .satisfied;
+    let goap = app.world.get::<GoapAgent>(agent).unwrap();
+    assert!(!goap.facts.get("has_food"));
+    assert!(!goap.facts.get("hunger_low"));
+    assert_eq!(goap.plan, None);
+    assert!(app.world.get::<Needs>(agent).unwrap().get(NeedKind::Hunger) >= satisfied - 0.01);
+}
+
+#[test]
+fn planners_back_off_from_closed_zones() {
+    let mut app = headless_app();
+    app.world.spawn().insert_bundle(
+        ZoneBundle::new("office", Vec2::new(0.0, -3000.0), 400.0, 400.0)
+            .with_kind(ZoneKind::Workplace)
+            .with_hours(9.0, 17.0),
+    );
+    app.world.spawn().insert_bundle(
+        ZoneBundle::new("kiosk", Vec2::new(3000.0, -3000.0), 400.0, 400.0)
+            .with_kind(ZoneKind::Shop),
+    );
+    app.update();
+    app.world.insert_resource(GoapActions::for_zones([
+        ("office", ZoneKind::Workplace),
+        ("kiosk", ZoneKind::Shop),
+    ]));
+    app.world.resource_mut::<TimeOfDay>().hours = 10.0;
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(0.0, -6000.0));
+    app.world.entity_mut(agent).insert(GoapAgent::default());
+    app.world
+        .get_mut::<Needs>(agent)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.1);
+    app.update();
+    assert_eq!(
+        app.world.get::<Agent>(agent).unwrap().destination,
+        Some(Vec2::new(0.0, -3000.0))
+    );
+
+    // the office closes before anna gets there, so she is turned away and gives up on the plan
+    app.world.resource_mut::<TimeOfDay>().hours = 20.0;
+    app.world.get_mut::<Transform>(agent).unwrap().translation = Vec3::new(0.0, -3300.0, 1.0);
+    app.update();
+    app.update();
+    let retry_delay = app.world.resource::<GoapSettings>().retry_delay;
+    let goap = app.world.get::<GoapAgent>(agent).unwrap();
+    assert_eq!(goap.plan, None);
+    assert_eq!(goap.retry_in, retry_delay);
+    assert_eq!(app.world.get::<Agent>(agent).unwrap().destination, None);
+
+    // while the office is closed there is no plan that uses it
+    let step = app.world.resource::<SimClock>().delta_seconds();
+    for _ in 0..(retry_delay / step).ceil() as usize + 2 {
+        app.update();
+        assert_eq!(app.world.get::<Agent>(agent).unwrap().destination, None);
+    }
+    assert_eq!(app.world.get::<GoapAgent>(agent).unwrap().plan, None);
+}
+
+#[test]
+fn planners_only_use_zones_agents_know_about() {
+    let mut app = headless_app();
+    app.world.spawn().insert_bundle(
+        ZoneBundle::new("office", Vec2::new(0.0, -3000.0), 400.0, 400.0)
+            .with_kind(ZoneKind::Workplace),
+    );
+    app.world.spawn().insert_bundle(
+        ZoneBundle::new("kiosk", Vec2::new(3000.0, -3000.0), 400.0, 400.0)
+            .with_kind(ZoneKind::Shop),
+    );
+    app.update();
+    app.world.insert_resource(GoapActions::for_zones([
+        ("office", ZoneKind::Workplace),
+        ("kiosk", ZoneKind::Shop),
+    ]));
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(-10000.0, 0.0));
+    app.world
+        .entity_mut(agent)
+        .insert(GoapAgent::default())
+        .insert(Beliefs::default());
+    app.world
+        .get_mut::<Needs>(agent)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.1);
+    app.update();
+
+    // anna has never seen the office or the kiosk, so she can not plan to eat
+    let retry_delay = app.world.resource::<GoapSettings>().retry_delay;
+    let goap = app.world.get::<GoapAgent>(agent).unwrap();
+    assert_eq!(goap.plan, None);
+    assert_eq!(goap.retry_in, retry_delay);
+    assert_eq!(app.world.get::<Agent>(agent).unwrap().destination, None);
+}
+
+#[test]
+fn paths_go_around_obstacles_and_are_invalidated() {
+    let mut app = headless_app();
+    let wall = app
+        .world
+        .spawn()
+        .insert_bundle(ObstacleBundle::new(Vec2::new(0.0, -7000.0), 2000.0, 200.0))
+        .id();
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(0.0, -8000.0));
+    app.world.entity_mut(agent).remove::<UtilityBrain>();
+    app.world.get_mut::<Agent>(agent).unwrap().destination = Some(Vec2::new(0.0, -6000.0));
+
+    app.update();
+
+    let grid = app.world.resource::<NavGrid>();
+    let path = app.world.get::<NavPath>(agent).unwrap();
+    assert_eq!(path.waypoints.last(), Some(&Vec2::new(0.0, -6000.0)));
+    assert!(path.waypoints.len() > 1, "the path bends around the wall");
+    let mut from = Vec2::new(0.0, -8000.0);
+    for waypoint in &path.waypoints {
+        assert!(grid.line_of_sight(from, *waypoint));
+        from = *waypoint;
+    }
+    let generation = path.generation;
+
+    app.world.despawn(wall);
+    app.update();
+    app.update();
+
+    let path = app.world.get::<NavPath>(agent).unwrap();
+    assert!(path.generation > generation);
+    assert_eq!(path.waypoints, vec![Vec2::new(0.0, -6000.0)]);
+}
+
+#[test]
+fn crowds_share_a_flow_field_around_obstacles() {
+    let mut app = headless_app();
+    let market = spawn_zone(
+        &mut app.world,
+        "market",
+        Vec2::new(0.0, -6000.0),
+        400.0,
+        400.0,
+    );
+    app.world
+        .spawn()
+        .insert_bundle(ObstacleBundle::new(Vec2::new(0.0, -7000.0), 2000.0, 200.0));
+    let crowd: Vec<Entity> = (0..5)
+        .map(|index| {
+            let position = Vec2::new(index as f32 * 300.0 - 600.0, -8000.0);
+            let agent = spawn_agent(&mut app.world, "crowd", position);
+            app.world.entity_mut(agent).remove::<UtilityBrain>();
+            app.world.get_mut::<Agent>(agent).unwrap().destination = Some(Vec2::new(0.0, -6000.0));
+            agent
+        })
+        .collect();
+
+    app.update();
+    app.update();
+
+    let fields = app.world.resource::<FlowFields>();
+    assert_eq!(fields.len(), 1);
+    assert_eq!(fields.builds, 1);
+    for agent in &crowd {
+        assert_eq!(
+            app.world.get::<FlowFollower>(*agent),
+            Some(&FlowFollower { zone: market })
+        );
+        assert!(app.world.get::<NavPath>(*agent).is_none());
+    }
+
+    // walking down the field from below the wall ends up in the market without crossing the wall
+    let grid = app.world.resource::<NavGrid>();
+    let field = fields.get(market).unwrap();
+    let mut position = Vec2::new(0.0, -8000.0);
+    for _ in 0..100 {
+        match field.next_step(grid, position) {
+            Some(next) => {
+                assert!(grid.line_of_sight(position, next));
+                position = next;
+            }
+            None => break,
+        }
+    }
+    assert_eq!(field.cost_at(grid, position), Some(0.0));
+}
+
+#[test]
+fn steering_arrives_without_overshooting_and_dodges_head_on() {
+    use npc_sim_bevy::steering::{apply_steering, arrive, avoid_agents};
+
+    // agents slow down on the way in and stop on their destination
+    let settings = SteeringSettings::default();
+    let destination = Vec2::new(500.0, 0.0);
+    let (mut position, mut velocity) = (Vec2::ZERO, Vec2::ZERO);
+    for _ in 0..600 {
+        let desired = arrive(position, destination, 1000.0, settings.slowing_radius);
+        velocity = apply_steering(velocity, desired, 1000.0, 4000.0, 1.0 / 60.0);
+        position += velocity / 60.0;
+        assert!(position.x <= destination.x + settings.arrival_radius);
+    }
+    assert!(position.distance(destination) <= settings.arrival_radius);
+
+    // two agents walking into each other dodge to opposite sides
+    let left = avoid_agents(
+        Vec2::ZERO,
+        Vec2::new(100.0, 0.0),
+        [(Vec2::new(100.0, 0.0), Vec2::new(-100.0, 0.0))].into_iter(),
+        settings.radius,
+        settings.time_horizon,
+    );
+    let right = avoid_agents(
+        Vec2::new(100.0, 0.0),
+        Vec2::new(-100.0, 0.0),
+        [(Vec2::ZERO, Vec2::new(100.0, 0.0))].into_iter(),
+        settings.radius,
+        settings.time_horizon,
+    );
+    assert!(left.y != 0.0);
+    assert_eq!(left.y.signum(), -right.y.signum());
+}
+
+#[test]
+fn spatial_index_follows_agents() {
+    let mut app = headless_app();
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(-6000.0, -6000.0));
+    app.update();
+
+    let index = app.world.resource::<SpatialIndex>();
+    assert!(index
+        .query_radius(Vec2::new(-6000.0, -5900.0), 150.0)
+        .any(|(entity, _)| entity == agent));
+
+    app.world.get_mut::<Transform>(agent).unwrap().translation = Vec3::new(6000.0, 6000.0, 1.0);
+    app.update();
+
+    let index = app.world.resource::<SpatialIndex>();
+    assert_eq!(
+        index
+            .nearest(Vec2::new(6000.0, 6000.0), 100.0)
+            .map(|(e, _)| e),
+        Some(agent)
+    );
+    assert!(index
+        .query_radius(Vec2::new(-6000.0, -6000.0), 150.0)
+        .next()
+        .is_none());
+
+    app.world.despawn(agent);
+    app.update();
+    assert!(app
+        .world
+        .resource::<SpatialIndex>()
+        .position(agent)
+        .is_none());
+
+    // zones too large to split into cells are still found, zones that are not finite are left out
+    let mut index = SpatialIndex::new(100.0);
+    let huge = Entity::from_raw(1);
+    let broken = Entity::from_raw(2);
+    let small = Entity::from_raw(3);
+    index.insert_zone(huge, Vec2::splat(-1.0e9), Vec2::splat(1.0e9));
+    index.insert_zone(broken, Vec2::new(f32::NAN, 0.0), Vec2::splat(f32::INFINITY));
+    index.insert_zone(small, Vec2::new(400.0, 400.0), Vec2::new(600.0, 600.0));
+    assert_eq!(index.zones_at(Vec2::new(500.0, 500.0)).count(), 2);
+    assert_eq!(
+        index
+            .zones_in_rect(Vec2::new(-50.0, -50.0), Vec2::new(50.0, 50.0))
+            .collect::<Vec<_>>(),
+        vec![huge]
+    );
+    assert_eq!(
+        index
+            .zones_in_rect(Vec2::splat(-1.0e12), Vec2::splat(1.0e12))
+            .collect::<Vec<_>>(),
+        vec![huge, small]
+    );
+    index.remove_zone(huge);
+    assert_eq!(
+        index.zones_at(Vec2::new(500.0, 500.0)).collect::<Vec<_>>(),
+        vec![small]
+    );
+}
+
+#[test]
+fn zone_transitions_send_events_and_track_dwell_time() {
+    let mut app = headless_app();
+    let market = spawn_zone(
+        &mut app.world,
+        "market",
+        Vec2::new(5000.0, 0.0),
+        200.0,
+        200.0,
+    );
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(5000.0, 0.0));
+    app.world.entity_mut(agent).remove::<UtilityBrain>();
+    let mut entered_reader = app
+        .world
+        .resource::<Events<AgentEnteredZone>>()
+        .get_reader();
+    let mut exited_reader = app.world.resource::<Events<AgentExitedZone>>().get_reader();
+
+    app.update();
+    let entered: Vec<AgentEnteredZone> = entered_reader
+        .iter(app.world.resource::<Events<AgentEnteredZone>>())
+        .copied()
+        .collect();
+    assert!(entered.contains(&AgentEnteredZone {
+        agent,
+        zone: market
+    }));
+
+    for _ in 0..5 {
+        app.update();
+    }
+    let mapping = app.world.resource::<AgentZoneMapping>();
+    // staying in the zone does not add the agent again
+    assert_eq!(mapping.agents_in(market), &[agent]);
+    assert!(mapping.is_in(agent, market));
+    let dwell = mapping.dwell_time(agent, market).unwrap();
+    assert!(dwell > 0.0);
+
+    app.world.get_mut::<Transform>(agent).unwrap().translation = Vec3::new(-5000.0, 0.0, 1.0);
+    app.update();
+    let exited: Vec<AgentExitedZone> = exited_reader
+        .iter(app.world.resource::<Events<AgentExitedZone>>())
+        .copied()
+        .collect();
+    assert_eq!(exited.len(), 1);
+    assert_eq!((exited[0].agent, exited[0].zone), (agent, market));
+    assert!(exited[0].dwell >= dwell);
+    let mapping = app.world.resource::<AgentZoneMapping>();
+    assert!(mapping.agents_in(market).is_empty());
+    assert_eq!(mapping.zones_containing(agent).count(), 0);
+}
+
+#[test]
+fn zones_can_have_any_shape() {
+    let l_shape = ZoneShape::Polygon {
+        points: vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(400.0, 0.0),
+            Vec2::new(400.0, 100.0),
+            Vec2::new(100.0, 100.0),
+            Vec2::new(100.0, 400.0),
+            Vec2::new(0.0, 400.0),
+        ],
+    };
+    assert!(l_shape.contains_point(Vec2::new(50.0, 300.0)));
+    assert!(!l_shape.contains_point(Vec2::new(300.0, 300.0)));
+    assert_eq!(l_shape.bounds(), (Vec2::ZERO, Vec2::splat(400.0)));
+
+    let wide = ZoneShape::Rectangle {
+        width: 400.0,
+        height: 100.0,
+    };
+    assert!(wide.contains_point(Vec2::new(150.0, 0.0)));
+    assert!(!wide.contains_point(Vec2::new(0.0, 150.0)));
+
+    let mut app = headless_app();
+    let pond = app
+        .world
+        .spawn()
+        .insert_bundle(ZoneBundle::with_shape(
+            "pond",
+            Vec2::new(5000.0, 5000.0),
+            ZoneShape::Difference {
+                base: Box::new(ZoneShape::Circle { radius: 300.0 }),
+                cuts: vec![ZoneShape::Circle { radius: 100.0 }],
+            },
+        ))
+        .id();
+    let shore = spawn_agent(&mut app.world, "anna", Vec2::new(5200.0, 5000.0));
+    let island = spawn_agent(&mut app.world, "bob", Vec2::new(5000.0, 5050.0));
+    app.update();
+
+    let mapping = app.world.resource::<AgentZoneMapping>();
+    assert!(mapping.is_in(shore, pond));
+    assert!(!mapping.is_in(island, pond));
+
+    // cuts are drawn as holes, and cuts that stick out of the base only take a bite out of it
+    let area = |outline: &Vec<Vec2>| -> f32 {
+        outline
+            .iter()
+            .zip(outline.iter().cycle().skip(1))
+            .map(|(a, b)| a.perp_dot(*b))
+            .sum::<f32>()
+            / 2.0
+    };
+    let square = |min: Vec2, max: Vec2| ZoneShape::Polygon {
+        points: vec![min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)],
+    };
+    let courtyard = ZoneShape::Difference {
+        base: Box::new(ZoneShape::Rectangle {
+            width: 400.0,
+            height: 400.0,
+        }),
+        cuts: vec![square(Vec2::splat(-50.0), Vec2::splat(50.0))],
+    };
+    let mut areas: Vec<f32> = courtyard.outlines().iter().map(area).collect();
+    areas.sort_by(f32::total_cmp);
+    assert_eq!(areas, vec![-10000.0, 160000.0]);
+    let bitten = ZoneShape::Difference {
+        base: Box::new(ZoneShape::Rectangle {
+            width: 400.0,
+            height: 400.0,
+        }),
+        cuts: vec![
+            square(Vec2::new(100.0, -100.0), Vec2::new(300.0, 100.0)),
+            square(Vec2::new(150.0, -50.0), Vec2::new(350.0, 150.0)),
+        ],
+    };
+    let outlines = bitten.outlines();
+    assert_eq!(outlines.len(), 1);
+    assert!((area(&outlines[0]) - (160000.0 - 100.0 * 200.0 - 50.0 * 50.0)).abs() < 1.0);
+}
+
+#[test]
+fn zones_queue_agents_when_full_and_refuse_them_when_closed() {
+    let mut app = headless_app();
+    let kiosk = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::new("kiosk", Vec2::new(5000.0, 5000.0), 200.0, 200.0)
+                .with_kind(ZoneKind::Shop)
+                .with_capacity(1),
+        )
+        .id();
+    let vault = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::new("vault", Vec2::new(-5000.0, 5000.0), 200.0, 200.0)
+                .with_kind(ZoneKind::Restricted)
+                .with_access(ZoneAccess::AllowList {
+                    agents: vec![],
+                    factions: vec!["guards".to_string()],
+                }),
+        )
+        .id();
+    let customer = spawn_agent(&mut app.world, "anna", Vec2::new(5000.0, 5000.0));
+    let waiting = spawn_agent(&mut app.world, "bob", Vec2::new(5000.0, 4850.0));
+    let thief = spawn_agent(&mut app.world, "carl", Vec2::new(-5000.0, 4850.0));
+    let guard = spawn_agent(&mut app.world, "dora", Vec2::new(-5000.0, 5150.0));
+    app.world
+        .entity_mut(guard)
+        .insert(Faction("guards".to_string()));
+    for agent in [customer, waiting, thief, guard] {
+        app.world.entity_mut(agent).remove::<UtilityBrain>();
+    }
+    app.update();
+
+    app.world.get_mut::<Agent>(waiting).unwrap().destination = Some(Vec2::new(5000.0, 5000.0));
+    app.world.get_mut::<Agent>(thief).unwrap().destination = Some(Vec2::new(-5000.0, 5000.0));
+    app.world.get_mut::<Agent>(guard).unwrap().destination = Some(Vec2::new(-5000.0, 5000.0));
+    let mut refused_reader = app.world.resource::<Events<ZoneRefused>>().get_reader();
+    app.update();
+
+    // the kiosk is full so bob waits outside
+    assert_eq!(app.world.get::<Agent>(waiting).unwrap().destination, None);
+    assert_eq!(app.world.get::<Queued>(waiting).unwrap().zone, kiosk);
+    assert_eq!(
+        app.world
+            .resource::<ZoneQueues>()
+            .queue(kiosk)
+            .collect::<Vec<_>>(),
+        vec![waiting]
+    );
+
+    // only guards may enter the vault
+    let refused: Vec<ZoneRefused> = refused_reader
+        .iter(app.world.resource::<Events<ZoneRefused>>())
+        .copied()
+        .collect();
+    assert_eq!(
+        refused,
+        vec![ZoneRefused {
+            agent: thief,
+            zone: vault,
+            reason: Refusal::NotAllowed
+        }]
+    );
+    assert!(app.world.get::<Agent>(guard).unwrap().destination.is_some());
+
+    // being sent to the kiosk again while waiting does not lose bob his place
+    app.world.get_mut::<Agent>(waiting).unwrap().destination = Some(Vec2::new(5000.0, 5000.0));
+    app.update();
+    assert_eq!(app.world.get::<Agent>(waiting).unwrap().destination, None);
+    assert_eq!(
+        app.world
+            .resource::<ZoneQueues>()
+            .queue(kiosk)
+            .collect::<Vec<_>>(),
+        vec![waiting]
+    );
+
+    // once anna leaves bob is let in
+    app.world
+        .get_mut::<Transform>(customer)
+        .unwrap()
+        .translation = Vec3::new(0.0, -8000.0, 1.0);
+    app.update();
+    app.update();
+    assert_eq!(
+        app.world.get::<Agent>(waiting).unwrap().destination,
+        Some(Vec2::new(5000.0, 5000.0))
+    );
+    assert!(app.world.get::<Queued>(waiting).is_none());
+}
+
+#[test]
+fn nested_zones_count_agents_and_cascade_rules() {
+    let mut app = headless_app();
+    let district = spawn_zone(
+        &mut app.world,
+        "harbour",
+        Vec2::new(6000.0, -6000.0),
+        3000.0,
+        3000.0,
+    );
+    let building = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::new("warehouse", Vec2::new(6500.0, -6000.0), 1000.0, 1000.0)
+                .with_parent(district)
+                .with_hours(8.0, 18.0),
+        )
+        .id();
+    let room = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::new("office", Vec2::new(6800.0, -6000.0), 200.0, 200.0)
+                .with_parent(building),
+        )
+        .id();
+    let clerk = spawn_agent(&mut app.world, "anna", Vec2::new(6800.0, -6000.0));
+    let visitor = spawn_agent(&mut app.world, "bob", Vec2::new(5900.0, -6000.0));
+    for agent in [clerk, visitor] {
+        app.world.entity_mut(agent).remove::<UtilityBrain>();
+    }
+    app.update();
+
+    // the clerk in the office is also in the warehouse and the harbour
+    let mapping = app.world.resource::<AgentZoneMapping>();
+    assert_eq!(mapping.ancestors(room), vec![building, district]);
+    assert_eq!(mapping.descendants(district), vec![building, room]);
+    assert!(mapping.is_within(clerk, district));
+    assert!(!mapping.is_within(visitor, building));
+    let mut in_harbour = mapping.agents_within(district);
+    in_harbour.sort();
+    let mut expected = vec![clerk, visitor];
+    expected.sort();
+    assert_eq!(in_harbour, expected);
+    assert_eq!(mapping.enclosing_zones(clerk).len(), 3);
+
+    // the office has no opening hours but the warehouse it is in is closed at night
+    app.world.resource_mut::<TimeOfDay>().hours = 22.0;
+    app.world.get_mut::<Agent>(visitor).unwrap().destination = Some(Vec2::new(6800.0, -6000.0));
+    let mut refused_reader = app.world.resource::<Events<ZoneRefused>>().get_reader();
+    app.update();
+    let refused: Vec<ZoneRefused> = refused_reader
+        .iter(app.world.resource::<Events<ZoneRefused>>())
+        .copied()
+        .collect();
+    assert_eq!(
+        refused,
+        vec![ZoneRefused {
+            agent: visitor,
+            zone: room,
+            reason: Refusal::Closed
+        }]
+    );
+}
+
+#[test]
+fn zone_definitions_round_trip_through_ron() {
+    let mut app = headless_app();
+    let hall = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::new("hall", Vec2::new(0.0, 9000.0), 800.0, 800.0)
+                .with_kind(ZoneKind::Workplace)
+                .with_hours(9.0, 17.0),
+        )
+        .id();
+    let booth = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::with_shape(
+                "booth",
+                Vec2::new(100.0, 9000.0),
+                ZoneShape::Circle { radius: 50.0 },
+            )
+            .with_parent(hall)
+            .with_capacity(2),
+        )
+        .id();
+    app.world
+        .entity_mut(booth)
+        .insert(ZoneColour(Color::rgba(1.0, 0.0, 0.0, 0.5)));
+
+    let definitions: Vec<ZoneDefinition> = [hall, booth]
+        .into_iter()
+        .map(|entity| {
+            let zone = app.world.get::<Zone>(entity).unwrap();
+            let position = app.world.get::<Transform>(entity).unwrap().translation;
+            let colour = app.world.get::<ZoneColour>(entity);
+            ZoneDefinition::from_zone(zone, position.truncate(), colour, |entity| {
+                app.world.get::<Zone>(entity).map(|zone| zone.name.clone())
+            })
+        })
+        .collect();
+    assert_eq!(definitions[1].parent.as_deref(), Some("hall"));
+    let ron = ron::to_string(&definitions).unwrap();
+    let loaded: Vec<ZoneDefinition> = ron::from_str(&ron).unwrap();
+    assert_eq!(loaded, definitions);
+
+    let mut queue = CommandQueue::default();
+    let entities = {
+        let mut commands = Commands::new(&mut queue, &app.world);
+        spawn_zone_definitions(&mut commands, &loaded, |_| None).unwrap()
+    };
+    queue.apply(&mut app.world);
+    let booth = app.world.get::<Zone>(entities[1]).unwrap();
+    assert_eq!(booth.parent, Some(entities[0]));
+    assert_eq!(booth.capacity, Some(2));
+    assert_eq!(booth.shape, ZoneShape::Circle { radius: 50.0 });
+
+    // zones that are part of a zone that is not in the file are rejected
+    let orphan = vec![ZoneDefinition {
+        parent: Some("attic".to_string()),
+        ..loaded[1].clone()
+    }];
+    let mut commands = Commands::new(&mut queue, &app.world);
+    let error = spawn_zone_definitions(&mut commands, &orphan, |_| None).unwrap_err();
+    assert!(error.to_string().contains("attic"));
+}
+
+#[test]
+fn scenarios_are_validated_before_spawning() {
+    let village = Scenario::village();
+    assert_eq!(village.agents.len(), 3);
+    let json = serde_json::to_string(&village).unwrap();
+    assert_eq!(Scenario::from_json(&json).unwrap(), village);
+
+    let broken = r#"(
+        name: "broken",
+        time_of_day: Some(30.0),
+        zones: [
+            (name: "shed", position: (0.0, 0.0), shape: Rectangle(width: 100.0, height: 100.0), parent: Some("farm")),
+            (name: "fence", position: (0.0, 0.0), shape: Polygon(points: [(0.0, 0.0), (50.0, 0.0), (100.0, 0.0)])),
+        ],
+        agents: [
+            (name: "tom", position: (0.0, 0.0), needs: {Hunger: 2.0}),
+            (name: "tom", position: (10.0, 0.0)),
+            (name: "ann", position: (20.0, 0.0), knows: Some(["barn"]), perception: Some((view_distance: 400.0, field_of_view: 400.0, hearing_radius: 10.0))),
+        ],
+        rumours: [
+            (agent: "ann", fact: Stole("bob")),
+        ],
+    )"#;
+    let problems = Scenario::from_ron(broken).unwrap_err();
+    assert!(problems.contains("hour 30"));
+    assert!(problems.contains("\"farm\""));
+    assert!(problems.contains("more than one agent named \"tom\""));
+    assert!(problems.contains("hunger 2"));
+    assert!(problems.contains("knows the zone \"barn\""));
+    assert!(problems.contains("about the agent \"bob\""));
+    assert!(problems.contains("field_of_view of 400"));
+    assert!(problems.contains("zone \"fence\" is a polygon with no area"));
+}
+
+#[test]
+fn scenarios_are_spawned_once_until_another_is_picked() {
+    let mut app = village_app();
+    app.update();
+    let count = |app: &mut App| {
+        let agents = app
+            .world
+            .query_filtered::<(), With<Agent>>()
+            .iter(&app.world)
+            .count();
+        let zones = app
+            .world
+            .query_filtered::<(), With<Zone>>()
+            .iter(&app.world)
+            .count();
+        (agents, zones)
+    };
+    let village = Scenario::village();
+    let spawned = (village.agents.len(), village.zones.len());
+    assert_eq!(count(&mut app), spawned);
+
+    // coming back to the simulation keeps the world as it is
+    let reenter = |app: &mut App| {
+        let mut state = app.world.resource_mut::<State<GameState>>();
+        state.set(GameState::Menu).unwrap();
+        app.update();
+        let mut state = app.world.resource_mut::<State<GameState>>();
+        state.set(GameState::Playing).unwrap();
+        app.update();
+    };
+    reenter(&mut app);
+    assert_eq!(count(&mut app), spawned);
+
+    // picking a scenario again replaces the world
+    app.world
+        .insert_resource(ActiveScenario(Some(Scenario::village())));
+    reenter(&mut app);
+    assert_eq!(count(&mut app), spawned);
+}
+
+#[test]
+fn every_shipped_scenario_is_listed_for_the_browser() {
+    let listed: Vec<String> =
+        ron::from_str(include_str!("../assets/scenarios/scenarios.ron")).unwrap();
+    let mut shipped: Vec<String> = std::fs::read_dir("assets/scenarios")
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".scenario.ron") || name.ends_with(".scenario.json"))
+        .collect();
+    shipped.sort();
+    assert_eq!(listed, shipped);
+}
+
+#[test]
+fn saved_simulations_resume_where_they_left_off() {
+    let mut app = village_app();
+    app.update();
+    let mut agents = app.world.query::<(Entity, &Agent)>();
+    let mut named = |world: &World, name: &str| {
+        agents
+            .iter(world)
+            .find(|(_, agent)| agent.name == name)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    };
+    let john = named(&app.world, "john");
+    let jane = named(&app.world, "jane");
+    let mut tree = BehaviourTree::new(BehaviourNode::Wait(5.0));
+    tree.blackboard.set("friend", BlackboardValue::Entity(john));
+    app.world.entity_mut(jane).insert(tree);
+    app.world
+        .get_mut::<Needs>(john)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.2);
+    for _ in 0..10 {
+        app.update();
+    }
+    let greg = named(&app.world, "greg");
+    let mut agents = app.world.query::<&Agent>();
+    assert_eq!(agents.iter(&app.world).count(), 3);
+
+    let saved = SaveFile::capture(&mut app.world);
+    assert_eq!(saved.version, SAVE_VERSION);
+    assert!(!saved.memberships.is_empty());
+    let loaded = SaveFile::from_json(&saved.to_json().unwrap()).unwrap();
+    assert_eq!(loaded, saved);
+
+    // the save replaces the village that the new app starts with
+    let mut resumed = village_app();
+    resumed.update();
+    let ids = loaded.restore(&mut resumed.world);
+    let mut resumed_agents = resumed.world.query::<&Agent>();
+    assert_eq!(resumed_agents.iter(&resumed.world).count(), 3);
+    let mut zones = resumed.world.query::<&Zone>();
+    assert_eq!(zones.iter(&resumed.world).count(), saved.zones.len());
+    assert_eq!(
+        resumed.world.resource::<TimeOfDay>().hours,
+        saved.time_of_day
+    );
+
+    let new_john = ids[&john.to_bits()];
+    let new_jane = ids[&jane.to_bits()];
+    assert_eq!(
+        resumed
+            .world
+            .get::<Needs>(new_john)
+            .unwrap()
+            .get(NeedKind::Hunger),
+        app.world.get::<Needs>(john).unwrap().get(NeedKind::Hunger)
+    );
+    let tree = resumed.world.get::<BehaviourTree>(new_jane).unwrap();
+    assert_eq!(
+        tree.blackboard.get("friend"),
+        Some(&BlackboardValue::Entity(new_john))
+    );
+    assert_eq!(
+        resumed.world.get::<GoapAgent>(ids[&greg.to_bits()]),
+        app.world.get::<GoapAgent>(greg)
+    );
+    let mapping = resumed.world.resource::<AgentZoneMapping>();
+    for (agent, zone, dwell) in saved.memberships.iter() {
+        assert_eq!(mapping.dwell_time(ids[agent], ids[zone]), Some(*dwell));
+    }
+
+    resumed.update();
+    assert_eq!(resumed_agents.iter(&resumed.world).count(), 3);
+
+    // files from a newer build and files that are not save files are refused
+    let mut newer: serde_json::Value = serde_json::from_str(&saved.to_json().unwrap()).unwrap();
+    newer["version"] = serde_json::Value::from(SAVE_VERSION + 1);
+    let error = SaveFile::from_json(&newer.to_string()).unwrap_err();
+    assert!(error.to_string().contains("version"));
+    assert!(SaveFile::from_json("{}").is_err());
+}
+
+#[test]
+fn runs_with_the_same_seed_are_identical() {
+    use npc_sim_bevy::clock::DAY_LENGTH_SECONDS;
+    use rand::Rng;
+
+    let mut first = village_app();
+    let mut second = village_app();
+    for app in [&mut first, &mut second] {
+        app.update();
+        // a cafe with room for one and a crowd of hungry villagers keeps agents queueing outside it
+        // and walking around the wall
+        let mut zones = app.world.query::<&mut Zone>();
+        for mut zone in zones.iter_mut(&mut app.world) {
+            if zone.name == "CAFE" {
+                zone.capacity = Some(1);
+            }
+        }
+        for villager in 0..6 {
+            let position = Vec2::new(-1000.0 + 400.0 * villager as f32, 800.0);
+            let entity = spawn_agent(&mut app.world, format!("villager {}", villager), position);
+            let mut needs = app.world.get_mut::<Needs>(entity).unwrap();
+            needs.set(NeedKind::Hunger, 0.1 * villager as f32);
+        }
+    }
+
+    let mut queued = false;
+    for checkpoint in 0..6 {
+        for _ in 0..500 {
+            first.update();
+            second.update();
+            queued |= first
+                .world
+                .resource::<ZoneQueues>()
+                .queues()
+                .next()
+                .is_some();
+        }
+        let first_state = SaveFile::capture(&mut first.world);
+        let second_state = SaveFile::capture(&mut second.world);
+        assert_eq!(
+            first_state.state_hash(),
+            second_state.state_hash(),
+            "runs drifted apart by checkpoint {}",
+            checkpoint
+        );
+    }
+    assert!(queued);
+    assert!(first.world.resource::<PathCache>().misses > 0);
+
+    // headless runs take exactly one tick of a fixed length every update
+    let clock = first.world.resource::<SimClock>();
+    assert_eq!(clock.tick, 3001);
+    let hours = (8.0 + clock.elapsed_seconds() * 24.0 / DAY_LENGTH_SECONDS as f64).rem_euclid(24.0);
+    let time_of_day = first.world.resource::<TimeOfDay>().hours as f64;
+    // the time of day adds up a tick at a time in f32, so it may be off by a few seconds
+    assert!((time_of_day - hours).abs() < 1e-2);
+
+    // the village scenario picks its own seed
+    assert_eq!(first.world.resource::<SimRng>().seed(), 42);
+
+    let mut rng = SimRng::new(7);
+    let drawn: Vec<u32> = (0..4).map(|_| rng.gen()).collect();
+    let mut same = SimRng::new(7);
+    assert_eq!(drawn, (0..4).map(|_| same.gen()).collect::<Vec<u32>>());
+    let mut other = SimRng::new(8);
+    assert_ne!(drawn, (0..4).map(|_| other.gen()).collect::<Vec<u32>>());
+
+    // a generator restored from its state carries on with the same numbers
+    let mut restored = SimRng::from_state(rng.state());
+    assert_eq!(rng.gen::<u64>(), restored.gen::<u64>());
+}
+
+#[test]
+fn saves_from_before_the_fixed_timestep_still_load() {
+    let mut app = headless_app();
+    for _ in 0..5 {
+        app.update();
+    }
+    let saved = SaveFile::capture(&mut app.world);
+    assert_eq!(saved.tick, 5);
+
+    let mut old: serde_json::Value = serde_json::from_str(&saved.to_json().unwrap()).unwrap();
+    old["version"] = serde_json::Value::from(1);
+    old.as_object_mut().unwrap().remove("tick");
+    old.as_object_mut().unwrap().remove("rng");
+    let loaded = SaveFile::from_json(&old.to_string()).unwrap();
+    assert_eq!(loaded.version, SAVE_VERSION);
+    assert_eq!(loaded.tick, 0);
+    assert_eq!(loaded.rng, SimRng::default().state());
+    // agents of files written before schedules, interactions, memories, rumours and perception
+    // carry on without them
+    let mut agents = saved.agents.clone();
+    for agent in agents.iter_mut() {
+        agent.schedule = None;
+        agent.interaction = None;
+        agent.interaction_cooldown = None;
+        agent.memory = None;
+        agent.beliefs = None;
+        agent.rumours = None;
+        agent.perception = None;
+    }
+    assert_eq!(loaded.agents, agents);
+
+    let mut resumed = headless_app();
+    resumed.update();
+    loaded.restore(&mut resumed.world);
+    assert_eq!(resumed.world.resource::<SimClock>().tick, 0);
+    resumed.update();
+    assert_eq!(resumed.world.resource::<SimClock>().tick, 1);
+}
+
+#[test]
+fn the_clock_pauses_steps_and_changes_speed() {
+    let mut app = headless_app();
+    app.update();
+    app.update();
+    assert_eq!(app.world.resource::<SimClock>().tick, 2);
+    let mut agents = app.world.query::<(&Agent, &Transform)>();
+    let positions = |world: &World, agents: &mut QueryState<(&Agent, &Transform)>| {
+        let mut positions: Vec<(String, Vec3)> = agents
+            .iter(world)
+            .map(|(agent, transform)| (agent.name.clone(), transform.translation))
+            .collect();
+        positions.sort_by(|a, b| a.0.cmp(&b.0));
+        positions
+    };
+
+    // nothing moves while paused, unless a single tick is asked for
+    app.world.resource_mut::<SimClock>().paused = true;
+    let before = positions(&app.world, &mut agents);
+    let hours = app.world.resource::<TimeOfDay>().hours;
+    for _ in 0..3 {
+        app.update();
+    }
+    assert_eq!(app.world.resource::<SimClock>().tick, 2);
+    assert_eq!(positions(&app.world, &mut agents), before);
+    assert_eq!(app.world.resource::<TimeOfDay>().hours, hours);
+    app.world.resource_mut::<SimClock>().step_once();
+    app.update();
+    app.update();
+    assert_eq!(app.world.resource::<SimClock>().tick, 3);
+
+    // headless runs take `speed` ticks every update
+    let mut clock = app.world.resource_mut::<SimClock>();
+    clock.paused = false;
+    clock.set_speed(4.0);
+    app.update();
+    assert_eq!(app.world.resource::<SimClock>().tick, 7);
+    app.world.resource_mut::<SimClock>().set_speed(0.25);
+    for _ in 0..4 {
+        app.update();
+    }
+    assert_eq!(app.world.resource::<SimClock>().tick, 8);
+
+    let mut clock = app.world.resource_mut::<SimClock>();
+    clock.set_speed(100.0);
+    assert_eq!(clock.speed(), 64.0);
+    clock.faster();
+    assert_eq!(clock.speed(), 64.0);
+    clock.set_speed(0.25);
+    clock.slower();
+    assert_eq!(clock.speed(), 0.25);
+    clock.set_speed(1.0);
+
+    // the clock starts a new day at midnight
+    app.world.resource_mut::<TimeOfDay>().hours = 23.9999;
+    app.update();
+    let clock = app.world.resource::<SimClock>();
+    assert_eq!(clock.day, 1);
+    assert_eq!(
+        clock.date_time(app.world.resource::<TimeOfDay>()),
+        "day 2 00:00"
+    );
+}
+
+#[test]
+fn agents_follow_their_daily_schedule() {
+    let schedule = DailySchedule::default();
+    assert_eq!(schedule.activity_at(23.0), ScheduledActivity::Sleep);
+    assert_eq!(schedule.activity_at(3.0), ScheduledActivity::Sleep);
+    assert_eq!(schedule.activity_at(8.0), ScheduledActivity::Leisure);
+    assert_eq!(schedule.activity_at(10.0), ScheduledActivity::Work);
+    assert_eq!(schedule.activity_at(20.0), ScheduledActivity::Leisure);
+
+    // varied schedules stay close to the original and depend only on the seed
+    let varied = schedule.varied(&mut SimRng::new(1));
+    assert_eq!(varied, schedule.varied(&mut SimRng::new(1)));
+    assert_ne!(varied, schedule.varied(&mut SimRng::new(2)));
+    for (block, original) in varied.blocks.iter().zip(schedule.blocks.iter()) {
+        for (hours, original) in [(block.start, original.start), (block.end, original.end)] {
+            let shift = (hours - original).rem_euclid(24.0);
+            assert!(shift.min(24.0 - shift) <= 0.75 + 1e-4);
+        }
+    }
+
+    let mut app = village_app();
+    app.update();
+    let mut schedules = app.world.query::<(&Agent, &DailySchedule)>();
+    let mut village: Vec<(String, DailySchedule)> = schedules
+        .iter(&app.world)
+        .map(|(agent, schedule)| (agent.name.clone(), schedule.clone()))
+        .collect();
+    village.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(village.len(), 3);
+    assert_ne!(village[0].1, village[1].1);
+    let saved = SaveFile::capture(&mut app.world);
+    assert!(saved.agents.iter().all(|agent| agent.schedule.is_some()));
+
+    // the schedule picks the zone, unless a need is more urgent
+    let anna = spawn_agent(&mut app.world, "anna", Vec2::new(-6000.0, -6000.0));
+    app.world.entity_mut(anna).insert(DailySchedule::default());
+    let choose = |app: &mut App, hours: f32| {
+        app.world.resource_mut::<TimeOfDay>().hours = hours;
+        app.world.get_mut::<UtilityBrain>(anna).unwrap().next_think = 0.0;
+        app.update();
+        let activity = app.world.get::<Activity>(anna).unwrap().action.clone();
+        (activity, app.world.get::<Agent>(anna).unwrap().destination)
+    };
+    assert_eq!(
+        choose(&mut app, 10.0),
+        ("work".to_string(), Some(Vec2::new(0.0, -3000.0)))
+    );
+    assert_eq!(
+        choose(&mut app, 23.0),
+        ("bedtime".to_string(), Some(Vec2::new(-3000.0, 1500.0)))
+    );
+    assert_eq!(choose(&mut app, 19.0).0, "leisure");
+    app.world
+        .get_mut::<Needs>(anna)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.05);
+    assert_eq!(
+        choose(&mut app, 10.0),
+        ("eat".to_string(), Some(Vec2::new(3000.0, 0.0)))
+    );
+
+    assert_eq!(TimeOfDay { hours: 12.0 }.daylight(), 1.0);
+    assert_eq!(TimeOfDay { hours: 0.0 }.daylight(), 0.0);
+    assert_eq!(TimeOfDay { hours: 6.0 }.daylight(), 0.5);
+
+    let problems = Scenario::from_ron(
+        r#"(
+            name: "Night shift",
+            agents: [(
+                name: "tom",
+                position: (0.0, 0.0),
+                schedule: Some((blocks: [(activity: Work, start: 22.0, end: 30.0)])),
+            )],
+        )"#,
+    )
+    .unwrap_err();
+    assert!(problems.contains("agent \"tom\" has a schedule"));
+}
+
+#[test]
+fn relationships_change_through_interactions() {
+    let mut app = village_app();
+    app.update();
+    let mut agents = app.world.query::<(Entity, &Agent)>();
+    let mut named = |world: &World, name: &str| {
+        agents
+            .iter(world)
+            .find(|(_, agent)| agent.name == name)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    };
+    let john = named(&app.world, "john");
+    let jane = named(&app.world, "jane");
+    let greg = named(&app.world, "greg");
+
+    // the village scenario starts with a family and a friendship
+    let graph = app.world.resource::<SocialGraph>();
+    assert_eq!(
+        graph.get(jane, john).unwrap().kind,
+        RelationshipKind::Family
+    );
+    assert_eq!(graph.friends_of(greg).collect::<Vec<_>>(), vec![john]);
+    let top = graph.top_relationships(john, 1);
+    assert_eq!(top.len(), 1);
+    assert_eq!(top[0].0, jane);
+    let index = app.world.resource::<SpatialIndex>();
+    assert_eq!(graph.friends_within(john, 1000.0, index), vec![greg]);
+    assert!(graph.friends_within(john, 100.0, index).is_empty());
+
+    // arguments turn friends into rivals, family stays family
+    let mut graph = app.world.resource_mut::<SocialGraph>();
+    for _ in 0..15 {
+        graph.record_interaction(john, greg, -1.0);
+        graph.record_interaction(john, jane, -1.0);
+    }
+    assert_eq!(graph.get(greg, john).unwrap().kind, RelationshipKind::Rival);
+    assert_eq!(
+        graph.get(jane, john).unwrap().kind,
+        RelationshipKind::Family
+    );
+    assert!((graph.get(jane, john).unwrap().trust - 0.15).abs() < 1e-4);
+    for _ in 0..15 {
+        graph.record_interaction(john, greg, 1.0);
+    }
+    assert_eq!(
+        graph.get(john, greg).unwrap().kind,
+        RelationshipKind::Friend
+    );
+
+    // agents spending time together get to know each other
+    let anna = spawn_agent(&mut app.world, "anna", Vec2::new(8000.0, 8000.0));
+    let bob = spawn_agent(&mut app.world, "bob", Vec2::new(8050.0, 8000.0));
+    for _ in 0..5 {
+        app.update();
+    }
+    let met = *app.world.resource::<SocialGraph>().get(anna, bob).unwrap();
+    assert!(met.familiarity > 0.0);
+    assert_eq!(met.kind, RelationshipKind::Acquaintance);
+
+    let saved = SaveFile::capture(&mut app.world);
+    assert!(saved
+        .relationships
+        .iter()
+        .any(|(from, to, _)| (*from, *to) == (anna.to_bits(), bob.to_bits())));
+    let mut resumed = village_app();
+    resumed.update();
+    let ids = saved.restore(&mut resumed.world);
+    assert_eq!(
+        resumed
+            .world
+            .resource::<SocialGraph>()
+            .get(ids[&anna.to_bits()], ids[&bob.to_bits()]),
+        Some(&met)
+    );
+
+    app.world.entity_mut(bob).despawn();
+    app.update();
+    let graph = app.world.resource::<SocialGraph>();
+    assert!(graph.get(anna, bob).is_none());
+    assert!(graph.get(bob, anna).is_none());
+
+    let problems = Scenario::from_ron(
+        r#"(
+            name: "Strangers",
+            agents: [(name: "tom", position: (0.0, 0.0))],
+            relationships: [(agents: ("tom", "tim"), kind: Friend, affinity: 2.0)],
+        )"#,
+    )
+    .unwrap_err();
+    assert!(problems.contains("\"tim\", there is no agent with that name"));
+    assert!(problems.contains("an affinity of 2"));
+}
+
+#[test]
+fn agents_close_to_each_other_interact() {
+    let mut app = headless_app();
+    app.update();
+    {
+        let mut settings = app.world.resource_mut::<InteractionSettings>();
+        settings.chance_per_second = f32::INFINITY;
+        settings.cooldown = 1000.0;
+    }
+    let anna = spawn_agent(&mut app.world, "anna", Vec2::new(8000.0, 8000.0));
+    let bob = spawn_agent(&mut app.world, "bob", Vec2::new(8050.0, 8000.0));
+    app.world
+        .get_mut::<Needs>(anna)
+        .unwrap()
+        .set(NeedKind::Social, 0.5);
+    let mut started_reader = app
+        .world
+        .resource::<Events<InteractionStarted>>()
+        .get_reader();
+    let mut ended_reader = app
+        .world
+        .resource::<Events<InteractionEnded>>()
+        .get_reader();
+
+    // strangers greet each other and stand still while they do
+    let mut started = Vec::new();
+    for _ in 0..5 {
+        app.update();
+        started.extend(
+            started_reader
+                .iter(app.world.resource::<Events<InteractionStarted>>())
+                .filter(|event| event.initiator == anna || event.partner == anna)
+                .copied(),
+        );
+    }
+    assert_eq!(started.len(), 1);
+    assert_eq!(started[0].kind, InteractionKind::Greeting);
+    let interaction = *app.world.get::<SocialInteraction>(anna).unwrap();
+    assert_eq!(interaction.partner, bob);
+    assert_eq!(
+        app.world.get::<SocialInteraction>(bob).unwrap().partner,
+        anna
+    );
+    assert!(app.world.get::<Agent>(anna).unwrap().destination.is_none());
+
+    // an interaction in progress is saved with its partner
+    let saved = SaveFile::capture(&mut app.world);
+    let mut resumed = headless_app();
+    resumed.update();
+    let ids = saved.restore(&mut resumed.world);
+    let restored = resumed
+        .world
+        .get::<SocialInteraction>(ids[&anna.to_bits()])
+        .unwrap();
+    assert_eq!(restored.partner, ids[&bob.to_bits()]);
+    assert_eq!(restored.kind, interaction.kind);
+
+    let mut ended = Vec::new();
+    for _ in 0..200 {
+        app.update();
+        ended.extend(
+            ended_reader
+                .iter(app.world.resource::<Events<InteractionEnded>>())
+                .filter(|event| event.initiator == anna || event.partner == anna)
+                .copied(),
+        );
+    }
+    assert_eq!(ended.len(), 1);
+    let outcome = ended[0].outcome.unwrap();
+    assert!(outcome > 0.0);
+
+    // the greeting was good company and left them a little fonder of each other
+    assert!(app.world.get::<Needs>(anna).unwrap().get(NeedKind::Social) > 0.5);
+    let graph = app.world.resource::<SocialGraph>();
+    assert!(graph.get(anna, bob).unwrap().affinity > 0.0);
+    assert!(graph.get(bob, anna).unwrap().familiarity >= 0.05);
+    for agent in [anna, bob] {
+        assert!(app.world.get::<SocialInteraction>(agent).is_none());
+        assert!(app.world.get::<InteractionCooldown>(agent).is_some());
+    }
+}
+
+#[test]
+fn agents_remember_and_plan_with_what_they_know() {
+    let mut app = headless_app();
+    app.update();
+    let bakery = spawn_zone(
+        &mut app.world,
+        "bakery",
+        Vec2::new(-7000.0, 0.0),
+        400.0,
+        400.0,
+    );
+    app.world.get_mut::<Zone>(bakery).unwrap().kind = ZoneKind::Shop;
+    let anna = spawn_agent(&mut app.world, "anna", Vec2::new(-10000.0, 0.0));
+    app.world
+        .entity_mut(anna)
+        .insert(EpisodicMemory::default())
+        .insert(Beliefs::default());
+    app.world
+        .get_mut::<Needs>(anna)
+        .unwrap()
+        .set(NeedKind::Hunger, 0.1);
+
+    // the cafe is closer, but anna does not know any zone and has nowhere to go
+    app.update();
+    assert!(app.world.get::<Beliefs>(anna).unwrap().zones.is_empty());
+    assert_eq!(app.world.get::<Agent>(anna).unwrap().destination, None);
+
+    // once told about the bakery anna heads there
+    app.world.entity_mut(anna).insert(LearnZones(vec![bakery]));
+    for _ in 0..70 {
+        app.update();
+    }
+    let beliefs = app.world.get::<Beliefs>(anna).unwrap();
+    assert!(beliefs.knows(bakery));
+    assert_eq!(beliefs.zone_named("bakery").unwrap().0, bakery);
+    assert_eq!(
+        app.world.get::<Agent>(anna).unwrap().destination,
+        Some(Vec2::new(-7000.0, 0.0))
+    );
+    assert!(app.world.get::<LearnZones>(anna).is_none());
+
+    // walking in is remembered, and zones in sight are learned
+    app.world.get_mut::<Transform>(anna).unwrap().translation = Vec3::new(-7000.0, 0.0, 1.0);
+    app.update();
+    let memory = app.world.get::<EpisodicMemory>(anna).unwrap();
+    let tick = app.world.resource::<SimClock>().tick;
+    assert!(memory.last_visit(bakery).is_some_and(|visit| visit <= tick));
+
+    // a save keeps what anna remembers and believes
+    let saved = SaveFile::capture(&mut app.world);
+    let mut resumed = headless_app();
+    resumed.update();
+    let ids = saved.restore(&mut resumed.world);
+    let resumed_anna = ids[&anna.to_bits()];
+    let resumed_bakery = ids[&bakery.to_bits()];
+    let memory = resumed.world.get::<EpisodicMemory>(resumed_anna).unwrap();
+    assert!(memory.last_visit(resumed_bakery).is_some());
+    let belief = resumed
+        .world
+        .get::<Beliefs>(resumed_anna)
+        .unwrap()
+        .get(resumed_bakery)
+        .unwrap();
+    assert_eq!(belief.zone.kind, ZoneKind::Shop);
+    assert_eq!(belief.center, Vec2::new(-7000.0, 0.0));
+
+    // a zone that is not where anna remembers it is forgotten once anna looks
+    app.world.get_mut::<Transform>(bakery).unwrap().translation = Vec3::new(9000.0, 9000.0, 0.0);
+    app.update();
+    assert!(!app.world.get::<Beliefs>(anna).unwrap().knows(bakery));
+
+    // memories fade and the weakest make room for new ones
+    let mut memory = EpisodicMemory {
+        capacity: 2,
+        ..Default::default()
+    };
+    memory.remember(EpisodeEvent::Visited { zone: bakery }, 1);
+    memory.remember(
+        EpisodeEvent::Met {
+            agent: anna,
+            kind: InteractionKind::Greeting,
+        },
+        2,
+    );
+    memory.fade(0.1, 0.1);
+    memory.remember(EpisodeEvent::Visited { zone: anna }, 3);
+    assert_eq!(memory.episodes.len(), 2);
+    assert_eq!(memory.last_visit(bakery), None);
+    assert!(memory.has_met(anna));
+    memory.fade(1.0, 0.1);
+    assert!(memory.episodes.is_empty());
+}
+
+#[test]
+fn agents_believe_zones_are_closed_until_they_see_them_open() {
+    let mut app = headless_app();
+    let bakery = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::new("bakery", Vec2::new(-7000.0, 0.0), 400.0, 400.0)
+                .with_kind(ZoneKind::Shop)
+                .with_hours(9.0, 17.0),
+        )
+        .id();
+    let anna = spawn_agent(&mut app.world, "anna", Vec2::new(-7000.0, 300.0));
+    app.world.entity_mut(anna).insert(Beliefs::default());
+    app.update();
+    app.world
+        .get_mut::<Beliefs>(anna)
+        .unwrap()
+        .zones
+        .get_mut(&bakery)
+        .unwrap()
+        .closed = true;
+
+    // seeing the bakery shut does not tell anna it is open
+    app.world.resource_mut::<TimeOfDay>().hours = 20.0;
+    app.update();
+    let beliefs = app.world.get::<Beliefs>(anna).unwrap();
+    assert!(beliefs.get(bakery).unwrap().closed);
+
+    // seeing it open does
+    app.world.resource_mut::<TimeOfDay>().hours = 10.0;
+    app.update();
+    let beliefs = app.world.get::<Beliefs>(anna).unwrap();
+    assert!(!beliefs.get(bakery).unwrap().closed);
+}
+
+#[test]
+fn rumours_spread_through_trust_and_are_measured() {
+    let mut app = village_app();
+    app.update();
+    let mut agents = app.world.query::<(Entity, &Agent)>();
+    let mut named = |world: &World, name: &str| {
+        agents
+            .iter(world)
+            .find(|(_, agent)| agent.name == name)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    };
+    let john = named(&app.world, "john");
+    let jane = named(&app.world, "jane");
+    let greg = named(&app.world, "greg");
+    let cafe = app
+        .world
+        .query::<(Entity, &Zone)>()
+        .iter(&app.world)
+        .find(|(_, zone)| zone.name == "CAFE")
+        .map(|(entity, _)| entity)
+        .unwrap();
+    *app.world.resource_mut::<RumourSettings>() = RumourSettings {
+        share_chance: 1.0,
+        distortion_chance: 0.0,
+        ..Default::default()
+    };
+    let end_conversation = |app: &mut App, kind, initiator, partner| {
+        app.world
+            .resource_mut::<Events<InteractionEnded>>()
+            .send(InteractionEnded {
+                kind,
+                initiator,
+                partner,
+                outcome: Some(0.5),
+            });
+        app.update();
+    };
+
+    // the village starts with jane knowing that greg stole something
+    let metrics = app.world.resource::<RumourMetrics>();
+    assert_eq!(metrics.spreads.len(), 1);
+    assert_eq!(metrics.spreads[0].origin, "jane");
+    assert_eq!(metrics.spreads[0].fact, "greg stole something");
+    assert_eq!(
+        app.world.get::<Rumours>(jane).unwrap().get(0).unwrap().fact,
+        Fact::Stole { agent: greg }
+    );
+
+    // arguments pass nothing on, a good talk does and john believes jane as much as john trusts
+    // jane
+    end_conversation(&mut app, InteractionKind::Argument, jane, john);
+    assert!(app.world.get::<Rumours>(john).unwrap().get(0).is_none());
+    let affinity = app
+        .world
+        .resource::<SocialGraph>()
+        .get(john, greg)
+        .unwrap()
+        .affinity;
+    end_conversation(&mut app, InteractionKind::Conversation, jane, john);
+    let rumour = *app.world.get::<Rumours>(john).unwrap().get(0).unwrap();
+    assert!((rumour.confidence - 0.9).abs() < 1e-5);
+    assert_eq!(rumour.hops, 1);
+    assert!(!rumour.distorted);
+    let relationship = app.world.resource::<SocialGraph>().get(john, greg).unwrap();
+    assert!(relationship.affinity < affinity);
+    let events = app.world.resource::<Events<RumourHeard>>();
+    let mut reader = events.get_reader();
+    let heard: Vec<&RumourHeard> = reader.iter(events).collect();
+    assert_eq!(heard.len(), 1);
+    assert_eq!((heard[0].teller, heard[0].listener), (jane, john));
+
+    // greg barely trusts john any more, so the rumour goes no further
+    app.world.resource_mut::<SocialGraph>().set(
+        greg,
+        john,
+        Relationship {
+            trust: 0.1,
+            ..Default::default()
+        },
+    );
+    end_conversation(&mut app, InteractionKind::Trade, john, greg);
+    assert!(app.world.get::<Rumours>(greg).unwrap().get(0).is_none());
+
+    // rumours that get distorted on the way put zones in the wrong place
+    app.world.get_mut::<Beliefs>(john).unwrap().forget(cafe);
+    app.world.resource_mut::<RumourSettings>().distortion_chance = 1.0;
+    let cafe_position = app
+        .world
+        .get::<Transform>(cafe)
+        .unwrap()
+        .translation
+        .truncate();
+    app.world
+        .resource_mut::<Events<StartRumour>>()
+        .send(StartRumour {
+            agent: jane,
+            fact: Fact::ZoneAt {
+                zone: cafe,
+                position: cafe_position,
+            },
+        });
+    end_conversation(&mut app, InteractionKind::Conversation, jane, john);
+    let rumour = *app.world.get::<Rumours>(john).unwrap().get(1).unwrap();
+    assert!(rumour.distorted);
+    let belief = app.world.get::<Beliefs>(john).unwrap().get(cafe).unwrap();
+    assert_ne!(belief.center, cafe_position);
+    assert!(belief.center.distance(cafe_position) <= 300.0 * 2f32.sqrt());
+
+    // hearing a zone is closed stops agents planning to go there
+    app.world.resource_mut::<RumourSettings>().distortion_chance = 0.0;
+    app.world
+        .resource_mut::<Events<StartRumour>>()
+        .send(StartRumour {
+            agent: jane,
+            fact: Fact::ZoneClosed { zone: cafe },
+        });
+    app.world.entity_mut(john).insert(LearnZones(vec![cafe]));
+    end_conversation(&mut app, InteractionKind::Conversation, jane, john);
+    assert!(
+        app.world
+            .get::<Beliefs>(john)
+            .unwrap()
+            .get(cafe)
+            .unwrap()
+            .closed
+    );
+
+    // the metrics say how far and how fast every rumour went
+    let metrics = app.world.resource::<RumourMetrics>().clone();
+    let summaries = metrics.summaries(3, app.world.resource::<SimClock>().delta_seconds());
+    assert_eq!(summaries.len(), 3);
+    assert_eq!(summaries[0].reached, 2);
+    assert!((summaries[0].fraction - 2.0 / 3.0).abs() < 1e-5);
+    assert_eq!(summaries[0].max_hops, 1);
+    assert!(summaries[0].seconds_to_half.is_some());
+    assert_eq!(summaries[1].distorted, 1);
+    let folder = std::env::temp_dir().join("npc-sim-rumour-metrics");
+    app.world
+        .resource_mut::<Events<ExportRumourMetrics>>()
+        .send(ExportRumourMetrics {
+            folder: folder.display().to_string(),
+        });
+    app.update();
+    let reach = std::fs::read_to_string(folder.join("rumour_reach.csv")).unwrap();
+    assert!(reach.starts_with("rumour,agent,seconds,hops,confidence,distorted,fact"));
+    assert!(reach.contains("0,john,"));
+    let summary = std::fs::read_to_string(folder.join("rumour_summary.csv")).unwrap();
+    assert_eq!(summary.lines().count(), 4);
+    assert!(app
+        .world
+        .resource::<MetricsStatus>()
+        .message
+        .starts_with("exported"));
+
+    // a save keeps who knows what and how far it spread
+    let saved = SaveFile::capture(&mut app.world);
+    let mut resumed = village_app();
+    resumed.update();
+    let ids = saved.restore(&mut resumed.world);
+    assert_eq!(*resumed.world.resource::<RumourMetrics>(), metrics);
+    let rumour = resumed
+        .world
+        .get::<Rumours>(ids[&john.to_bits()])
+        .unwrap()
+        .get(0)
+        .unwrap()
+        .fact;
+    assert_eq!(
+        rumour,
+        Fact::Stole {
+            agent: ids[&greg.to_bits()]
+        }
+    );
+}
+
+#[test]
+fn agents_perceive_what_is_in_view_and_earshot() {
+    let mut app = headless_app();
+    app.update();
+    let watcher = spawn_agent(&mut app.world, "watcher", Vec2::new(-8000.0, 8000.0));
+    app.world
+        .entity_mut(watcher)
+        .insert(Perception::default())
+        .insert(Perceived::default())
+        .insert(Beliefs::default());
+    let ahead = spawn_agent(&mut app.world, "ahead", Vec2::new(-7700.0, 8000.0));
+    let behind = spawn_agent(&mut app.world, "behind", Vec2::new(-8120.0, 8000.0));
+    let far = spawn_agent(&mut app.world, "far", Vec2::new(-7000.0, 8000.0));
+    let pond = spawn_zone(
+        &mut app.world,
+        "pond",
+        Vec2::new(-7600.0, 8000.0),
+        100.0,
+        100.0,
+    );
+    app.update();
+    app.update();
+
+    // agents face along +x until they move, so the agent ahead is seen and the one behind heard
+    let perceived = app.world.get::<Perceived>(watcher).unwrap().clone();
+    assert!(perceived.seen.contains(&ahead));
+    assert!(!perceived.heard.contains(&ahead));
+    assert!(perceived.heard.contains(&behind));
+    assert!(!perceived.seen.contains(&behind));
+    assert!(!perceived.perceives(far));
+    assert!(perceived.seen.contains(&pond));
+    assert!(app.world.get::<Beliefs>(watcher).unwrap().knows(pond));
+
+    // a wall hides the agent and the zone, but the pond is not forgotten for being out of sight
+    app.world
+        .spawn()
+        .insert_bundle(ObstacleBundle::new(Vec2::new(-7850.0, 8000.0), 50.0, 400.0));
+    app.update();
+    app.update();
+    let perceived = app.world.get::<Perceived>(watcher).unwrap();
+    assert!(!perceived.seen.contains(&ahead));
+    assert!(!perceived.seen.contains(&pond));
+    assert!(perceived.heard.contains(&behind));
+    assert!(app.world.get::<Beliefs>(watcher).unwrap().knows(pond));
+
+    // turning around brings the agent behind into view
+    app.world.get_mut::<Transform>(watcher).unwrap().rotation =
+        Quat::from_rotation_z(std::f32::consts::PI);
+    app.update();
+    assert!(app
+        .world
+        .get::<Perceived>(watcher)
+        .unwrap()
+        .seen
+        .contains(&behind));
+
+    // a save keeps how far agents see
+    let perception = Perception {
+        field_of_view: 90.0,
+        ..Default::default()
+    };
+    app.world.entity_mut(watcher).insert(perception);
+    let saved = SaveFile::capture(&mut app.world);
+    let mut resumed = headless_app();
+    resumed.update();
+    let ids = saved.restore(&mut resumed.world);
+    let resumed_watcher = ids[&watcher.to_bits()];
+    assert_eq!(
+        resumed.world.get::<Perception>(resumed_watcher),
+        Some(&perception)
+    );
+    // the spatial index only picks up the restored agents at the end of the first tick
+    resumed.update();
+    resumed.update();
+    assert!(resumed
+        .world
+        .get::<Perceived>(resumed_watcher)
+        .unwrap()
+        .seen
+        .contains(&ids[&behind.to_bits()]));
+}
+
+#[test]
+fn agents_missing_components_survive_a_save() {
+    let mut app = headless_app();
+    app.update();
+    let bare = app
+        .world
+        .spawn()
+        .insert(Agent::new("bare"))
+        .insert_bundle(TransformBundle::from_transform(Transform::from_xyz(
+            50.0, 60.0, 1.0,
+        )))
+        .id();
+    let saved = SaveFile::capture(&mut app.world);
+    let saved_bare = saved
+        .agents
+        .iter()
+        .find(|agent| agent.id == bare.to_bits())
+        .unwrap();
+    assert_eq!(saved_bare.position, Vec2::new(50.0, 60.0));
+    assert_eq!(saved_bare.needs, None);
+    assert_eq!(saved_bare.brain, None);
+
+    // only what the agent had comes back, and the simulation carries on around it
+    let loaded = SaveFile::from_json(&saved.to_json().unwrap()).unwrap();
+    let mut resumed = headless_app();
+    resumed.update();
+    let ids = loaded.restore(&mut resumed.world);
+    let resumed_bare = ids[&bare.to_bits()];
+    assert_eq!(
+        resumed.world.get::<Agent>(resumed_bare).unwrap().name,
+        "bare"
+    );
+    assert_eq!(
+        resumed
+            .world
+            .get::<Transform>(resumed_bare)
+            .unwrap()
+            .translation,
+        Vec3::new(50.0, 60.0, 1.0)
+    );
+    assert!(resumed.world.get::<Needs>(resumed_bare).is_none());
+    assert!(resumed.world.get::<UtilityBrain>(resumed_bare).is_none());
+    assert!(resumed.world.get::<Velocity>(resumed_bare).is_none());
+    for _ in 0..10 {
+        resumed.update();
+    }
+    assert!(resumed.world.get::<Agent>(resumed_bare).is_some());
+}
+
+#[test]
+fn behaviour_trees_wait_in_line_for_full_zones() {
+    let mut app = headless_app();
+    let kiosk = app
+        .world
+        .spawn()
+        .insert_bundle(
+            ZoneBundle::new("kiosk", Vec2::new(5000.0, 5000.0), 200.0, 200.0)
+                .with_kind(ZoneKind::Shop)
+                .with_capacity(1),
+        )
+        .id();
+    let customer = spawn_agent(&mut app.world, "anna", Vec2::new(5000.0, 5000.0));
+    let waiting = spawn_agent(&mut app.world, "bob", Vec2::new(5000.0, 4850.0));
+    for agent in [customer, waiting] {
+        app.world.entity_mut(agent).remove::<UtilityBrain>();
+    }
+    let root = BehaviourNode::from_ron(
+        r#"Sequence([
+            MoveToZone("kiosk"),
+            SetBlackboard(key: "shopped", value: Bool(true)),
+        ])"#,
+    )
+    .unwrap();
+    app.update();
+    app.world
+        .entity_mut(waiting)
+        .insert(BehaviourTree::new(root));
+
+    // the tree keeps running while bob waits outside, without taking bob out of the queue
+    for _ in 0..5 {
+        app.update();
+    }
+    assert_eq!(app.world.get::<Queued>(waiting).unwrap().zone, kiosk);
+    assert_eq!(app.world.get::<Agent>(waiting).unwrap().destination, None);
+    assert_eq!(
+        app.world
+            .resource::<ZoneQueues>()
+            .queue(kiosk)
+            .collect::<Vec<_>>(),
+        vec![waiting]
+    );
+    assert_eq!(
+        app.world.get::<BehaviourTree>(waiting).unwrap().status,
+        Status::Running
+    );
+
+    // once anna leaves bob is let in and the tree finishes inside
+    app.world
+        .get_mut::<Transform>(customer)
+        .unwrap()
+        .translation = Vec3::new(0.0, -8000.0, 1.0);
+    app.update();
+    app.update();
+    assert!(app.world.get::<Queued>(waiting).is_none());
+    assert_eq!(
+        app.world.get::<Agent>(waiting).unwrap().destination,
+        Some(Vec2::new(5000.0, 5000.0))
+    );
+    app.world.get_mut::<Transform>(waiting).unwrap().translation = Vec3::new(5000.0, 5000.0, 1.0);
+    app.update();
+    let tree = app.world.get::<BehaviourTree>(waiting).unwrap();
+    assert_eq!(tree.status, Status::Success);
+    assert_eq!(
+        tree.blackboard.get("shopped"),
+        Some(&BlackboardValue::Bool(true))
+    );
+}
+
+#[test]
+fn behaviour_trees_give_up_on_zones_that_turn_them_away() {
+    let mut app = headless_app();
+    app.world.spawn().insert_bundle(
+        ZoneBundle::new("kiosk", Vec2::new(5000.0, 5000.0), 200.0, 200.0)
+            .with_kind(ZoneKind::Shop)
+            .with_hours(9.0, 17.0),
+    );
+    let agent = spawn_agent(&mut app.world, "anna", Vec2::new(5000.0, 4850.0));
+    app.world.entity_mut(agent).remove::<UtilityBrain>();
+    let root = BehaviourNode::from_ron(
+        r#"Selector([
+            MoveToZone("kiosk"),
+            SetBlackboard(key: "gave_up", value: Bool(true)),
+        ])"#,
+    )
+    .unwrap();
+    app.update();
+    app.world.resource_mut::<TimeOfDay>().hours = 20.0;
+    app.world.entity_mut(agent).insert(BehaviourTree::new(root));
+
+    // the kiosk is closed, so anna is turned away at the door and tries something else
+    app.update();
+    app.update();
+    let tree = app.world.get::<BehaviourTree>(agent).unwrap();
+    assert_eq!(tree.status, Status::Success);
+    assert_eq!(
+        tree.blackboard.get("gave_up"),
+        Some(&BlackboardValue::Bool(true))
+    );
+    assert_eq!(app.world.get::<Agent>(agent).unwrap().destination, None);
+}
+
+/// `utility_context` is the context of an agent at the origin at noon, with no zones around
+fn utility_context<'a>(
+    needs: Option<&'a Needs>,
+    settings: &'a NeedSettings,
+    activity: &'a str,
+) -> UtilityContext<'a> {
+    UtilityContext {
+        agent: Entity::from_raw(0),
+        position: Vec2::ZERO,
+        activity,
+        needs,
+        need_settings: settings,
+        faction: None,
+        schedule: None,
+        time_of_day: 0.5,
+        zones: &[],
+    }
+}
+
+#[test]
+fn response_curves_are_clamped() {
+    let linear = ResponseCurve::Linear {
+        slope: 2.0,
+        offset: -0.5,
+    };
+    assert_eq!(linear.evaluate(0.0), 0.0);
+    assert_eq!(linear.evaluate(0.5), 0.5);
+    assert_eq!(linear.evaluate(2.0), 1.0);
+
+    let polynomial = ResponseCurve::Polynomial {
+        slope: 1.0,
+        exponent: 2.0,
+        offset: 0.0,
+    };
+    assert_eq!(polynomial.evaluate(0.5), 0.25);
+    assert_eq!(polynomial.evaluate(-1.0), 0.0);
+
+    let logistic = ResponseCurve::Logistic {
+        steepness: -12.0,
+        midpoint: 0.3,
+    };
+    assert!((logistic.evaluate(0.3) - 0.5).abs() < 1e-6);
+    assert!(logistic.evaluate(0.0) > 0.95);
+    assert!(logistic.evaluate(1.0) < 0.01);
+
+    let window = ResponseCurve::Window {
+        start: 0.25,
+        end: 0.75,
+    };
+    assert_eq!(window.evaluate(0.5), 1.0);
+    assert_eq!(window.evaluate(0.8), 0.0);
+    let night = ResponseCurve::Window {
+        start: 0.9,
+        end: 0.1,
+    };
+    assert_eq!(night.evaluate(0.95), 1.0);
+    assert_eq!(night.evaluate(0.05), 1.0);
+    assert_eq!(night.evaluate(0.5), 0.0);
+}
+
+#[test]
+fn combined_scores_do_not_punish_many_considerations() {
+    assert_eq!(combine_scores([]), 0.0);
+    assert_eq!(combine_scores([0.5]), 0.5);
+    assert_eq!(combine_scores([0.0, 1.0]), 0.0);
+    assert_eq!(combine_scores([1.0, 1.0, 1.0]), 1.0);
+
+    let two = combine_scores([0.5, 0.5]);
+    let four = combine_scores([0.5; 4]);
+    assert!(two > 0.25 && two < 0.5);
+    assert!(four > 0.5f32.powi(4));
+}
+
+#[test]
+fn need_actions_follow_the_need_settings() {
+    let mut settings = NeedSettings::default();
+    settings.get_mut(NeedKind::Hunger).critical = 0.4;
+    settings.get_mut(NeedKind::Hunger).satisfied = 0.9;
+    let eat = NeedAction::new("eat", NeedKind::Hunger);
+    let mut needs = Needs::default();
+
+    // urgency is centered on the critical level
+    needs.set(NeedKind::Hunger, 0.4);
+    let urgency = eat.urgency(
+        &utility_context(Some(&needs), &settings, ""),
+        &ActionTarget::Stay,
+    );
+    assert!((urgency - 0.5).abs() < 1e-6);
+    needs.set(NeedKind::Hunger, 0.8);
+    let urgency = eat.urgency(
+        &utility_context(Some(&needs), &settings, ""),
+        &ActionTarget::Stay,
+    );
+    assert!(urgency < 0.01);
+
+    // agents already eating keep at it until they are satisfied
+    let urgency = eat.urgency(
+        &utility_context(Some(&needs), &settings, "eat"),
+        &ActionTarget::Stay,
+    );
+    assert_eq!(urgency, 1.0);
+    needs.set(NeedKind::Hunger, 0.9);
+    let urgency = eat.urgency(
+        &utility_context(Some(&needs), &settings, "eat"),
+        &ActionTarget::Stay,
+    );
+    assert_eq!(urgency, 0.0);
+}
+
+struct Nap {
+    considerations: Vec<ScoredConsideration>,
+}
+
+impl UtilityAction for Nap {
+    fn name(&self) -> &str {
+        "nap"
+    }
+
+    fn considerations(&self) -> &[ScoredConsideration] {
+        &self.considerations
+    }
+
+    fn target(&self, _context: &UtilityContext) -> Option<ActionTarget> {
+        Some(ActionTarget::Stay)
+    }
+
+    fn weight(&self) -> f32 {
+        0.5
+    }
+}
+
+#[test]
+fn custom_actions_are_scored_from_their_considerations() {
+    let nap = Nap {
+        considerations: vec![
+            ScoredConsideration::new(
+                Consideration::Need(NeedKind::Energy),
+                ResponseCurve::Linear {
+                    slope: -1.0,
+                    offset: 1.0,
+                },
+            ),
+            ScoredConsideration::new(
+                Consideration::TimeOfDay,
+                ResponseCurve::Window {
+                    start: 0.4,
+                    end: 0.6,
+                },
+            ),
+        ],
+    };
+    // considerations can be written down in data files
+    let ron = ron::to_string(&nap.considerations).unwrap();
+    let loaded: Vec<ScoredConsideration> = ron::from_str(&ron).unwrap();
+    assert_eq!(loaded, nap.considerations);
+
+    let settings = NeedSettings::default();
+    let mut needs = Needs::default();
+    needs.set(NeedKind::Energy, 0.2);
+    let context = utility_context(Some(&needs), &settings, "");
+
+    let expected = combine_scores([0.8, 1.0]) * 0.5;
+    assert!((nap.score(&context, &ActionTarget::Stay) - expected).abs() < 1e-6);
+
+    let mut actions = UtilityActions::default();
+    actions.add(IdleAction::default()).add(nap);
+    let best = actions
+        .iter()
+        .max_by(|a, b| {
+            a.score(&context, &ActionTarget::Stay)
+                .total_cmp(&b.score(&context, &ActionTarget::Stay))
+        })
+        .unwrap();
+    assert_eq!(best.name(), "nap");
+}